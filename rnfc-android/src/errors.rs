@@ -44,6 +44,7 @@ impl_from_throwable!(AsTechError);
 #[derive(Clone, Debug)]
 pub enum TransceiveError {
     BufferTooSmall,
+    Unsupported,
     Exception(String),
 }
 impl Display for TransceiveError {
@@ -11,7 +11,7 @@ use java_spaghetti::sys::{JNIEnv, jobject};
 use java_spaghetti::{ByteArray, Env, Global, Local, Null, PrimitiveArray, Ref, VM};
 use log::{info, warn};
 use rnfc_traits::iso_dep::Reader as IsoDepReader;
-use rnfc_traits::iso14443a::Reader as Iso14443aReader;
+use rnfc_traits::iso14443a::{RawFrame, Reader as Iso14443aReader};
 
 use crate::bindings::android::app::Activity;
 use crate::bindings::android::nfc::tech::{IsoDep, NfcA};
@@ -271,6 +271,34 @@ impl<'a> Iso14443aReader for Iso14443aTag<'a> {
         rx[..rxd.len()].copy_from_slice(&rxd);
         Ok(rxd.len())
     }
+
+    async fn transceive_raw(&mut self, tx: &[u8], rx: &mut [u8], opts: RawFrame) -> Result<usize, Self::Error> {
+        // Android always sends whole bytes with parity and CRC, and checks and strips the CRC of
+        // responses. 4-bit ACK/NAKs come back as a single byte.
+        let standard = RawFrame::standard(opts.timeout_1fc, tx.len());
+        let no_rx_crc = RawFrame {
+            rx_crc: false,
+            ..standard
+        };
+        if opts != standard && opts != no_rx_crc {
+            return Err(TransceiveError::Unsupported);
+        }
+        let n = self.transceive(tx, rx, opts.timeout_1fc).await?;
+        if opts.rx_crc {
+            return Ok(n * 8);
+        }
+        // No command sent without CRC check has a single byte response, so this is an ACK/NAK.
+        if n == 1 && rx[0] < 0x10 {
+            return Ok(4);
+        }
+        // The caller checks the CRC itself, put back the one Android already checked.
+        if n + 2 > rx.len() {
+            return Err(TransceiveError::BufferTooSmall);
+        }
+        let crc = crc_a(&rx[..n]);
+        rx[n..n + 2].copy_from_slice(&crc);
+        Ok((n + 2) * 8)
+    }
 }
 
 pub struct IsoDepTag<'a> {
@@ -324,6 +352,17 @@ impl NfcAdapter_ReaderCallbackProxy for ReaderCallback {
     }
 }
 
+/// CRC_A of ISO/IEC 14443-3, in transmission order.
+fn crc_a(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0x6363;
+    for &b in data {
+        let b = b ^ crc as u8;
+        let b = b ^ (b << 4);
+        crc = (crc >> 8) ^ ((b as u16) << 8) ^ ((b as u16) << 3) ^ ((b as u16) >> 4);
+    }
+    crc.to_le_bytes()
+}
+
 fn u8toi8(slice: &[u8]) -> &[i8] {
     let len = slice.len();
     let data = slice.as_ptr() as *const i8;
@@ -77,6 +77,25 @@ where
 
         Ok(Iso14443a { inner: self })
     }
+
+    fn set_speed(&mut self, tx: ll::BitRate, rx: ll::BitRate) {
+        // Modulation pulse width, in 1/fc units: shorter at higher bit rates.
+        let (tx_speed, modwidth) = match tx {
+            ll::BitRate::Kbps106 => (regs::Speed::_106KBPS, 0x27),
+            ll::BitRate::Kbps212 => (regs::Speed::_212KBPS, 0x15),
+            ll::BitRate::Kbps424 => (regs::Speed::_424KBPS, 0x0A),
+            ll::BitRate::Kbps848 => (regs::Speed::_848KBPS, 0x05),
+        };
+        let rx_speed = match rx {
+            ll::BitRate::Kbps106 => regs::Speed::_106KBPS,
+            ll::BitRate::Kbps212 => regs::Speed::_212KBPS,
+            ll::BitRate::Kbps424 => regs::Speed::_424KBPS,
+            ll::BitRate::Kbps848 => regs::Speed::_848KBPS,
+        };
+        self.regs().txmode().modify(|w| w.set_speed(tx_speed));
+        self.regs().rxmode().modify(|w| w.set_speed(rx_speed));
+        self.regs().modwidth().write_value(modwidth);
+    }
 }
 
 impl<'d, I, NpdPin, IrqPin> Drop for Iso14443a<'d, I, NpdPin, IrqPin>
@@ -103,16 +122,40 @@ where
 
         let r = &mut *self.inner;
 
-        let (tx, crc, timeout_1fc, lastbits, rxalign) = match opts {
-            ll::Frame::Anticoll { bits } => (&tx[..(bits + 7) / 8], false, 65536, (bits % 8) as u8, (bits % 8) as u8),
-            ll::Frame::ReqA => (&[0x26][..], false, 16384, 7, 0),
-            ll::Frame::WupA => (&[0x52][..], false, 16384, 7, 0),
-            ll::Frame::Standard { timeout_1fc } => (tx, true, timeout_1fc, 0, 0),
+        if matches!(opts, ll::Frame::ReqA | ll::Frame::WupA) {
+            // Polling is always at 106 kbps.
+            r.set_speed(ll::BitRate::Kbps106, ll::BitRate::Kbps106);
+        }
+
+        let (tx, tx_crc, rx_crc, parity, timeout_1fc, lastbits, rxalign) = match opts {
+            ll::Frame::Anticoll { bits } => (
+                &tx[..(bits + 7) / 8],
+                false,
+                false,
+                true,
+                65536,
+                (bits % 8) as u8,
+                (bits % 8) as u8,
+            ),
+            ll::Frame::ReqA => (&[0x26][..], false, false, true, 16384, 7, 0),
+            ll::Frame::WupA => (&[0x52][..], false, false, true, 16384, 7, 0),
+            ll::Frame::Standard { timeout_1fc } => (tx, true, true, true, timeout_1fc, 0, 0),
+            ll::Frame::Raw(f) => (
+                &tx[..f.tx_bits.div_ceil(8)],
+                f.tx_crc,
+                f.rx_crc,
+                f.parity,
+                f.timeout_1fc,
+                (f.tx_bits % 8) as u8,
+                0,
+            ),
+            _ => todo!(),
         };
 
-        // Set CRC
-        r.regs().txmode().modify(|w| w.set_crcen(crc));
-        r.regs().rxmode().modify(|w| w.set_crcen(crc));
+        // Set CRC and parity
+        r.regs().txmode().modify(|w| w.set_crcen(tx_crc));
+        r.regs().rxmode().modify(|w| w.set_crcen(rx_crc));
+        r.regs().manualrcv().modify(|w| w.set_paritydisable(!parity));
 
         // Set timeout
         r.set_timer(timeout_1fc);
@@ -299,7 +342,30 @@ where
                 return Err(Error::Collision);
             }
             debug!("RX: {:02x}", Bytes(&rx[..rx_pos]));
+
+            // rxbits is the number of valid bits in the last byte, 0 if it's complete.
+            if let ll::Frame::Raw(_) = opts {
+                let rxbits = r.regs().control().read().rxbits() as usize;
+                if rxbits != 0 && rx_pos != 0 {
+                    return Ok((rx_pos - 1) * 8 + rxbits);
+                }
+            }
             Ok(rx_pos * 8)
         }
     }
+
+    fn supports_bit_rate(&self, _bit_rate: ll::BitRate) -> bool {
+        true
+    }
+
+    async fn set_bit_rate(&mut self, tx: ll::BitRate, rx: ll::BitRate) -> Result<(), Self::Error> {
+        debug!("bit rate: tx {:?} rx {:?}", tx, rx);
+        self.inner.set_speed(tx, rx);
+        Ok(())
+    }
+
+    async fn delay(&mut self, delay_1fc: u32) {
+        // fc = 13.56 MHz
+        Timer::after(Duration::from_micros((delay_1fc as u64 * 1_000_000).div_ceil(13_560_000))).await
+    }
 }
@@ -127,6 +127,15 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
         this.cmd(Command::ResetRxgain)?;
 
         let is_anticoll = matches!(opts, ll::Frame::Anticoll { .. });
+        let parity = match opts {
+            ll::Frame::Raw(f) => f.parity,
+            _ => true,
+        };
+
+        if matches!(opts, ll::Frame::ReqA | ll::Frame::WupA) {
+            // Polling is always at 106 kbps.
+            this.set_bit_rate(regs::BitRateE::_106, regs::BitRateE::_106)?;
+        }
 
         let (raw, cmd, timeout_1fc) = match opts {
             ll::Frame::ReqA => (true, Command::TransmitReqa, NFCA_FDTMIN),
@@ -144,6 +153,19 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
                 this.iface.write_fifo(tx).map_err(Error::Interface)?;
                 (false, Command::TransmitWithCrc, timeout_1fc)
             }
+            ll::Frame::Raw(f) => {
+                this.regs().num_tx_bytes2().write_value((f.tx_bits as u8).into())?;
+                this.regs().num_tx_bytes1().write_value((f.tx_bits >> 8) as u8)?;
+                this.iface
+                    .write_fifo(&tx[..f.tx_bits.div_ceil(8)])
+                    .map_err(Error::Interface)?;
+                let cmd = match f.tx_crc {
+                    true => Command::TransmitWithCrc,
+                    false => Command::TransmitWithoutCrc,
+                };
+                (!f.rx_crc, cmd, f.timeout_1fc)
+            }
+            _ => todo!(),
         };
         this.regs().corr_conf1().write(|w| {
             w.0 = 0x11;
@@ -152,6 +174,8 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
 
         this.regs().iso14443a_nfc().write(|w| {
             w.set_antcl(is_anticoll);
+            w.set_no_tx_par(!parity);
+            w.set_no_rx_par(!parity);
         })?;
         this.regs().aux().write(|w| {
             w.set_no_crc_rx(raw);
@@ -230,7 +254,9 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
             debug!("RX: FifoUnderflow");
             return Err(Error::FifoUnderflow);
         }
-        if stat.np_lb() {
+        // Incomplete last bytes are expected in raw frames.
+        let is_raw = matches!(opts, ll::Frame::Raw(_));
+        if stat.np_lb() && !is_raw {
             debug!("RX: FramingLastByteMissingParity");
             return Err(Error::FramingLastByteMissingParity);
         }
@@ -275,7 +301,37 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
 
             this.iface.read_fifo(&mut rx[..rx_bytes]).map_err(Error::Interface)?;
             debug!("RX: {:02x}", Bytes(&rx[..rx_bytes]));
+
+            // fifo_lb is the number of valid bits in the last byte, 0 if it's complete.
+            let last_bits = stat.fifo_lb() as usize;
+            if is_raw && last_bits != 0 && rx_bytes != 0 {
+                return Ok((rx_bytes - 1) * 8 + last_bits);
+            }
             Ok(rx_bytes * 8)
         }
     }
+
+    fn supports_bit_rate(&self, _bit_rate: ll::BitRate) -> bool {
+        true
+    }
+
+    async fn set_bit_rate(&mut self, tx: ll::BitRate, rx: ll::BitRate) -> Result<(), Self::Error> {
+        debug!("bit rate: tx {:?} rx {:?}", tx, rx);
+        self.inner.set_bit_rate(bit_rate(tx), bit_rate(rx))?;
+        Ok(())
+    }
+
+    async fn delay(&mut self, delay_1fc: u32) {
+        // fc = 13.56 MHz
+        Timer::after(Duration::from_micros((delay_1fc as u64 * 1_000_000).div_ceil(13_560_000))).await
+    }
+}
+
+fn bit_rate(b: ll::BitRate) -> regs::BitRateE {
+    match b {
+        ll::BitRate::Kbps106 => regs::BitRateE::_106,
+        ll::BitRate::Kbps212 => regs::BitRateE::_212,
+        ll::BitRate::Kbps424 => regs::BitRateE::_424,
+        ll::BitRate::Kbps848 => regs::BitRateE::_848,
+    }
 }
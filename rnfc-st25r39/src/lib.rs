@@ -669,10 +669,7 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
         self.regs().corr_conf2().write_value(0x00.into())?;
         */
 
-        self.regs().bit_rate().write(|w| {
-            w.set_rxrate(regs::BitRateE::_106);
-            w.set_txrate(regs::BitRateE::_106);
-        })?;
+        self.set_bit_rate(regs::BitRateE::_106, regs::BitRateE::_106)?;
 
         // defaults
         self.regs().iso14443a_nfc().write(|_| {})?;
@@ -787,6 +784,13 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
         panic!("NRT out of range")
     }
 
+    fn set_bit_rate(&mut self, tx: regs::BitRateE, rx: regs::BitRateE) -> Result<(), Error<I::Error>> {
+        self.regs().bit_rate().write(|w| {
+            w.set_txrate(tx);
+            w.set_rxrate(rx);
+        })
+    }
+
     pub fn raw(&mut self) -> Raw<'_, I, IrqPin> {
         Raw { inner: self }
     }
@@ -1,4 +1,4 @@
-pub use crate::iso14443a_ll::Error;
+pub use crate::iso14443a_ll::{BitRate, Error, RawFrame};
 
 pub const UID_MAX_LEN: usize = 10;
 
@@ -7,9 +7,45 @@ pub trait Reader {
 
     async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error>;
 
+    /// Bit-oriented transceive. Returns the number of received bits.
+    async fn transceive_raw(&mut self, tx: &[u8], rx: &mut [u8], opts: RawFrame) -> Result<usize, Self::Error>;
+
     fn uid(&self) -> &[u8];
     fn atqa(&self) -> [u8; 2];
     fn sak(&self) -> u8;
+
+    /// Whether the reader can send and receive at `bit_rate`. 106 kbps is always supported.
+    fn supports_bit_rate(&self, bit_rate: BitRate) -> bool {
+        bit_rate == BitRate::Kbps106
+    }
+
+    /// Switch the bit rate of the following frames, `tx` from reader to card and `rx` from
+    /// card to reader, after negotiating it with the card. Only called with supported bit
+    /// rates.
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        let _ = (tx, rx);
+        Ok(())
+    }
+
+    /// Wait `delay_1fc` before sending the next frame, such as the start-up frame guard
+    /// time after the ATS. The default doesn't wait, for readers that handle guard times
+    /// themselves.
+    async fn delay(&mut self, delay_1fc: u32) {
+        let _ = delay_1fc;
+    }
+
+    /// Whether the reader can select the card again with [`reselect`](Self::reselect).
+    fn supports_reselect(&self) -> bool {
+        false
+    }
+
+    /// Wake up and select the card with `uid` again, after a DESELECT or a communication
+    /// failure left it idle or halted. The reader then stands for that card, which may not
+    /// be the one it was selected for. Only called if supported.
+    async fn reselect(&mut self, uid: &[u8]) -> Result<(), Self::Error> {
+        let _ = uid;
+        Ok(())
+    }
 }
 
 impl<T: Reader> Reader for &mut T {
@@ -19,6 +55,10 @@ impl<T: Reader> Reader for &mut T {
         T::transceive(self, tx, rx, timeout_1fc).await
     }
 
+    async fn transceive_raw(&mut self, tx: &[u8], rx: &mut [u8], opts: RawFrame) -> Result<usize, Self::Error> {
+        T::transceive_raw(self, tx, rx, opts).await
+    }
+
     fn uid(&self) -> &[u8] {
         T::uid(self)
     }
@@ -28,4 +68,24 @@ impl<T: Reader> Reader for &mut T {
     fn sak(&self) -> u8 {
         T::sak(self)
     }
+
+    fn supports_bit_rate(&self, bit_rate: BitRate) -> bool {
+        T::supports_bit_rate(self, bit_rate)
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        T::set_bit_rate(self, tx, rx).await
+    }
+
+    async fn delay(&mut self, delay_1fc: u32) {
+        T::delay(self, delay_1fc).await
+    }
+
+    fn supports_reselect(&self) -> bool {
+        T::supports_reselect(self)
+    }
+
+    async fn reselect(&mut self, uid: &[u8]) -> Result<(), Self::Error> {
+        T::reselect(self, uid).await
+    }
 }
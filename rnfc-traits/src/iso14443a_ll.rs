@@ -1,6 +1,7 @@
 use core::convert::Infallible;
 use core::fmt::Debug;
 
+#[non_exhaustive]
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Frame {
@@ -8,6 +9,50 @@ pub enum Frame {
     WupA,
     ReqA,
     Anticoll { bits: usize },
+    Raw(RawFrame),
+}
+
+/// Bit-oriented frame, for short frames and frames with custom CRC or parity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RawFrame {
+    pub timeout_1fc: u32,
+    /// Number of bits of `tx` to send, LSB first.
+    pub tx_bits: usize,
+    /// Append CRC_A on transmit.
+    pub tx_crc: bool,
+    /// Check and strip CRC_A on receive.
+    pub rx_crc: bool,
+    /// Generate and check parity bits. If false, `tx` and `rx` are raw bit streams
+    /// where each data byte is followed by its parity bit, provided by the caller.
+    pub parity: bool,
+}
+
+impl RawFrame {
+    /// Frame equivalent to [`Frame::Standard`].
+    pub const fn standard(timeout_1fc: u32, tx_len: usize) -> Self {
+        Self {
+            timeout_1fc,
+            tx_bits: tx_len * 8,
+            tx_crc: true,
+            rx_crc: true,
+            parity: true,
+        }
+    }
+}
+
+/// Bit rate of one direction of the link, ISO/IEC 14443-2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitRate {
+    /// fc/128, the bit rate of activation.
+    Kbps106,
+    /// fc/64
+    Kbps212,
+    /// fc/32
+    Kbps424,
+    /// fc/16
+    Kbps848,
 }
 
 #[non_exhaustive]
@@ -39,6 +84,26 @@ pub trait Reader {
     type Error: Error;
 
     async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error>;
+
+    /// Whether the reader can send and receive at `bit_rate`. 106 kbps is always supported.
+    fn supports_bit_rate(&self, bit_rate: BitRate) -> bool {
+        bit_rate == BitRate::Kbps106
+    }
+
+    /// Switch the bit rate of the following frames, `tx` from reader to card and `rx` from
+    /// card to reader. Only called with supported bit rates. [`Frame::ReqA`] and
+    /// [`Frame::WupA`] switch back to 106 kbps.
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        let _ = (tx, rx);
+        Ok(())
+    }
+
+    /// Wait `delay_1fc` before sending the next frame, such as the start-up frame guard
+    /// time after the ATS. The default doesn't wait, for readers that handle guard times
+    /// themselves.
+    async fn delay(&mut self, delay_1fc: u32) {
+        let _ = delay_1fc;
+    }
 }
 
 impl<T: Reader> Reader for &mut T {
@@ -47,4 +112,16 @@ impl<T: Reader> Reader for &mut T {
     async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error> {
         T::transceive(self, tx, rx, opts).await
     }
+
+    fn supports_bit_rate(&self, bit_rate: BitRate) -> bool {
+        T::supports_bit_rate(self, bit_rate)
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        T::set_bit_rate(self, tx, rx).await
+    }
+
+    async fn delay(&mut self, delay_1fc: u32) {
+        T::delay(self, delay_1fc).await
+    }
 }
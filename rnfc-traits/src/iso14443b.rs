@@ -0,0 +1,35 @@
+pub use crate::iso14443b_ll::Error;
+
+pub const PUPI_LEN: usize = 4;
+pub const APPLICATION_DATA_LEN: usize = 4;
+pub const PROTOCOL_INFO_MAX_LEN: usize = 4;
+
+/// An NFC-B card that has been activated with ATTRIB.
+pub trait Reader {
+    type Error: Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error>;
+
+    fn pupi(&self) -> [u8; PUPI_LEN];
+    fn application_data(&self) -> [u8; APPLICATION_DATA_LEN];
+    /// Protocol info bytes from the ATQB. 3 bytes, or 4 bytes for an extended ATQB.
+    fn protocol_info(&self) -> &[u8];
+}
+
+impl<T: Reader> Reader for &mut T {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        T::transceive(self, tx, rx, timeout_1fc).await
+    }
+
+    fn pupi(&self) -> [u8; PUPI_LEN] {
+        T::pupi(self)
+    }
+    fn application_data(&self) -> [u8; APPLICATION_DATA_LEN] {
+        T::application_data(self)
+    }
+    fn protocol_info(&self) -> &[u8] {
+        T::protocol_info(self)
+    }
+}
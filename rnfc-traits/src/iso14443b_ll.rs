@@ -0,0 +1,36 @@
+pub use crate::iso14443a_ll::{Error, ErrorKind};
+
+/// Frame kind for an NFC-B exchange.
+///
+/// In all cases `tx` contains the complete frame as built by the caller, without CRC_B.
+/// The reader appends CRC_B on transmit, and checks and strips it on receive.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame {
+    /// Regular frame, such as ATTRIB, HLTB or ISO-DEP blocks.
+    Standard { timeout_1fc: u32 },
+    /// REQB. The response is an ATQB, expected within FWT(ATQB).
+    ReqB,
+    /// WUPB. The response is an ATQB, expected within FWT(ATQB).
+    WupB,
+    /// Slot-MARKER for slot number `slot` (2..=16). The response is an ATQB, expected within FWT(ATQB).
+    SlotMarker { slot: u8 },
+}
+
+pub trait Reader {
+    type Error: Error;
+
+    /// Transceive a frame. Returns the number of received bytes, excluding CRC_B.
+    ///
+    /// If several cards answer in the same slot, this must fail with an error
+    /// of kind [`ErrorKind::Corruption`].
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error>;
+}
+
+impl<T: Reader> Reader for &mut T {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error> {
+        T::transceive(self, tx, rx, opts).await
+    }
+}
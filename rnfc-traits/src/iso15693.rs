@@ -0,0 +1,34 @@
+pub use crate::iso14443a_ll::{Error, ErrorKind};
+
+pub const UID_LEN: usize = 8;
+
+/// Frame kind for an NFC-V exchange.
+///
+/// The reader must use single subcarrier and high data rate, 1 out of 4 coding.
+/// The reader appends the CRC on transmit, and checks and strips it on receive.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame {
+    /// Regular request, the response is expected within `timeout_1fc`.
+    Standard { timeout_1fc: u32 },
+    /// Send only an EOF, to switch to the next slot of a 16-slot inventory. `tx` is empty.
+    Eof { timeout_1fc: u32 },
+}
+
+pub trait Reader {
+    type Error: Error;
+
+    /// Transceive a frame. Returns the number of received bytes, excluding the CRC.
+    ///
+    /// If several tags answer in the same slot, this must fail with an error
+    /// of kind [`ErrorKind::Corruption`].
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error>;
+}
+
+impl<T: Reader> Reader for &mut T {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error> {
+        T::transceive(self, tx, rx, opts).await
+    }
+}
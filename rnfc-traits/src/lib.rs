@@ -6,5 +6,9 @@ mod fmt;
 
 pub mod iso14443a;
 pub mod iso14443a_ll;
+pub mod iso14443b;
+pub mod iso14443b_ll;
+pub mod iso15693;
 
 pub mod iso_dep;
+pub mod nfcf;
@@ -0,0 +1,51 @@
+pub use crate::iso14443a_ll::{Error, ErrorKind};
+
+pub const IDM_LEN: usize = 8;
+pub const PMM_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitRate {
+    Kbps212,
+    Kbps424,
+}
+
+/// Frame kind for an NFC-F exchange.
+///
+/// Frames are length-prefixed: `tx` and `rx` start with the LEN byte, which counts
+/// itself and the payload but not the CRC. The reader adds the preamble, sync code
+/// and CRC on transmit, and checks and strips them on receive.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame {
+    /// Regular command, the response is expected within `timeout_1fc`.
+    Standard { timeout_1fc: u32 },
+    /// SENSF_REQ with `slots` time slots (1, 2, 4, 8 or 16).
+    ///
+    /// The reader must keep receiving until the last time slot has ended, and store all
+    /// SENSF_RES frames received without errors back to back in `rx`. Frames corrupted by
+    /// collisions are dropped. The returned length is the total for all frames.
+    SensfReq { slots: u8 },
+}
+
+pub trait Reader {
+    type Error: Error;
+
+    /// Switch the TX and RX bit rate.
+    async fn set_bit_rate(&mut self, bit_rate: BitRate) -> Result<(), Self::Error>;
+
+    /// Transceive a frame. Returns the number of received bytes, including the LEN byte.
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error>;
+}
+
+impl<T: Reader> Reader for &mut T {
+    type Error = T::Error;
+
+    async fn set_bit_rate(&mut self, bit_rate: BitRate) -> Result<(), Self::Error> {
+        T::set_bit_rate(self, bit_rate).await
+    }
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error> {
+        T::transceive(self, tx, rx, opts).await
+    }
+}
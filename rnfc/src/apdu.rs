@@ -0,0 +1,514 @@
+//! ISO 7816-4 APDUs on top of ISO-DEP.
+//!
+//! [`Command`] encodes command APDUs of all 4 cases, with short or extended Lc and Le.
+//! [`transceive`] sends one over any [`iso_dep::Reader`](rnfc_traits::iso_dep::Reader) and
+//! takes care of the transport-level status words: it fetches remaining data with GET
+//! RESPONSE on 61xx, re-sends the command with the right Le on 6Cxx, and splits data longer
+//! than 255 bytes with command chaining.
+
+use rnfc_traits::iso_dep::Reader;
+
+use crate::fmt::Bytes;
+
+/// Command chaining bit of interindustry CLA bytes: more commands of the chain follow.
+pub const CLA_CHAINING: u8 = 0x10;
+
+/// Longest data in a short APDU.
+pub const MAX_SHORT_LC: usize = 255;
+/// Biggest Le of a short APDU, encoded as 00.
+pub const MAX_SHORT_LE: usize = 256;
+/// Longest data in an extended APDU.
+pub const MAX_EXTENDED_LC: usize = 65535;
+/// Biggest Le of an extended APDU, encoded as 0000.
+pub const MAX_EXTENDED_LE: usize = 65536;
+
+const INS_GET_RESPONSE: u8 = 0xC0;
+
+/// Header, extended Lc, data of a chained command and extended Le.
+const TX_BUF_LEN: usize = 4 + 3 + MAX_SHORT_LC + 2;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The card sent a response without a status word, or kept asking for a different Le.
+    Protocol,
+    /// The card answered with a status word other than 9000.
+    Status(StatusWord),
+    /// The response doesn't fit in the buffer.
+    TooBig,
+}
+
+/// Status word, SW1-SW2, of a response APDU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatusWord(pub u16);
+
+impl StatusWord {
+    pub const OK: Self = Self(0x9000);
+    pub const VERIFICATION_FAILED: Self = Self(0x6300);
+    pub const WRONG_LENGTH: Self = Self(0x6700);
+    pub const LAST_COMMAND_EXPECTED: Self = Self(0x6883);
+    pub const CHAINING_NOT_SUPPORTED: Self = Self(0x6884);
+    pub const SECURITY_STATUS_NOT_SATISFIED: Self = Self(0x6982);
+    pub const AUTH_METHOD_BLOCKED: Self = Self(0x6983);
+    pub const CONDITIONS_NOT_SATISFIED: Self = Self(0x6985);
+    pub const WRONG_DATA: Self = Self(0x6A80);
+    pub const FUNCTION_NOT_SUPPORTED: Self = Self(0x6A81);
+    pub const FILE_NOT_FOUND: Self = Self(0x6A82);
+    pub const RECORD_NOT_FOUND: Self = Self(0x6A83);
+    pub const INCORRECT_P1P2: Self = Self(0x6A86);
+    pub const REFERENCED_DATA_NOT_FOUND: Self = Self(0x6A88);
+    pub const WRONG_P1P2: Self = Self(0x6B00);
+    pub const INS_NOT_SUPPORTED: Self = Self(0x6D00);
+    pub const CLA_NOT_SUPPORTED: Self = Self(0x6E00);
+
+    pub fn from_bytes(sw1: u8, sw2: u8) -> Self {
+        Self(u16::from_be_bytes([sw1, sw2]))
+    }
+
+    pub fn sw1(&self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    pub fn sw2(&self) -> u8 {
+        self.0 as u8
+    }
+
+    /// 9000, normal processing.
+    pub fn is_ok(&self) -> bool {
+        *self == Self::OK
+    }
+
+    /// 62xx or 63xx: processed, with a warning.
+    pub fn is_warning(&self) -> bool {
+        matches!(self.sw1(), 0x62 | 0x63)
+    }
+
+    /// 61xx: the number of response bytes still available, to be fetched with GET RESPONSE.
+    pub fn bytes_available(&self) -> Option<usize> {
+        match self.sw1() {
+            0x61 => Some(le_from_byte(self.sw2())),
+            _ => None,
+        }
+    }
+
+    /// 6Cxx: wrong Le, the command must be sent again with the returned one.
+    pub fn correct_le(&self) -> Option<usize> {
+        match self.sw1() {
+            0x6C => Some(le_from_byte(self.sw2())),
+            _ => None,
+        }
+    }
+
+    /// 63Cx: verification failed, with the number of tries left.
+    pub fn tries_left(&self) -> Option<u8> {
+        match self.0 & 0xFFF0 {
+            0x63C0 => Some(self.sw2() & 0x0F),
+            _ => None,
+        }
+    }
+}
+
+fn le_from_byte(b: u8) -> usize {
+    match b {
+        0 => MAX_SHORT_LE,
+        n => n as usize,
+    }
+}
+
+/// ISO 7816-4 command case, from the presence of data and Le.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Case {
+    /// No data, no Le.
+    Case1,
+    /// Le only.
+    Case2,
+    /// Data only.
+    Case3,
+    /// Data and Le.
+    Case4,
+}
+
+/// A command APDU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Command<'a> {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: &'a [u8],
+    /// Maximum number of response bytes expected, or `None` for no Le field.
+    pub le: Option<usize>,
+}
+
+impl<'a> Command<'a> {
+    /// A case 1 command, add data and Le with [`with_data`](Self::with_data) and
+    /// [`with_le`](Self::with_le).
+    pub const fn new(cla: u8, ins: u8, p1: u8, p2: u8) -> Self {
+        Self {
+            cla,
+            ins,
+            p1,
+            p2,
+            data: &[],
+            le: None,
+        }
+    }
+
+    pub const fn with_data(self, data: &'a [u8]) -> Self {
+        // Not the defmt macro, which isn't const.
+        core::assert!(data.len() <= MAX_EXTENDED_LC);
+        Self { data, ..self }
+    }
+
+    /// Set Le. Use [`MAX_SHORT_LE`] to get as much as a short APDU allows, Le = 00.
+    pub const fn with_le(self, le: usize) -> Self {
+        core::assert!(le > 0 && le <= MAX_EXTENDED_LE);
+        Self { le: Some(le), ..self }
+    }
+
+    pub fn case(&self) -> Case {
+        match (self.data.is_empty(), self.le.is_some()) {
+            (true, false) => Case::Case1,
+            (true, true) => Case::Case2,
+            (false, false) => Case::Case3,
+            (false, true) => Case::Case4,
+        }
+    }
+
+    /// Whether Lc or Le don't fit in a short APDU. Both fields are then extended.
+    pub fn is_extended(&self) -> bool {
+        self.data.len() > MAX_SHORT_LC || self.le.is_some_and(|le| le > MAX_SHORT_LE)
+    }
+
+    /// Length of the encoded command.
+    pub fn encoded_len(&self) -> usize {
+        let ext = self.is_extended();
+        let lc = match (self.data.len(), ext) {
+            (0, _) => 0,
+            (n, false) => 1 + n,
+            (n, true) => 3 + n,
+        };
+        let le = match (self.le, ext) {
+            (None, _) => 0,
+            (Some(_), false) => 1,
+            (Some(_), true) if self.data.is_empty() => 3,
+            (Some(_), true) => 2,
+        };
+        4 + lc + le
+    }
+
+    /// Encode the command into `buf`, returning its length, or `None` if it doesn't fit.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        let buf = buf.get_mut(..len)?;
+        let ext = self.is_extended();
+
+        buf[..4].copy_from_slice(&[self.cla, self.ins, self.p1, self.p2]);
+        let mut pos = 4;
+        if ext {
+            // Extended length marker.
+            buf[pos] = 0x00;
+            pos += 1;
+        }
+        if !self.data.is_empty() {
+            let lc = self.data.len() as u16;
+            if ext {
+                buf[pos..][..2].copy_from_slice(&lc.to_be_bytes());
+                pos += 2;
+            } else {
+                buf[pos] = lc as u8;
+                pos += 1;
+            }
+            buf[pos..][..self.data.len()].copy_from_slice(self.data);
+            pos += self.data.len();
+        }
+        if let Some(le) = self.le {
+            // The maximum wraps around to zero.
+            if ext {
+                buf[pos..][..2].copy_from_slice(&(le as u16).to_be_bytes());
+                pos += 2;
+            } else {
+                buf[pos] = le as u8;
+                pos += 1;
+            }
+        }
+        debug_assert!(pos == len);
+        Some(len)
+    }
+}
+
+/// A response APDU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response<'a> {
+    pub data: &'a [u8],
+    pub sw: StatusWord,
+}
+
+impl<'a> Response<'a> {
+    /// Split a response into data and status word.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let (data, sw) = data.split_last_chunk::<2>()?;
+        Some(Self {
+            data,
+            sw: StatusWord::from_bytes(sw[0], sw[1]),
+        })
+    }
+
+    /// The response data if the status word is 9000.
+    pub fn ok<E>(self) -> Result<&'a [u8], Error<E>> {
+        match self.sw.is_ok() {
+            true => Ok(self.data),
+            false => Err(Error::Status(self.sw)),
+        }
+    }
+}
+
+/// Send a command and receive the response into `rx`, with any status word.
+///
+/// The response data of all GET RESPONSE commands is concatenated in `rx`, which needs room
+/// for the status word too. Data longer than [`MAX_SHORT_LC`] is sent with command chaining,
+/// for interindustry CLA bytes only: each link but the last has [`CLA_CHAINING`] set and must
+/// be answered with 9000. Encode extended commands with [`Command::encode`] to send them in
+/// one piece instead.
+pub async fn transceive<'r, T: Reader>(
+    reader: &mut T,
+    cmd: &Command<'_>,
+    rx: &'r mut [u8],
+) -> Result<Response<'r>, Error<T::Error>> {
+    let mut tx = [0; TX_BUF_LEN];
+
+    let mut data = cmd.data;
+    while data.len() > MAX_SHORT_LC {
+        let (chunk, rest) = data.split_at(MAX_SHORT_LC);
+        let link = Command {
+            cla: cmd.cla | CLA_CHAINING,
+            le: None,
+            ..*cmd
+        }
+        .with_data(chunk);
+        let n = exchange(reader, &link, &mut tx, rx).await?;
+        let sw = StatusWord::from_bytes(rx[n - 2], rx[n - 1]);
+        if !sw.is_ok() {
+            debug!("chaining interrupted: {:04x}", sw.0);
+            return Ok(Response { data: &[], sw });
+        }
+        data = rest;
+    }
+
+    let mut current = Command { data, ..*cmd };
+    let mut len = 0;
+    // The command is sent again once with the Le from 6Cxx, cards asking again are broken.
+    let mut resent = false;
+    loop {
+        let n = exchange(reader, &current, &mut tx, &mut rx[len..]).await?;
+        let sw = StatusWord::from_bytes(rx[len + n - 2], rx[len + n - 1]);
+        let got = n - 2;
+
+        if let Some(le) = sw.correct_le() {
+            if got != 0 || resent || current.le == Some(le) {
+                warn!("bad 6Cxx response");
+                return Err(Error::Protocol);
+            }
+            debug!("wrong Le, sending again with Le={}", le);
+            current = current.with_le(le);
+            resent = true;
+            continue;
+        }
+
+        if let Some(available) = sw.bytes_available() {
+            if got == 0 && current.ins == INS_GET_RESPONSE {
+                warn!("GET RESPONSE returned no data");
+                return Err(Error::Protocol);
+            }
+            len += got;
+            let room = rx.len() - len;
+            if room <= 2 {
+                return Err(Error::TooBig);
+            }
+            // GET RESPONSE is interindustry, keep only the logical channel.
+            current = Command::new(cmd.cla & 0x03, INS_GET_RESPONSE, 0x00, 0x00).with_le(available.min(room - 2));
+            continue;
+        }
+
+        len += got;
+        rx[len..][..2].copy_from_slice(&sw.0.to_be_bytes());
+        return Ok(Response { data: &rx[..len], sw });
+    }
+}
+
+/// Send a command and check the status word is 9000. Returns the response data.
+pub async fn command<'r, T: Reader>(reader: &mut T, cmd: &Command<'_>, rx: &'r mut [u8]) -> Result<&'r [u8], Error<T::Error>> {
+    transceive(reader, cmd, rx).await?.ok()
+}
+
+/// Send a single command APDU, returning the response length including the status word.
+async fn exchange<T: Reader>(
+    reader: &mut T,
+    cmd: &Command<'_>,
+    tx: &mut [u8; TX_BUF_LEN],
+    rx: &mut [u8],
+) -> Result<usize, Error<T::Error>> {
+    let n = unwrap!(cmd.encode(tx));
+    debug!("APDU TX: {:02x}", Bytes(&tx[..n]));
+    let n = reader.transceive(&tx[..n], rx).await.map_err(Error::Lower)?;
+    debug!("APDU RX: {:02x}", Bytes(&rx[..n]));
+    if n < 2 {
+        warn!("response too short");
+        return Err(Error::Protocol);
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::test_util::{MockReader, mock};
+
+    fn encode(cmd: &Command) -> Vec<u8> {
+        let mut buf = vec![0; cmd.encoded_len()];
+        assert_eq!(cmd.encode(&mut buf), Some(buf.len()));
+        buf
+    }
+
+    #[test]
+    fn test_encode_short() {
+        let cmd = Command::new(0x00, 0xA4, 0x04, 0x00);
+        assert_eq!(cmd.case(), Case::Case1);
+        assert_eq!(encode(&cmd), hex!("00A40400"));
+
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(MAX_SHORT_LE);
+        assert_eq!(cmd.case(), Case::Case2);
+        assert_eq!(encode(&cmd), hex!("00B0000000"));
+
+        let cmd = Command::new(0x00, 0xD6, 0x00, 0x10).with_data(&[1, 2, 3]);
+        assert_eq!(cmd.case(), Case::Case3);
+        assert_eq!(encode(&cmd), hex!("00D6001003010203"));
+
+        let cmd = Command::new(0x00, 0xA4, 0x04, 0x00).with_data(&[0xA0, 0x00]).with_le(0x10);
+        assert_eq!(cmd.case(), Case::Case4);
+        assert!(!cmd.is_extended());
+        assert_eq!(encode(&cmd), hex!("00A4040002A00010"));
+
+        assert_eq!(cmd.encode(&mut [0; 7]), None);
+    }
+
+    #[test]
+    fn test_encode_extended() {
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(MAX_EXTENDED_LE);
+        assert!(cmd.is_extended());
+        assert_eq!(encode(&cmd), hex!("00B00000000000"));
+
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(0x0101);
+        assert_eq!(encode(&cmd), hex!("00B00000000101"));
+
+        let data = [0xAB; 0x100];
+        let cmd = Command::new(0x00, 0xD6, 0x00, 0x00).with_data(&data);
+        let buf = encode(&cmd);
+        assert_eq!(buf[..7], hex!("00D60000000100"));
+        assert_eq!(buf.len(), 7 + 0x100);
+
+        // Extended Le makes Lc extended too.
+        let cmd = Command::new(0x00, 0x2A, 0x00, 0x00).with_data(&[0x01]).with_le(0x200);
+        assert_eq!(encode(&cmd), hex!("002A0000 000001 01 0200"));
+    }
+
+    #[test]
+    fn test_status_word() {
+        let r = Response::parse(&hex!("0102 6A82")).unwrap();
+        assert_eq!(r.data, hex!("0102"));
+        assert_eq!(r.sw, StatusWord::FILE_NOT_FOUND);
+        assert!(!r.sw.is_ok());
+        assert!(matches!(r.ok::<()>(), Err(Error::Status(StatusWord::FILE_NOT_FOUND))));
+        assert_eq!(Response::parse(&hex!("90")), None);
+
+        assert_eq!(StatusWord(0x6100).bytes_available(), Some(256));
+        assert_eq!(StatusWord(0x6C10).correct_le(), Some(0x10));
+        assert_eq!(StatusWord(0x63C2).tries_left(), Some(2));
+        assert_eq!(StatusWord(0x6310).tries_left(), None);
+        assert!(StatusWord(0x6310).is_warning());
+    }
+
+    #[tokio::test]
+    async fn test_get_response() {
+        let mut r = mock!(
+            "00A4040002A00000" => "6F03 6104",
+            "00C0000004" => "840201 02 6102",
+            "00C0000002" => "0304 9000",
+        );
+        let cmd = Command::new(0x00, 0xA4, 0x04, 0x00)
+            .with_data(&[0xA0, 0x00])
+            .with_le(MAX_SHORT_LE);
+        let mut rx = [0; 32];
+        let resp = transceive(&mut r, &cmd, &mut rx).await.unwrap();
+        assert_eq!(resp.data, hex!("6F03 840201 02 0304"));
+        assert_eq!(resp.sw, StatusWord::OK);
+        r.assert_done();
+
+        // GET RESPONSE Le is limited by the room left in the buffer.
+        let mut r = mock!(
+            "00CA9F7F00" => "6120",
+            "00C0000006" => "010203040506 6118",
+        );
+        let cmd = Command::new(0x00, 0xCA, 0x9F, 0x7F).with_le(MAX_SHORT_LE);
+        let mut rx = [0; 8];
+        assert!(matches!(transceive(&mut r, &cmd, &mut rx).await, Err(Error::TooBig)));
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_wrong_le() {
+        let mut r = mock!(
+            "00B0000000" => "6C03",
+            "00B0000003" => "010203 9000",
+            // Asking for the same Le again is a protocol error.
+            "00B0000002" => "6C02",
+            // So is asking for another one after sending again.
+            "00B0000002" => "6C04",
+            "00B0000004" => "6C05",
+        );
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(MAX_SHORT_LE);
+        let mut rx = [0; 16];
+        assert_eq!(command(&mut r, &cmd, &mut rx).await.unwrap(), hex!("010203"));
+
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(2);
+        assert!(matches!(transceive(&mut r, &cmd, &mut rx).await, Err(Error::Protocol)));
+        assert!(matches!(transceive(&mut r, &cmd, &mut rx).await, Err(Error::Protocol)));
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_chaining() {
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut first = hex!("10DA0000FF").to_vec();
+        first.extend_from_slice(&data[..255]);
+        let mut last = hex!("00DA00002D").to_vec();
+        last.extend_from_slice(&data[255..]);
+        last.push(0x00);
+
+        let mut r = MockReader {
+            expected: vec![
+                (first.clone().leak(), &hex!("9000")),
+                (last.leak(), &hex!("AA 9000")),
+                // The card refuses the chain.
+                (first.leak(), &hex!("6884")),
+            ],
+            pos: 0,
+        };
+        let cmd = Command::new(0x00, 0xDA, 0x00, 0x00).with_data(&data).with_le(MAX_SHORT_LE);
+        let mut rx = [0; 16];
+        assert_eq!(command(&mut r, &cmd, &mut rx).await.unwrap(), hex!("AA"));
+
+        let resp = transceive(&mut r, &cmd, &mut rx).await.unwrap();
+        assert_eq!(resp.sw, StatusWord::CHAINING_NOT_SUPPORTED);
+        r.assert_done();
+    }
+}
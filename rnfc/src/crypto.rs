@@ -0,0 +1,229 @@
+//! Block ciphers, CBC, CMAC and ISO/IEC 9797-1 padding, shared by the secure messaging of
+//! DESFire, eMRTD, GlobalPlatform and MIFARE Ultralight C.
+
+use aes::Aes128;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use des::{TdesEde2, TdesEde3};
+use heapless::Vec;
+
+/// Largest block size, AES.
+pub const MAX_BLOCK_SIZE: usize = 16;
+
+/// A block cipher key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Key {
+    /// 2K3DES key. Single DES keys are 2K3DES keys with both halves equal.
+    TDes2([u8; 16]),
+    /// 3K3DES key.
+    TDes3([u8; 24]),
+    /// AES-128 key.
+    Aes([u8; 16]),
+}
+
+impl Key {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::TDes2(k) => k,
+            Self::TDes3(k) => k,
+            Self::Aes(k) => k,
+        }
+    }
+
+    /// Whether this is a single DES key, a 2K3DES key with both halves equal.
+    pub fn is_single_des(&self) -> bool {
+        match self {
+            Self::TDes2(k) => k[..8] == k[8..],
+            _ => false,
+        }
+    }
+}
+
+// Without an allocator, boxing the bigger variant isn't an option.
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Cipher {
+    TDes2(TdesEde2),
+    TDes3(TdesEde3),
+    Aes(Aes128),
+}
+
+impl Cipher {
+    pub fn new(key: &Key) -> Self {
+        match key {
+            Key::TDes2(k) => Self::TDes2(TdesEde2::new(GenericArray::from_slice(k))),
+            Key::TDes3(k) => Self::TDes3(TdesEde3::new(GenericArray::from_slice(k))),
+            Key::Aes(k) => Self::Aes(Aes128::new(GenericArray::from_slice(k))),
+        }
+    }
+
+    pub fn aes(key: &[u8; 16]) -> Self {
+        Self::Aes(Aes128::new(GenericArray::from_slice(key)))
+    }
+
+    pub fn block_size(&self) -> usize {
+        match self {
+            Self::Aes(_) => 16,
+            _ => 8,
+        }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8]) {
+        match self {
+            Self::TDes2(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+            Self::TDes3(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Aes(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+        }
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8]) {
+        match self {
+            Self::TDes2(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+            Self::TDes3(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Aes(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+        }
+    }
+
+    /// CBC encrypt `data` in place. `iv` is updated to the last ciphertext block, for chaining.
+    pub fn cbc_encrypt(&self, iv: &mut [u8], data: &mut [u8]) {
+        let bs = self.block_size();
+        debug_assert!(iv.len() == bs && data.len().is_multiple_of(bs));
+        for block in data.chunks_exact_mut(bs) {
+            xor(block, iv);
+            self.encrypt_block(block);
+            iv.copy_from_slice(block);
+        }
+    }
+
+    /// CBC decrypt `data` in place. `iv` is updated to the last ciphertext block, for chaining.
+    pub fn cbc_decrypt(&self, iv: &mut [u8], data: &mut [u8]) {
+        let bs = self.block_size();
+        debug_assert!(iv.len() == bs && data.len().is_multiple_of(bs));
+        let mut next_iv = [0; MAX_BLOCK_SIZE];
+        for block in data.chunks_exact_mut(bs) {
+            next_iv[..bs].copy_from_slice(block);
+            self.decrypt_block(block);
+            xor(block, iv);
+            iv.copy_from_slice(&next_iv[..bs]);
+        }
+    }
+
+    /// CMAC of `data`, chained from `iv` instead of zeros like EV1 secure messaging does.
+    /// Only the first [`block_size`](Self::block_size) bytes of the result are used.
+    pub fn cmac(&self, iv: &[u8], data: &[u8]) -> [u8; MAX_BLOCK_SIZE] {
+        let bs = self.block_size();
+        let rb = match bs {
+            16 => 0x87,
+            _ => 0x1B,
+        };
+
+        let mut k1 = [0; MAX_BLOCK_SIZE];
+        self.encrypt_block(&mut k1[..bs]);
+        shift_subkey(&mut k1[..bs], rb);
+        let mut k2 = k1;
+        shift_subkey(&mut k2[..bs], rb);
+
+        let full_blocks = match data.len() {
+            0 => 0,
+            n => (n - 1) / bs,
+        };
+        let mut mac = [0; MAX_BLOCK_SIZE];
+        mac[..bs].copy_from_slice(iv);
+        for block in data[..full_blocks * bs].chunks_exact(bs) {
+            xor(&mut mac[..bs], block);
+            self.encrypt_block(&mut mac[..bs]);
+        }
+
+        let last = &data[full_blocks * bs..];
+        let mut block = [0; MAX_BLOCK_SIZE];
+        block[..last.len()].copy_from_slice(last);
+        if last.len() == bs {
+            xor(&mut block[..bs], &k1[..bs]);
+        } else {
+            block[last.len()] = 0x80;
+            xor(&mut block[..bs], &k2[..bs]);
+        }
+        xor(&mut mac[..bs], &block[..bs]);
+        self.encrypt_block(&mut mac[..bs]);
+        mac
+    }
+}
+
+fn shift_subkey(k: &mut [u8], rb: u8) {
+    let msb = k[0] >> 7;
+    for i in 0..k.len() {
+        let next = k.get(i + 1).map_or(0, |b| b >> 7);
+        k[i] = k[i] << 1 | next;
+    }
+    if msb != 0 {
+        k[k.len() - 1] ^= rb;
+    }
+}
+
+pub(crate) fn xor(a: &mut [u8], b: &[u8]) {
+    for (a, b) in a.iter_mut().zip(b) {
+        *a ^= b;
+    }
+}
+
+/// Append ISO/IEC 9797-1 padding method 2.
+pub(crate) fn pad<const N: usize>(buf: &mut Vec<u8, N>, block_size: usize) -> Option<()> {
+    buf.push(0x80).ok()?;
+    while !buf.len().is_multiple_of(block_size) {
+        buf.push(0x00).ok()?;
+    }
+    Some(())
+}
+
+/// Length of data padded with ISO/IEC 9797-1 padding method 2.
+pub(crate) fn unpad(data: &[u8]) -> Option<usize> {
+    let n = data.iter().rposition(|&b| b != 0x00)?;
+    (data[n] == 0x80).then_some(n)
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_cmac_aes() {
+        // NIST SP 800-38B, AES-128 examples.
+        let c = Cipher::aes(&hex!("2b7e151628aed2a6abf7158809cf4f3c"));
+        let iv = [0; 16];
+        assert_eq!(c.cmac(&iv, &[]), hex!("bb1d6929e95937287fa37d129b756746"));
+        assert_eq!(
+            c.cmac(&iv, &hex!("6bc1bee22e409f96e93d7e117393172a")),
+            hex!("070a16b46b4d4144f79bdd9dd04a287c")
+        );
+        assert_eq!(
+            c.cmac(
+                &iv,
+                &hex!("6bc1bee22e409f96e93d7e117393172a ae2d8a571e03ac9c9eb76fac45af8e51 30c81c46a35ce411")
+            ),
+            hex!("dfa66747de9ae63030ca32611497c827")
+        );
+    }
+
+    #[test]
+    fn test_cmac_tdes() {
+        // NIST SP 800-38B, three-key TDEA example with an empty message.
+        let c = Cipher::new(&Key::TDes3(hex!("8aa83bf8cbda1062 0bc1bf19fbb6cd58 bc313d4a371ca8b5")));
+        assert_eq!(c.cmac(&[0; 8], &[])[..8], hex!("b7a688e122ffaf95"));
+    }
+
+    #[test]
+    fn test_cbc() {
+        let c = Cipher::new(&Key::TDes2(hex!("00112233445566778899AABBCCDDEEFF")));
+        let plain = hex!("0102030405060708 1112131415161718");
+        let mut data = plain;
+        let mut iv = [0; 8];
+        c.cbc_encrypt(&mut iv, &mut data);
+        assert_eq!(iv, data[8..]);
+
+        let mut iv = [0; 8];
+        c.cbc_decrypt(&mut iv, &mut data);
+        assert_eq!(data, plain);
+    }
+}
@@ -0,0 +1,950 @@
+//! FIDO2 security keys over NFC: the CTAP NFC binding, with CTAP2 and U2F commands.
+//!
+//! [`Ctap::select_ctap2`] selects the FIDO applet and tells whether the authenticator
+//! speaks CTAP2 or only U2F. Authenticators that support both answer SELECT with the U2F
+//! version, so they're told apart from U2F-only ones with authenticatorGetInfo. CTAP2
+//! commands go in NFCCTAP_MSG APDUs, chained when longer than a short APDU. While the
+//! authenticator works, for example waiting for the user to touch it, it answers 9100 and
+//! gets polled with NFCCTAP_GETRESPONSE until the response is ready.
+//!
+//! Requests and responses are CBOR, see [`MakeCredential`], [`GetAssertion`] and [`Info`].
+//! Responses borrow from the receive buffer given to each command, which needs room for
+//! the whole response: [`DEFAULT_MAX_MSG_SIZE`] bytes, or the `max_msg_size` of [`Info`].
+//!
+//! Authenticators that only support U2F take [`Ctap::u2f_register`] and
+//! [`Ctap::u2f_authenticate`] instead, raw U2F messages in ISO 7816-4 APDUs.
+
+pub mod cbor;
+
+use heapless::Vec;
+use rnfc_traits::iso_dep::Reader;
+
+use crate::apdu::{self, Command, StatusWord};
+use crate::fmt::Bytes;
+use crate::tlv;
+
+/// AID of the FIDO applet.
+pub const FIDO_AID: &[u8] = &[0xA0, 0x00, 0x00, 0x06, 0x47, 0x2F, 0x00, 0x01];
+
+/// Message size all authenticators support.
+pub const DEFAULT_MAX_MSG_SIZE: usize = 1024;
+/// Longest request we send, command byte included.
+pub const MAX_REQUEST_LEN: usize = 1024;
+/// NFCCTAP_GETRESPONSE polls before giving up on an authenticator that stays busy.
+pub const MAX_GETRESPONSE_POLLS: usize = 1000;
+
+pub const CMD_MAKE_CREDENTIAL: u8 = 0x01;
+pub const CMD_GET_ASSERTION: u8 = 0x02;
+pub const CMD_GET_INFO: u8 = 0x04;
+pub const CMD_CLIENT_PIN: u8 = 0x06;
+pub const CMD_RESET: u8 = 0x07;
+pub const CMD_GET_NEXT_ASSERTION: u8 = 0x08;
+
+pub const STATUS_OK: u8 = 0x00;
+pub const ERR_INVALID_PARAMETER: u8 = 0x02;
+pub const ERR_CREDENTIAL_EXCLUDED: u8 = 0x19;
+pub const ERR_UNSUPPORTED_ALGORITHM: u8 = 0x26;
+pub const ERR_OPERATION_DENIED: u8 = 0x27;
+pub const ERR_NO_CREDENTIALS: u8 = 0x2E;
+pub const ERR_USER_ACTION_TIMEOUT: u8 = 0x2F;
+pub const ERR_PIN_AUTH_INVALID: u8 = 0x33;
+pub const ERR_PIN_REQUIRED: u8 = 0x36;
+
+/// COSE algorithm identifier of ECDSA with SHA-256 on P-256.
+pub const ALG_ES256: i32 = -7;
+/// COSE algorithm identifier of EdDSA.
+pub const ALG_EDDSA: i32 = -8;
+
+const CLA: u8 = 0x00;
+const CLA_PROPRIETARY: u8 = 0x80;
+const INS_SELECT: u8 = 0xA4;
+const INS_NFCCTAP_MSG: u8 = 0x10;
+const INS_NFCCTAP_GETRESPONSE: u8 = 0x11;
+const INS_U2F_REGISTER: u8 = 0x01;
+const INS_U2F_AUTHENTICATE: u8 = 0x02;
+
+/// NFCCTAP_MSG P1: we poll with NFCCTAP_GETRESPONSE, so the authenticator may answer 9100.
+const P1_GETRESPONSE_SUPPORTED: u8 = 0x80;
+
+const U2F_ENFORCE_USER_PRESENCE: u8 = 0x03;
+const U2F_CHECK_ONLY: u8 = 0x07;
+const U2F_REGISTER_ID: u8 = 0x05;
+
+/// Response not ready yet, poll with NFCCTAP_GETRESPONSE.
+const SW_STATUS_UPDATE: StatusWord = StatusWord(0x9100);
+
+const STATUS_PROCESSING: u8 = 0x01;
+const STATUS_UPNEEDED: u8 = 0x02;
+
+const PUBLIC_KEY: &str = "public-key";
+
+/// Authenticator data flags.
+pub const FLAG_USER_PRESENT: u8 = 0x01;
+pub const FLAG_USER_VERIFIED: u8 = 0x04;
+pub const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+pub const FLAG_EXTENSIONS: u8 = 0x80;
+
+const MAX_VERSIONS: usize = 8;
+const MAX_EXTENSIONS: usize = 8;
+const MAX_OPTIONS: usize = 16;
+const MAX_PIN_PROTOCOLS: usize = 4;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The authenticator sent a malformed response.
+    Protocol,
+    /// The authenticator answered with a status word other than 9000.
+    Status(StatusWord),
+    /// A request or response doesn't fit in the buffers.
+    TooBig,
+    /// The authenticator returned a CTAP2 error status, one of the `ERR_*` constants.
+    Ctap(u8),
+    /// The authenticator was still busy after [`MAX_GETRESPONSE_POLLS`] polls.
+    Timeout,
+}
+
+impl<E> From<apdu::Error<E>> for Error<E> {
+    fn from(e: apdu::Error<E>) -> Self {
+        match e {
+            apdu::Error::Lower(e) => Self::Lower(e),
+            apdu::Error::Protocol => Self::Protocol,
+            apdu::Error::Status(sw) => Self::Status(sw),
+            apdu::Error::TooBig => Self::TooBig,
+        }
+    }
+}
+
+/// Protocol version returned by SELECT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Version {
+    /// U2F, possibly CTAP2 too: authenticators supporting both answer this.
+    U2fV2,
+    /// CTAP2 only.
+    Fido2,
+}
+
+/// A relying party.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RelyingParty<'a> {
+    pub id: &'a str,
+    pub name: Option<&'a str>,
+}
+
+/// A user account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct User<'a> {
+    pub id: &'a [u8],
+    pub name: Option<&'a str>,
+    pub display_name: Option<&'a str>,
+}
+
+/// authenticatorMakeCredential request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MakeCredential<'a> {
+    pub client_data_hash: &'a [u8; 32],
+    pub rp: RelyingParty<'a>,
+    pub user: User<'a>,
+    /// COSE algorithms, most preferred first.
+    pub algorithms: &'a [i32],
+    /// IDs of credentials the authenticator mustn't already have.
+    pub exclude_list: &'a [&'a [u8]],
+    /// Make a discoverable credential.
+    pub resident_key: bool,
+    /// Ask for user verification, built-in only: use `pin_uv_auth_param` for a PIN.
+    pub user_verification: bool,
+    pub pin_uv_auth_param: Option<&'a [u8]>,
+    pub pin_uv_auth_protocol: Option<u8>,
+}
+
+impl MakeCredential<'_> {
+    fn encode<const N: usize>(&self, e: &mut cbor::Encoder<N>) -> Result<(), cbor::Error> {
+        let exclude = !self.exclude_list.is_empty();
+        let options = self.resident_key || self.user_verification;
+        let pin = self.pin_uv_auth_param.is_some();
+        e.map(4 + exclude as usize + options as usize + 2 * pin as usize)?;
+
+        e.unsigned(0x01)?;
+        e.bytes(self.client_data_hash)?;
+
+        e.unsigned(0x02)?;
+        e.map(1 + self.rp.name.is_some() as usize)?;
+        e.text("id")?;
+        e.text(self.rp.id)?;
+        if let Some(name) = self.rp.name {
+            e.text("name")?;
+            e.text(name)?;
+        }
+
+        e.unsigned(0x03)?;
+        e.map(1 + self.user.name.is_some() as usize + self.user.display_name.is_some() as usize)?;
+        e.text("id")?;
+        e.bytes(self.user.id)?;
+        if let Some(name) = self.user.name {
+            e.text("name")?;
+            e.text(name)?;
+        }
+        if let Some(name) = self.user.display_name {
+            e.text("displayName")?;
+            e.text(name)?;
+        }
+
+        e.unsigned(0x04)?;
+        e.array(self.algorithms.len())?;
+        for &alg in self.algorithms {
+            e.map(2)?;
+            e.text("alg")?;
+            e.int(alg as i64)?;
+            e.text("type")?;
+            e.text(PUBLIC_KEY)?;
+        }
+
+        if exclude {
+            e.unsigned(0x05)?;
+            encode_credentials(e, self.exclude_list)?;
+        }
+        if options {
+            e.unsigned(0x07)?;
+            e.map(self.resident_key as usize + self.user_verification as usize)?;
+            if self.resident_key {
+                e.text("rk")?;
+                e.bool(true)?;
+            }
+            if self.user_verification {
+                e.text("uv")?;
+                e.bool(true)?;
+            }
+        }
+        encode_pin(e, 0x08, self.pin_uv_auth_param, self.pin_uv_auth_protocol)
+    }
+}
+
+/// authenticatorGetAssertion request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GetAssertion<'a> {
+    pub rp_id: &'a str,
+    pub client_data_hash: &'a [u8; 32],
+    /// IDs of the credentials to use. Empty to use discoverable credentials.
+    pub allow_list: &'a [&'a [u8]],
+    /// Ask for user presence. Without it the authenticator only checks the credential.
+    pub user_presence: bool,
+    /// Ask for user verification, built-in only: use `pin_uv_auth_param` for a PIN.
+    pub user_verification: bool,
+    pub pin_uv_auth_param: Option<&'a [u8]>,
+    pub pin_uv_auth_protocol: Option<u8>,
+}
+
+impl GetAssertion<'_> {
+    fn encode<const N: usize>(&self, e: &mut cbor::Encoder<N>) -> Result<(), cbor::Error> {
+        let allow = !self.allow_list.is_empty();
+        // User presence defaults to true, user verification to false.
+        let options = !self.user_presence || self.user_verification;
+        let pin = self.pin_uv_auth_param.is_some();
+        e.map(2 + allow as usize + options as usize + 2 * pin as usize)?;
+
+        e.unsigned(0x01)?;
+        e.text(self.rp_id)?;
+        e.unsigned(0x02)?;
+        e.bytes(self.client_data_hash)?;
+        if allow {
+            e.unsigned(0x03)?;
+            encode_credentials(e, self.allow_list)?;
+        }
+        if options {
+            e.unsigned(0x05)?;
+            e.map(!self.user_presence as usize + self.user_verification as usize)?;
+            if !self.user_presence {
+                e.text("up")?;
+                e.bool(false)?;
+            }
+            if self.user_verification {
+                e.text("uv")?;
+                e.bool(true)?;
+            }
+        }
+        encode_pin(e, 0x06, self.pin_uv_auth_param, self.pin_uv_auth_protocol)
+    }
+}
+
+/// Encode a list of PublicKeyCredentialDescriptors.
+fn encode_credentials<const N: usize>(e: &mut cbor::Encoder<N>, ids: &[&[u8]]) -> Result<(), cbor::Error> {
+    e.array(ids.len())?;
+    for id in ids {
+        e.map(2)?;
+        e.text("id")?;
+        e.bytes(id)?;
+        e.text("type")?;
+        e.text(PUBLIC_KEY)?;
+    }
+    Ok(())
+}
+
+/// Encode pinUvAuthParam and pinUvAuthProtocol, at keys `key` and `key + 1`.
+fn encode_pin<const N: usize>(
+    e: &mut cbor::Encoder<N>,
+    key: u64,
+    param: Option<&[u8]>,
+    protocol: Option<u8>,
+) -> Result<(), cbor::Error> {
+    if let Some(param) = param {
+        e.unsigned(key)?;
+        e.bytes(param)?;
+        e.unsigned(key + 1)?;
+        e.unsigned(protocol.unwrap_or(1) as u64)?;
+    }
+    Ok(())
+}
+
+/// authenticatorGetInfo response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Info<'a> {
+    /// Supported versions: `U2F_V2`, `FIDO_2_0`, `FIDO_2_1`...
+    pub versions: Vec<&'a str, MAX_VERSIONS>,
+    pub extensions: Vec<&'a str, MAX_EXTENSIONS>,
+    pub aaguid: [u8; 16],
+    /// Options and their values, like `("rk", true)` or `("clientPin", false)`.
+    pub options: Vec<(&'a str, bool), MAX_OPTIONS>,
+    pub max_msg_size: Option<u32>,
+    pub pin_uv_auth_protocols: Vec<u8, MAX_PIN_PROTOCOLS>,
+}
+
+impl<'a> Info<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, cbor::Error> {
+        let mut info = Self {
+            versions: Vec::new(),
+            extensions: Vec::new(),
+            aaguid: [0; 16],
+            options: Vec::new(),
+            max_msg_size: None,
+            pin_uv_auth_protocols: Vec::new(),
+        };
+        let mut d = cbor::Decoder::new(data);
+        for _ in 0..d.map()? {
+            match d.unsigned()? {
+                0x01 => text_list(&mut d, &mut info.versions)?,
+                0x02 => text_list(&mut d, &mut info.extensions)?,
+                0x03 => info.aaguid = d.bytes()?.try_into().map_err(|_| cbor::Error::UnexpectedType)?,
+                0x04 => {
+                    for _ in 0..d.map()? {
+                        let option = (d.text()?, d.bool()?);
+                        if info.options.push(option).is_err() {
+                            warn!("too many options, ignoring {}", option.0);
+                        }
+                    }
+                }
+                0x05 => info.max_msg_size = Some(u32::try_from(d.unsigned()?).map_err(|_| cbor::Error::UnexpectedType)?),
+                0x06 => {
+                    for _ in 0..d.array()? {
+                        let protocol = d.unsigned()?;
+                        if let Ok(p) = u8::try_from(protocol) {
+                            let _ = info.pin_uv_auth_protocols.push(p);
+                        }
+                    }
+                }
+                _ => {
+                    d.skip()?;
+                }
+            }
+        }
+        Ok(info)
+    }
+
+    /// Value of an option, `None` if the authenticator doesn't support it.
+    pub fn option(&self, name: &str) -> Option<bool> {
+        self.options.iter().find(|(n, _)| *n == name).map(|&(_, v)| v)
+    }
+}
+
+fn text_list<'a, const N: usize>(d: &mut cbor::Decoder<'a>, out: &mut Vec<&'a str, N>) -> Result<(), cbor::Error> {
+    for _ in 0..d.array()? {
+        let s = d.text()?;
+        if out.push(s).is_err() {
+            warn!("too many entries, ignoring {}", s);
+        }
+    }
+    Ok(())
+}
+
+/// A credential created by makeCredential, from the authenticator data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AttestedCredential<'a> {
+    pub aaguid: &'a [u8],
+    pub id: &'a [u8],
+    /// Public key as a COSE_Key, in CBOR.
+    pub public_key: &'a [u8],
+}
+
+/// Authenticator data, signed in attestations and assertions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AuthenticatorData<'a> {
+    /// The whole encoded authenticator data, to check signatures.
+    pub raw: &'a [u8],
+    pub rp_id_hash: &'a [u8],
+    pub flags: u8,
+    pub sign_count: u32,
+    pub credential: Option<AttestedCredential<'a>>,
+}
+
+impl<'a> AuthenticatorData<'a> {
+    pub fn parse(raw: &'a [u8]) -> Option<Self> {
+        let rp_id_hash = raw.get(..32)?;
+        let flags = *raw.get(32)?;
+        let sign_count = u32::from_be_bytes(raw.get(33..37)?.try_into().ok()?);
+        let credential = match flags & FLAG_ATTESTED_CREDENTIAL {
+            0 => None,
+            _ => {
+                let aaguid = raw.get(37..53)?;
+                let len = u16::from_be_bytes(raw.get(53..55)?.try_into().ok()?) as usize;
+                let id = raw.get(55..55 + len)?;
+                let public_key = cbor::Decoder::new(&raw[55 + len..]).skip().ok()?;
+                Some(AttestedCredential { aaguid, id, public_key })
+            }
+        };
+        Some(Self {
+            raw,
+            rp_id_hash,
+            flags,
+            sign_count,
+            credential,
+        })
+    }
+
+    pub fn user_present(&self) -> bool {
+        self.flags & FLAG_USER_PRESENT != 0
+    }
+
+    pub fn user_verified(&self) -> bool {
+        self.flags & FLAG_USER_VERIFIED != 0
+    }
+}
+
+/// authenticatorMakeCredential response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Attestation<'a> {
+    /// Attestation statement format, like `packed` or `fido-u2f`.
+    pub fmt: &'a str,
+    pub auth_data: AuthenticatorData<'a>,
+    /// Attestation statement, in CBOR.
+    pub att_stmt: &'a [u8],
+}
+
+impl<'a> Attestation<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let mut fmt = None;
+        let mut auth_data = None;
+        let mut att_stmt = None;
+        let mut d = cbor::Decoder::new(data);
+        for _ in 0..d.map().ok()? {
+            match d.unsigned().ok()? {
+                0x01 => fmt = Some(d.text().ok()?),
+                0x02 => auth_data = Some(AuthenticatorData::parse(d.bytes().ok()?)?),
+                0x03 => att_stmt = Some(d.skip().ok()?),
+                _ => {
+                    d.skip().ok()?;
+                }
+            }
+        }
+        let auth_data = auth_data?;
+        auth_data.credential?;
+        Some(Self {
+            fmt: fmt?,
+            auth_data,
+            att_stmt: att_stmt?,
+        })
+    }
+}
+
+/// authenticatorGetAssertion response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Assertion<'a> {
+    /// ID of the credential used. Authenticators may leave it out if the allow list had
+    /// a single credential.
+    pub credential_id: Option<&'a [u8]>,
+    pub auth_data: AuthenticatorData<'a>,
+    /// Signature over the authenticator data and the client data hash.
+    pub signature: &'a [u8],
+    /// User ID, for discoverable credentials.
+    pub user_id: Option<&'a [u8]>,
+    /// Number of discoverable credentials for the relying party, in the first response.
+    /// Get the others with [`Ctap::get_next_assertion`].
+    pub number_of_credentials: Option<u32>,
+}
+
+impl<'a> Assertion<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let mut credential_id = None;
+        let mut auth_data = None;
+        let mut signature = None;
+        let mut user_id = None;
+        let mut number_of_credentials = None;
+        let mut d = cbor::Decoder::new(data);
+        for _ in 0..d.map().ok()? {
+            match d.unsigned().ok()? {
+                0x01 => credential_id = Some(find_id(&mut d)?),
+                0x02 => auth_data = Some(AuthenticatorData::parse(d.bytes().ok()?)?),
+                0x03 => signature = Some(d.bytes().ok()?),
+                0x04 => user_id = Some(find_id(&mut d)?),
+                0x05 => number_of_credentials = Some(u32::try_from(d.unsigned().ok()?).ok()?),
+                _ => {
+                    d.skip().ok()?;
+                }
+            }
+        }
+        Some(Self {
+            credential_id,
+            auth_data: auth_data?,
+            signature: signature?,
+            user_id,
+            number_of_credentials,
+        })
+    }
+}
+
+/// Read a map with an `id` byte string, a credential descriptor or a user entity.
+fn find_id<'a>(d: &mut cbor::Decoder<'a>) -> Option<&'a [u8]> {
+    let mut id = None;
+    for _ in 0..d.map().ok()? {
+        match d.text().ok()? {
+            "id" => id = Some(d.bytes().ok()?),
+            _ => {
+                d.skip().ok()?;
+            }
+        }
+    }
+    id
+}
+
+/// U2F registration response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct U2fRegistration<'a> {
+    /// Uncompressed P-256 point.
+    pub public_key: &'a [u8],
+    pub key_handle: &'a [u8],
+    /// Attestation certificate, in DER.
+    pub certificate: &'a [u8],
+    /// Signature over the registration data, with the attestation key.
+    pub signature: &'a [u8],
+}
+
+impl<'a> U2fRegistration<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let (&id, data) = data.split_first()?;
+        if id != U2F_REGISTER_ID {
+            return None;
+        }
+        let (public_key, data) = data.split_at_checked(65)?;
+        let (&len, data) = data.split_first()?;
+        let (key_handle, data) = data.split_at_checked(len as usize)?;
+        let (_, len, header_len) = tlv::parse_header(data).ok()?;
+        let (certificate, signature) = data.split_at_checked(header_len + len)?;
+        Some(Self {
+            public_key,
+            key_handle,
+            certificate,
+            signature,
+        })
+    }
+}
+
+/// U2F authentication response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct U2fAuthentication<'a> {
+    pub user_present: bool,
+    pub counter: u32,
+    /// Signature over the application parameter, the user presence byte, the counter and
+    /// the challenge parameter.
+    pub signature: &'a [u8],
+}
+
+impl<'a> U2fAuthentication<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        Some(Self {
+            user_present: *data.first()? & 0x01 != 0,
+            counter: u32::from_be_bytes(data.get(1..5)?.try_into().ok()?),
+            signature: data.get(5..).filter(|s| !s.is_empty())?,
+        })
+    }
+}
+
+/// A FIDO authenticator.
+pub struct Ctap<T: Reader> {
+    reader: T,
+}
+
+impl<T: Reader> Ctap<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader }
+    }
+
+    /// Get the underlying ISO-DEP reader, for sending other commands.
+    pub fn reader(&mut self) -> &mut T {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    /// Select the FIDO applet, returning the protocol version it reports. See
+    /// [`select_ctap2`](Self::select_ctap2) to know whether it speaks CTAP2.
+    pub async fn select(&mut self) -> Result<Version, Error<T::Error>> {
+        let mut rx = [0; 32];
+        let cmd = Command::new(CLA, INS_SELECT, 0x04, 0x00)
+            .with_data(FIDO_AID)
+            .with_le(apdu::MAX_SHORT_LE);
+        match apdu::command(&mut self.reader, &cmd, &mut rx).await? {
+            b"FIDO_2_0" => Ok(Version::Fido2),
+            b"U2F_V2" => Ok(Version::U2fV2),
+            v => {
+                warn!("unknown FIDO version {:02x}", Bytes(v));
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Select the FIDO applet, returning the authenticator info if it speaks CTAP2, or
+    /// `None` if it only speaks U2F.
+    ///
+    /// An authenticator answering [`Version::U2fV2`] gets probed with authenticatorGetInfo,
+    /// which U2F-only ones reject with an error status word.
+    pub async fn select_ctap2<'r>(&mut self, rx: &'r mut [u8]) -> Result<Option<Info<'r>>, Error<T::Error>> {
+        let version = self.select().await?;
+        match self.get_info(rx).await {
+            Ok(info) => Ok(Some(info)),
+            Err(Error::Status(sw)) if version == Version::U2fV2 => {
+                debug!("getInfo rejected with {:04x}, U2F only", sw.0);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Send a CTAP2 command with CBOR parameters, returning the CBOR response.
+    ///
+    /// `rx` must fit the whole response, plus the status word.
+    pub async fn send<'r>(&mut self, command: u8, params: &[u8], rx: &'r mut [u8]) -> Result<&'r [u8], Error<T::Error>> {
+        let mut tx = Vec::<u8, MAX_REQUEST_LEN>::new();
+        tx.push(command).map_err(|_| Error::TooBig)?;
+        tx.extend_from_slice(params).map_err(|_| Error::TooBig)?;
+
+        let mut cmd = Command::new(CLA_PROPRIETARY, INS_NFCCTAP_MSG, P1_GETRESPONSE_SUPPORTED, 0x00)
+            .with_data(&tx)
+            .with_le(apdu::MAX_SHORT_LE);
+        let mut polls = 0;
+        let len = loop {
+            let resp = apdu::transceive(&mut self.reader, &cmd, rx).await?;
+            match resp.sw {
+                StatusWord::OK => break resp.data.len(),
+                SW_STATUS_UPDATE if polls == MAX_GETRESPONSE_POLLS => {
+                    warn!("authenticator still busy after {} polls", polls);
+                    return Err(Error::Timeout);
+                }
+                SW_STATUS_UPDATE => {
+                    polls += 1;
+                    match resp.data.first() {
+                        Some(&STATUS_PROCESSING) => debug!("authenticator processing"),
+                        Some(&STATUS_UPNEEDED) => debug!("authenticator waiting for user presence"),
+                        _ => debug!("status update {:02x}", Bytes(resp.data)),
+                    }
+                    cmd = Command::new(CLA_PROPRIETARY, INS_NFCCTAP_GETRESPONSE, 0x00, 0x00).with_le(apdu::MAX_SHORT_LE);
+                }
+                sw => return Err(Error::Status(sw)),
+            }
+        };
+
+        let (&status, data) = rx[..len].split_first().ok_or(Error::Protocol)?;
+        if status != STATUS_OK {
+            debug!("CTAP2 error {:02x}", status);
+            return Err(Error::Ctap(status));
+        }
+        Ok(data)
+    }
+
+    /// authenticatorGetInfo.
+    pub async fn get_info<'r>(&mut self, rx: &'r mut [u8]) -> Result<Info<'r>, Error<T::Error>> {
+        let data = self.send(CMD_GET_INFO, &[], rx).await?;
+        Info::parse(data).map_err(|_| {
+            warn!("malformed getInfo response");
+            Error::Protocol
+        })
+    }
+
+    /// authenticatorMakeCredential.
+    pub async fn make_credential<'r>(
+        &mut self,
+        req: &MakeCredential<'_>,
+        rx: &'r mut [u8],
+    ) -> Result<Attestation<'r>, Error<T::Error>> {
+        let mut params = cbor::Encoder::<{ MAX_REQUEST_LEN - 1 }>::new();
+        req.encode(&mut params).map_err(|_| Error::TooBig)?;
+        let data = self.send(CMD_MAKE_CREDENTIAL, params.as_bytes(), rx).await?;
+        Attestation::parse(data).ok_or_else(|| {
+            warn!("malformed makeCredential response");
+            Error::Protocol
+        })
+    }
+
+    /// authenticatorGetAssertion.
+    pub async fn get_assertion<'r>(
+        &mut self,
+        req: &GetAssertion<'_>,
+        rx: &'r mut [u8],
+    ) -> Result<Assertion<'r>, Error<T::Error>> {
+        let mut params = cbor::Encoder::<{ MAX_REQUEST_LEN - 1 }>::new();
+        req.encode(&mut params).map_err(|_| Error::TooBig)?;
+        let data = self.send(CMD_GET_ASSERTION, params.as_bytes(), rx).await?;
+        parse_assertion(data)
+    }
+
+    /// authenticatorGetNextAssertion, for the next discoverable credential after
+    /// [`get_assertion`](Self::get_assertion).
+    pub async fn get_next_assertion<'r>(&mut self, rx: &'r mut [u8]) -> Result<Assertion<'r>, Error<T::Error>> {
+        let data = self.send(CMD_GET_NEXT_ASSERTION, &[], rx).await?;
+        parse_assertion(data)
+    }
+
+    /// U2F_REGISTER, with the SHA-256 of the client data and of the application ID.
+    pub async fn u2f_register<'r>(
+        &mut self,
+        challenge: &[u8; 32],
+        application: &[u8; 32],
+        rx: &'r mut [u8],
+    ) -> Result<U2fRegistration<'r>, Error<T::Error>> {
+        let mut data = [0; 64];
+        data[..32].copy_from_slice(challenge);
+        data[32..].copy_from_slice(application);
+        let cmd = Command::new(CLA, INS_U2F_REGISTER, 0x00, 0x00)
+            .with_data(&data)
+            .with_le(apdu::MAX_SHORT_LE);
+        let resp = apdu::command(&mut self.reader, &cmd, rx).await?;
+        U2fRegistration::parse(resp).ok_or_else(|| {
+            warn!("malformed U2F registration");
+            Error::Protocol
+        })
+    }
+
+    /// U2F_AUTHENTICATE, signing with the key of `key_handle`.
+    pub async fn u2f_authenticate<'r>(
+        &mut self,
+        challenge: &[u8; 32],
+        application: &[u8; 32],
+        key_handle: &[u8],
+        rx: &'r mut [u8],
+    ) -> Result<U2fAuthentication<'r>, Error<T::Error>> {
+        let data = u2f_authenticate_data(challenge, application, key_handle).ok_or(Error::TooBig)?;
+        let cmd = Command::new(CLA, INS_U2F_AUTHENTICATE, U2F_ENFORCE_USER_PRESENCE, 0x00)
+            .with_data(&data)
+            .with_le(apdu::MAX_SHORT_LE);
+        let resp = apdu::command(&mut self.reader, &cmd, rx).await?;
+        U2fAuthentication::parse(resp).ok_or_else(|| {
+            warn!("malformed U2F authentication");
+            Error::Protocol
+        })
+    }
+
+    /// Check whether `key_handle` was registered by this authenticator for `application`,
+    /// without user presence or signature.
+    pub async fn u2f_check_key_handle(&mut self, application: &[u8; 32], key_handle: &[u8]) -> Result<bool, Error<T::Error>> {
+        let data = u2f_authenticate_data(&[0; 32], application, key_handle).ok_or(Error::TooBig)?;
+        let mut rx = [0; 2];
+        let cmd = Command::new(CLA, INS_U2F_AUTHENTICATE, U2F_CHECK_ONLY, 0x00).with_data(&data);
+        // Known key handles are answered with "test of user presence required".
+        match apdu::transceive(&mut self.reader, &cmd, &mut rx).await?.sw {
+            StatusWord::CONDITIONS_NOT_SATISFIED => Ok(true),
+            StatusWord::WRONG_DATA => Ok(false),
+            sw => Err(Error::Status(sw)),
+        }
+    }
+}
+
+/// U2F_AUTHENTICATE data: challenge, application, and the key handle with its length.
+fn u2f_authenticate_data(challenge: &[u8; 32], application: &[u8; 32], key_handle: &[u8]) -> Option<Vec<u8, { 65 + 255 }>> {
+    let mut data = Vec::new();
+    data.extend_from_slice(challenge).ok()?;
+    data.extend_from_slice(application).ok()?;
+    data.push(u8::try_from(key_handle.len()).ok()?).ok()?;
+    data.extend_from_slice(key_handle).ok()?;
+    Some(data)
+}
+
+fn parse_assertion<E>(data: &[u8]) -> Result<Assertion<'_>, Error<E>> {
+    Assertion::parse(data).ok_or_else(|| {
+        warn!("malformed getAssertion response");
+        Error::Protocol
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::test_util::mock;
+
+    const CLIENT_DATA_HASH: [u8; 32] = hex!("000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F");
+
+    #[tokio::test]
+    async fn test_get_info() {
+        let mut ctap = Ctap::new(mock!(
+            // Dual-mode authenticators answer U2F_V2.
+            "00A4040008A0000006472F000100" => "5532465F5632 9000",
+            // Still processing, poll.
+            "80108000010400" => "01 9100",
+            "8011000000" => "00A60182665532465F5632684649444F5F325F3002816B686D61632D7365637265740350F8A011F38C0A4D15800617111F9EDC7D04A462726BF5627570F564706C6174F469636C69656E7450696EF4051904B0068101 9000",
+        ));
+        let mut rx = [0; DEFAULT_MAX_MSG_SIZE];
+        let info = ctap.select_ctap2(&mut rx).await.unwrap().unwrap();
+        assert_eq!(info.versions, ["U2F_V2", "FIDO_2_0"]);
+        assert_eq!(info.extensions, ["hmac-secret"]);
+        assert_eq!(info.aaguid, hex!("F8A011F38C0A4D15800617111F9EDC7D"));
+        assert_eq!(info.option("rk"), Some(true));
+        assert_eq!(info.option("clientPin"), Some(false));
+        assert_eq!(info.option("uv"), None);
+        assert_eq!(info.max_msg_size, Some(1200));
+        assert_eq!(info.pin_uv_auth_protocols, [1]);
+        ctap.reader().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_poll_limit() {
+        let mut r = mock!(
+            "80108000010400" => "01 9100",
+        );
+        for _ in 0..MAX_GETRESPONSE_POLLS {
+            r.expected.push((&hex!("8011000000"), &hex!("02 9100")));
+        }
+        let mut ctap = Ctap::new(r);
+        let mut rx = [0; DEFAULT_MAX_MSG_SIZE];
+        assert!(matches!(ctap.get_info(&mut rx).await, Err(Error::Timeout)));
+        ctap.reader().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_make_credential() {
+        let mut ctap = Ctap::new(mock!(
+            // The request is chained.
+            "901080 00FF 01A6015820000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F02A26269646B6578616D706C652E636F6D646E616D65674578616D706C6503A36269645820404142434445464748494A4B4C4D4E4F505152535455565758595A5B5C5D5E5F646E616D6565616C6963656B646973706C61794E616D6565416C6963650482A263616C672664747970656A7075626C69632D6B6579A263616C672764747970656A7075626C69632D6B65790582A26269645840A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A16474"
+                => "9000",
+            "801080 006A 7970656A7075626C69632D6B6579A26269645840A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A2A264747970656A7075626C69632D6B657907A162726BF5 00"
+                => "02 9100",
+            // The response comes in two parts.
+            "8011000000"
+                => "00A301646E6F6E6502589411111111111111111111111111111111111111111111111111111111111111114500000000000000000000000000000000000000000010C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0A50102032620012158202222222222222222 613D",
+            "00C000003D"
+                => "222222222222222222222222222222222222222222222222225820333333333333333333333333333333333333333333333333333333333333333303A0 9000",
+        ));
+        let req = MakeCredential {
+            client_data_hash: &CLIENT_DATA_HASH,
+            rp: RelyingParty {
+                id: "example.com",
+                name: Some("Example"),
+            },
+            user: User {
+                id: &hex!("404142434445464748494A4B4C4D4E4F505152535455565758595A5B5C5D5E5F"),
+                name: Some("alice"),
+                display_name: Some("Alice"),
+            },
+            algorithms: &[ALG_ES256, ALG_EDDSA],
+            exclude_list: &[&[0xA1; 64], &[0xA2; 64]],
+            resident_key: true,
+            user_verification: false,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let mut rx = [0; DEFAULT_MAX_MSG_SIZE];
+        let att = ctap.make_credential(&req, &mut rx).await.unwrap();
+        assert_eq!(att.fmt, "none");
+        assert_eq!(att.att_stmt, hex!("A0"));
+        assert_eq!(att.auth_data.rp_id_hash, [0x11; 32]);
+        assert!(att.auth_data.user_present());
+        assert!(att.auth_data.user_verified());
+        assert_eq!(att.auth_data.sign_count, 0);
+        let cred = att.auth_data.credential.unwrap();
+        assert_eq!(cred.aaguid, [0; 16]);
+        assert_eq!(cred.id, [0xC0; 16]);
+        assert_eq!(cred.public_key.len(), 77);
+        ctap.reader().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_get_assertion() {
+        let mut ctap = Ctap::new(mock!(
+            "801080005F 02A4016B6578616D706C652E636F6D025820000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F0381A262696450C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C064747970656A7075626C69632D6B657905A1627570F4 00"
+                => "00A301A262696450C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C064747970656A7075626C69632D6B6579025825111111111111111111111111111111111111111111111111111111111111111100000000050346304455555555 9000",
+            "80108000010800" => "2E 9000",
+        ));
+        let req = GetAssertion {
+            rp_id: "example.com",
+            client_data_hash: &CLIENT_DATA_HASH,
+            allow_list: &[&[0xC0; 16]],
+            user_presence: false,
+            user_verification: false,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let mut rx = [0; DEFAULT_MAX_MSG_SIZE];
+        let assertion = ctap.get_assertion(&req, &mut rx).await.unwrap();
+        assert_eq!(assertion.credential_id, Some(&[0xC0; 16][..]));
+        assert!(!assertion.auth_data.user_present());
+        assert_eq!(assertion.auth_data.sign_count, 5);
+        assert_eq!(assertion.auth_data.credential, None);
+        assert_eq!(assertion.signature, hex!("304455555555"));
+        assert_eq!(assertion.user_id, None);
+
+        assert!(matches!(
+            ctap.get_next_assertion(&mut rx).await,
+            Err(Error::Ctap(ERR_NO_CREDENTIALS))
+        ));
+        ctap.reader().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_u2f() {
+        let mut ctap = Ctap::new(mock!(
+            "00A4040008A0000006472F000100" => "5532465F5632 9000",
+            // U2F only: NFCCTAP_MSG isn't supported.
+            "80108000010400" => "6D00",
+            "0001000040 AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB 00"
+                => "05 04CCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC 04 DDDDDDDD 3003020101 30440220 9000",
+            "0002070045 0000000000000000000000000000000000000000000000000000000000000000 BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB 04 DDDDDDDD"
+                => "6985",
+            "0002030045 AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB 04 DDDDDDDD 00"
+                => "01 0000002A 30440220 9000",
+        ));
+        let mut rx = [0; 64];
+        assert!(ctap.select_ctap2(&mut rx).await.unwrap().is_none());
+
+        let challenge = [0xAA; 32];
+        let application = [0xBB; 32];
+        let mut rx = [0; 512];
+        let reg = ctap.u2f_register(&challenge, &application, &mut rx).await.unwrap();
+        assert_eq!(reg.public_key[..2], [0x04, 0xCC]);
+        assert_eq!(reg.key_handle, [0xDD; 4]);
+        assert_eq!(reg.certificate, hex!("3003020101"));
+        assert_eq!(reg.signature, hex!("30440220"));
+
+        let key_handle = [0xDD; 4];
+        assert!(ctap.u2f_check_key_handle(&application, &key_handle).await.unwrap());
+        let auth = ctap
+            .u2f_authenticate(&challenge, &application, &key_handle, &mut rx)
+            .await
+            .unwrap();
+        assert!(auth.user_present);
+        assert_eq!(auth.counter, 42);
+        assert_eq!(auth.signature, hex!("30440220"));
+        ctap.reader().assert_done();
+    }
+}
@@ -0,0 +1,393 @@
+//! The CBOR subset used by CTAP2: integers, byte and text strings, arrays, maps and
+//! booleans, with definite lengths only.
+//!
+//! [`Encoder`] writes items one after the other: an array or map header is followed by its
+//! items, or its keys and values. Requests must be in CTAP2 canonical form, so map keys
+//! have to be written sorted: integers first in ascending order, then text strings by
+//! length and bytewise.
+//!
+//! [`Decoder`] reads items in the same way, and [`Decoder::skip`] jumps over unknown ones.
+
+use heapless::Vec;
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const SIMPLE_FALSE: u8 = 20;
+const SIMPLE_TRUE: u8 = 21;
+const SIMPLE_NULL: u8 = 22;
+
+/// Maximum nesting of arrays and maps skipped by [`Decoder::skip`].
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Data ended in the middle of an item.
+    Truncated,
+    /// Indefinite length, reserved additional information, or nesting too deep.
+    Invalid,
+    /// The item has another type than the one asked for, or doesn't fit it.
+    UnexpectedType,
+    /// The output buffer is too small.
+    BufferTooSmall,
+}
+
+/// Type of a data item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Type {
+    Unsigned,
+    Negative,
+    Bytes,
+    Text,
+    Array,
+    Map,
+    Tag,
+    Simple,
+}
+
+/// Serializes data items.
+pub struct Encoder<const N: usize> {
+    buf: Vec<u8, N>,
+}
+
+impl<const N: usize> Default for Encoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Encoder<N> {
+    pub const fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn unsigned(&mut self, v: u64) -> Result<(), Error> {
+        self.head(MAJOR_UNSIGNED, v)
+    }
+
+    pub fn int(&mut self, v: i64) -> Result<(), Error> {
+        match v {
+            0.. => self.head(MAJOR_UNSIGNED, v as u64),
+            // -1 - v, without overflowing for i64::MIN.
+            _ => self.head(MAJOR_NEGATIVE, !v as u64),
+        }
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) -> Result<(), Error> {
+        self.head(MAJOR_BYTES, v.len() as u64)?;
+        self.write(v)
+    }
+
+    pub fn text(&mut self, v: &str) -> Result<(), Error> {
+        self.head(MAJOR_TEXT, v.len() as u64)?;
+        self.write(v.as_bytes())
+    }
+
+    pub fn bool(&mut self, v: bool) -> Result<(), Error> {
+        self.write(&[MAJOR_SIMPLE << 5 | if v { SIMPLE_TRUE } else { SIMPLE_FALSE }])
+    }
+
+    /// Start an array of `len` items.
+    pub fn array(&mut self, len: usize) -> Result<(), Error> {
+        self.head(MAJOR_ARRAY, len as u64)
+    }
+
+    /// Start a map of `len` key-value pairs.
+    pub fn map(&mut self, len: usize) -> Result<(), Error> {
+        self.head(MAJOR_MAP, len as u64)
+    }
+
+    /// Append already encoded items.
+    pub fn raw(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.write(data)
+    }
+
+    fn head(&mut self, major: u8, v: u64) -> Result<(), Error> {
+        let major = major << 5;
+        match v {
+            0..24 => self.write(&[major | v as u8]),
+            24..0x100 => self.write(&[major | 24, v as u8]),
+            0x100..0x10000 => {
+                self.write(&[major | 25])?;
+                self.write(&(v as u16).to_be_bytes())
+            }
+            0x10000..0x1_0000_0000 => {
+                self.write(&[major | 26])?;
+                self.write(&(v as u32).to_be_bytes())
+            }
+            _ => {
+                self.write(&[major | 27])?;
+                self.write(&v.to_be_bytes())
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.buf.extend_from_slice(data).map_err(|_| Error::BufferTooSmall)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn finish(self) -> Vec<u8, N> {
+        self.buf
+    }
+}
+
+/// Reads data items from a buffer, without copying.
+#[derive(Clone)]
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Bytes not read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    /// Type of the next item, without reading it.
+    pub fn datatype(&self) -> Result<Type, Error> {
+        let first = *self.remaining().first().ok_or(Error::Truncated)?;
+        Ok(match first >> 5 {
+            MAJOR_UNSIGNED => Type::Unsigned,
+            MAJOR_NEGATIVE => Type::Negative,
+            MAJOR_BYTES => Type::Bytes,
+            MAJOR_TEXT => Type::Text,
+            MAJOR_ARRAY => Type::Array,
+            MAJOR_MAP => Type::Map,
+            MAJOR_TAG => Type::Tag,
+            _ => Type::Simple,
+        })
+    }
+
+    pub fn unsigned(&mut self) -> Result<u64, Error> {
+        self.expect(MAJOR_UNSIGNED)
+    }
+
+    pub fn int(&mut self) -> Result<i64, Error> {
+        let (major, v) = self.peek_head()?;
+        let v = i64::try_from(v).map_err(|_| Error::UnexpectedType)?;
+        let v = match major {
+            MAJOR_UNSIGNED => v,
+            MAJOR_NEGATIVE => -1 - v,
+            _ => return Err(Error::UnexpectedType),
+        };
+        self.head()?;
+        Ok(v)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.expect(MAJOR_BYTES)?;
+        self.take(to_usize(len)?)
+    }
+
+    pub fn text(&mut self) -> Result<&'a str, Error> {
+        let len = self.expect(MAJOR_TEXT)?;
+        core::str::from_utf8(self.take(to_usize(len)?)?).map_err(|_| Error::Invalid)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.peek_head()? {
+            (MAJOR_SIMPLE, v) if v == SIMPLE_FALSE as u64 || v == SIMPLE_TRUE as u64 => {
+                self.head()?;
+                Ok(v == SIMPLE_TRUE as u64)
+            }
+            _ => Err(Error::UnexpectedType),
+        }
+    }
+
+    /// Read an array header, returning the number of items that follow.
+    pub fn array(&mut self) -> Result<usize, Error> {
+        to_usize(self.expect(MAJOR_ARRAY)?)
+    }
+
+    /// Read a map header, returning the number of key-value pairs that follow.
+    pub fn map(&mut self) -> Result<usize, Error> {
+        to_usize(self.expect(MAJOR_MAP)?)
+    }
+
+    /// Skip the next item, with everything it contains. Returns its encoding.
+    pub fn skip(&mut self) -> Result<&'a [u8], Error> {
+        let start = self.pos;
+        let res = self.skip_inner();
+        if res.is_err() {
+            self.pos = start;
+        }
+        res.map(|()| &self.data[start..self.pos])
+    }
+
+    fn skip_inner(&mut self) -> Result<(), Error> {
+        // Items left to skip at each nesting level.
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut depth = 1;
+        stack[0] = 1;
+        while depth > 0 {
+            if stack[depth - 1] == 0 {
+                depth -= 1;
+                continue;
+            }
+            stack[depth - 1] -= 1;
+
+            let (major, v) = self.head()?;
+            let nested = match major {
+                MAJOR_BYTES | MAJOR_TEXT => {
+                    self.take(to_usize(v)?)?;
+                    0
+                }
+                MAJOR_ARRAY => to_usize(v)?,
+                MAJOR_MAP => to_usize(v)?.checked_mul(2).ok_or(Error::Invalid)?,
+                // The tagged item follows.
+                MAJOR_TAG => 1,
+                _ => 0,
+            };
+            if nested > 0 {
+                *stack.get_mut(depth).ok_or(Error::Invalid)? = nested;
+                depth += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a header of type `major`, returning its argument.
+    fn expect(&mut self, major: u8) -> Result<u64, Error> {
+        match self.peek_head()? {
+            (m, v) if m == major => {
+                self.head()?;
+                Ok(v)
+            }
+            _ => Err(Error::UnexpectedType),
+        }
+    }
+
+    fn peek_head(&self) -> Result<(u8, u64), Error> {
+        self.clone().head()
+    }
+
+    /// Read the initial byte and argument of an item.
+    fn head(&mut self) -> Result<(u8, u64), Error> {
+        let first = *self.remaining().first().ok_or(Error::Truncated)?;
+        let major = first >> 5;
+        let info = first & 0x1F;
+        let n = match info {
+            0..24 => {
+                self.pos += 1;
+                return Ok((major, info as u64));
+            }
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err(Error::Invalid),
+        };
+        let arg = self.data.get(self.pos + 1..self.pos + 1 + n).ok_or(Error::Truncated)?;
+        self.pos += 1 + n;
+        Ok((major, arg.iter().fold(0, |acc, &b| acc << 8 | b as u64)))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let data = self.remaining().get(..len).ok_or(Error::Truncated)?;
+        self.pos += len;
+        Ok(data)
+    }
+}
+
+fn to_usize(v: u64) -> Result<usize, Error> {
+    usize::try_from(v).map_err(|_| Error::Invalid)
+}
+
+/// `null`, which some authenticators send for absent optional values.
+pub fn is_null(data: &[u8]) -> bool {
+    data.first() == Some(&(MAJOR_SIMPLE << 5 | SIMPLE_NULL))
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // RFC 8949 appendix A.
+        let mut e = Encoder::<64>::new();
+        e.unsigned(0).unwrap();
+        e.unsigned(23).unwrap();
+        e.unsigned(24).unwrap();
+        e.unsigned(1000).unwrap();
+        e.unsigned(1000000).unwrap();
+        e.unsigned(1000000000000).unwrap();
+        e.int(-1).unwrap();
+        e.int(-1000).unwrap();
+        e.int(i64::MIN).unwrap();
+        assert_eq!(
+            e.as_bytes(),
+            hex!("00 17 1818 1903e8 1a000f4240 1b000000e8d4a51000 20 3903e7 3b7fffffffffffffff")
+        );
+
+        let mut e = Encoder::<64>::new();
+        e.map(2).unwrap();
+        e.text("a").unwrap();
+        e.unsigned(1).unwrap();
+        e.text("b").unwrap();
+        e.array(2).unwrap();
+        e.bytes(&hex!("01020304")).unwrap();
+        e.bool(true).unwrap();
+        assert_eq!(e.as_bytes(), hex!("a2 6161 01 6162 82 4401020304 f5"));
+
+        let mut e = Encoder::<4>::new();
+        assert_eq!(e.text("IETF"), Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn test_decode() {
+        let data = hex!("a2 6161 01 6162 83 4401020304 f4 3903e7 1b000000e8d4a51000");
+        let mut d = Decoder::new(&data);
+        assert_eq!(d.datatype(), Ok(Type::Map));
+        assert_eq!(d.map(), Ok(2));
+        assert_eq!(d.text(), Ok("a"));
+        assert_eq!(d.text(), Err(Error::UnexpectedType));
+        assert_eq!(d.int(), Ok(1));
+        assert_eq!(d.text(), Ok("b"));
+        assert_eq!(d.array(), Ok(3));
+        assert_eq!(d.bytes(), Ok(&hex!("01020304")[..]));
+        assert_eq!(d.bool(), Ok(false));
+        assert_eq!(d.unsigned(), Err(Error::UnexpectedType));
+        assert_eq!(d.int(), Ok(-1000));
+        assert_eq!(d.unsigned(), Ok(1000000000000));
+        assert_eq!(d.remaining(), &[]);
+        assert_eq!(d.datatype(), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn test_skip() {
+        let data = hex!("a1 01 82 a1 6161 c2 4100 f6 02 5f");
+        let mut d = Decoder::new(&data);
+        assert_eq!(d.skip(), Ok(&data[..10]));
+        // Indefinite lengths aren't supported.
+        assert_eq!(d.int(), Ok(2));
+        assert_eq!(d.skip(), Err(Error::Invalid));
+        assert_eq!(d.remaining(), hex!("5f"));
+
+        let mut d = Decoder::new(&data[..6]);
+        assert_eq!(d.skip(), Err(Error::Truncated));
+        assert_eq!(d.remaining(), &data[..6]);
+
+        assert!(is_null(&hex!("f6")));
+        assert!(!is_null(&hex!("f4")));
+    }
+}
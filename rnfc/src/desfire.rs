@@ -0,0 +1,1340 @@
+//! MIFARE DESFire EV1, EV2 and EV3 application layer.
+//!
+//! Native commands are sent wrapped in ISO 7816-4 APDUs (`90 cmd 00 00 Lc data 00`) over
+//! anything implementing [`iso_dep::Reader`](rnfc_traits::iso_dep::Reader). Long commands and
+//! responses are split in additional frames with the `AF` status.
+//!
+//! Authentication sets up secure messaging for the following commands, until another
+//! application is selected or a command fails:
+//!
+//! - [`authenticate_iso`](Desfire::authenticate_iso) and [`authenticate_aes`](Desfire::authenticate_aes)
+//!   use EV1 secure messaging, which every DESFire since EV1 supports.
+//! - [`authenticate_ev2_first`](Desfire::authenticate_ev2_first) uses EV2 secure messaging,
+//!   with AES keys only.
+//!
+//! File commands take the [`CommMode`] of the file, which must match its settings. Commands
+//! that need it are enciphered regardless, such as [`change_key`](Desfire::change_key).
+
+pub(crate) mod crypto;
+pub(crate) mod session;
+
+use core::ops::RangeInclusive;
+
+use rand_core::CryptoRngCore;
+use rnfc_traits::iso_dep::Reader;
+
+pub use self::crypto::{KeyType, crc32};
+use self::session::{Buf, Request, Session};
+pub use crate::crypto::Key;
+use crate::crypto::{Cipher, MAX_BLOCK_SIZE, xor};
+use crate::fmt::Bytes;
+
+/// Application ID of the PICC level, selected after activation.
+pub const PICC_AID: [u8; 3] = [0; 3];
+
+const CLA: u8 = 0x90;
+
+const CMD_AUTHENTICATE_ISO: u8 = 0x1A;
+const CMD_AUTHENTICATE_AES: u8 = 0xAA;
+const CMD_AUTHENTICATE_EV2_FIRST: u8 = 0x71;
+const CMD_CHANGE_KEY_SETTINGS: u8 = 0x54;
+const CMD_GET_KEY_SETTINGS: u8 = 0x45;
+const CMD_CHANGE_KEY: u8 = 0xC4;
+const CMD_GET_KEY_VERSION: u8 = 0x64;
+const CMD_CREATE_APPLICATION: u8 = 0xCA;
+const CMD_DELETE_APPLICATION: u8 = 0xDA;
+const CMD_GET_APPLICATION_IDS: u8 = 0x6A;
+const CMD_SELECT_APPLICATION: u8 = 0x5A;
+const CMD_FORMAT_PICC: u8 = 0xFC;
+const CMD_GET_VERSION: u8 = 0x60;
+const CMD_FREE_MEMORY: u8 = 0x6E;
+const CMD_GET_CARD_UID: u8 = 0x51;
+const CMD_GET_FILE_IDS: u8 = 0x6F;
+const CMD_GET_FILE_SETTINGS: u8 = 0xF5;
+const CMD_CHANGE_FILE_SETTINGS: u8 = 0x5F;
+const CMD_CREATE_STD_DATA_FILE: u8 = 0xCD;
+const CMD_CREATE_BACKUP_DATA_FILE: u8 = 0xCB;
+const CMD_CREATE_VALUE_FILE: u8 = 0xCC;
+const CMD_CREATE_LINEAR_RECORD_FILE: u8 = 0xC1;
+const CMD_CREATE_CYCLIC_RECORD_FILE: u8 = 0xC0;
+const CMD_DELETE_FILE: u8 = 0xDF;
+const CMD_READ_DATA: u8 = 0xBD;
+const CMD_WRITE_DATA: u8 = 0x3D;
+const CMD_GET_VALUE: u8 = 0x6C;
+const CMD_CREDIT: u8 = 0x0C;
+const CMD_DEBIT: u8 = 0xDC;
+const CMD_LIMITED_CREDIT: u8 = 0x1C;
+const CMD_WRITE_RECORD: u8 = 0x3B;
+const CMD_READ_RECORDS: u8 = 0xBB;
+const CMD_CLEAR_RECORD_FILE: u8 = 0xEB;
+const CMD_COMMIT_TRANSACTION: u8 = 0xC7;
+const CMD_ABORT_TRANSACTION: u8 = 0xA7;
+const CMD_ADDITIONAL_FRAME: u8 = 0xAF;
+
+const STATUS_OK: u8 = 0x00;
+const STATUS_ADDITIONAL_FRAME: u8 = 0xAF;
+
+/// Biggest command data sent in a single frame, the rest goes in additional frames.
+const MAX_FRAME_DATA: usize = 48;
+/// Biggest chunk of file data in a single ReadData, WriteData, ReadRecords or WriteRecord.
+/// Secure messaging overhead must still fit in a message.
+const MAX_CHUNK: usize = 240;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The card sent a malformed response, or an ISO status word instead of a native one.
+    Protocol,
+    /// The card answered with an error status, such as `9D` permission denied or `AE`
+    /// authentication error. Any session is dropped.
+    Status(u8),
+    /// The card's authentication response doesn't match: it doesn't know the key.
+    AuthFailed,
+    /// The MAC or CRC of a response is wrong. The session is dropped.
+    Integrity,
+    /// The data doesn't fit in a message or in the buffer.
+    TooBig,
+    /// The key type isn't supported by this authentication method.
+    InvalidKey,
+    /// The command needs authentication first.
+    NotAuthenticated,
+    /// Applications have 1 to 14 keys.
+    InvalidKeyCount,
+}
+
+/// Communication mode of a file, or of a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommMode {
+    Plain = 0,
+    /// Data is sent in plain, with a MAC.
+    Mac = 1,
+    /// Data is enciphered.
+    Full = 3,
+}
+
+impl CommMode {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            1 => Self::Mac,
+            3 => Self::Full,
+            _ => Self::Plain,
+        }
+    }
+}
+
+/// Hardware or software information from GetVersion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProductInfo {
+    /// 0x04 for NXP.
+    pub vendor_id: u8,
+    pub product_type: u8,
+    pub subtype: u8,
+    pub major_version: u8,
+    pub minor_version: u8,
+    /// Storage size code: `2^(n/2)` bytes, or between that and twice that if bit 0 is set.
+    pub storage_size: u8,
+    /// 0x05 for ISO 14443-2 and -3.
+    pub protocol: u8,
+}
+
+impl ProductInfo {
+    fn parse(data: &[u8]) -> Self {
+        Self {
+            vendor_id: data[0],
+            product_type: data[1],
+            subtype: data[2],
+            major_version: data[3],
+            minor_version: data[4],
+            storage_size: data[5],
+            protocol: data[6],
+        }
+    }
+
+    /// Storage size in bytes, rounded down. `None` if it doesn't fit in `usize`.
+    pub fn storage_bytes(&self) -> Option<usize> {
+        1usize.checked_shl(u32::from(self.storage_size >> 1))
+    }
+}
+
+/// Response to GetVersion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    pub hardware: ProductInfo,
+    pub software: ProductInfo,
+    pub uid: [u8; 7],
+    pub batch: [u8; 5],
+    /// Production week, BCD.
+    pub production_week: u8,
+    /// Production year, BCD.
+    pub production_year: u8,
+}
+
+impl Version {
+    /// Parse the concatenated response frames. Newer cards append more data, which is ignored.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 28 {
+            return None;
+        }
+        Some(Self {
+            hardware: ProductInfo::parse(&data[0..7]),
+            software: ProductInfo::parse(&data[7..14]),
+            uid: unwrap!(data[14..21].try_into()),
+            batch: unwrap!(data[21..26].try_into()),
+            production_week: data[26],
+            production_year: data[27],
+        })
+    }
+}
+
+/// Response to GetKeySettings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeySettings {
+    /// Key settings byte: bits 0-3 are flags, bits 4-7 the key needed to change keys.
+    pub settings: u8,
+    pub key_count: u8,
+    pub key_type: KeyType,
+}
+
+/// File access rights: key numbers 0-13, [`FREE`](Self::FREE) or [`NEVER`](Self::NEVER).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AccessRights {
+    pub read: u8,
+    pub write: u8,
+    pub read_write: u8,
+    pub change: u8,
+}
+
+impl AccessRights {
+    /// Access without authentication.
+    pub const FREE: u8 = 0x0E;
+    /// No access.
+    pub const NEVER: u8 = 0x0F;
+
+    pub fn decode(bytes: [u8; 2]) -> Self {
+        Self {
+            read: bytes[1] >> 4,
+            write: bytes[1] & 0x0F,
+            read_write: bytes[0] >> 4,
+            change: bytes[0] & 0x0F,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; 2] {
+        [
+            (self.read_write & 0x0F) << 4 | self.change & 0x0F,
+            (self.read & 0x0F) << 4 | self.write & 0x0F,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FileType {
+    StandardData,
+    BackupData,
+    Value,
+    LinearRecord,
+    CyclicRecord,
+}
+
+/// Type-specific part of the file settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FileInfo {
+    Data {
+        size: u32,
+    },
+    Value {
+        lower_limit: i32,
+        upper_limit: i32,
+        limited_credit_value: i32,
+        limited_credit_enabled: bool,
+    },
+    Record {
+        record_size: u32,
+        max_records: u32,
+        records: u32,
+    },
+}
+
+/// Response to GetFileSettings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FileSettings {
+    pub file_type: FileType,
+    pub comm_mode: CommMode,
+    pub access: AccessRights,
+    pub info: FileInfo,
+}
+
+impl FileSettings {
+    /// Parse a GetFileSettings response. Settings added by EV2, such as Secure Dynamic
+    /// Messaging, are ignored.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let file_type = match *data.first()? {
+            0 => FileType::StandardData,
+            1 => FileType::BackupData,
+            2 => FileType::Value,
+            3 => FileType::LinearRecord,
+            4 => FileType::CyclicRecord,
+            _ => return None,
+        };
+        let comm_mode = CommMode::from_bits(*data.get(1)?);
+        let access = AccessRights::decode(unwrap!(data.get(2..4)?.try_into()));
+        let v = &data[4..];
+        let info = match file_type {
+            FileType::StandardData | FileType::BackupData => FileInfo::Data { size: u24(v.get(0..3)?) },
+            FileType::Value => {
+                let v = v.get(0..13)?;
+                FileInfo::Value {
+                    lower_limit: i32::from_le_bytes(unwrap!(v[0..4].try_into())),
+                    upper_limit: i32::from_le_bytes(unwrap!(v[4..8].try_into())),
+                    limited_credit_value: i32::from_le_bytes(unwrap!(v[8..12].try_into())),
+                    limited_credit_enabled: v[12] & 0x01 != 0,
+                }
+            }
+            FileType::LinearRecord | FileType::CyclicRecord => {
+                let v = v.get(0..9)?;
+                FileInfo::Record {
+                    record_size: u24(&v[0..3]),
+                    max_records: u24(&v[3..6]),
+                    records: u24(&v[6..9]),
+                }
+            }
+        };
+        Some(Self {
+            file_type,
+            comm_mode,
+            access,
+            info,
+        })
+    }
+}
+
+/// A DESFire card.
+pub struct Desfire<T: Reader> {
+    reader: T,
+    session: Option<Session>,
+    /// Authenticated key number, valid while `session` is set.
+    key_no: u8,
+    /// Selected application, `None` if selected with ISOSelectFile.
+    aid: Option<[u8; 3]>,
+}
+
+impl<T: Reader> Desfire<T> {
+    /// Wrap an activated card. The PICC level is selected after activation.
+    pub fn new(reader: T) -> Self {
+        Self {
+            reader,
+            session: None,
+            key_no: 0,
+            aid: Some(PICC_AID),
+        }
+    }
+
+    /// Get the underlying ISO-DEP reader, for sending other commands.
+    pub fn reader(&mut self) -> &mut T {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Authenticated key number, if any.
+    pub fn authenticated_key(&self) -> Option<u8> {
+        self.session.as_ref().map(|_| self.key_no)
+    }
+
+    pub async fn get_version(&mut self) -> Result<Version, Error<T::Error>> {
+        let mut buf = [0; 64];
+        let n = self.command(CMD_GET_VERSION, &[], &mut buf).await?;
+        Version::parse(&buf[..n]).ok_or_else(|| {
+            warn!("malformed version");
+            Error::Protocol
+        })
+    }
+
+    /// Authenticate with AuthenticateISO, for DES, 2K3DES and 3K3DES keys.
+    pub async fn authenticate_iso(
+        &mut self,
+        rng: &mut impl CryptoRngCore,
+        key_no: u8,
+        key: &Key,
+    ) -> Result<(), Error<T::Error>> {
+        if matches!(key, Key::Aes(_)) {
+            return Err(Error::InvalidKey);
+        }
+        self.authenticate_ev1(CMD_AUTHENTICATE_ISO, rng, key_no, key).await
+    }
+
+    /// Authenticate with AuthenticateAES.
+    pub async fn authenticate_aes(
+        &mut self,
+        rng: &mut impl CryptoRngCore,
+        key_no: u8,
+        key: &Key,
+    ) -> Result<(), Error<T::Error>> {
+        if !matches!(key, Key::Aes(_)) {
+            return Err(Error::InvalidKey);
+        }
+        self.authenticate_ev1(CMD_AUTHENTICATE_AES, rng, key_no, key).await
+    }
+
+    async fn authenticate_ev1(
+        &mut self,
+        cmd: u8,
+        rng: &mut impl CryptoRngCore,
+        key_no: u8,
+        key: &Key,
+    ) -> Result<(), Error<T::Error>> {
+        self.session = None;
+        let cipher = Cipher::new(key);
+        let bs = cipher.block_size();
+        // Nonces are 8 bytes for 2K3DES, 16 bytes for 3K3DES and AES.
+        let n = match key {
+            Key::TDes2(_) => 8,
+            _ => 16,
+        };
+
+        let mut resp = Buf::new();
+        let status = self.frame(cmd, &[key_no], &mut resp).await?;
+        expect_additional_frame(status)?;
+        if resp.len() != n {
+            warn!("wrong challenge length");
+            return Err(Error::Protocol);
+        }
+
+        // All three steps chain the IV through.
+        let mut iv = [0; MAX_BLOCK_SIZE];
+        let iv = &mut iv[..bs];
+        let mut rnd_b = [0; 16];
+        rnd_b[..n].copy_from_slice(&resp);
+        cipher.cbc_decrypt(iv, &mut rnd_b[..n]);
+
+        let mut rnd_a = [0; 16];
+        rng.fill_bytes(&mut rnd_a[..n]);
+        let mut token = [0; 32];
+        token[..n].copy_from_slice(&rnd_a[..n]);
+        token[n..2 * n].copy_from_slice(&rnd_b[..n]);
+        token[n..2 * n].rotate_left(1);
+        cipher.cbc_encrypt(iv, &mut token[..2 * n]);
+
+        resp.clear();
+        let status = self.frame(CMD_ADDITIONAL_FRAME, &token[..2 * n], &mut resp).await?;
+        expect_ok(status)?;
+        if resp.len() != n {
+            warn!("wrong authentication response length");
+            return Err(Error::Protocol);
+        }
+        cipher.cbc_decrypt(iv, &mut resp);
+        rnd_a[..n].rotate_left(1);
+        if resp[..] != rnd_a[..n] {
+            warn!("authentication response mismatch");
+            return Err(Error::AuthFailed);
+        }
+        rnd_a[..n].rotate_right(1);
+
+        debug!("authenticated with key {}", key_no);
+        self.session = Some(Session::ev1(key, &rnd_a[..n], &rnd_b[..n]));
+        self.key_no = key_no;
+        Ok(())
+    }
+
+    /// Authenticate with AuthenticateEV2First, for AES keys.
+    pub async fn authenticate_ev2_first(
+        &mut self,
+        rng: &mut impl CryptoRngCore,
+        key_no: u8,
+        key: &Key,
+    ) -> Result<(), Error<T::Error>> {
+        let Key::Aes(k) = key else {
+            return Err(Error::InvalidKey);
+        };
+        self.session = None;
+        let cipher = Cipher::aes(k);
+
+        // No capabilities sent.
+        let mut resp = Buf::new();
+        let status = self.frame(CMD_AUTHENTICATE_EV2_FIRST, &[key_no, 0x00], &mut resp).await?;
+        expect_additional_frame(status)?;
+        let mut rnd_b: [u8; 16] = resp[..].try_into().map_err(|_| {
+            warn!("wrong challenge length");
+            Error::Protocol
+        })?;
+        cipher.cbc_decrypt(&mut [0; 16], &mut rnd_b);
+
+        let mut rnd_a = [0; 16];
+        rng.fill_bytes(&mut rnd_a);
+        let mut token = [0; 32];
+        token[..16].copy_from_slice(&rnd_a);
+        token[16..].copy_from_slice(&rnd_b);
+        token[16..].rotate_left(1);
+        cipher.cbc_encrypt(&mut [0; 16], &mut token);
+
+        resp.clear();
+        let status = self.frame(CMD_ADDITIONAL_FRAME, &token, &mut resp).await?;
+        expect_ok(status)?;
+        if resp.len() != 32 {
+            warn!("wrong authentication response length");
+            return Err(Error::Protocol);
+        }
+        // TI || RndA' || PDcap2 || PCDcap2
+        cipher.cbc_decrypt(&mut [0; 16], &mut resp);
+        let mut expected = rnd_a;
+        expected.rotate_left(1);
+        if resp[4..20] != expected {
+            warn!("authentication response mismatch");
+            return Err(Error::AuthFailed);
+        }
+        let ti: [u8; 4] = unwrap!(resp[0..4].try_into());
+        debug!("authenticated with key {}, TI {:02x}", key_no, Bytes(&ti));
+
+        self.session = Some(Session::ev2(k, &rnd_a, &rnd_b, ti));
+        self.key_no = key_no;
+        Ok(())
+    }
+
+    /// Select an application, or the PICC level with [`PICC_AID`]. Drops any session.
+    pub async fn select_application(&mut self, aid: [u8; 3]) -> Result<(), Error<T::Error>> {
+        self.session = None;
+        self.command(CMD_SELECT_APPLICATION, &aid, &mut []).await?;
+        self.aid = Some(aid);
+        Ok(())
+    }
+
+    /// Get the IDs of the applications into `buf`, returning their count.
+    pub async fn get_application_ids(&mut self, buf: &mut [[u8; 3]]) -> Result<usize, Error<T::Error>> {
+        // Up to 28 applications.
+        let mut data = [0; 84];
+        let n = self.command(CMD_GET_APPLICATION_IDS, &[], &mut data).await?;
+        if n % 3 != 0 {
+            warn!("malformed application IDs");
+            return Err(Error::Protocol);
+        }
+        if n / 3 > buf.len() {
+            return Err(Error::TooBig);
+        }
+        for (dst, src) in buf.iter_mut().zip(data[..n].chunks_exact(3)) {
+            dst.copy_from_slice(src);
+        }
+        Ok(n / 3)
+    }
+
+    /// Create an application with `key_count` keys, of type `key_type`.
+    pub async fn create_application(
+        &mut self,
+        aid: [u8; 3],
+        key_settings: u8,
+        key_count: u8,
+        key_type: KeyType,
+    ) -> Result<(), Error<T::Error>> {
+        if !(1..=14).contains(&key_count) {
+            return Err(Error::InvalidKeyCount);
+        }
+        let [a0, a1, a2] = aid;
+        let header = [a0, a1, a2, key_settings, key_count | key_type.bits()];
+        self.command(CMD_CREATE_APPLICATION, &header, &mut []).await?;
+        Ok(())
+    }
+
+    pub async fn delete_application(&mut self, aid: [u8; 3]) -> Result<(), Error<T::Error>> {
+        self.command(CMD_DELETE_APPLICATION, &aid, &mut []).await?;
+        Ok(())
+    }
+
+    /// Delete all applications and files. Needs authentication with the PICC master key.
+    pub async fn format_picc(&mut self) -> Result<(), Error<T::Error>> {
+        self.command(CMD_FORMAT_PICC, &[], &mut []).await?;
+        Ok(())
+    }
+
+    /// Get the key settings of the selected application, or of the PICC.
+    pub async fn get_key_settings(&mut self) -> Result<KeySettings, Error<T::Error>> {
+        let mut buf = [0; 2];
+        let n = self.command(CMD_GET_KEY_SETTINGS, &[], &mut buf).await?;
+        let key_type = KeyType::from_bits(buf[1]);
+        match (n, key_type) {
+            (2, Some(key_type)) => Ok(KeySettings {
+                settings: buf[0],
+                key_count: buf[1] & 0x0F,
+                key_type,
+            }),
+            _ => {
+                warn!("malformed key settings");
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Change the key settings of the selected application, or of the PICC.
+    pub async fn change_key_settings(&mut self, settings: u8) -> Result<(), Error<T::Error>> {
+        self.require_auth()?;
+        let data = [settings];
+        let req = Request::new(CMD_CHANGE_KEY_SETTINGS, &[], &data, CommMode::Full);
+        self.exchange(&req, &mut []).await?;
+        Ok(())
+    }
+
+    pub async fn get_key_version(&mut self, key_no: u8) -> Result<u8, Error<T::Error>> {
+        let mut buf = [0; 1];
+        match self.command(CMD_GET_KEY_VERSION, &[key_no], &mut buf).await? {
+            1 => Ok(buf[0]),
+            _ => {
+                warn!("malformed key version");
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Change key `key_no` to `new_key`, with `version` for AES keys.
+    ///
+    /// Changing a key other than the authenticated one needs its current value in `old_key`.
+    /// Changing the authenticated key ends the session.
+    pub async fn change_key(&mut self, key_no: u8, new_key: &Key, version: u8, old_key: &Key) -> Result<(), Error<T::Error>> {
+        self.require_auth()?;
+        let same_key = key_no & 0x0F == self.key_no & 0x0F;
+
+        // The PICC master key can change type, given in the key number.
+        let mut header = key_no;
+        if self.aid == Some(PICC_AID) {
+            header |= new_key.key_type().bits();
+        }
+
+        let new = new_key.as_bytes();
+        let mut data = [0; 24 + 1 + 4];
+        data[..new.len()].copy_from_slice(new);
+        if !same_key {
+            xor(&mut data[..new.len()], old_key.as_bytes());
+        }
+        let mut len = new.len();
+        if let Key::Aes(_) = new_key {
+            data[len] = version;
+            len += 1;
+        }
+
+        // The CRC of the new key goes after the command CRC in EV1, in the data in EV2.
+        let mut key_crc = None;
+        if !same_key {
+            match self.session {
+                Some(Session::Ev1 { .. }) => key_crc = Some(crc32(new)),
+                _ => {
+                    data[len..len + 4].copy_from_slice(&crc32(new));
+                    len += 4;
+                }
+            }
+        }
+
+        let header = [header];
+        let req = Request {
+            key_crc,
+            ends_session: same_key,
+            ..Request::new(CMD_CHANGE_KEY, &header, &data[..len], CommMode::Full)
+        };
+        self.exchange(&req, &mut []).await?;
+        Ok(())
+    }
+
+    /// Get the real UID, when random IDs are enabled. Needs authentication.
+    pub async fn get_card_uid(&mut self) -> Result<[u8; 7], Error<T::Error>> {
+        self.require_auth()?;
+        let mut uid = [0; 7];
+        let req = Request::new(CMD_GET_CARD_UID, &[], &[], CommMode::Full);
+        match self.exchange(&req, &mut uid).await? {
+            7 => Ok(uid),
+            _ => {
+                warn!("malformed UID");
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Free memory on the card, in bytes.
+    pub async fn free_memory(&mut self) -> Result<u32, Error<T::Error>> {
+        let mut buf = [0; 3];
+        match self.command(CMD_FREE_MEMORY, &[], &mut buf).await? {
+            3 => Ok(u24(&buf)),
+            _ => {
+                warn!("malformed free memory");
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Get the IDs of the files in the selected application into `buf`, returning their count.
+    pub async fn get_file_ids(&mut self, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        let mut data = [0; 32];
+        let n = self.command(CMD_GET_FILE_IDS, &[], &mut data).await?;
+        if n > buf.len() {
+            return Err(Error::TooBig);
+        }
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    pub async fn get_file_settings(&mut self, file: u8) -> Result<FileSettings, Error<T::Error>> {
+        let mut buf = [0; 64];
+        let n = self.command(CMD_GET_FILE_SETTINGS, &[file], &mut buf).await?;
+        FileSettings::parse(&buf[..n]).ok_or_else(|| {
+            warn!("malformed file settings");
+            Error::Protocol
+        })
+    }
+
+    /// Change the communication mode and access rights of a file.
+    ///
+    /// `mode` is how the command is sent: [`CommMode::Full`] if the change access right
+    /// is a key, [`CommMode::Plain`] if it's free.
+    pub async fn change_file_settings(
+        &mut self,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        mode: CommMode,
+    ) -> Result<(), Error<T::Error>> {
+        let [a0, a1] = access.encode();
+        let data = [comm_mode as u8, a0, a1];
+        let header = [file];
+        let req = Request::new(CMD_CHANGE_FILE_SETTINGS, &header, &data, mode);
+        self.exchange(&req, &mut []).await?;
+        Ok(())
+    }
+
+    pub async fn create_std_data_file(
+        &mut self,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        size: u32,
+    ) -> Result<(), Error<T::Error>> {
+        self.create_data_file(CMD_CREATE_STD_DATA_FILE, file, comm_mode, access, size)
+            .await
+    }
+
+    /// Create a backup data file, whose writes take effect on
+    /// [`commit_transaction`](Self::commit_transaction).
+    pub async fn create_backup_data_file(
+        &mut self,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        size: u32,
+    ) -> Result<(), Error<T::Error>> {
+        self.create_data_file(CMD_CREATE_BACKUP_DATA_FILE, file, comm_mode, access, size)
+            .await
+    }
+
+    async fn create_data_file(
+        &mut self,
+        cmd: u8,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        size: u32,
+    ) -> Result<(), Error<T::Error>> {
+        let [a0, a1] = access.encode();
+        let [s0, s1, s2, _] = size.to_le_bytes();
+        self.command(cmd, &[file, comm_mode as u8, a0, a1, s0, s1, s2], &mut [])
+            .await?;
+        Ok(())
+    }
+
+    /// Create a value file holding `value`, which must stay within `limits`.
+    pub async fn create_value_file(
+        &mut self,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        limits: RangeInclusive<i32>,
+        value: i32,
+        limited_credit: bool,
+    ) -> Result<(), Error<T::Error>> {
+        let [a0, a1] = access.encode();
+        let mut header = [0; 17];
+        header[..4].copy_from_slice(&[file, comm_mode as u8, a0, a1]);
+        header[4..8].copy_from_slice(&limits.start().to_le_bytes());
+        header[8..12].copy_from_slice(&limits.end().to_le_bytes());
+        header[12..16].copy_from_slice(&value.to_le_bytes());
+        header[16] = limited_credit as u8;
+        self.command(CMD_CREATE_VALUE_FILE, &header, &mut []).await?;
+        Ok(())
+    }
+
+    pub async fn create_linear_record_file(
+        &mut self,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        record_size: u32,
+        max_records: u32,
+    ) -> Result<(), Error<T::Error>> {
+        self.create_record_file(
+            CMD_CREATE_LINEAR_RECORD_FILE,
+            file,
+            comm_mode,
+            access,
+            record_size,
+            max_records,
+        )
+        .await
+    }
+
+    /// Create a cyclic record file, where writing a record when full overwrites the oldest one.
+    /// One record is kept free for this, so `max_records` must be at least 2.
+    pub async fn create_cyclic_record_file(
+        &mut self,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        record_size: u32,
+        max_records: u32,
+    ) -> Result<(), Error<T::Error>> {
+        self.create_record_file(
+            CMD_CREATE_CYCLIC_RECORD_FILE,
+            file,
+            comm_mode,
+            access,
+            record_size,
+            max_records,
+        )
+        .await
+    }
+
+    async fn create_record_file(
+        &mut self,
+        cmd: u8,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        record_size: u32,
+        max_records: u32,
+    ) -> Result<(), Error<T::Error>> {
+        let [a0, a1] = access.encode();
+        let [s0, s1, s2, _] = record_size.to_le_bytes();
+        let [m0, m1, m2, _] = max_records.to_le_bytes();
+        self.command(cmd, &[file, comm_mode as u8, a0, a1, s0, s1, s2, m0, m1, m2], &mut [])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_file(&mut self, file: u8) -> Result<(), Error<T::Error>> {
+        self.command(CMD_DELETE_FILE, &[file], &mut []).await?;
+        Ok(())
+    }
+
+    /// Read `buf.len()` bytes from a data file at `offset`.
+    pub async fn read_data(&mut self, file: u8, offset: u32, buf: &mut [u8], mode: CommMode) -> Result<(), Error<T::Error>> {
+        self.read_chunked(CMD_READ_DATA, file, offset, buf, mode).await
+    }
+
+    /// Write `data` to a data file at `offset`.
+    pub async fn write_data(&mut self, file: u8, offset: u32, data: &[u8], mode: CommMode) -> Result<(), Error<T::Error>> {
+        self.write_chunked(CMD_WRITE_DATA, file, offset, data, mode).await
+    }
+
+    /// Read `count` records, starting `offset` records back from the newest one, into `buf`
+    /// which must hold exactly `count` records, oldest first.
+    pub async fn read_records(
+        &mut self,
+        file: u8,
+        offset: u32,
+        count: u32,
+        buf: &mut [u8],
+        mode: CommMode,
+    ) -> Result<(), Error<T::Error>> {
+        if buf.len() > MAX_CHUNK {
+            return Err(Error::TooBig);
+        }
+        let [o0, o1, o2, _] = offset.to_le_bytes();
+        let [c0, c1, c2, _] = count.to_le_bytes();
+        let header = [file, o0, o1, o2, c0, c1, c2];
+        let req = Request::new(CMD_READ_RECORDS, &header, &[], mode);
+        let n = self.exchange(&req, buf).await?;
+        if n != buf.len() {
+            warn!("short read");
+            return Err(Error::Protocol);
+        }
+        Ok(())
+    }
+
+    /// Write `data` at `offset` in a new record, or in the record being written if the
+    /// transaction isn't committed yet.
+    pub async fn write_record(&mut self, file: u8, offset: u32, data: &[u8], mode: CommMode) -> Result<(), Error<T::Error>> {
+        self.write_chunked(CMD_WRITE_RECORD, file, offset, data, mode).await
+    }
+
+    /// Clear a record file, effective on [`commit_transaction`](Self::commit_transaction).
+    pub async fn clear_record_file(&mut self, file: u8) -> Result<(), Error<T::Error>> {
+        self.command(CMD_CLEAR_RECORD_FILE, &[file], &mut []).await?;
+        Ok(())
+    }
+
+    async fn read_chunked(
+        &mut self,
+        cmd: u8,
+        file: u8,
+        offset: u32,
+        buf: &mut [u8],
+        mode: CommMode,
+    ) -> Result<(), Error<T::Error>> {
+        for (i, c) in buf.chunks_mut(MAX_CHUNK).enumerate() {
+            let [o0, o1, o2, _] = (offset + (i * MAX_CHUNK) as u32).to_le_bytes();
+            let [l0, l1, l2, _] = (c.len() as u32).to_le_bytes();
+            let header = [file, o0, o1, o2, l0, l1, l2];
+            let req = Request::new(cmd, &header, &[], mode);
+            let n = self.exchange(&req, c).await?;
+            if n != c.len() {
+                warn!("short read");
+                return Err(Error::Protocol);
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_chunked(
+        &mut self,
+        cmd: u8,
+        file: u8,
+        offset: u32,
+        data: &[u8],
+        mode: CommMode,
+    ) -> Result<(), Error<T::Error>> {
+        for (i, c) in data.chunks(MAX_CHUNK).enumerate() {
+            let [o0, o1, o2, _] = (offset + (i * MAX_CHUNK) as u32).to_le_bytes();
+            let [l0, l1, l2, _] = (c.len() as u32).to_le_bytes();
+            let header = [file, o0, o1, o2, l0, l1, l2];
+            let req = Request::new(cmd, &header, c, mode);
+            self.exchange(&req, &mut []).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_value(&mut self, file: u8, mode: CommMode) -> Result<i32, Error<T::Error>> {
+        let mut buf = [0; 4];
+        let header = [file];
+        let req = Request::new(CMD_GET_VALUE, &header, &[], mode);
+        match self.exchange(&req, &mut buf).await? {
+            4 => Ok(i32::from_le_bytes(buf)),
+            _ => {
+                warn!("malformed value");
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Increase a value file, effective on [`commit_transaction`](Self::commit_transaction).
+    pub async fn credit(&mut self, file: u8, amount: i32, mode: CommMode) -> Result<(), Error<T::Error>> {
+        self.value_op(CMD_CREDIT, file, amount, mode).await
+    }
+
+    /// Decrease a value file, effective on [`commit_transaction`](Self::commit_transaction).
+    pub async fn debit(&mut self, file: u8, amount: i32, mode: CommMode) -> Result<(), Error<T::Error>> {
+        self.value_op(CMD_DEBIT, file, amount, mode).await
+    }
+
+    /// Increase a value file by at most the sum of the debits of the last transaction,
+    /// with only write access.
+    pub async fn limited_credit(&mut self, file: u8, amount: i32, mode: CommMode) -> Result<(), Error<T::Error>> {
+        self.value_op(CMD_LIMITED_CREDIT, file, amount, mode).await
+    }
+
+    async fn value_op(&mut self, cmd: u8, file: u8, amount: i32, mode: CommMode) -> Result<(), Error<T::Error>> {
+        let (header, data) = ([file], amount.to_le_bytes());
+        let req = Request::new(cmd, &header, &data, mode);
+        self.exchange(&req, &mut []).await?;
+        Ok(())
+    }
+
+    /// Validate all writes to backup, value and record files of the selected application.
+    pub async fn commit_transaction(&mut self) -> Result<(), Error<T::Error>> {
+        self.command(CMD_COMMIT_TRANSACTION, &[], &mut []).await?;
+        Ok(())
+    }
+
+    /// Cancel all writes to backup, value and record files of the selected application.
+    pub async fn abort_transaction(&mut self) -> Result<(), Error<T::Error>> {
+        self.command(CMD_ABORT_TRANSACTION, &[], &mut []).await?;
+        Ok(())
+    }
+
+    /// Record that an application was selected with ISOSelectFile, which drops any session.
+    pub(crate) fn iso_selected(&mut self) {
+        self.session = None;
+        self.aid = None;
+    }
+
+    pub(crate) fn session(&mut self) -> Option<&mut Session> {
+        self.session.as_mut()
+    }
+
+    pub(crate) fn end_session(&mut self) {
+        self.session = None;
+    }
+
+    fn require_auth(&self) -> Result<(), Error<T::Error>> {
+        match self.session {
+            Some(_) => Ok(()),
+            None => Err(Error::NotAuthenticated),
+        }
+    }
+
+    /// Send a management command, MACed in EV2 sessions. Everything is in the header, so
+    /// EV1 sessions don't MAC the command, but check the MAC of the response.
+    async fn command(&mut self, cmd: u8, header: &[u8], rx: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        self.exchange(&Request::new(cmd, header, &[], CommMode::Mac), rx).await
+    }
+
+    /// Send a command with secure messaging if authenticated, and receive the plain response
+    /// into `rx`. Returns the response length.
+    ///
+    /// For enciphered responses, `rx.len()` is the expected length.
+    pub(crate) async fn exchange(&mut self, req: &Request<'_>, rx: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        let mut msg = Buf::new();
+        match &mut self.session {
+            Some(session) => session.protect(req, &mut msg).ok_or(Error::TooBig)?,
+            None => {
+                unwrap!(msg.push(req.cmd));
+                msg.extend_from_slice(req.header).map_err(|_| Error::TooBig)?;
+                msg.extend_from_slice(req.data).map_err(|_| Error::TooBig)?;
+            }
+        }
+        if req.ends_session {
+            self.session = None;
+        }
+
+        let res = self.exchange_frames(&msg).await;
+        let mut resp = match res {
+            Ok(resp) => resp,
+            Err(e) => {
+                self.session = None;
+                return Err(e);
+            }
+        };
+
+        if let Some(session) = &mut self.session
+            && session.unprotect(req.mode, &mut resp, rx.len()).is_none()
+        {
+            warn!("response integrity check failed");
+            self.session = None;
+            return Err(Error::Integrity);
+        }
+
+        if resp.len() > rx.len() {
+            warn!("response too long");
+            return Err(Error::Protocol);
+        }
+        rx[..resp.len()].copy_from_slice(&resp);
+        Ok(resp.len())
+    }
+
+    /// Send a native message split in frames, and collect the response frames.
+    async fn exchange_frames(&mut self, msg: &[u8]) -> Result<Buf, Error<T::Error>> {
+        let (cmd, data) = (msg[0], &msg[1..]);
+        let mut resp = Buf::new();
+
+        let mut pos = data.len().min(MAX_FRAME_DATA);
+        let mut status = self.frame(cmd, &data[..pos], &mut resp).await?;
+        while pos < data.len() {
+            expect_additional_frame(status)?;
+            let end = data.len().min(pos + MAX_FRAME_DATA);
+            status = self.frame(CMD_ADDITIONAL_FRAME, &data[pos..end], &mut resp).await?;
+            pos = end;
+        }
+        while status == STATUS_ADDITIONAL_FRAME {
+            status = self.frame(CMD_ADDITIONAL_FRAME, &[], &mut resp).await?;
+        }
+        expect_ok(status)?;
+        Ok(resp)
+    }
+
+    /// Send a single ISO-wrapped frame, appending the response data to `resp`.
+    /// Returns the native status.
+    async fn frame(&mut self, cmd: u8, data: &[u8], resp: &mut Buf) -> Result<u8, Error<T::Error>> {
+        let mut tx = [0; 6 + MAX_FRAME_DATA];
+        tx[..4].copy_from_slice(&[CLA, cmd, 0x00, 0x00]);
+        let len = match data.len() {
+            0 => 5,
+            n => {
+                tx[4] = n as u8;
+                tx[5..][..n].copy_from_slice(data);
+                6 + n
+            }
+        };
+
+        debug!("APDU TX: {:02x}", Bytes(&tx[..len]));
+        let mut rx = [0; 258];
+        let n = self.reader.transceive(&tx[..len], &mut rx).await.map_err(Error::Lower)?;
+        debug!("APDU RX: {:02x}", Bytes(&rx[..n]));
+        if n < 2 || rx[n - 2] != 0x91 {
+            warn!("not a native status");
+            return Err(Error::Protocol);
+        }
+        resp.extend_from_slice(&rx[..n - 2]).map_err(|_| Error::TooBig)?;
+        Ok(rx[n - 1])
+    }
+}
+
+fn expect_ok<E>(status: u8) -> Result<(), Error<E>> {
+    match status {
+        STATUS_OK => Ok(()),
+        s => {
+            debug!("status {:02x}", s);
+            Err(Error::Status(s))
+        }
+    }
+}
+
+fn expect_additional_frame<E>(status: u8) -> Result<(), Error<E>> {
+    match status {
+        STATUS_ADDITIONAL_FRAME => Ok(()),
+        STATUS_OK => {
+            warn!("missing additional frame");
+            Err(Error::Protocol)
+        }
+        s => {
+            debug!("status {:02x}", s);
+            Err(Error::Status(s))
+        }
+    }
+}
+
+fn u24(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], 0])
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::test_util::{FixedRng, mock};
+
+    #[tokio::test]
+    async fn test_get_version() {
+        let mut r = mock!(
+            "9060000000" => "04010101001805 91AF",
+            "90AF000000" => "04010101041A05 91AF",
+            "90AF000000" => "04123456789ABC BA34999930 2619 9100",
+        );
+        let mut card = Desfire::new(&mut r);
+        let version = card.get_version().await.unwrap();
+        assert_eq!(version.hardware.vendor_id, 0x04);
+        assert_eq!(version.software.major_version, 0x01);
+        assert_eq!(version.software.minor_version, 0x04);
+        assert_eq!(version.hardware.storage_bytes(), Some(4096));
+        let huge = ProductInfo {
+            storage_size: 0xFF,
+            ..version.hardware
+        };
+        assert_eq!(huge.storage_bytes(), None);
+        assert_eq!(version.uid, hex!("04123456789ABC"));
+        assert_eq!(version.production_year, 0x19);
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_plain() {
+        let mut r = mock!(
+            "905A00000312345600" => "9100",
+            "90F500000102 00" => "0000E0EE200000 9100",
+            "90BD0000070200000004000000" => "01020304 9100",
+            // Split in two frames.
+            "903D000030020000003C0000AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA00" => "91AF",
+            "90AF000013AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA00" => "9100",
+            "90BD0000070300000004000000" => "919D",
+        );
+        let mut card = Desfire::new(&mut r);
+        card.select_application(hex!("123456")).await.unwrap();
+
+        let settings = card.get_file_settings(2).await.unwrap();
+        assert_eq!(settings.file_type, FileType::StandardData);
+        assert_eq!(settings.comm_mode, CommMode::Plain);
+        assert_eq!(
+            settings.access,
+            AccessRights {
+                read: AccessRights::FREE,
+                write: AccessRights::FREE,
+                read_write: AccessRights::FREE,
+                change: 0,
+            }
+        );
+        assert_eq!(settings.info, FileInfo::Data { size: 32 });
+
+        let mut buf = [0; 4];
+        card.read_data(2, 0, &mut buf, CommMode::Plain).await.unwrap();
+        assert_eq!(buf, hex!("01020304"));
+        card.write_data(2, 0, &[0xAA; 60], CommMode::Plain).await.unwrap();
+
+        assert!(matches!(
+            card.read_data(3, 0, &mut buf, CommMode::Plain).await,
+            Err(Error::Status(0x9D))
+        ));
+        assert!(matches!(
+            card.create_application(hex!("654321"), 0x0F, 15, KeyType::Aes).await,
+            Err(Error::InvalidKeyCount)
+        ));
+        r.assert_done();
+    }
+
+    /// RndA of the public AuthenticateAES example.
+    const RND_A_AES: [u8; 16] = hex!("F44B26F5686F3A391CD38EBD10772281");
+
+    #[tokio::test]
+    async fn test_ev1_aes() {
+        let mut r = mock!(
+            "905A00000312345600" => "9100",
+            // Public AuthenticateAES example with the default key: RndB = C05DDD714FD788A6B7B754F3C4D066E8,
+            // RndA = F44B26F5686F3A391CD38EBD10772281, session key F44B26F5C05DDD7110772281C4D066E8.
+            "90AA0000010000" => "B969FDFE56FD91FC9DE6F6F213B8FD1E 91AF",
+            "90AF00002036AAD7DF6E436BA08D18613830A70D5AD43E3D3F4A8D47541EEE623A934E477400" => "800DB680BC146BD121D6578F2D2E2059 9100",
+            "906F000000" => "0102 F7C47918600C2E7E 9100",
+            "90BD0000070100000005000000" => "45F581AF56CC9C29BD8F38A939568E7C 9100",
+            "903D00001302000000040000CAFEBABEE4E9BD87D88A867100" => "8737417AF8769696 9100",
+            "90C4000021012198CA9303A213B8DCF9C0D002DA09753BCA1A33364CA18538F52CDE42DA077900" => "DA771678ECB5CED6 9100",
+            // Last byte of the MAC changed.
+            "906C0000010300" => "64000000 935E1D88213A102F 9100",
+        );
+        let mut card = Desfire::new(&mut r);
+        card.select_application(hex!("123456")).await.unwrap();
+        let mut rng = FixedRng(&RND_A_AES);
+        card.authenticate_aes(&mut rng, 0, &Key::DEFAULT_AES).await.unwrap();
+        assert_eq!(card.authenticated_key(), Some(0));
+
+        let mut ids = [0; 4];
+        assert_eq!(card.get_file_ids(&mut ids).await.unwrap(), 2);
+        assert_eq!(ids[..2], [1, 2]);
+
+        let mut buf = [0; 5];
+        card.read_data(1, 0, &mut buf, CommMode::Full).await.unwrap();
+        assert_eq!(buf, hex!("1122334455"));
+
+        card.write_data(2, 0, &hex!("CAFEBABE"), CommMode::Mac).await.unwrap();
+
+        card.change_key(1, &Key::Aes([0x11; 16]), 0x10, &Key::DEFAULT_AES)
+            .await
+            .unwrap();
+        assert!(card.is_authenticated());
+
+        assert!(matches!(card.get_value(3, CommMode::Mac).await, Err(Error::Integrity)));
+        assert!(!card.is_authenticated());
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_ev1_iso() {
+        let mut r = mock!(
+            // Challenge of the public native authentication example with the default DES key,
+            // RndB = 4FD1B75942A8B8E1. AuthenticateISO sends the same cryptogram for DES keys.
+            "901A0000010000" => "5D994CE085F24089 91AF",
+            "90AF00001021D0AD5F2FD97454A746CC80567F1B1C00" => "913C6DED84221C41 9100",
+            "90BD0000070100000003000000" => "6B3FC74CA6BF4686 9100",
+        );
+        let mut card = Desfire::new(&mut r);
+        let mut rng = FixedRng(&hex!("849B36C5F8BF4A09"));
+        card.authenticate_iso(&mut rng, 0, &Key::DEFAULT_DES).await.unwrap();
+
+        let mut buf = [0; 3];
+        card.read_data(1, 0, &mut buf, CommMode::Full).await.unwrap();
+        assert_eq!(buf, *b"ABC");
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_ev1_auth_failed() {
+        let mut r = mock!(
+            "90AA0000010000" => "B969FDFE56FD91FC9DE6F6F213B8FD1E 91AF",
+            "90AF00002036AAD7DF6E436BA08D18613830A70D5AD43E3D3F4A8D47541EEE623A934E477400" => "91AE",
+        );
+        let mut card = Desfire::new(&mut r);
+        let mut rng = FixedRng(&RND_A_AES);
+        assert!(matches!(
+            card.authenticate_aes(&mut rng, 0, &Key::DEFAULT_AES).await,
+            Err(Error::Status(0xAE))
+        ));
+        assert!(!card.is_authenticated());
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_ev2() {
+        let mut r = mock!(
+            "905A00000312345600" => "9100",
+            // AN12196, AuthenticateEV2First with key 0.
+            "9071000002000000" => "A04C124213C186F22399D33AC2A30215 91AF",
+            "90AF00002035C3E05A752E0144BAC0DE51C1F22C56B34408A23D8AEA266CAB947EA8E0118D00" => "3FA64DB5446D1F34CD6EA311167F5E4985B89690C04A05F17FA7AB2F08120663 9100",
+            "90F500000902046FD9C80D11D17500" => "0000E0EE000100 46A881E858967904 9100",
+            "903D00001F020000000A00000622414803F8BCB54B7B123195F3F33BADD596F9F8424D9500" => "57BFF87B1241E93D 9100",
+            "90BD00000F020000000A00005D9D4D9C3386741F00" => "98CCA812BC9AE45EDB6465880BE9E476 C7D979B23932D980 9100",
+            "90C400002900D761AA55BD62C0E624A5B1246533DC5C2BFA24528F0971320F6706D2639268D494DF9E1AC0DADF1C00" => "9100",
+        );
+        let mut card = Desfire::new(&mut r);
+        card.select_application(hex!("123456")).await.unwrap();
+        let mut rng = FixedRng(&hex!("13C5DB8A5930439FC3DEF9A4C675360F"));
+        card.authenticate_ev2_first(&mut rng, 0, &Key::DEFAULT_AES).await.unwrap();
+
+        let settings = card.get_file_settings(2).await.unwrap();
+        assert_eq!(settings.info, FileInfo::Data { size: 256 });
+
+        let data = hex!("0102030405060708090A");
+        card.write_data(2, 0, &data, CommMode::Full).await.unwrap();
+        let mut buf = [0; 10];
+        card.read_data(2, 0, &mut buf, CommMode::Full).await.unwrap();
+        assert_eq!(buf, data);
+
+        card.change_key(0, &Key::Aes([0x22; 16]), 1, &Key::DEFAULT_AES).await.unwrap();
+        assert!(!card.is_authenticated());
+        r.assert_done();
+    }
+
+    #[test]
+    fn test_access_rights() {
+        let access = AccessRights {
+            read: 1,
+            write: 2,
+            read_write: 3,
+            change: AccessRights::NEVER,
+        };
+        assert_eq!(access.encode(), [0x3F, 0x12]);
+        assert_eq!(AccessRights::decode([0x3F, 0x12]), access);
+    }
+
+    #[test]
+    fn test_file_settings() {
+        let s = FileSettings::parse(&hex!("02 01 1000 00000000 E8030000 00000000 01")).unwrap();
+        assert_eq!(s.file_type, FileType::Value);
+        assert_eq!(s.comm_mode, CommMode::Mac);
+        assert_eq!(
+            s.info,
+            FileInfo::Value {
+                lower_limit: 0,
+                upper_limit: 1000,
+                limited_credit_value: 0,
+                limited_credit_enabled: true,
+            }
+        );
+
+        let s = FileSettings::parse(&hex!("04 03 0000 100000 050000 020000")).unwrap();
+        assert_eq!(s.file_type, FileType::CyclicRecord);
+        assert_eq!(
+            s.info,
+            FileInfo::Record {
+                record_size: 16,
+                max_records: 5,
+                records: 2,
+            }
+        );
+
+        assert!(FileSettings::parse(&hex!("00 00 0000 10")).is_none());
+        assert!(FileSettings::parse(&hex!("05 00 0000 100000")).is_none());
+    }
+}
@@ -0,0 +1,76 @@
+//! Key types and CRC used by DESFire secure messaging.
+
+use crate::crypto::Key;
+
+impl Key {
+    /// Default key of a new card or application: all zeros, single DES.
+    pub const DEFAULT_DES: Self = Self::TDes2([0; 16]);
+    /// Default AES key: all zeros.
+    pub const DEFAULT_AES: Self = Self::Aes([0; 16]);
+
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            Self::TDes2(_) => KeyType::TDes,
+            Self::TDes3(_) => KeyType::TDes3,
+            Self::Aes(_) => KeyType::Aes,
+        }
+    }
+}
+
+/// Type of the keys of an application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KeyType {
+    /// DES or 2K3DES.
+    TDes,
+    /// 3K3DES.
+    TDes3,
+    Aes,
+}
+
+impl KeyType {
+    /// Key type bits, used in the key settings of applications and in key numbers.
+    pub(crate) fn bits(&self) -> u8 {
+        match self {
+            Self::TDes => 0x00,
+            Self::TDes3 => 0x40,
+            Self::Aes => 0x80,
+        }
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Option<Self> {
+        match bits & 0xC0 {
+            0x00 => Some(Self::TDes),
+            0x40 => Some(Self::TDes3),
+            0x80 => Some(Self::Aes),
+            _ => None,
+        }
+    }
+}
+
+/// CRC32 as used by DESFire: the IEEE 802.3 polynomial without the final XOR, little endian.
+pub fn crc32(data: &[u8]) -> [u8; 4] {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                0 => crc >> 1,
+                _ => crc >> 1 ^ 0xEDB8_8320,
+            };
+        }
+    }
+    crc.to_le_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        // Check value of CRC-32/JAMCRC in the CRC catalogue.
+        assert_eq!(crc32(b"123456789"), 0x340BC6D9u32.to_le_bytes());
+        assert_eq!(crc32(&[]), [0xFF; 4]);
+    }
+}
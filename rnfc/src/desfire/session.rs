@@ -0,0 +1,327 @@
+//! Secure messaging state after authentication.
+//!
+//! EV1 secure messaging, set up by AuthenticateISO and AuthenticateAES, chains a single IV
+//! through all CMACs and encryptions. The PICC MACs every successful response, but commands
+//! only carry a MAC if they write data to a MACed file.
+//!
+//! EV2 secure messaging, set up by AuthenticateEV2First, has separate encryption and MAC
+//! session keys, a transaction identifier and a command counter instead of IV chaining.
+
+use heapless::Vec;
+
+use super::CommMode;
+use super::crypto::crc32;
+use crate::crypto::{Cipher, Key, MAX_BLOCK_SIZE, xor};
+
+/// Largest message handled, command or response, before splitting in frames.
+pub(crate) const MAX_MESSAGE: usize = 320;
+
+pub(crate) type Buf = Vec<u8, MAX_MESSAGE>;
+
+/// Length of the MACs sent on the air, truncated.
+const MAC_LEN: usize = 8;
+
+const STATUS_OK: u8 = 0x00;
+
+/// A command before protection.
+pub(crate) struct Request<'a> {
+    pub cmd: u8,
+    /// Part sent in plain in all communication modes, such as the file number and offset.
+    pub header: &'a [u8],
+    /// Part enciphered in [`CommMode::Full`].
+    pub data: &'a [u8],
+    pub mode: CommMode,
+    /// EV1 ChangeKey for another key than the authenticated one: CRC of the new key, enciphered
+    /// after the CRC of the command.
+    pub key_crc: Option<[u8; 4]>,
+    /// The command changes the authenticated key: the session ends once it's sent, and the
+    /// response is plain.
+    pub ends_session: bool,
+}
+
+impl<'a> Request<'a> {
+    pub fn new(cmd: u8, header: &'a [u8], data: &'a [u8], mode: CommMode) -> Self {
+        Self {
+            cmd,
+            header,
+            data,
+            mode,
+            key_crc: None,
+            ends_session: false,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Session {
+    Ev1 {
+        cipher: Cipher,
+        iv: [u8; MAX_BLOCK_SIZE],
+    },
+    Ev2 {
+        enc: Cipher,
+        mac: Cipher,
+        ti: [u8; 4],
+        cmd_ctr: u16,
+    },
+}
+
+impl Session {
+    /// Derive the EV1 session key from the authentication nonces.
+    pub fn ev1(key: &Key, rnd_a: &[u8], rnd_b: &[u8]) -> Self {
+        let (a, b) = (rnd_a, rnd_b);
+        let session_key = match key {
+            Key::TDes2(_) if key.is_single_des() => {
+                let mut k = [0; 16];
+                k[0..4].copy_from_slice(&a[0..4]);
+                k[4..8].copy_from_slice(&b[0..4]);
+                k.copy_within(0..8, 8);
+                Key::TDes2(k)
+            }
+            Key::TDes2(_) => Key::TDes2(concat(&[&a[0..4], &b[0..4], &a[4..8], &b[4..8]])),
+            Key::TDes3(_) => Key::TDes3(concat(&[&a[0..4], &b[0..4], &a[6..10], &b[6..10], &a[12..16], &b[12..16]])),
+            Key::Aes(_) => Key::Aes(concat(&[&a[0..4], &b[0..4], &a[12..16], &b[12..16]])),
+        };
+        Self::Ev1 {
+            cipher: Cipher::new(&session_key),
+            iv: [0; MAX_BLOCK_SIZE],
+        }
+    }
+
+    /// Derive the EV2 session keys from the authentication nonces.
+    pub fn ev2(key: &[u8; 16], rnd_a: &[u8; 16], rnd_b: &[u8; 16], ti: [u8; 4]) -> Self {
+        let mut sv = [0; 32];
+        sv[6..8].copy_from_slice(&rnd_a[0..2]);
+        sv[8..14].copy_from_slice(&rnd_a[2..8]);
+        xor(&mut sv[8..14], &rnd_b[0..6]);
+        sv[14..24].copy_from_slice(&rnd_b[6..16]);
+        sv[24..32].copy_from_slice(&rnd_a[8..16]);
+
+        let cipher = Cipher::aes(key);
+        sv[0..6].copy_from_slice(&[0xA5, 0x5A, 0x00, 0x01, 0x00, 0x80]);
+        let enc = cipher.cmac(&[0; 16], &sv);
+        sv[0..2].copy_from_slice(&[0x5A, 0xA5]);
+        let mac = cipher.cmac(&[0; 16], &sv);
+
+        Self::Ev2 {
+            enc: Cipher::aes(&enc),
+            mac: Cipher::aes(&mac),
+            ti,
+            cmd_ctr: 0,
+        }
+    }
+
+    /// Protect a command, writing the native message into `out`.
+    /// Returns `None` if it doesn't fit.
+    pub fn protect(&mut self, req: &Request<'_>, out: &mut Buf) -> Option<()> {
+        out.push(req.cmd).ok()?;
+        out.extend_from_slice(req.header).ok()?;
+        let data_start = out.len();
+        out.extend_from_slice(req.data).ok()?;
+
+        match self {
+            Self::Ev1 { cipher, iv } => {
+                let bs = cipher.block_size();
+                if req.mode == CommMode::Full && !req.data.is_empty() {
+                    out.extend_from_slice(&crc32(out)).ok()?;
+                    if let Some(crc) = req.key_crc {
+                        out.extend_from_slice(&crc).ok()?;
+                    }
+                    pad(out, data_start, bs, false)?;
+                    cipher.cbc_encrypt(&mut iv[..bs], &mut out[data_start..]);
+                } else {
+                    *iv = cipher.cmac(&iv[..bs], out);
+                    if req.mode == CommMode::Mac && !req.data.is_empty() {
+                        out.extend_from_slice(&iv[..MAC_LEN]).ok()?;
+                    }
+                }
+            }
+            Self::Ev2 { enc, mac, ti, cmd_ctr } => {
+                if req.mode == CommMode::Plain {
+                    return Some(());
+                }
+                if req.mode == CommMode::Full && !req.data.is_empty() {
+                    pad(out, data_start, 16, true)?;
+                    let mut iv = ev2_iv(enc, [0xA5, 0x5A], ti, *cmd_ctr);
+                    enc.cbc_encrypt(&mut iv, &mut out[data_start..]);
+                }
+
+                let mut input = Buf::new();
+                input.push(req.cmd).ok()?;
+                input.extend_from_slice(&cmd_ctr.to_le_bytes()).ok()?;
+                input.extend_from_slice(ti).ok()?;
+                input.extend_from_slice(&out[1..]).ok()?;
+                out.extend_from_slice(&truncate_mac(&mac.cmac(&[0; 16], &input))).ok()?;
+            }
+        }
+        Some(())
+    }
+
+    /// Check and remove the protection of a successful response, in place.
+    ///
+    /// `expected_len` is the length of the plain data, needed to find the CRC of EV1
+    /// enciphered responses. Returns `None` on a MAC, CRC or padding mismatch.
+    pub fn unprotect(&mut self, mode: CommMode, resp: &mut Buf, expected_len: usize) -> Option<()> {
+        match self {
+            Self::Ev1 { cipher, iv } => {
+                let bs = cipher.block_size();
+                if mode == CommMode::Full && expected_len > 0 {
+                    if resp.len() != (expected_len + 4).next_multiple_of(bs) {
+                        return None;
+                    }
+                    cipher.cbc_decrypt(&mut iv[..bs], resp);
+                    let (data, rest) = resp.split_at(expected_len);
+                    let mut crc_input = Buf::new();
+                    crc_input.extend_from_slice(data).ok()?;
+                    crc_input.push(STATUS_OK).ok()?;
+                    if rest[..4] != crc32(&crc_input) || rest[4..].iter().any(|&b| b != 0) {
+                        return None;
+                    }
+                    resp.truncate(expected_len);
+                } else {
+                    let data_len = resp.len().checked_sub(MAC_LEN)?;
+                    let received: [u8; MAC_LEN] = resp[data_len..].try_into().ok()?;
+                    resp[data_len] = STATUS_OK;
+                    *iv = cipher.cmac(&iv[..bs], &resp[..data_len + 1]);
+                    if iv[..MAC_LEN] != received {
+                        return None;
+                    }
+                    resp.truncate(data_len);
+                }
+            }
+            Self::Ev2 { enc, mac, ti, cmd_ctr } => {
+                *cmd_ctr = cmd_ctr.wrapping_add(1);
+                if mode == CommMode::Plain {
+                    return Some(());
+                }
+
+                let data_len = resp.len().checked_sub(MAC_LEN)?;
+                let mut input = Buf::new();
+                input.push(STATUS_OK).ok()?;
+                input.extend_from_slice(&cmd_ctr.to_le_bytes()).ok()?;
+                input.extend_from_slice(ti).ok()?;
+                input.extend_from_slice(&resp[..data_len]).ok()?;
+                if truncate_mac(&mac.cmac(&[0; 16], &input)) != resp[data_len..] {
+                    return None;
+                }
+                resp.truncate(data_len);
+
+                if mode == CommMode::Full && data_len > 0 {
+                    if !data_len.is_multiple_of(16) {
+                        return None;
+                    }
+                    let mut iv = ev2_iv(enc, [0x5A, 0xA5], ti, *cmd_ctr);
+                    enc.cbc_decrypt(&mut iv, resp);
+                    let end = resp.iter().rposition(|&b| b != 0)?;
+                    if resp[end] != 0x80 || resp.len() - end > 16 {
+                        return None;
+                    }
+                    resp.truncate(end);
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+/// Pad from `start` to a multiple of `bs`: with 80 00.. if `iso`, which always adds at
+/// least one byte, else with zeros.
+fn pad(buf: &mut Buf, start: usize, bs: usize, iso: bool) -> Option<()> {
+    if iso {
+        buf.push(0x80).ok()?;
+    }
+    let len = (buf.len() - start).next_multiple_of(bs);
+    buf.resize(start + len, 0).ok()
+}
+
+fn ev2_iv(enc: &Cipher, label: [u8; 2], ti: &[u8; 4], cmd_ctr: u16) -> [u8; 16] {
+    let mut iv = [0; 16];
+    iv[0..2].copy_from_slice(&label);
+    iv[2..6].copy_from_slice(ti);
+    iv[6..8].copy_from_slice(&cmd_ctr.to_le_bytes());
+    enc.encrypt_block(&mut iv);
+    iv
+}
+
+/// EV2 MACs keep the odd bytes of the CMAC, counting from 0.
+fn truncate_mac(mac: &[u8; 16]) -> [u8; MAC_LEN] {
+    core::array::from_fn(|i| mac[i * 2 + 1])
+}
+
+fn concat<const N: usize>(parts: &[&[u8]]) -> [u8; N] {
+    let mut res = [0; N];
+    let mut pos = 0;
+    for p in parts {
+        res[pos..pos + p.len()].copy_from_slice(p);
+        pos += p.len();
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_ev2_session_keys() {
+        // AN12196, AuthenticateEV2First with key 0.
+        let rnd_a = hex!("13C5DB8A5930439FC3DEF9A4C675360F");
+        let rnd_b = hex!("B9E2FC789B64BF237CCCAA20EC7E6E48");
+        let Session::Ev2 { enc, mac, .. } = Session::ev2(&[0; 16], &rnd_a, &rnd_b, hex!("9D00C4DF")) else {
+            unreachable!()
+        };
+
+        // Check the keys through their encryption of a zero block.
+        let mut block = [0; 16];
+        enc.encrypt_block(&mut block);
+        let mut expected = [0; 16];
+        Cipher::aes(&hex!("1309C877509E5A215007FF0ED19CA564")).encrypt_block(&mut expected);
+        assert_eq!(block, expected);
+
+        let mut block = [0; 16];
+        mac.encrypt_block(&mut block);
+        let mut expected = [0; 16];
+        Cipher::aes(&hex!("4C6626F5E72EA694202139295C7A7FC7")).encrypt_block(&mut expected);
+        assert_eq!(block, expected);
+    }
+
+    #[test]
+    fn test_ev1_session_keys() {
+        let a = hex!("00112233445566778899AABBCCDDEEFF");
+        let b = hex!("FFEEDDCCBBAA99887766554433221100");
+        let key = |s: &Session| match s {
+            Session::Ev1 { cipher, .. } => {
+                let mut block = [0; 16];
+                cipher.encrypt_block(&mut block[..cipher.block_size()]);
+                block
+            }
+            _ => unreachable!(),
+        };
+        let check = |master: Key, session: Key| {
+            let mut expected = [0; 16];
+            let c = Cipher::new(&session);
+            c.encrypt_block(&mut expected[..c.block_size()]);
+            assert_eq!(key(&Session::ev1(&master, &a, &b)), expected);
+        };
+
+        check(Key::DEFAULT_DES, Key::TDes2(hex!("00112233FFEEDDCC 00112233FFEEDDCC")));
+        check(
+            Key::TDes2(hex!("00000000000000000000000000000001")),
+            Key::TDes2(hex!("00112233FFEEDDCC 44556677BBAA9988")),
+        );
+        check(
+            Key::TDes3([0; 24]),
+            Key::TDes3(hex!("00112233FFEEDDCC 66778899 99887766 CCDDEEFF 33221100")),
+        );
+        check(Key::DEFAULT_AES, Key::Aes(hex!("00112233FFEEDDCC CCDDEEFF33221100")));
+
+        // Public AuthenticateAES example with the default key.
+        let a = hex!("F44B26F5686F3A391CD38EBD10772281");
+        let b = hex!("C05DDD714FD788A6B7B754F3C4D066E8");
+        let mut expected = [0; 16];
+        Cipher::aes(&hex!("F44B26F5C05DDD7110772281C4D066E8")).encrypt_block(&mut expected);
+        assert_eq!(key(&Session::ev1(&Key::DEFAULT_AES, &a, &b)), expected);
+    }
+}
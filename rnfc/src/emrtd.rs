@@ -0,0 +1,694 @@
+//! ICAO 9303 electronic passports and ID cards (eMRTD): access control and LDS1 files.
+//!
+//! The chip only gives access to its files after authenticating with the MRZ or the CAN
+//! printed on the document. [`Emrtd::pace`] runs PACE with ECDH generic mapping, using a
+//! [`PaceInfo`] from EF.CardAccess, and [`Emrtd::bac`] runs Basic Access Control, for older
+//! documents without EF.CardAccess. Both set up secure messaging, which then wraps all
+//! commands, including the READ BINARY of [`Emrtd::read_file`].
+//!
+//! PACE runs before selecting the LDS1 application, BAC after:
+//!
+//! ```ignore
+//! let mut doc = Emrtd::new(reader);
+//! match doc.read_card_access().await? {
+//!     Some(info) => {
+//!         doc.pace(&mut rng, &Password::Mrz(&key), &info).await?;
+//!         doc.select_application().await?;
+//!     }
+//!     None => {
+//!         doc.select_application().await?;
+//!         doc.bac(&mut rng, &key).await?;
+//!     }
+//! }
+//! let n = doc.read_file(emrtd::DG1, &mut buf).await?;
+//! let mrz = lds::parse_dg1(&buf[..n]);
+//! ```
+//!
+//! Chip authentication, active authentication and passive authentication of EF.SOD
+//! signatures are not implemented.
+
+pub(crate) mod ec;
+pub mod lds;
+pub(crate) mod sm;
+
+use heapless::Vec;
+use rand_core::CryptoRngCore;
+use rnfc_traits::iso_dep::Reader;
+use sha1::{Digest, Sha1};
+
+use self::lds::{PaceCipher, PaceInfo};
+use self::sm::{KDF_ENC, KDF_MAC, KDF_PACE, SecureMessaging, kdf};
+use crate::apdu::{self, Command, Response, StatusWord};
+use crate::crypto::{self, Cipher, Key, xor};
+use crate::tlv::{self, Tag};
+
+/// AID of the LDS1 eMRTD application.
+pub const LDS1_AID: &[u8] = &[0xA0, 0x00, 0x00, 0x02, 0x47, 0x10, 0x01];
+
+pub const EF_CARD_ACCESS: u16 = 0x011C;
+pub const EF_COM: u16 = 0x011E;
+pub const EF_SOD: u16 = 0x011D;
+pub const DG1: u16 = 0x0101;
+pub const DG2: u16 = 0x0102;
+
+const SFI_CARD_ACCESS: u8 = 0x1C;
+
+const CLA: u8 = 0x00;
+const INS_SELECT: u8 = 0xA4;
+const INS_READ_BINARY: u8 = 0xB0;
+const INS_READ_BINARY_ODD: u8 = 0xB1;
+const INS_GET_CHALLENGE: u8 = 0x84;
+const INS_EXTERNAL_AUTHENTICATE: u8 = 0x82;
+const INS_MSE: u8 = 0x22;
+const INS_GENERAL_AUTHENTICATE: u8 = 0x86;
+
+const TAG_OFFSET: Tag = Tag(0x54);
+const TAG_DISCRETIONARY_DATA: Tag = Tag(0x53);
+const TAG_CRYPTOGRAPHIC_MECHANISM: Tag = Tag(0x80);
+const TAG_PASSWORD_REFERENCE: Tag = Tag(0x83);
+const TAG_DOMAIN_PARAMETERS: Tag = Tag(0x84);
+const TAG_DYNAMIC_AUTH_DATA: Tag = Tag(0x7C);
+const TAG_ENCRYPTED_NONCE: Tag = Tag(0x80);
+const TAG_MAPPING_DATA_PCD: Tag = Tag(0x81);
+const TAG_MAPPING_DATA_PICC: Tag = Tag(0x82);
+const TAG_EPHEMERAL_KEY_PCD: Tag = Tag(0x83);
+const TAG_EPHEMERAL_KEY_PICC: Tag = Tag(0x84);
+const TAG_TOKEN_PCD: Tag = Tag(0x85);
+const TAG_TOKEN_PICC: Tag = Tag(0x86);
+const TAG_PUBLIC_KEY: Tag = Tag(0x7F49);
+const TAG_PUBLIC_KEY_OID: Tag = Tag(0x06);
+const TAG_PUBLIC_POINT: Tag = Tag(0x86);
+
+/// Chip answer to a failed BAC or PACE: verification failed.
+const SW_AUTH_FAILED: StatusWord = StatusWord(0x6300);
+/// Secure messaging data objects missing or incorrect. The chip has dropped the session.
+const SW_SM_MISSING: StatusWord = StatusWord(0x6987);
+const SW_SM_INCORRECT: StatusWord = StatusWord(0x6988);
+
+/// Response data of a short APDU and GET RESPONSE, plus the status word.
+const RX_BUF_LEN: usize = 256 + 2;
+/// Bytes read per READ BINARY, so that protected responses fit in a short APDU.
+const READ_CHUNK_LEN: usize = sm::MAX_COMMAND_DATA;
+/// Highest offset of READ BINARY with even INS, in P1-P2.
+const MAX_SHORT_OFFSET: usize = 0x7FFF;
+/// Tag and length of any file, to read it in one go then.
+const FILE_HEADER_LEN: usize = 4;
+/// Longest MRZ information: a long document number, and the dates with check digits.
+const MAX_MRZ_INFO_LEN: usize = 48;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The chip sent a malformed response.
+    Protocol,
+    /// The chip answered with a status word other than 9000.
+    Status(StatusWord),
+    /// A response or a file doesn't fit in the buffers.
+    TooBig,
+    /// The MRZ key or CAN has invalid characters.
+    InvalidKey,
+    /// The chip rejected the MRZ key or CAN, or its authentication failed.
+    AuthFailed,
+    /// The [`PaceInfo`] has domain parameters we don't support.
+    UnsupportedParameters,
+    /// A protected response had a wrong MAC, or the chip closed the secure messaging
+    /// session. Authenticate again to go on.
+    SecureMessaging,
+}
+
+impl<E> From<apdu::Error<E>> for Error<E> {
+    fn from(e: apdu::Error<E>) -> Self {
+        match e {
+            apdu::Error::Lower(e) => Self::Lower(e),
+            apdu::Error::Protocol => Self::Protocol,
+            apdu::Error::Status(sw) => Self::Status(sw),
+            apdu::Error::TooBig => Self::TooBig,
+        }
+    }
+}
+
+/// The MRZ fields used as access key: document number, date of birth and date of expiry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MrzKey<'a> {
+    /// Document number, without check digit. Shorter ones are padded with `<`.
+    pub document_number: &'a str,
+    /// Date of birth, YYMMDD.
+    pub date_of_birth: &'a str,
+    /// Date of expiry, YYMMDD.
+    pub date_of_expiry: &'a str,
+}
+
+impl MrzKey<'_> {
+    /// The MRZ information the keys are derived from: each field followed by its check
+    /// digit, the document number padded to 9 characters.
+    pub fn mrz_info(&self) -> Option<Vec<u8, MAX_MRZ_INFO_LEN>> {
+        let mut info = Vec::new();
+        info.extend_from_slice(self.document_number.as_bytes()).ok()?;
+        while info.len() < 9 {
+            info.push(b'<').ok()?;
+        }
+        info.push(check_digit(&info)?).ok()?;
+
+        for date in [self.date_of_birth, self.date_of_expiry] {
+            if date.len() != 6 || !date.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            info.extend_from_slice(date.as_bytes()).ok()?;
+            info.push(check_digit(date.as_bytes())?).ok()?;
+        }
+        Some(info)
+    }
+}
+
+/// ICAO 9303-3 check digit of an MRZ field, as an ASCII digit.
+pub fn check_digit(field: &[u8]) -> Option<u8> {
+    let mut sum = 0u32;
+    for (i, &c) in field.iter().enumerate() {
+        let v = match c {
+            b'0'..=b'9' => c - b'0',
+            b'A'..=b'Z' => c - b'A' + 10,
+            b'<' => 0,
+            _ => return None,
+        };
+        sum += v as u32 * [7, 3, 1][i % 3];
+    }
+    Some(b'0' + (sum % 10) as u8)
+}
+
+/// PACE password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Password<'a> {
+    Mrz(&'a MrzKey<'a>),
+    /// Card access number, printed on ID cards.
+    Can(&'a str),
+}
+
+impl Password<'_> {
+    fn reference(&self) -> u8 {
+        match self {
+            Self::Mrz(_) => 0x01,
+            Self::Can(_) => 0x02,
+        }
+    }
+
+    /// K_π, the key encrypting the PACE nonce.
+    fn key(&self) -> Option<[u8; 16]> {
+        match self {
+            Self::Mrz(key) => Some(kdf(&Sha1::digest(key.mrz_info()?), KDF_PACE)),
+            Self::Can(can) => {
+                if can.is_empty() || !can.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                Some(kdf(can.as_bytes(), KDF_PACE))
+            }
+        }
+    }
+}
+
+/// An eMRTD chip.
+pub struct Emrtd<T: Reader> {
+    reader: T,
+    sm: Option<SecureMessaging>,
+}
+
+impl<T: Reader> Emrtd<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader, sm: None }
+    }
+
+    /// Get the underlying ISO-DEP reader, for sending other commands. These bypass secure
+    /// messaging.
+    pub fn reader(&mut self) -> &mut T {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    /// Whether commands are sent with secure messaging, after BAC or PACE.
+    pub fn is_secured(&self) -> bool {
+        self.sm.is_some()
+    }
+
+    /// Read EF.CardAccess from the master file, returning the PACE protocol to use, or
+    /// `None` if the chip doesn't support PACE, or none of the protocols we do.
+    pub async fn read_card_access(&mut self) -> Result<Option<PaceInfo>, Error<T::Error>> {
+        let mut rx = [0; RX_BUF_LEN];
+        let cmd = Command::new(CLA, INS_READ_BINARY, 0x80 | SFI_CARD_ACCESS, 0x00).with_le(apdu::MAX_SHORT_LE);
+        match self.command(&cmd, &mut rx).await {
+            Ok(data) => Ok(PaceInfo::parse(data)),
+            Err(Error::Status(StatusWord::FILE_NOT_FOUND)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Select the LDS1 eMRTD application.
+    pub async fn select_application(&mut self) -> Result<(), Error<T::Error>> {
+        let mut rx = [0; RX_BUF_LEN];
+        let cmd = Command::new(CLA, INS_SELECT, 0x04, 0x0C).with_data(LDS1_AID);
+        self.command(&cmd, &mut rx).await?;
+        Ok(())
+    }
+
+    /// Select an elementary file of the current application.
+    pub async fn select_file(&mut self, fid: u16) -> Result<(), Error<T::Error>> {
+        let mut rx = [0; RX_BUF_LEN];
+        let fid = fid.to_be_bytes();
+        let cmd = Command::new(CLA, INS_SELECT, 0x02, 0x0C).with_data(&fid);
+        self.command(&cmd, &mut rx).await?;
+        Ok(())
+    }
+
+    /// Basic Access Control, with the LDS1 application selected.
+    pub async fn bac(&mut self, rng: &mut impl CryptoRngCore, key: &MrzKey<'_>) -> Result<(), Error<T::Error>> {
+        self.sm = None;
+        let info = key.mrz_info().ok_or(Error::InvalidKey)?;
+        let seed = bac_seed(&info);
+        let k_enc = Cipher::new(&Key::TDes2(kdf(&seed, KDF_ENC)));
+        let k_mac = kdf(&seed, KDF_MAC);
+
+        let mut rx = [0; RX_BUF_LEN];
+        let cmd = Command::new(CLA, INS_GET_CHALLENGE, 0x00, 0x00).with_le(8);
+        let rnd_ic: [u8; 8] = self.command(&cmd, &mut rx).await?.try_into().map_err(|_| Error::Protocol)?;
+
+        let mut rnd_ifd = [0; 8];
+        let mut k_ifd = [0; 16];
+        rng.fill_bytes(&mut rnd_ifd);
+        rng.fill_bytes(&mut k_ifd);
+
+        let mut data = [0; 40];
+        data[..8].copy_from_slice(&rnd_ifd);
+        data[8..16].copy_from_slice(&rnd_ic);
+        data[16..32].copy_from_slice(&k_ifd);
+        k_enc.cbc_encrypt(&mut [0; 8], &mut data[..32]);
+        let mac = bac_mac(&k_mac, &data[..32]);
+        data[32..].copy_from_slice(&mac);
+
+        let cmd = Command::new(CLA, INS_EXTERNAL_AUTHENTICATE, 0x00, 0x00)
+            .with_data(&data)
+            .with_le(data.len());
+        let resp = match self.command(&cmd, &mut rx).await {
+            Err(Error::Status(SW_AUTH_FAILED)) => return Err(Error::AuthFailed),
+            r => r?,
+        };
+        if resp.len() != 40 || bac_mac(&k_mac, &resp[..32]) != resp[32..] {
+            warn!("BAC response has a wrong MAC");
+            return Err(Error::AuthFailed);
+        }
+        let mut plain = [0; 32];
+        plain.copy_from_slice(&resp[..32]);
+        k_enc.cbc_decrypt(&mut [0; 8], &mut plain);
+        if plain[..8] != rnd_ic || plain[8..16] != rnd_ifd {
+            warn!("BAC response has wrong nonces");
+            return Err(Error::AuthFailed);
+        }
+
+        let mut seed = k_ifd;
+        xor(&mut seed, &plain[16..]);
+        let mut ssc = [0; 8];
+        ssc[..4].copy_from_slice(&rnd_ic[4..]);
+        ssc[4..].copy_from_slice(&rnd_ifd[4..]);
+        self.sm = Some(SecureMessaging::tdes(&kdf(&seed, KDF_ENC), &kdf(&seed, KDF_MAC), ssc));
+        debug!("BAC done");
+        Ok(())
+    }
+
+    /// PACE with ECDH generic mapping, before selecting the LDS1 application.
+    pub async fn pace(
+        &mut self,
+        rng: &mut impl CryptoRngCore,
+        password: &Password<'_>,
+        info: &PaceInfo,
+    ) -> Result<(), Error<T::Error>> {
+        self.sm = None;
+        let k_pi = password.key().ok_or(Error::InvalidKey)?;
+        let Some(curve) = info.curve() else {
+            warn!("unsupported PACE domain parameters {}", info.parameter_id);
+            return Err(Error::UnsupportedParameters);
+        };
+        let g = curve.generator();
+        let oid = info.oid();
+
+        let mut mse = tlv::Encoder::<18>::new();
+        unwrap!(mse.push(TAG_CRYPTOGRAPHIC_MECHANISM, &oid));
+        unwrap!(mse.push(TAG_PASSWORD_REFERENCE, &[password.reference()]));
+        unwrap!(mse.push(TAG_DOMAIN_PARAMETERS, &[info.parameter_id]));
+        let mut rx = [0; RX_BUF_LEN];
+        let cmd = Command::new(CLA, INS_MSE, 0xC1, 0xA4).with_data(mse.as_bytes());
+        self.command(&cmd, &mut rx).await?;
+
+        // Decrypt the nonce.
+        let z = self.general_authenticate(true, None, TAG_ENCRYPTED_NONCE, &mut rx).await?;
+        let (cipher, block_size) = match info.cipher {
+            PaceCipher::TDes => (Cipher::new(&Key::TDes2(k_pi)), 8),
+            PaceCipher::Aes128 => (Cipher::aes(&k_pi), 16),
+        };
+        if z.len() != block_size {
+            warn!("PACE nonce has a wrong length");
+            return Err(Error::Protocol);
+        }
+        let mut s = [0; ec::LEN];
+        let nonce = &mut s[ec::LEN - block_size..];
+        nonce.copy_from_slice(z);
+        cipher.cbc_decrypt(&mut [0; 16][..block_size], nonce);
+
+        // Map the nonce to a new generator: G~ = s * G + H, H being the ECDH shared point.
+        let sk_map = curve.random_scalar(rng);
+        let pk_map = unwrap!(curve.mul(&sk_map, &g));
+        let data = self
+            .general_authenticate(
+                true,
+                Some((TAG_MAPPING_DATA_PCD, &pk_map.encode())),
+                TAG_MAPPING_DATA_PICC,
+                &mut rx,
+            )
+            .await?;
+        let pk_map_picc = curve.decode(data).ok_or(Error::Protocol)?;
+        if pk_map_picc == pk_map {
+            return Err(Error::Protocol);
+        }
+        let h = curve.mul(&sk_map, &pk_map_picc).ok_or(Error::Protocol)?;
+        let mapped = curve.mul(&s, &g).and_then(|sg| curve.add(&sg, &h)).ok_or(Error::Protocol)?;
+
+        // Key agreement on the new generator.
+        let sk = curve.random_scalar(rng);
+        let pk = curve.mul(&sk, &mapped).ok_or(Error::Protocol)?;
+        let data = self
+            .general_authenticate(
+                true,
+                Some((TAG_EPHEMERAL_KEY_PCD, &pk.encode())),
+                TAG_EPHEMERAL_KEY_PICC,
+                &mut rx,
+            )
+            .await?;
+        let pk_picc = curve.decode(data).ok_or(Error::Protocol)?;
+        if pk_picc == pk {
+            return Err(Error::Protocol);
+        }
+        let secret = curve.mul(&sk, &pk_picc).ok_or(Error::Protocol)?.x();
+
+        let ks_enc = kdf(&secret, KDF_ENC);
+        let ks_mac = kdf(&secret, KDF_MAC);
+        let sm = match info.cipher {
+            PaceCipher::TDes => SecureMessaging::tdes(&ks_enc, &ks_mac, [0; 8]),
+            PaceCipher::Aes128 => SecureMessaging::aes(&ks_enc, &ks_mac),
+        };
+
+        // Mutual authentication, each side MACing the other's ephemeral key.
+        let token = sm.token(&public_key_data(&oid, &pk_picc)?).ok_or(Error::Protocol)?;
+        let expected = sm.token(&public_key_data(&oid, &pk)?).ok_or(Error::Protocol)?;
+        let token_picc = match self
+            .general_authenticate(false, Some((TAG_TOKEN_PCD, &token)), TAG_TOKEN_PICC, &mut rx)
+            .await
+        {
+            Err(Error::Status(sw)) if sw.sw1() == 0x63 => return Err(Error::AuthFailed),
+            r => r?,
+        };
+        if token_picc != expected {
+            warn!("PACE token of the chip is wrong");
+            return Err(Error::AuthFailed);
+        }
+
+        self.sm = Some(sm);
+        debug!("PACE done");
+        Ok(())
+    }
+
+    /// Read a whole elementary file of the current application into `buf`, returning its
+    /// length.
+    ///
+    /// All LDS files are a single data object: the tag and length in the first bytes give
+    /// the file size.
+    pub async fn read_file(&mut self, fid: u16, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        self.select_file(fid).await?;
+
+        let mut header = [0; FILE_HEADER_LEN];
+        let n = self.read_binary(0, &mut header).await?;
+        let (_, len, header_len) = tlv::parse_header(&header[..n]).map_err(|_| Error::Protocol)?;
+        let total = header_len + len;
+        let buf = buf.get_mut(..total).ok_or(Error::TooBig)?;
+
+        let mut offset = n.min(total);
+        buf[..offset].copy_from_slice(&header[..offset]);
+        while offset < total {
+            let end = total.min(offset + READ_CHUNK_LEN);
+            let n = self.read_binary(offset, &mut buf[offset..end]).await?;
+            if n == 0 {
+                warn!("READ BINARY returned no data");
+                return Err(Error::Protocol);
+            }
+            offset += n;
+        }
+        Ok(total)
+    }
+
+    /// READ BINARY of the current file at `offset`, up to `buf.len()` bytes.
+    async fn read_binary(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        let mut rx = [0; RX_BUF_LEN];
+        let data = if offset <= MAX_SHORT_OFFSET {
+            let cmd = Command::new(CLA, INS_READ_BINARY, (offset >> 8) as u8, offset as u8).with_le(buf.len());
+            self.command(&cmd, &mut rx).await?
+        } else {
+            // Bigger offsets go in a data object, and so does the response.
+            let offset = (offset as u32).to_be_bytes();
+            let skip = offset.iter().take_while(|&&b| b == 0).count();
+            let mut data = tlv::Encoder::<6>::new();
+            unwrap!(data.push(TAG_OFFSET, &offset[skip..]));
+            let cmd = Command::new(CLA, INS_READ_BINARY_ODD, 0x00, 0x00)
+                .with_data(data.as_bytes())
+                .with_le(buf.len() + 3);
+            let resp = self.command(&cmd, &mut rx).await?;
+            tlv::find(resp, TAG_DISCRETIONARY_DATA).ok_or(Error::Protocol)?.value
+        };
+        let buf = buf.get_mut(..data.len()).ok_or(Error::Protocol)?;
+        buf.copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    /// GENERAL AUTHENTICATE of PACE, returning the data object `expected` of the response.
+    async fn general_authenticate<'r>(
+        &mut self,
+        chained: bool,
+        data: Option<(Tag, &[u8])>,
+        expected: Tag,
+        rx: &'r mut [u8],
+    ) -> Result<&'r [u8], Error<T::Error>> {
+        let mut enc = tlv::Encoder::<{ 4 + 3 + ec::POINT_LEN }>::new();
+        unwrap!(enc.push_with(TAG_DYNAMIC_AUTH_DATA, |e| match data {
+            Some((tag, value)) => e.push(tag, value),
+            None => Ok(()),
+        }));
+        let cla = match chained {
+            true => CLA | apdu::CLA_CHAINING,
+            false => CLA,
+        };
+        let cmd = Command::new(cla, INS_GENERAL_AUTHENTICATE, 0x00, 0x00)
+            .with_data(enc.as_bytes())
+            .with_le(apdu::MAX_SHORT_LE);
+        let resp = self.command(&cmd, rx).await?;
+        match tlv::find_path(resp, &[TAG_DYNAMIC_AUTH_DATA, expected]) {
+            Some(t) => Ok(t.value),
+            None => {
+                warn!("GENERAL AUTHENTICATE response without {:x}", expected.0);
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Send a command, with secure messaging if set up, and check the status word is 9000.
+    async fn command<'r>(&mut self, cmd: &Command<'_>, rx: &'r mut [u8]) -> Result<&'r [u8], Error<T::Error>> {
+        Ok(self.transceive(cmd, rx).await?.ok()?)
+    }
+
+    /// Send a command, with secure messaging if set up.
+    async fn transceive<'r>(&mut self, cmd: &Command<'_>, rx: &'r mut [u8]) -> Result<Response<'r>, Error<T::Error>> {
+        let Some(sm) = &mut self.sm else {
+            return Ok(apdu::transceive(&mut self.reader, cmd, rx).await?);
+        };
+
+        let (cla, data) = sm.protect(cmd).ok_or(Error::TooBig)?;
+        let protected = Command::new(cla, cmd.ins, cmd.p1, cmd.p2)
+            .with_data(data.as_bytes())
+            .with_le(apdu::MAX_SHORT_LE);
+        let mut raw = [0; RX_BUF_LEN];
+        let resp = apdu::transceive(&mut self.reader, &protected, &mut raw).await?;
+
+        if resp.data.is_empty() {
+            // Plain status words only come when the chip dropped the session.
+            debug!("unprotected response {:04x}, secure messaging closed", resp.sw.0);
+            self.sm = None;
+            return Err(match resp.sw {
+                SW_SM_MISSING | SW_SM_INCORRECT => Error::SecureMessaging,
+                sw if sw.is_ok() => Error::Protocol,
+                sw => Error::Status(sw),
+            });
+        }
+        match sm.unprotect(resp.data, rx) {
+            Some((n, sw)) => Ok(Response { data: &rx[..n], sw }),
+            None => {
+                self.sm = None;
+                Err(Error::SecureMessaging)
+            }
+        }
+    }
+}
+
+/// K_seed of BAC: the first 16 bytes of the SHA-1 of the MRZ information.
+fn bac_seed(mrz_info: &[u8]) -> [u8; 16] {
+    unwrap!(Sha1::digest(mrz_info)[..16].try_into())
+}
+
+/// Retail MAC of BAC authentication data.
+fn bac_mac(key: &[u8; 16], data: &[u8]) -> [u8; sm::MAC_LEN] {
+    let mut buf = Vec::<u8, 40>::new();
+    unwrap!(buf.extend_from_slice(data));
+    unwrap!(crypto::pad(&mut buf, 8));
+    sm::retail_mac(key, &buf)
+}
+
+/// Public key data object authenticated by the PACE tokens.
+fn public_key_data<E>(oid: &[u8], key: &ec::Point) -> Result<Vec<u8, 160>, Error<E>> {
+    let mut enc = tlv::Encoder::<160>::new();
+    enc.push_with(TAG_PUBLIC_KEY, |e| {
+        e.push(TAG_PUBLIC_KEY_OID, oid)?;
+        e.push(TAG_PUBLIC_POINT, &key.encode())
+    })
+    .map_err(|_| Error::TooBig)?;
+    Ok(enc.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::test_util::{ScriptedRng, mock};
+
+    const BAC_KEY: MrzKey<'static> = MrzKey {
+        document_number: "L898902C",
+        date_of_birth: "690806",
+        date_of_expiry: "940623",
+    };
+
+    #[test]
+    fn test_mrz_info() {
+        // ICAO 9303-11 appendices D.2 and G.1.
+        assert_eq!(BAC_KEY.mrz_info().unwrap(), b"L898902C<369080619406236");
+        let key = MrzKey {
+            document_number: "T22000129",
+            date_of_birth: "640812",
+            date_of_expiry: "101031",
+        };
+        assert_eq!(key.mrz_info().unwrap(), b"T22000129364081251010318");
+
+        let key = MrzKey {
+            date_of_birth: "6908O6",
+            ..BAC_KEY
+        };
+        assert_eq!(key.mrz_info(), None);
+        assert_eq!(check_digit(b"l898902c"), None);
+    }
+
+    #[tokio::test]
+    async fn test_bac() {
+        // ICAO 9303-11 appendix D: BAC, then reading EF.COM.
+        let mut doc = Emrtd::new(mock!(
+            "00A4040C07A0000002471001" => "9000",
+            "0084000008" => "4608F919887022129000",
+            "008200002872C29C2371CC9BDB65B779B8E8D37B29ECC154AA56A8799FAE2F498F76ED92F25F1448EEA8AD90A728"
+                => "46B9342A41396CD7386BF5803104D7CEDC122B9132139BAF2EEDC94EE178534F2F2D235D074D74499000",
+            "0CA4020C158709016375432908C044F68E08BF8B92D635FF24F800" => "990290008E08FA855A5D4C50A8ED9000",
+            "0CB000000D9701048E08ED6705417E96BA5500" => "8709019FF0EC34F9922651990290008E08AD55CC17140B2DED9000",
+            "0CB000040D9701128E082EA28A70F3C7B53500"
+                => "871901FB9235F4E4037F2327DCC8964F1F9B8C30F42C8E2FFF224A990290008E08C8B2787EAEA07D749000",
+        ));
+        let mut rng = ScriptedRng(hex!("781723860C06C226 0B795240CB7049B01C19B33E32804F0B").to_vec());
+
+        doc.select_application().await.unwrap();
+        doc.bac(&mut rng, &BAC_KEY).await.unwrap();
+        assert!(doc.is_secured());
+
+        let mut buf = [0; 64];
+        let n = doc.read_file(EF_COM, &mut buf).await.unwrap();
+        assert_eq!(buf[..n], hex!("60145F0104303130365F36063034303030305C026175"));
+        let com = lds::Com::parse(&buf[..n]).unwrap();
+        assert_eq!(com.data_groups, [1, 2]);
+        doc.reader().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_bac_wrong_key() {
+        let mut doc = Emrtd::new(mock!(
+            "0084000008" => "4608F919887022129000",
+            "008200002872C29C2371CC9BDB65B779B8E8D37B29ECC154AA56A8799FAE2F498F76ED92F25F1448EEA8AD90A728" => "6300",
+        ));
+        let mut rng = ScriptedRng(hex!("781723860C06C226 0B795240CB7049B01C19B33E32804F0B").to_vec());
+        assert!(matches!(doc.bac(&mut rng, &BAC_KEY).await, Err(Error::AuthFailed)));
+        assert!(!doc.is_secured());
+        doc.reader().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_pace() {
+        // ICAO 9303-11 appendix G.1: PACE ECDH-GM-AES-128 on brainpoolP256r1, then reading
+        // DG1 with AES secure messaging.
+        let mut doc = Emrtd::new(mock!(
+            "00B09C0000" => "3114 3012 060A04007F00070202040202 020102 02010D 9000",
+            "0022C1A412 800A04007F00070202040202 830101 84010D" => "9000",
+            "10860000027C0000" => "7C12801095A3A016522EE98D01E76CB6B98B42C39000",
+            "10860000457C438141047ACF3EFC982EC45565A4B155129EFBC74650DCBFA6362D896FC70262E0C2CC5E544552DCB6725218799115B55C9BAA6D9F6BC3A9618E70C25AF71777A9C4922D00"
+                => "7C43824104824FBA91C9CBE26BEF53A0EBE7342A3BF178CEA9F45DE0B70AA601651FBA3F5730D8C879AAA9C9F73991E61B58F4D52EB87A0A0C709A49DC63719363CCD13C549000",
+            "10860000457C438341042DB7A64C0355044EC9DF190514C625CBA2CEA48754887122F3A5EF0D5EDD301C3556F3B3B186DF10B857B58F6A7EB80F20BA5DC7BE1D43D9BF850149FBB3646200"
+                => "7C438441049E880F842905B8B3181F7AF7CAA9F0EFB743847F44A306D2D28C1D9EC65DF6DB7764B22277A2EDDC3C265A9F018F9CB852E111B768B326904B59A0193776F0949000",
+            "008600000C7C0A8508C2B0BD78D94BA86600" => "7C0A86083ABB9674BCE93C089000",
+            "0CA4040C1D871101752F676B09FAC86A87D632749A49C7CC8E08C18BA1FCE707BD9F00" => "990290008E08BEA7B381C494A0799000",
+            "0CA4020C1D871101E6214A838D40B539C090DCFF7F0DFB0F8E080A90AA4B8941D06400" => "990290008E08E00BFFE5473D41409000",
+            "0CB000000D9701048E085F466809FF5A7CFA00"
+                => "87110198A20705A4A2BF6238C3398C026863D2990290008E087D4175C1A998AF679000",
+            "0CB000040D9701598E08BACCD26B7C3D0FCB00"
+                => "876101963ADB589A655F72D97D6874ACE1529E264F1870235A80CEB63372A74BB77BBE4FA57AD0FB5D260DEDAAA80D28659BCD7F9F5C4FA157C28380A02F3990FD99AB4E2E6C7F52CD6ACAE4A913B25154A904ED0C82AA1019563E13F400A56580BDEF990290008E08B7BFAB63668D4A949000",
+            // A response with a wrong MAC.
+            "0CA4020C1D8711010EEF00A7A4EDA9EEC86146534A1769F58E08A3C8C818CE1F4D6A00" => "990290008E087CC772514766A76E9000",
+        ));
+        let mut rng = ScriptedRng(
+            hex!(
+                "7F4EF07B9EA82FD78AD689B38D0BC78CF21F249D953BC46F4C6E19259C010F99"
+                "A73FB703AC1436A18E0CFA5ABB3F7BEC7A070E7A6788486BEE230C4A22762595"
+            )
+            .to_vec(),
+        );
+        let key = MrzKey {
+            document_number: "T22000129",
+            date_of_birth: "640812",
+            date_of_expiry: "101031",
+        };
+
+        let info = doc.read_card_access().await.unwrap().unwrap();
+        doc.pace(&mut rng, &Password::Mrz(&key), &info).await.unwrap();
+        doc.select_application().await.unwrap();
+
+        let mut buf = [0; 128];
+        let n = doc.read_file(DG1, &mut buf).await.unwrap();
+        assert_eq!(
+            lds::parse_dg1(&buf[..n]).unwrap(),
+            "P<UTOERIKSSON<<ANNA<MARIA<<<<<<<<<<<<<<<<<<<L898902C36UTO7408122F1204159ZE184226B<<<<<10"
+        );
+
+        assert!(matches!(doc.select_file(DG2).await, Err(Error::SecureMessaging)));
+        assert!(!doc.is_secured());
+        doc.reader().assert_done();
+
+        let info = PaceInfo {
+            cipher: PaceCipher::Aes128,
+            parameter_id: 0x0E,
+        };
+        assert!(matches!(
+            doc.pace(&mut rng, &Password::Mrz(&key), &info).await,
+            Err(Error::UnsupportedParameters)
+        ));
+    }
+}
@@ -0,0 +1,319 @@
+//! Elliptic curve arithmetic on 256-bit short Weierstrass curves, for PACE.
+//!
+//! PACE generic mapping needs point addition with a generator computed at runtime, which
+//! fixed-curve crates don't offer, and brainpool curves which most documents use. Points
+//! are kept in projective coordinates during scalar multiplication, so there's a single
+//! inversion at the end.
+//!
+//! Scalar multiplication must not leak the scalar through its timing: in generic mapping
+//! one of them is the nonce, which an eavesdropper could use to test password guesses
+//! against the encrypted nonce. It's a Montgomery ladder with conditional swaps, over the
+//! complete addition formulas of Renes, Costello and Batina (2016), which have no special
+//! cases for doubling or the point at infinity.
+
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+use crypto_bigint::subtle::{Choice, ConditionallySelectable};
+use crypto_bigint::{Encoding, U256};
+use rand_core::CryptoRngCore;
+
+type Fe = DynResidue<{ U256::LIMBS }>;
+
+/// Length of a field element or scalar.
+pub const LEN: usize = 32;
+/// Length of an uncompressed point: `04 || x || y`.
+pub const POINT_LEN: usize = 1 + 2 * LEN;
+
+/// Curve domain parameters.
+pub struct Curve {
+    p: U256,
+    a: U256,
+    b: U256,
+    gx: U256,
+    gy: U256,
+    n: U256,
+}
+
+/// brainpoolP256r1, RFC 5639.
+pub const BRAINPOOL_P256R1: Curve = Curve {
+    p: U256::from_be_hex("A9FB57DBA1EEA9BC3E660A909D838D726E3BF623D52620282013481D1F6E5377"),
+    a: U256::from_be_hex("7D5A0975FC2C3057EEF67530417AFFE7FB8055C126DC5C6CE94A4B44F330B5D9"),
+    b: U256::from_be_hex("26DC5C6CE94A4B44F330B5D9BBD77CBF958416295CF7E1CE6BCCDC18FF8C07B6"),
+    gx: U256::from_be_hex("8BD2AEB9CB7E57CB2C4B482FFC81B7AFB9DE27E1E3BD23C23A4453BD9ACE3262"),
+    gy: U256::from_be_hex("547EF835C3DAC4FD97F8461A14611DC9C27745132DED8E545C1D54C72F046997"),
+    n: U256::from_be_hex("A9FB57DBA1EEA9BC3E660A909D838D718C397AA3B561A6F7901E0E82974856A7"),
+};
+
+/// NIST P-256, FIPS 186-4.
+pub const NIST_P256: Curve = Curve {
+    p: U256::from_be_hex("FFFFFFFF00000001000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFF"),
+    a: U256::from_be_hex("FFFFFFFF00000001000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFC"),
+    b: U256::from_be_hex("5AC635D8AA3A93E7B3EBBD55769886BC651D06B0CC53B0F63BCE3C3E27D2604B"),
+    gx: U256::from_be_hex("6B17D1F2E12C4247F8BCE6E563A440F277037D812DEB33A0F4A13945D898C296"),
+    gy: U256::from_be_hex("4FE342E2FE1A7F9B8EE7EB4A7C0F9E162BCE33576B315ECECBB6406837BF51F5"),
+    n: U256::from_be_hex("FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551"),
+};
+
+/// An affine point other than the point at infinity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    x: U256,
+    y: U256,
+}
+
+impl Point {
+    /// The x coordinate, the shared secret of ECDH.
+    pub fn x(&self) -> [u8; LEN] {
+        self.x.to_be_bytes()
+    }
+
+    /// Uncompressed encoding.
+    pub fn encode(&self) -> [u8; POINT_LEN] {
+        let mut buf = [0; POINT_LEN];
+        buf[0] = 0x04;
+        buf[1..][..LEN].copy_from_slice(&self.x.to_be_bytes());
+        buf[1 + LEN..].copy_from_slice(&self.y.to_be_bytes());
+        buf
+    }
+}
+
+/// A point in projective coordinates, (X/Z, Y/Z). (0, 1, 0) is the point at infinity.
+#[derive(Clone, Copy)]
+struct Projective {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+}
+
+impl Projective {
+    fn conditional_swap(a: &mut Self, b: &mut Self, choice: Choice) {
+        Fe::conditional_swap(&mut a.x, &mut b.x, choice);
+        Fe::conditional_swap(&mut a.y, &mut b.y, choice);
+        Fe::conditional_swap(&mut a.z, &mut b.z, choice);
+    }
+}
+
+impl Curve {
+    pub fn generator(&self) -> Point {
+        Point { x: self.gx, y: self.gy }
+    }
+
+    /// Decode an uncompressed point, checking it's on the curve.
+    pub fn decode(&self, data: &[u8]) -> Option<Point> {
+        if data.len() != POINT_LEN || data[0] != 0x04 {
+            return None;
+        }
+        let x = U256::from_be_slice(&data[1..][..LEN]);
+        let y = U256::from_be_slice(&data[1 + LEN..]);
+        if x >= self.p || y >= self.p {
+            return None;
+        }
+        let f = Field::new(self);
+        let (fx, fy) = (Fe::new(&x, f.params), Fe::new(&y, f.params));
+        let rhs = fx.square() * fx + f.a * fx + Fe::new(&self.b, f.params);
+        (fy.square() == rhs).then_some(Point { x, y })
+    }
+
+    /// Generate a random scalar in 1..n.
+    pub fn random_scalar(&self, rng: &mut impl CryptoRngCore) -> [u8; LEN] {
+        loop {
+            let mut k = [0; LEN];
+            rng.fill_bytes(&mut k);
+            if self.is_scalar(&k) {
+                return k;
+            }
+        }
+    }
+
+    fn is_scalar(&self, k: &[u8; LEN]) -> bool {
+        let k = U256::from_be_bytes(*k);
+        k != U256::ZERO && k < self.n
+    }
+
+    /// `p + q`, or `None` for the point at infinity.
+    pub fn add(&self, p: &Point, q: &Point) -> Option<Point> {
+        let f = Field::new(self);
+        f.to_affine(&f.add(&f.to_projective(p), &f.to_projective(q)))
+    }
+
+    /// `k * p`, or `None` for the point at infinity. Constant time in `k`.
+    pub fn mul(&self, k: &[u8; LEN], p: &Point) -> Option<Point> {
+        let f = Field::new(self);
+        // Invariant: r1 = r0 + p.
+        let mut r0 = f.infinity();
+        let mut r1 = f.to_projective(p);
+        for byte in k {
+            for bit in (0..8).rev() {
+                let choice = Choice::from(byte >> bit & 1);
+                Projective::conditional_swap(&mut r0, &mut r1, choice);
+                r1 = f.add(&r0, &r1);
+                r0 = f.add(&r0, &r0);
+                Projective::conditional_swap(&mut r0, &mut r1, choice);
+            }
+        }
+        f.to_affine(&r0)
+    }
+}
+
+/// Field arithmetic context, to compute the Montgomery parameters once per operation.
+struct Field {
+    params: DynResidueParams<{ U256::LIMBS }>,
+    a: Fe,
+    /// 3b, for the complete addition formulas.
+    b3: Fe,
+}
+
+impl Field {
+    fn new(curve: &Curve) -> Self {
+        let params = DynResidueParams::new(&curve.p);
+        let b = Fe::new(&curve.b, params);
+        Self {
+            params,
+            a: Fe::new(&curve.a, params),
+            b3: b + b + b,
+        }
+    }
+
+    fn infinity(&self) -> Projective {
+        Projective {
+            x: Fe::zero(self.params),
+            y: Fe::one(self.params),
+            z: Fe::zero(self.params),
+        }
+    }
+
+    fn to_projective(&self, p: &Point) -> Projective {
+        Projective {
+            x: Fe::new(&p.x, self.params),
+            y: Fe::new(&p.y, self.params),
+            z: Fe::one(self.params),
+        }
+    }
+
+    fn to_affine(&self, p: &Projective) -> Option<Point> {
+        let (z_inv, ok) = p.z.invert();
+        if !bool::from(ok) {
+            return None;
+        }
+        Some(Point {
+            x: (p.x * z_inv).retrieve(),
+            y: (p.y * z_inv).retrieve(),
+        })
+    }
+
+    /// Complete addition for any `a`, Renes-Costello-Batina algorithm 1. Also doubles, and
+    /// handles the point at infinity, without branches.
+    fn add(&self, p: &Projective, q: &Projective) -> Projective {
+        let (a, b3) = (self.a, self.b3);
+        let t0 = p.x * q.x;
+        let t1 = p.y * q.y;
+        let t2 = p.z * q.z;
+        let t3 = (p.x + p.y) * (q.x + q.y) - (t0 + t1);
+        let t4 = (p.x + p.z) * (q.x + q.z) - (t0 + t2);
+        let t5 = (p.y + p.z) * (q.y + q.z) - (t1 + t2);
+        let z3 = a * t4 + b3 * t2;
+        let x3 = t1 - z3;
+        let z3 = t1 + z3;
+        let y3 = x3 * z3;
+        let t1 = t0 + t0 + t0 + a * t2;
+        let t2 = a * (t0 - a * t2);
+        let t4 = b3 * t4 + t2;
+        Projective {
+            x: t3 * x3 - t5 * t4,
+            y: y3 + t1 * t4,
+            z: t5 * z3 + t3 * t1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn point(curve: &Curve, x: [u8; 32], y: [u8; 32]) -> Point {
+        let mut buf = [0x04; POINT_LEN];
+        buf[1..33].copy_from_slice(&x);
+        buf[33..].copy_from_slice(&y);
+        curve.decode(&buf).unwrap()
+    }
+
+    #[test]
+    fn test_brainpool() {
+        // ICAO 9303-11 appendix G.1, PACE generic mapping.
+        let c = &BRAINPOOL_P256R1;
+        let g = c.generator();
+        let sk_map = hex!("7F4EF07B9EA82FD78AD689B38D0BC78CF21F249D953BC46F4C6E19259C010F99");
+        let pk_map = point(
+            c,
+            hex!("7ACF3EFC982EC45565A4B155129EFBC74650DCBFA6362D896FC70262E0C2CC5E"),
+            hex!("544552DCB6725218799115B55C9BAA6D9F6BC3A9618E70C25AF71777A9C4922D"),
+        );
+        assert_eq!(c.mul(&sk_map, &g), Some(pk_map));
+
+        let pk_map_picc = point(
+            c,
+            hex!("824FBA91C9CBE26BEF53A0EBE7342A3BF178CEA9F45DE0B70AA601651FBA3F57"),
+            hex!("30D8C879AAA9C9F73991E61B58F4D52EB87A0A0C709A49DC63719363CCD13C54"),
+        );
+        let h = c.mul(&sk_map, &pk_map_picc).unwrap();
+        assert_eq!(
+            h,
+            point(
+                c,
+                hex!("60332EF2450B5D247EF6D3868397D398852ED6E8CAF6FFEEF6BF85CA57057FD5"),
+                hex!("0840CA7415BAF3E43BD414D35AA4608B93A2CAF3A4E3EA4E82C9C13D03EB7181"),
+            )
+        );
+
+        let mut s = [0; 32];
+        s[16..].copy_from_slice(&hex!("3F00C4D39D153F2B2A214A078D899B22"));
+        let mapped = c.add(&c.mul(&s, &g).unwrap(), &h).unwrap();
+        assert_eq!(
+            mapped,
+            point(
+                c,
+                hex!("8CED63C91426D4F0EB1435E7CB1D74A46723A0AF21C89634F65A9AE87A9265E2"),
+                hex!("8C879506743F8611AC33645C5B985C80B5F09A0B83407C1B6A4D857AE76FE522"),
+            )
+        );
+    }
+
+    #[test]
+    fn test_p256() {
+        let c = &NIST_P256;
+        let g = c.generator();
+        let mut one = [0; 32];
+        one[31] = 1;
+        assert_eq!(c.mul(&one, &g), Some(g));
+
+        // n - 1 gives -G, and adding G to it gives the point at infinity.
+        let n_1 = hex!("FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632550");
+        let minus_g = c.mul(&n_1, &g).unwrap();
+        assert_eq!(minus_g.x(), g.x());
+        assert_eq!(c.add(&minus_g, &g), None);
+        assert!(!c.is_scalar(&hex!("FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551")));
+        assert!(!c.is_scalar(&[0; 32]));
+
+        // RFC 6979 appendix A.2.5 key pair.
+        let x = hex!("C9AFA9D845BA75166B5C215767B1D6934E50C3DB36E89B127B8A622B120F6721");
+        assert_eq!(
+            c.mul(&x, &g).unwrap().encode(),
+            hex!(
+                "04"
+                "60FED4BA255A9D31C961EB74C6356D68C049B8923B61FA6CE669622E60F29FB6"
+                "7903FE1008B8BC99A41AE9E95628BC64F2F1B20C2D7E9F5177A3C294D4462299"
+            )
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        let c = &BRAINPOOL_P256R1;
+        let mut enc = c.generator().encode();
+        assert_eq!(c.decode(&enc), Some(c.generator()));
+        enc[64] ^= 1;
+        assert_eq!(c.decode(&enc), None);
+        assert_eq!(c.decode(&enc[..64]), None);
+    }
+}
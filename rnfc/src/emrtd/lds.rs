@@ -0,0 +1,309 @@
+//! Parsing of LDS1 files: EF.CardAccess, EF.COM, DG1 and DG2.
+
+use heapless::{String, Vec};
+
+use super::ec::{self, Curve};
+use crate::tlv::{self, Tag};
+
+const TAG_SET: Tag = Tag(0x31);
+const TAG_SEQUENCE: Tag = Tag(0x30);
+const TAG_OID: Tag = Tag(0x06);
+const TAG_INTEGER: Tag = Tag(0x02);
+
+const TAG_COM: Tag = Tag(0x60);
+const TAG_LDS_VERSION: Tag = Tag(0x5F01);
+const TAG_UNICODE_VERSION: Tag = Tag(0x5F36);
+const TAG_TAG_LIST: Tag = Tag(0x5C);
+
+const TAG_DG1: Tag = Tag(0x61);
+const TAG_MRZ: Tag = Tag(0x5F1F);
+
+const TAG_DG2: Tag = Tag(0x75);
+const TAG_BIOMETRIC_GROUP: Tag = Tag(0x7F61);
+const TAG_BIOMETRIC_TEMPLATE: Tag = Tag(0x7F60);
+const TAG_BIOMETRIC_DATA: Tag = Tag(0x5F2E);
+
+/// id-PACE-ECDH-GM, BSI TR-03110-3: followed by one byte for the cipher.
+const OID_PACE_ECDH_GM: [u8; 9] = [0x04, 0x00, 0x7F, 0x00, 0x07, 0x02, 0x02, 0x04, 0x02];
+
+/// Standardized domain parameters, BSI TR-03110-3 table 4.
+const PARAM_NIST_P256: u8 = 12;
+const PARAM_BRAINPOOL_P256R1: u8 = 13;
+
+/// Tags of the data groups, in data group order: EF.COM lists these.
+const DATA_GROUP_TAGS: [u8; 16] = [
+    0x61, 0x75, 0x63, 0x76, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x6B, 0x6C, 0x6D, 0x6E, 0x6F, 0x70,
+];
+
+/// Length of the MRZ of a TD3 document, the longest.
+pub const MAX_MRZ_LEN: usize = 90;
+
+/// Cipher and MAC of a PACE protocol, which also set the secure messaging algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PaceCipher {
+    /// 3DES in CBC mode, retail MAC.
+    TDes,
+    /// AES-128 in CBC mode, CMAC.
+    Aes128,
+}
+
+/// A PACE protocol supported by the chip, from EF.CardAccess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PaceInfo {
+    pub cipher: PaceCipher,
+    /// Standardized domain parameters ID.
+    pub parameter_id: u8,
+}
+
+impl PaceInfo {
+    /// Parse EF.CardAccess, returning the first PaceInfo we support: ECDH generic mapping,
+    /// 3DES or AES-128, on P-256 or brainpoolP256r1.
+    pub fn parse(card_access: &[u8]) -> Option<Self> {
+        let set = tlv::find(card_access, TAG_SET)?;
+        set.children()
+            .map_while(Result::ok)
+            .filter(|t| t.tag == TAG_SEQUENCE)
+            .find_map(|info| Self::parse_one(&info))
+    }
+
+    fn parse_one(info: &tlv::Tlv<'_>) -> Option<Self> {
+        let mut it = info.children().map_while(Result::ok);
+        let oid = it.next().filter(|t| t.tag == TAG_OID)?.value;
+        let version = it.next().filter(|t| t.tag == TAG_INTEGER)?.value;
+        // The parameter ID is optional in general, but always there for standardized
+        // domain parameters.
+        let parameter_id = it.next().filter(|t| t.tag == TAG_INTEGER)?.value;
+
+        let (&last, prefix) = oid.split_last()?;
+        if prefix != OID_PACE_ECDH_GM || version != [0x02] {
+            return None;
+        }
+        let cipher = match last {
+            0x01 => PaceCipher::TDes,
+            0x02 => PaceCipher::Aes128,
+            _ => return None,
+        };
+        let &[parameter_id] = parameter_id else {
+            return None;
+        };
+        let info = Self { cipher, parameter_id };
+        info.curve()?;
+        Some(info)
+    }
+
+    /// Protocol object identifier, without tag and length.
+    pub fn oid(&self) -> [u8; 10] {
+        let mut oid = [0; 10];
+        oid[..9].copy_from_slice(&OID_PACE_ECDH_GM);
+        oid[9] = match self.cipher {
+            PaceCipher::TDes => 0x01,
+            PaceCipher::Aes128 => 0x02,
+        };
+        oid
+    }
+
+    pub(crate) fn curve(&self) -> Option<&'static Curve> {
+        match self.parameter_id {
+            PARAM_NIST_P256 => Some(&ec::NIST_P256),
+            PARAM_BRAINPOOL_P256R1 => Some(&ec::BRAINPOOL_P256R1),
+            _ => None,
+        }
+    }
+}
+
+/// EF.COM: LDS version and the data groups present.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Com {
+    /// LDS version, `aabb` for version aa.bb.
+    pub lds_version: String<4>,
+    /// Unicode version, `aabbcc` for version aa.bb.cc.
+    pub unicode_version: String<6>,
+    /// Numbers of the data groups present, 1 to 16.
+    pub data_groups: Vec<u8, 16>,
+}
+
+impl Com {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let com = tlv::find(data, TAG_COM)?;
+        let mut data_groups = Vec::new();
+        for tag in com.find(TAG_TAG_LIST)?.value {
+            match DATA_GROUP_TAGS.iter().position(|t| t == tag) {
+                Some(i) => data_groups.push(i as u8 + 1).ok()?,
+                None => warn!("unknown data group tag {:02x}", tag),
+            }
+        }
+        Some(Self {
+            lds_version: ascii(com.find(TAG_LDS_VERSION)?.value)?,
+            unicode_version: ascii(com.find(TAG_UNICODE_VERSION)?.value)?,
+            data_groups,
+        })
+    }
+
+    pub fn has_data_group(&self, n: u8) -> bool {
+        self.data_groups.contains(&n)
+    }
+}
+
+/// Parse DG1, returning the MRZ as a single line without separators: 88 characters for TD3
+/// passports, 72 for TD2 and 90 for TD1 cards.
+pub fn parse_dg1(data: &[u8]) -> Option<String<MAX_MRZ_LEN>> {
+    let mrz = tlv::find(data, TAG_DG1)?.find(TAG_MRZ)?;
+    ascii(mrz.value)
+}
+
+fn ascii<const N: usize>(data: &[u8]) -> Option<String<N>> {
+    if !data.is_ascii() {
+        return None;
+    }
+    String::try_from(core::str::from_utf8(data).ok()?).ok()
+}
+
+/// Encoding of a face image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ImageType {
+    Jpeg,
+    Jpeg2000,
+    Unknown(u8),
+}
+
+/// The first face image of DG2, from its ISO/IEC 19794-5 facial record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FaceImage<'a> {
+    pub image_type: ImageType,
+    pub width: u16,
+    pub height: u16,
+    /// The encoded image.
+    pub data: &'a [u8],
+}
+
+impl<'a> FaceImage<'a> {
+    /// Facial record header: format identifier, version, record length, number of images.
+    const RECORD_HEADER_LEN: usize = 4 + 4 + 4 + 2;
+    /// Facial information block, without the feature points.
+    const FACIAL_INFO_LEN: usize = 20;
+    const FEATURE_POINT_LEN: usize = 8;
+    const IMAGE_INFO_LEN: usize = 12;
+
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let template = tlv::find(data, TAG_DG2)?.find_path(&[TAG_BIOMETRIC_GROUP, TAG_BIOMETRIC_TEMPLATE])?;
+        let record = template.find(TAG_BIOMETRIC_DATA)?.value;
+
+        if record.get(..8)? != b"FAC\x00010\x00" {
+            warn!("unknown facial record format");
+            return None;
+        }
+        let count = u16::from_be_bytes(record.get(12..14)?.try_into().ok()?);
+        if count == 0 {
+            return None;
+        }
+
+        let face = &record[Self::RECORD_HEADER_LEN..];
+        let block_len = u32::from_be_bytes(face.get(..4)?.try_into().ok()?) as usize;
+        let face = face.get(..block_len)?;
+        let feature_points = u16::from_be_bytes(face.get(4..6)?.try_into().ok()?) as usize;
+        let info = Self::FACIAL_INFO_LEN + feature_points * Self::FEATURE_POINT_LEN;
+        let image_info = face.get(info..info + Self::IMAGE_INFO_LEN)?;
+
+        Some(Self {
+            image_type: match image_info[1] {
+                0 => ImageType::Jpeg,
+                1 => ImageType::Jpeg2000,
+                t => ImageType::Unknown(t),
+            },
+            width: u16::from_be_bytes([image_info[2], image_info[3]]),
+            height: u16::from_be_bytes([image_info[4], image_info[5]]),
+            data: &face[info + Self::IMAGE_INFO_LEN..],
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_pace_info() {
+        // ICAO 9303-11 appendix G.1, ECDH-GM-AES-128 with brainpoolP256r1.
+        let card_access = hex!("3114 3012 060A04007F00070202040202 020102 02010D");
+        let info = PaceInfo::parse(&card_access).unwrap();
+        assert_eq!(
+            info,
+            PaceInfo {
+                cipher: PaceCipher::Aes128,
+                parameter_id: 13
+            }
+        );
+        assert_eq!(info.oid(), hex!("04007F00070202040202"));
+
+        // DH generic mapping is skipped.
+        let card_access = hex!(
+            "3128"
+            "3012 060A04007F00070202040102 020102 020102"
+            "3012 060A04007F00070202040201 020102 02010C"
+        );
+        assert_eq!(
+            PaceInfo::parse(&card_access),
+            Some(PaceInfo {
+                cipher: PaceCipher::TDes,
+                parameter_id: 12
+            })
+        );
+
+        // brainpoolP384r1 isn't supported.
+        assert_eq!(
+            PaceInfo::parse(&hex!("3114 3012 060A04007F00070202040202 020102 020110")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_com() {
+        // ICAO 9303-11 appendix D.4.
+        let com = Com::parse(&hex!("60145F0104303130365F36063034303030305C026175")).unwrap();
+        assert_eq!(com.lds_version, "0106");
+        assert_eq!(com.unicode_version, "040000");
+        assert_eq!(com.data_groups, [1, 2]);
+        assert!(com.has_data_group(2));
+        assert!(!com.has_data_group(3));
+    }
+
+    #[test]
+    fn test_dg1() {
+        let mrz = "P<UTOERIKSSON<<ANNA<MARIA<<<<<<<<<<<<<<<<<<<L898902C36UTO7408122F1204159ZE184226B<<<<<10";
+        let mut dg1 = std::vec![0x61, 0x5B, 0x5F, 0x1F, 0x58];
+        dg1.extend_from_slice(mrz.as_bytes());
+        assert_eq!(parse_dg1(&dg1).unwrap(), mrz);
+        assert_eq!(parse_dg1(&dg1[..20]), None);
+    }
+
+    #[test]
+    fn test_face_image() {
+        let dg2 = hex!(
+            "75 4D 7F61 4A 0201 01 7F60 44"
+            "A1 07 8102 0008 8701 01"
+            // Record header, facial information with one feature point, image information.
+            "5F2E 38 46414300 30313000 00000038 0001"
+            "0000002A 0001 01 00 00 000000 0000 000000 000000"
+            "0100000000000000"
+            "01 01 0280 01E0 01 02 0000 0000"
+            "FF4F"
+        );
+        assert_eq!(
+            FaceImage::parse(&dg2),
+            Some(FaceImage {
+                image_type: ImageType::Jpeg2000,
+                width: 640,
+                height: 480,
+                data: &hex!("FF4F"),
+            })
+        );
+        assert_eq!(FaceImage::parse(&dg2[..dg2.len() - 1]), None);
+    }
+}
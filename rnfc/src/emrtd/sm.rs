@@ -0,0 +1,331 @@
+//! ICAO 9303-11 secure messaging, with 3DES keys from BAC or PACE-3DES, or AES keys from
+//! PACE-AES.
+//!
+//! Command data goes encrypted in DO'87', or DO'85' for odd INS, Le in DO'97', and a MAC
+//! over the send sequence counter, the header and these objects in DO'8E'. Responses carry
+//! DO'87', the status word in DO'99' and DO'8E'. The send sequence counter is incremented
+//! before protecting a command and before checking a response.
+
+use heapless::Vec;
+use sha1::{Digest, Sha1};
+
+use crate::apdu::{Command, StatusWord};
+use crate::crypto::{Cipher, Key, pad, unpad, xor};
+use crate::tlv::{self, Tag};
+
+const CLA_SM: u8 = 0x0C;
+const TAG_ENCRYPTED_PADDED: Tag = Tag(0x87);
+const TAG_ENCRYPTED: Tag = Tag(0x85);
+const TAG_LE: Tag = Tag(0x97);
+const TAG_STATUS: Tag = Tag(0x99);
+const TAG_MAC: Tag = Tag(0x8E);
+
+/// Padding indicator in DO'87': ISO/IEC 9797-1 padding method 2.
+const PADDING_INDICATOR: u8 = 0x01;
+
+/// Data field of a protected command, which is always a short APDU.
+pub(crate) const MAX_PROTECTED_LEN: usize = 255;
+/// Longest command data that fits in a protected command with AES padding.
+pub(crate) const MAX_COMMAND_DATA: usize = 0xDF;
+
+pub(crate) const MAC_LEN: usize = 8;
+
+/// KDF of ICAO 9303-11 section 9.7.1 for 3DES and AES-128 keys: the first 16 bytes of
+/// SHA-1(secret || counter).
+pub(crate) fn kdf(secret: &[u8], counter: u32) -> [u8; 16] {
+    let mut h = Sha1::new();
+    h.update(secret);
+    h.update(counter.to_be_bytes());
+    let digest = h.finalize();
+    unwrap!(digest[..16].try_into())
+}
+
+/// KDF counter for encryption keys.
+pub(crate) const KDF_ENC: u32 = 1;
+/// KDF counter for MAC keys.
+pub(crate) const KDF_MAC: u32 = 2;
+/// KDF counter for the PACE password key.
+pub(crate) const KDF_PACE: u32 = 3;
+
+/// ISO/IEC 9797-1 MAC algorithm 3 with DES: CBC-MAC with K1, and the last block with 3DES.
+/// `data` must be padded.
+pub(crate) fn retail_mac(key: &[u8; 16], data: &[u8]) -> [u8; MAC_LEN] {
+    let mut k1 = [0; 16];
+    k1[..8].copy_from_slice(&key[..8]);
+    k1[8..].copy_from_slice(&key[..8]);
+    let single = Cipher::new(&Key::TDes2(k1));
+
+    debug_assert!(!data.is_empty() && data.len().is_multiple_of(MAC_LEN));
+    let (init, last) = data.split_at(data.len() - MAC_LEN);
+    let mut mac = [0; MAC_LEN];
+    for block in init.chunks_exact(MAC_LEN) {
+        xor(&mut mac, block);
+        single.encrypt_block(&mut mac);
+    }
+    xor(&mut mac, last);
+    Cipher::new(&Key::TDes2(*key)).encrypt_block(&mut mac);
+    mac
+}
+
+// Without an allocator, boxing the bigger variant isn't an option.
+#[allow(clippy::large_enum_variant)]
+enum Keys {
+    TDes { enc: Cipher, mac: [u8; 16] },
+    Aes { enc: Cipher, mac: Cipher },
+}
+
+/// A secure messaging session.
+pub(crate) struct SecureMessaging {
+    keys: Keys,
+    /// Send sequence counter, right-aligned: 3DES only uses the last 8 bytes.
+    ssc: [u8; 16],
+}
+
+impl SecureMessaging {
+    pub fn tdes(ks_enc: &[u8; 16], ks_mac: &[u8; 16], ssc: [u8; 8]) -> Self {
+        let mut s = [0; 16];
+        s[8..].copy_from_slice(&ssc);
+        Self {
+            keys: Keys::TDes {
+                enc: Cipher::new(&Key::TDes2(*ks_enc)),
+                mac: *ks_mac,
+            },
+            ssc: s,
+        }
+    }
+
+    /// AES session, with the send sequence counter starting at zero.
+    pub fn aes(ks_enc: &[u8; 16], ks_mac: &[u8; 16]) -> Self {
+        Self {
+            keys: Keys::Aes {
+                enc: Cipher::aes(ks_enc),
+                mac: Cipher::aes(ks_mac),
+            },
+            ssc: [0; 16],
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        match self.keys {
+            Keys::TDes { .. } => 8,
+            Keys::Aes { .. } => 16,
+        }
+    }
+
+    fn ssc(&self) -> &[u8] {
+        &self.ssc[16 - self.block_size()..]
+    }
+
+    fn increment_ssc(&mut self) {
+        let bs = self.block_size();
+        for b in self.ssc[16 - bs..].iter_mut().rev() {
+            *b = b.wrapping_add(1);
+            if *b != 0 {
+                break;
+            }
+        }
+    }
+
+    fn cipher(&self) -> &Cipher {
+        match &self.keys {
+            Keys::TDes { enc, .. } => enc,
+            Keys::Aes { enc, .. } => enc,
+        }
+    }
+
+    /// IV for the current send sequence counter: zero for 3DES, E(KSenc, SSC) for AES.
+    fn iv(&self) -> [u8; 16] {
+        let mut iv = [0; 16];
+        if let Keys::Aes { enc, .. } = &self.keys {
+            iv.copy_from_slice(&self.ssc);
+            enc.encrypt_block(&mut iv);
+        }
+        iv
+    }
+
+    /// MAC of padded data.
+    fn mac_padded(&self, data: &[u8]) -> [u8; MAC_LEN] {
+        match &self.keys {
+            Keys::TDes { mac, .. } => retail_mac(mac, data),
+            Keys::Aes { mac, .. } => unwrap!(mac.cmac(&[0; 16], data)[..MAC_LEN].try_into()),
+        }
+    }
+
+    /// PACE authentication token: the MAC of the unpadded data for AES, padded for 3DES.
+    pub fn token(&self, data: &[u8]) -> Option<[u8; MAC_LEN]> {
+        match &self.keys {
+            Keys::TDes { mac, .. } => {
+                let mut buf = Vec::<u8, 160>::from_slice(data).ok()?;
+                pad(&mut buf, 8)?;
+                Some(retail_mac(mac, &buf))
+            }
+            Keys::Aes { mac, .. } => mac.cmac(&[0; 16], data)[..MAC_LEN].try_into().ok(),
+        }
+    }
+
+    /// Protect a command, returning the CLA byte and the data field of the protected command.
+    pub fn protect(&mut self, cmd: &Command<'_>) -> Option<(u8, tlv::Encoder<MAX_PROTECTED_LEN>)> {
+        self.increment_ssc();
+        let bs = self.block_size();
+        let cla = cmd.cla | CLA_SM;
+
+        let mut dos = tlv::Encoder::<MAX_PROTECTED_LEN>::new();
+        if !cmd.data.is_empty() {
+            let mut padded = Vec::<u8, { MAX_COMMAND_DATA + 16 }>::from_slice(cmd.data).ok()?;
+            pad(&mut padded, bs)?;
+            let mut iv = self.iv();
+            self.cipher().cbc_encrypt(&mut iv[..bs], &mut padded);
+
+            let odd = cmd.ins & 1 != 0;
+            let mut value = Vec::<u8, { MAX_COMMAND_DATA + 17 }>::new();
+            if !odd {
+                value.push(PADDING_INDICATOR).ok()?;
+            }
+            value.extend_from_slice(&padded).ok()?;
+            let tag = match odd {
+                true => TAG_ENCRYPTED,
+                false => TAG_ENCRYPTED_PADDED,
+            };
+            dos.push(tag, &value).ok()?;
+        }
+        if let Some(le) = cmd.le {
+            // The maximum of short and extended Le wraps around to zero.
+            match le {
+                0..=256 => dos.push(TAG_LE, &[le as u8]).ok()?,
+                _ => dos.push(TAG_LE, &(le as u16).to_be_bytes()).ok()?,
+            }
+        }
+
+        let mut input = Vec::<u8, { 16 + 16 + MAX_PROTECTED_LEN + 16 }>::new();
+        input.extend_from_slice(self.ssc()).ok()?;
+        input.extend_from_slice(&[cla, cmd.ins, cmd.p1, cmd.p2]).ok()?;
+        pad(&mut input, bs)?;
+        input.extend_from_slice(dos.as_bytes()).ok()?;
+        pad(&mut input, bs)?;
+        let mac = self.mac_padded(&input);
+        dos.push(TAG_MAC, &mac).ok()?;
+
+        Some((cla, dos))
+    }
+
+    /// Check and decrypt the data field of a protected response into `out`. Returns the
+    /// plain data length and the status word, or `None` if the response is malformed or its
+    /// MAC is wrong.
+    pub fn unprotect(&mut self, data: &[u8], out: &mut [u8]) -> Option<(usize, StatusWord)> {
+        self.increment_ssc();
+        let bs = self.block_size();
+
+        let mut encrypted = None;
+        let mut status = None;
+        let mut mac = None;
+        let mut it = tlv::iter(data);
+        let mut mac_pos = 0;
+        while let Some(tlv) = it.next() {
+            let tlv = tlv.ok()?;
+            match tlv.tag {
+                TAG_ENCRYPTED_PADDED => match tlv.value.split_first() {
+                    Some((&PADDING_INDICATOR, rest)) => encrypted = Some(rest),
+                    _ => return None,
+                },
+                TAG_ENCRYPTED => encrypted = Some(tlv.value),
+                TAG_STATUS => status = Some(tlv.value),
+                TAG_MAC => {
+                    mac = Some(tlv.value);
+                    break;
+                }
+                _ => {}
+            }
+            mac_pos = data.len() - it.remaining().len();
+        }
+
+        let mut input = Vec::<u8, { 16 + 256 + 16 }>::new();
+        input.extend_from_slice(self.ssc()).ok()?;
+        input.extend_from_slice(&data[..mac_pos]).ok()?;
+        pad(&mut input, bs)?;
+        let expected = self.mac_padded(&input);
+        let mac = mac?;
+        if mac.len() != MAC_LEN || mac.iter().zip(&expected).fold(0, |acc, (a, b)| acc | (a ^ b)) != 0 {
+            warn!("secure messaging MAC mismatch");
+            return None;
+        }
+
+        let &[sw1, sw2] = status? else {
+            return None;
+        };
+        let sw = StatusWord::from_bytes(sw1, sw2);
+
+        let n = match encrypted {
+            Some(enc) => {
+                if enc.is_empty() || !enc.len().is_multiple_of(bs) || enc.len() > out.len() {
+                    return None;
+                }
+                let out = &mut out[..enc.len()];
+                out.copy_from_slice(enc);
+                let mut iv = self.iv();
+                self.cipher().cbc_decrypt(&mut iv[..bs], out);
+                unpad(out)?
+            }
+            None => 0,
+        };
+        Some((n, sw))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_bac_secure_messaging() {
+        // ICAO 9303-11 appendix D.4.
+        let mut sm = SecureMessaging::tdes(
+            &hex!("979EC13B1CBFE9DCD01AB0FED307EAE5"),
+            &hex!("F1CB1F1FB5ADF208806B89DC579DC1F8"),
+            hex!("887022120C06C226"),
+        );
+
+        let cmd = Command::new(0x00, 0xA4, 0x02, 0x0C).with_data(&hex!("011E"));
+        let (cla, data) = sm.protect(&cmd).unwrap();
+        assert_eq!(cla, 0x0C);
+        assert_eq!(data.as_bytes(), hex!("8709016375432908C044F6 8E08BF8B92D635FF24F8"));
+        let mut out = [0; 16];
+        assert_eq!(
+            sm.unprotect(&hex!("990290008E08FA855A5D4C50A8ED"), &mut out),
+            Some((0, StatusWord::OK))
+        );
+
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(4);
+        let (_, data) = sm.protect(&cmd).unwrap();
+        assert_eq!(data.as_bytes(), hex!("970104 8E08ED6705417E96BA55"));
+        assert_eq!(
+            sm.unprotect(&hex!("8709019FF0EC34F9922651 99029000 8E08AD55CC17140B2DED"), &mut out),
+            Some((4, StatusWord::OK))
+        );
+        assert_eq!(out[..4], hex!("60145F01"));
+
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x04).with_le(0x12);
+        let (_, data) = sm.protect(&cmd).unwrap();
+        assert_eq!(data.as_bytes(), hex!("970112 8E082EA28A70F3C7B535"));
+        let mut out = [0; 32];
+        let resp = hex!("871901FB9235F4E4037F2327DCC8964F1F9B8C30F42C8E2FFF224A 99029000 8E08C8B2787EAEA07D74");
+        // A corrupted MAC is detected, without going out of sync.
+        let mut bad = resp;
+        bad[bad.len() - 1] ^= 1;
+        let ssc = sm.ssc;
+        assert_eq!(sm.unprotect(&bad, &mut out), None);
+        sm.ssc = ssc;
+        assert_eq!(sm.unprotect(&resp, &mut out), Some((0x12, StatusWord::OK)));
+        assert_eq!(out[..0x12], hex!("04303130365F36063034303030305C026175"));
+    }
+
+    #[test]
+    fn test_kdf() {
+        // ICAO 9303-11 appendix D.2: Kseed from the MRZ, then Kenc and Kmac. DES parity bits
+        // aren't adjusted, they're ignored by the cipher.
+        let seed = hex!("239AB9CB282DAF66231DC5A4DF6BFBAE");
+        assert_eq!(kdf(&seed, KDF_ENC), hex!("AB94FCEDF2664EDFB9B291F85D7F77F2"));
+        assert_eq!(kdf(&seed, KDF_MAC), hex!("7862D9ECE03C1BCD4D77089DCF131442"));
+    }
+}
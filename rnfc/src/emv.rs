@@ -0,0 +1,693 @@
+//! EMV contactless card reading: application selection, GET PROCESSING OPTIONS and READ RECORD.
+//!
+//! This is enough of a kernel to read the PAN, expiry date and Track 2 Equivalent Data of
+//! payment cards. There's no offline data authentication, risk management or cardholder
+//! verification, so it can't be used to approve a transaction.
+//!
+//! [`Emv::read_card`] runs the whole flow: SELECT 2PAY.SYS.DDF01, pick the application with
+//! the highest priority, SELECT it, fill its PDOL from [`TerminalData`], GET PROCESSING
+//! OPTIONS, then READ RECORD every record in the AFL.
+
+use heapless::{String, Vec};
+use rnfc_traits::iso_dep::Reader;
+
+use crate::apdu::{self, Command, StatusWord};
+use crate::fmt::Bytes;
+use crate::tlv::{self, Tag};
+
+/// Name of the Proximity Payment System Environment.
+pub const PPSE_NAME: &[u8] = b"2PAY.SYS.DDF01";
+
+pub const TAG_FCI_TEMPLATE: Tag = Tag(0x6F);
+pub const TAG_FCI_PROPRIETARY: Tag = Tag(0xA5);
+pub const TAG_FCI_DISCRETIONARY: Tag = Tag(0xBF0C);
+pub const TAG_DIRECTORY_ENTRY: Tag = Tag(0x61);
+pub const TAG_AID: Tag = Tag(0x4F);
+pub const TAG_DF_NAME: Tag = Tag(0x84);
+pub const TAG_LABEL: Tag = Tag(0x50);
+pub const TAG_PRIORITY: Tag = Tag(0x87);
+pub const TAG_PDOL: Tag = Tag(0x9F38);
+pub const TAG_COMMAND_TEMPLATE: Tag = Tag(0x83);
+pub const TAG_RESPONSE_FORMAT_1: Tag = Tag(0x80);
+pub const TAG_RESPONSE_FORMAT_2: Tag = Tag(0x77);
+pub const TAG_RECORD_TEMPLATE: Tag = Tag(0x70);
+pub const TAG_AIP: Tag = Tag(0x82);
+pub const TAG_AFL: Tag = Tag(0x94);
+pub const TAG_PAN: Tag = Tag(0x5A);
+pub const TAG_EXPIRY: Tag = Tag(0x5F24);
+pub const TAG_TRACK2: Tag = Tag(0x57);
+pub const TAG_CARDHOLDER_NAME: Tag = Tag(0x5F20);
+
+pub const TAG_AMOUNT_AUTHORISED: Tag = Tag(0x9F02);
+pub const TAG_AMOUNT_OTHER: Tag = Tag(0x9F03);
+pub const TAG_TERMINAL_COUNTRY_CODE: Tag = Tag(0x9F1A);
+pub const TAG_TRANSACTION_CURRENCY_CODE: Tag = Tag(0x5F2A);
+pub const TAG_TRANSACTION_DATE: Tag = Tag(0x9A);
+pub const TAG_TRANSACTION_TYPE: Tag = Tag(0x9C);
+pub const TAG_TRANSACTION_TIME: Tag = Tag(0x9F21);
+pub const TAG_UNPREDICTABLE_NUMBER: Tag = Tag(0x9F37);
+pub const TAG_TTQ: Tag = Tag(0x9F66);
+pub const TAG_TVR: Tag = Tag(0x95);
+pub const TAG_TERMINAL_TYPE: Tag = Tag(0x9F35);
+
+/// Terminal data elements with format `n`, which are padded and truncated on the left.
+const NUMERIC_TAGS: &[Tag] = &[
+    TAG_AMOUNT_AUTHORISED,
+    TAG_AMOUNT_OTHER,
+    TAG_TERMINAL_COUNTRY_CODE,
+    TAG_TRANSACTION_CURRENCY_CODE,
+    TAG_TRANSACTION_DATE,
+    TAG_TRANSACTION_TYPE,
+    TAG_TRANSACTION_TIME,
+];
+
+const CLA: u8 = 0x00;
+const CLA_PROPRIETARY: u8 = 0x80;
+const INS_SELECT: u8 = 0xA4;
+const INS_READ_RECORD: u8 = 0xB2;
+const INS_GPO: u8 = 0xA8;
+
+/// Maximum number of applications kept from the PPSE.
+pub const MAX_APPLICATIONS: usize = 8;
+/// Maximum number of AFL entries.
+pub const MAX_AFL_ENTRIES: usize = 16;
+const MAX_AID_LEN: usize = 16;
+const MAX_LABEL_LEN: usize = 16;
+const MAX_DOL_LEN: usize = 64;
+const MAX_PDOL_DATA_LEN: usize = 128;
+/// Response data of a short APDU and GET RESPONSE, plus the status word.
+const RX_BUF_LEN: usize = 256 + 2;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The card sent a malformed response.
+    Protocol,
+    /// The card answered with a status word other than 9000.
+    Status(StatusWord),
+    /// A response or a data object doesn't fit in the buffers.
+    TooBig,
+    /// The PPSE lists no application.
+    NoApplication,
+}
+
+impl<E> From<apdu::Error<E>> for Error<E> {
+    fn from(e: apdu::Error<E>) -> Self {
+        match e {
+            apdu::Error::Lower(e) => Self::Lower(e),
+            apdu::Error::Protocol => Self::Protocol,
+            apdu::Error::Status(sw) => Self::Status(sw),
+            apdu::Error::TooBig => Self::TooBig,
+        }
+    }
+}
+
+/// An application listed in the PPSE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Application {
+    pub aid: Vec<u8, MAX_AID_LEN>,
+    pub label: Vec<u8, MAX_LABEL_LEN>,
+    /// Application Priority Indicator. The low nibble is the priority, 1 being the highest
+    /// and 0 meaning no priority.
+    pub priority: Option<u8>,
+}
+
+impl Application {
+    /// Priority as a sort key, lowest first. Applications without one come last.
+    fn rank(&self) -> u8 {
+        match self.priority.map(|p| p & 0x0F) {
+            None | Some(0) => 0x10,
+            Some(p) => p,
+        }
+    }
+}
+
+/// Pick the application with the highest priority, the first one listed on ties.
+pub fn choose_application(apps: &[Application]) -> Option<&Application> {
+    apps.iter().min_by_key(|a| a.rank())
+}
+
+/// The selected application, from its FCI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SelectedApplication {
+    pub aid: Vec<u8, MAX_AID_LEN>,
+    pub label: Vec<u8, MAX_LABEL_LEN>,
+    /// Processing Options Data Object List, empty if the card didn't send one.
+    pub pdol: Vec<u8, MAX_DOL_LEN>,
+}
+
+/// Terminal data elements, used to fill the PDOL.
+///
+/// Elements the card asks for that aren't here are sent as zeros. The unpredictable number,
+/// [`TAG_UNPREDICTABLE_NUMBER`], should be fresh random bytes for every transaction.
+#[derive(Debug, Clone, Copy)]
+pub struct TerminalData<'a> {
+    pub elements: &'a [(Tag, &'a [u8])],
+}
+
+impl<'a> TerminalData<'a> {
+    pub const fn new(elements: &'a [(Tag, &'a [u8])]) -> Self {
+        Self { elements }
+    }
+
+    pub fn get(&self, tag: Tag) -> Option<&'a [u8]> {
+        self.elements.iter().find(|(t, _)| *t == tag).map(|(_, v)| *v)
+    }
+}
+
+/// Fill a Data Object List with terminal data, appending the values to `out`.
+///
+/// Values of the wrong length are adjusted as specified in EMV Book 3 section 5.4: numeric
+/// ones are padded with zeros or truncated on the left, others on the right.
+pub fn build_dol<const N: usize>(dol: &[u8], terminal: &TerminalData<'_>, out: &mut Vec<u8, N>) -> Option<()> {
+    let mut pos = 0;
+    while pos < dol.len() {
+        let (tag, n) = Tag::parse(&dol[pos..]).ok()?;
+        let len = *dol.get(pos + n)? as usize;
+        pos += n + 1;
+
+        let start = out.len();
+        out.resize(start + len, 0).ok()?;
+        let Some(value) = terminal.get(tag) else {
+            continue;
+        };
+        let field = &mut out[start..];
+        let n = value.len().min(len);
+        match NUMERIC_TAGS.contains(&tag) {
+            true => field[len - n..].copy_from_slice(&value[value.len() - n..]),
+            false => field[..n].copy_from_slice(&value[..n]),
+        }
+    }
+    Some(())
+}
+
+/// An Application File Locator entry: records `first..=last` of file `sfi`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AflEntry {
+    pub sfi: u8,
+    pub first: u8,
+    pub last: u8,
+    /// Number of records, from `first`, used in offline data authentication.
+    pub oda_records: u8,
+}
+
+/// GET PROCESSING OPTIONS result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProcessingOptions {
+    /// Application Interchange Profile.
+    pub aip: [u8; 2],
+    pub afl: Vec<AflEntry, MAX_AFL_ENTRIES>,
+}
+
+impl ProcessingOptions {
+    /// Parse a GPO response, in format 1 or 2.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (tlv, _) = tlv::Tlv::parse(data).ok()?;
+        let (aip, afl) = match tlv.tag {
+            TAG_RESPONSE_FORMAT_1 => (tlv.value.get(..2)?, &tlv.value[2..]),
+            TAG_RESPONSE_FORMAT_2 => (tlv.find(TAG_AIP)?.value, tlv.find(TAG_AFL).map_or(&[][..], |t| t.value)),
+            _ => return None,
+        };
+        if !afl.len().is_multiple_of(4) {
+            return None;
+        }
+
+        let mut entries = Vec::new();
+        for e in afl.chunks_exact(4) {
+            let entry = AflEntry {
+                sfi: e[0] >> 3,
+                first: e[1],
+                last: e[2],
+                oda_records: e[3],
+            };
+            if !(1..31).contains(&entry.sfi) || entry.first == 0 || entry.last < entry.first {
+                return None;
+            }
+            entries.push(entry).ok()?;
+        }
+        Some(Self {
+            aip: aip.try_into().ok()?,
+            afl: entries,
+        })
+    }
+}
+
+/// Expiry date, year and month.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Expiry {
+    pub year: u16,
+    pub month: u8,
+}
+
+impl Expiry {
+    /// Parse a BCD `YYMM` date, which may be followed by the day.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let [yy, mm, ..] = *data else {
+            return None;
+        };
+        let month = bcd(mm)?;
+        if !(1..=12).contains(&month) {
+            return None;
+        }
+        Some(Self {
+            year: 2000 + bcd(yy)? as u16,
+            month,
+        })
+    }
+}
+
+fn bcd(b: u8) -> Option<u8> {
+    let (hi, lo) = (b >> 4, b & 0x0F);
+    (hi < 10 && lo < 10).then_some(hi * 10 + lo)
+}
+
+/// A Primary Account Number, up to 19 digits.
+pub type Pan = String<19>;
+
+/// Parse a compressed numeric PAN: BCD digits, padded with F.
+pub fn parse_pan(data: &[u8]) -> Option<Pan> {
+    let mut pan = Pan::new();
+    for nibble in nibbles(data) {
+        match nibble {
+            0..=9 => pan.push((b'0' + nibble) as char).ok()?,
+            0xF => break,
+            _ => return None,
+        }
+    }
+    (!pan.is_empty()).then_some(pan)
+}
+
+fn nibbles(data: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    data.iter().flat_map(|b| [b >> 4, b & 0x0F])
+}
+
+/// Track 2 Equivalent Data, the magnetic stripe track 2 contents in BCD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Track2 {
+    pub pan: Pan,
+    pub expiry: Expiry,
+    /// 3-digit service code.
+    pub service_code: u16,
+    pub discretionary_data: String<32>,
+}
+
+impl Track2 {
+    /// Parse `PAN D YYMM service-code discretionary-data`, padded with F.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut it = nibbles(data);
+
+        let mut pan = Pan::new();
+        for nibble in it.by_ref() {
+            match nibble {
+                0..=9 => pan.push((b'0' + nibble) as char).ok()?,
+                0xD => break,
+                _ => return None,
+            }
+        }
+        if pan.is_empty() {
+            return None;
+        }
+
+        let mut digit = || it.next().filter(|&n| n < 10);
+        let (y1, y2, m1, m2) = (digit()?, digit()?, digit()?, digit()?);
+        let expiry = Expiry::parse(&[y1 << 4 | y2, m1 << 4 | m2])?;
+        let service_code = digit()? as u16 * 100 + digit()? as u16 * 10 + digit()? as u16;
+
+        let mut discretionary_data = String::new();
+        for nibble in it {
+            match nibble {
+                0..=9 => discretionary_data.push((b'0' + nibble) as char).ok()?,
+                0xF => break,
+                _ => return None,
+            }
+        }
+
+        Some(Self {
+            pan,
+            expiry,
+            service_code,
+            discretionary_data,
+        })
+    }
+}
+
+/// Card data read by [`Emv::read_card`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CardData {
+    pub application: SelectedApplication,
+    pub processing_options: ProcessingOptions,
+    pub pan: Option<Pan>,
+    pub expiry: Option<Expiry>,
+    pub track2: Option<Track2>,
+    pub cardholder_name: Option<String<26>>,
+}
+
+impl CardData {
+    /// Take the data elements this struct keeps from a record or GPO response template.
+    fn collect(&mut self, template: &tlv::Tlv<'_>) {
+        for tlv in template.children().map_while(Result::ok) {
+            match tlv.tag {
+                TAG_PAN => self.pan = parse_pan(tlv.value),
+                TAG_EXPIRY => self.expiry = Expiry::parse(tlv.value),
+                TAG_TRACK2 => self.track2 = Track2::parse(tlv.value),
+                TAG_CARDHOLDER_NAME => {
+                    self.cardholder_name = core::str::from_utf8(tlv.value)
+                        .ok()
+                        .and_then(|s| String::try_from(s.trim_end()).ok())
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// An EMV contactless card.
+pub struct Emv<T: Reader> {
+    reader: T,
+}
+
+impl<T: Reader> Emv<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader }
+    }
+
+    /// Get the underlying ISO-DEP reader, for sending other commands.
+    pub fn reader(&mut self) -> &mut T {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    /// Select the PPSE and return the applications it lists, in order.
+    pub async fn select_ppse(&mut self) -> Result<Vec<Application, MAX_APPLICATIONS>, Error<T::Error>> {
+        let mut rx = [0; RX_BUF_LEN];
+        let fci = self.select(PPSE_NAME, &mut rx).await?;
+        let Some(dir) = tlv::find_path(fci, &[TAG_FCI_TEMPLATE, TAG_FCI_PROPRIETARY, TAG_FCI_DISCRETIONARY]) else {
+            warn!("PPSE FCI has no directory");
+            return Err(Error::Protocol);
+        };
+
+        let mut apps = Vec::new();
+        for entry in dir.children().map_while(Result::ok).filter(|t| t.tag == TAG_DIRECTORY_ENTRY) {
+            let Some(aid) = entry.find(TAG_AID) else {
+                warn!("directory entry without AID");
+                continue;
+            };
+            let Ok(aid) = Vec::from_slice(aid.value) else {
+                warn!("directory entry with AID too long: {} bytes", aid.value.len());
+                continue;
+            };
+            let app = Application {
+                aid,
+                label: truncated(entry.find(TAG_LABEL).map(|t| t.value)),
+                priority: entry.find(TAG_PRIORITY).and_then(|t| t.value.first().copied()),
+            };
+            if apps.push(app).is_err() {
+                warn!("too many applications, ignoring the rest");
+                break;
+            }
+        }
+        if apps.is_empty() {
+            return Err(Error::NoApplication);
+        }
+        Ok(apps)
+    }
+
+    /// Select an application by AID.
+    pub async fn select_application(&mut self, aid: &[u8]) -> Result<SelectedApplication, Error<T::Error>> {
+        let mut rx = [0; RX_BUF_LEN];
+        let fci = self.select(aid, &mut rx).await?;
+        let Some(fci) = tlv::find(fci, TAG_FCI_TEMPLATE) else {
+            warn!("no FCI template");
+            return Err(Error::Protocol);
+        };
+
+        let prop = fci.find(TAG_FCI_PROPRIETARY);
+        let pdol = prop.and_then(|p| p.find(TAG_PDOL)).map_or(&[][..], |t| t.value);
+        Ok(SelectedApplication {
+            aid: Vec::from_slice(fci.find(TAG_DF_NAME).map_or(aid, |t| t.value)).map_err(|_| Error::Protocol)?,
+            label: truncated(prop.and_then(|p| p.find(TAG_LABEL)).map(|t| t.value)),
+            pdol: Vec::from_slice(pdol).map_err(|_| Error::TooBig)?,
+        })
+    }
+
+    /// GET PROCESSING OPTIONS, with the PDOL filled from `terminal`.
+    pub async fn get_processing_options(
+        &mut self,
+        pdol: &[u8],
+        terminal: &TerminalData<'_>,
+    ) -> Result<ProcessingOptions, Error<T::Error>> {
+        let mut rx = [0; RX_BUF_LEN];
+        let resp = self.gpo(pdol, terminal, &mut rx).await?;
+        parse_gpo(resp)
+    }
+
+    /// READ RECORD `record` of file `sfi` into `buf`, returning the record template length.
+    pub async fn read_record(&mut self, sfi: u8, record: u8, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        let mut rx = [0; RX_BUF_LEN];
+        let cmd = Command::new(CLA, INS_READ_RECORD, record, sfi << 3 | 0x04).with_le(apdu::MAX_SHORT_LE);
+        let resp = apdu::command(&mut self.reader, &cmd, &mut rx).await?;
+        let buf = buf.get_mut(..resp.len()).ok_or(Error::TooBig)?;
+        buf.copy_from_slice(resp);
+        Ok(resp.len())
+    }
+
+    /// Run the whole flow: select the PPSE and the best application, GET PROCESSING OPTIONS,
+    /// and read all records in the AFL.
+    pub async fn read_card(&mut self, terminal: &TerminalData<'_>) -> Result<CardData, Error<T::Error>> {
+        let apps = self.select_ppse().await?;
+        let app = unwrap!(choose_application(&apps));
+        debug!("selecting {:02x}", Bytes(&app.aid));
+        let application = self.select_application(&app.aid).await?;
+
+        let mut rx = [0; RX_BUF_LEN];
+        let resp = self.gpo(&application.pdol, terminal, &mut rx).await?;
+        let processing_options = parse_gpo(resp)?;
+
+        let mut card = CardData {
+            application,
+            processing_options,
+            pan: None,
+            expiry: None,
+            track2: None,
+            cardholder_name: None,
+        };
+        // Format 2 responses may carry card data too, Track 2 in particular.
+        if let Ok((gpo, _)) = tlv::Tlv::parse(resp) {
+            card.collect(&gpo);
+        }
+
+        let afl = card.processing_options.afl.clone();
+        let mut buf = [0; RX_BUF_LEN];
+        for entry in afl {
+            for record in entry.first..=entry.last {
+                let n = self.read_record(entry.sfi, record, &mut buf).await?;
+                match tlv::find(&buf[..n], TAG_RECORD_TEMPLATE) {
+                    Some(template) => card.collect(&template),
+                    None => warn!("record {} of SFI {} has no template", record, entry.sfi),
+                }
+            }
+        }
+
+        // Cards without the PAN or expiry date elements still have them in Track 2.
+        if let Some(t2) = &card.track2 {
+            if card.pan.is_none() {
+                card.pan = Some(t2.pan.clone());
+            }
+            if card.expiry.is_none() {
+                card.expiry = Some(t2.expiry);
+            }
+        }
+        Ok(card)
+    }
+
+    /// GET PROCESSING OPTIONS, returning the raw response.
+    async fn gpo<'r>(
+        &mut self,
+        pdol: &[u8],
+        terminal: &TerminalData<'_>,
+        rx: &'r mut [u8],
+    ) -> Result<&'r [u8], Error<T::Error>> {
+        let mut data = Vec::<u8, MAX_PDOL_DATA_LEN>::new();
+        build_dol(pdol, terminal, &mut data).ok_or(Error::TooBig)?;
+        let mut template = tlv::Encoder::<{ MAX_PDOL_DATA_LEN + 3 }>::new();
+        template.push(TAG_COMMAND_TEMPLATE, &data).map_err(|_| Error::TooBig)?;
+
+        let cmd = Command::new(CLA_PROPRIETARY, INS_GPO, 0x00, 0x00)
+            .with_data(template.as_bytes())
+            .with_le(apdu::MAX_SHORT_LE);
+        Ok(apdu::command(&mut self.reader, &cmd, rx).await?)
+    }
+
+    /// SELECT by name, returning the FCI.
+    async fn select<'r>(&mut self, name: &[u8], rx: &'r mut [u8]) -> Result<&'r [u8], Error<T::Error>> {
+        let cmd = Command::new(CLA, INS_SELECT, 0x04, 0x00)
+            .with_data(name)
+            .with_le(apdu::MAX_SHORT_LE);
+        Ok(apdu::command(&mut self.reader, &cmd, rx).await?)
+    }
+}
+
+fn parse_gpo<E>(resp: &[u8]) -> Result<ProcessingOptions, Error<E>> {
+    ProcessingOptions::parse(resp).ok_or_else(|| {
+        warn!("malformed GPO response");
+        Error::Protocol
+    })
+}
+
+/// Copy a label, keeping what fits.
+fn truncated<const N: usize>(data: Option<&[u8]>) -> Vec<u8, N> {
+    data.unwrap_or(&[]).iter().take(N).copied().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::test_util::mock;
+
+    fn app(aid: &[u8], priority: Option<u8>) -> Application {
+        Application {
+            aid: Vec::from_slice(aid).unwrap(),
+            label: Vec::new(),
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_choose_application() {
+        let apps = [
+            app(&[1], None),
+            app(&[2], Some(0x02)),
+            app(&[3], Some(0x81)),
+            app(&[4], Some(0x01)),
+        ];
+        // The cardholder confirmation bit doesn't affect priority.
+        assert_eq!(choose_application(&apps).unwrap().aid, [3]);
+        assert_eq!(choose_application(&apps[..1]).unwrap().aid, [1]);
+        assert_eq!(choose_application(&[app(&[1], Some(0)), app(&[2], None)]).unwrap().aid, [1]);
+        assert_eq!(choose_application(&[]), None);
+    }
+
+    #[test]
+    fn test_build_dol() {
+        let terminal = TerminalData::new(&[
+            (TAG_TTQ, &hex!("36000000")),
+            (TAG_AMOUNT_AUTHORISED, &hex!("1000")),
+            (TAG_TRANSACTION_CURRENCY_CODE, &hex!("000978")),
+            (TAG_TERMINAL_TYPE, &hex!("2211")),
+        ]);
+        let mut out = Vec::<u8, 32>::new();
+        build_dol(&hex!("9F6604 9F0206 9F3704 5F2A02 9F3501"), &terminal, &mut out).unwrap();
+        assert_eq!(out, hex!("36000000 000000001000 00000000 0978 22"));
+
+        // Truncated DOL.
+        assert_eq!(build_dol(&hex!("9F66"), &terminal, &mut Vec::<u8, 32>::new()), None);
+        assert_eq!(build_dol(&hex!("9F6621"), &terminal, &mut Vec::<u8, 32>::new()), None);
+    }
+
+    #[test]
+    fn test_track2() {
+        let t2 = Track2::parse(&hex!("4761739001010010D24122011143804489FF")).unwrap();
+        assert_eq!(t2.pan, "4761739001010010");
+        assert_eq!(t2.expiry, Expiry { year: 2024, month: 12 });
+        assert_eq!(t2.service_code, 201);
+        assert_eq!(t2.discretionary_data, "1143804489");
+
+        assert_eq!(Track2::parse(&hex!("D2412201")), None);
+        assert_eq!(Track2::parse(&hex!("4761D24132")), None);
+
+        assert_eq!(parse_pan(&hex!("5413330089010434 FFFF")).unwrap(), "5413330089010434");
+        assert_eq!(parse_pan(&hex!("54133300890104345F")).unwrap(), "54133300890104345");
+        assert_eq!(parse_pan(&hex!("5A")), None);
+        assert_eq!(Expiry::parse(&hex!("251231")), Some(Expiry { year: 2025, month: 12 }));
+        assert_eq!(Expiry::parse(&hex!("2513")), None);
+    }
+
+    #[test]
+    fn test_processing_options() {
+        let po = ProcessingOptions::parse(&hex!("80 06 1980 08010100")).unwrap();
+        assert_eq!(po.aip, hex!("1980"));
+        assert_eq!(
+            po.afl,
+            [AflEntry {
+                sfi: 1,
+                first: 1,
+                last: 1,
+                oda_records: 0
+            }]
+        );
+
+        let po = ProcessingOptions::parse(&hex!("77 0A 8202 2000 9404 10020301")).unwrap();
+        assert_eq!(po.aip, hex!("2000"));
+        assert_eq!(po.afl[0].sfi, 2);
+        assert_eq!(po.afl[0].last, 3);
+
+        // AFL without a whole number of entries, with record 0, or ending before it starts.
+        assert_eq!(ProcessingOptions::parse(&hex!("80 05 1980 080101")), None);
+        assert_eq!(ProcessingOptions::parse(&hex!("80 06 1980 08000100")), None);
+        assert_eq!(ProcessingOptions::parse(&hex!("80 06 1980 08020100")), None);
+    }
+
+    #[tokio::test]
+    async fn test_select_ppse_long_aid() {
+        // The first entry has a 17-byte AID.
+        let mut r = mock!(
+            "00A404000E325041592E5359532E444446303100" => "6F3B840E325041592E5359532E4444463031A529BF0C2661164F11A0A1A2A3A4A5A6A7A8A9AAABACADAEAFB0870101610C4F07A0000000041010870102 9000",
+        );
+        let apps = Emv::new(&mut r).select_ppse().await.unwrap();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].aid, hex!("A0000000041010"));
+        assert_eq!(apps[0].priority, Some(2));
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_read_card() {
+        let mut r = mock!(
+            "00A404000E325041592E5359532E444446303100" => "6F49840E325041592E5359532E4444463031A537BF0C3461184F07A0000000041010500A4D41535445524341524487010261184F07A0000000031010500A56495341204445424954870101 9000",
+            "00A4040007A000000003101000" => "6F298407A0000000031010A51E500A564953412044454249548701019F380C9F66049F02069F37045F2A02 9000",
+            "80A800001283103600000000000000100011223344097800" => "772C820220009408080101001002030057124761739001010010D24122011143804489FF9F100706010A03A00000 9000",
+            "00B2010C00" => "70225A0847617390010100105F24032412315F200F43415244484F4C4445522F56495341 9000",
+            "00B2021400" => "70038F0109 9000",
+            "00B2031400" => "6A83",
+        );
+        let terminal = TerminalData::new(&[
+            (TAG_TTQ, &hex!("36000000")),
+            (TAG_AMOUNT_AUTHORISED, &hex!("000000001000")),
+            (TAG_UNPREDICTABLE_NUMBER, &hex!("11223344")),
+            (TAG_TRANSACTION_CURRENCY_CODE, &hex!("0978")),
+        ]);
+
+        let mut emv = Emv::new(&mut r);
+        assert!(matches!(
+            emv.read_card(&terminal).await,
+            Err(Error::Status(StatusWord::RECORD_NOT_FOUND))
+        ));
+        r.assert_done();
+
+        r.pos = 0;
+        r.expected[5].1 = &hex!("70038F0109 9000");
+        let mut emv = Emv::new(&mut r);
+        let card = emv.read_card(&terminal).await.unwrap();
+        assert_eq!(card.application.aid, hex!("A0000000031010"));
+        assert_eq!(card.application.label, b"VISA DEBIT");
+        assert_eq!(card.processing_options.aip, hex!("2000"));
+        assert_eq!(card.pan.unwrap(), "4761739001010010");
+        assert_eq!(card.expiry, Some(Expiry { year: 2024, month: 12 }));
+        assert_eq!(card.track2.unwrap().service_code, 201);
+        assert_eq!(card.cardholder_name.unwrap(), "CARDHOLDER/VISA");
+        r.assert_done();
+    }
+}
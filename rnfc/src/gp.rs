@@ -0,0 +1,548 @@
+//! GlobalPlatform card management: SCP03 secure channel and card content management.
+//!
+//! [`Gp::open_secure_channel`] authenticates to the selected security domain with its AES
+//! key set, and all following commands are then wrapped with the security level asked for.
+//! Over that channel, [`Gp::get_status`] lists the registry, and [`Gp::delete`],
+//! [`Gp::install_for_load`], [`Gp::load`] and [`Gp::install`] manage load files and
+//! applets:
+//!
+//! ```ignore
+//! let mut gp = Gp::new(reader);
+//! gp.select(gp::ISD_AID).await?;
+//! gp.open_secure_channel(&mut rng, &keys, 0x00, SecurityLevel::C_MAC_C_DEC_R_MAC).await?;
+//! for app in gp.get_status(Scope::Applications).await? {
+//!     info!("{:02x} {:02x}", Bytes(&app.aid), app.lifecycle);
+//! }
+//! gp.install_for_load(PACKAGE_AID, gp::ISD_AID).await?;
+//! gp.load(&cap).await?;
+//! gp.install(PACKAGE_AID, APPLET_AID, APPLET_AID, &[0x00], &[]).await?;
+//! ```
+//!
+//! Only SCP03 with AES-128 keys is supported, in "i" = 0x00 or 0x10 mode (random or
+//! pseudo-random card challenge). SCP02, DAP and load file hashes are not implemented.
+
+mod scp03;
+
+use heapless::Vec;
+use rand_core::CryptoRngCore;
+use rnfc_traits::iso_dep::Reader;
+
+use self::scp03::{CHALLENGE_LEN, CRYPTOGRAM_LEN, Handshake, MAX_COMMAND_DATA, Session};
+pub use self::scp03::{Keys, SecurityLevel};
+use crate::apdu::{self, Command, Response, StatusWord};
+use crate::fmt::Bytes;
+use crate::tlv::{self, Tag};
+
+/// AID of the issuer security domain on most cards.
+pub const ISD_AID: &[u8] = &[0xA0, 0x00, 0x00, 0x01, 0x51, 0x00, 0x00, 0x00];
+
+/// Default key set of development cards, 404142..4F for all keys.
+pub const DEFAULT_KEY: [u8; 16] = [
+    0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x4B, 0x4C, 0x4D, 0x4E, 0x4F,
+];
+
+/// Most registry entries returned by [`Gp::get_status`].
+pub const MAX_REGISTRY_ENTRIES: usize = 32;
+/// Longest AID, ISO 7816-5.
+pub const MAX_AID_LEN: usize = 16;
+
+const CLA_ISO: u8 = 0x00;
+const CLA_GP: u8 = 0x80;
+const INS_SELECT: u8 = 0xA4;
+const INS_INITIALIZE_UPDATE: u8 = 0x50;
+const INS_EXTERNAL_AUTHENTICATE: u8 = 0x82;
+const INS_GET_STATUS: u8 = 0xF2;
+const INS_DELETE: u8 = 0xE4;
+const INS_INSTALL: u8 = 0xE6;
+const INS_LOAD: u8 = 0xE8;
+
+const SCP03: u8 = 0x03;
+
+const INSTALL_FOR_LOAD: u8 = 0x02;
+const INSTALL_FOR_INSTALL_AND_MAKE_SELECTABLE: u8 = 0x0C;
+
+const GET_STATUS_FIRST: u8 = 0x02;
+const GET_STATUS_NEXT: u8 = 0x03;
+const DELETE_RELATED: u8 = 0x80;
+const LOAD_LAST_BLOCK: u8 = 0x80;
+
+const TAG_AID: Tag = Tag(0x4F);
+const TAG_REGISTRY_ENTRY: Tag = Tag(0xE3);
+const TAG_LIFECYCLE: Tag = Tag(0x9F70);
+const TAG_PRIVILEGES: Tag = Tag(0xC5);
+const TAG_LOAD_FILE_AID: Tag = Tag(0xC4);
+const TAG_INSTALL_PARAMETERS: Tag = Tag(0xC9);
+const TAG_LOAD_FILE_DATA: u8 = 0xC4;
+
+/// GET STATUS has more entries to send.
+const SW_MORE_DATA: StatusWord = StatusWord(0x6310);
+
+/// Response data of a short APDU and GET RESPONSE, plus the status word.
+const RX_BUF_LEN: usize = 256 + 2;
+/// INITIALIZE UPDATE response: key diversification data, key information, card challenge
+/// and cryptogram, and the optional sequence counter.
+const INITIALIZE_UPDATE_LEN: usize = 10 + 3 + CHALLENGE_LEN + CRYPTOGRAM_LEN;
+const SEQUENCE_COUNTER_LEN: usize = 3;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The card sent a malformed response.
+    Protocol,
+    /// The card answered with a status word other than 9000.
+    Status(StatusWord),
+    /// A command or response doesn't fit in the buffers.
+    TooBig,
+    /// The card cryptogram is wrong or the card rejected the host cryptogram: the keys don't
+    /// match the card's, or the key set doesn't use SCP03.
+    AuthFailed,
+    /// A response had a wrong R-MAC, or the card rejected the secure channel. Open it again
+    /// to go on.
+    SecureChannel,
+}
+
+impl<E> From<apdu::Error<E>> for Error<E> {
+    fn from(e: apdu::Error<E>) -> Self {
+        match e {
+            apdu::Error::Lower(e) => Self::Lower(e),
+            apdu::Error::Protocol => Self::Protocol,
+            apdu::Error::Status(sw) => Self::Status(sw),
+            apdu::Error::TooBig => Self::TooBig,
+        }
+    }
+}
+
+/// Registry part listed by GET STATUS, its P1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Scope {
+    IssuerSecurityDomain = 0x80,
+    /// Applications and supplementary security domains.
+    Applications = 0x40,
+    LoadFiles = 0x20,
+    /// Load files with their executable modules. The modules aren't returned.
+    LoadFilesAndModules = 0x10,
+}
+
+/// An entry of the card registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RegistryEntry {
+    pub aid: Vec<u8, MAX_AID_LEN>,
+    /// Life cycle state, GlobalPlatform section 11.1.1: 07 selectable for applications,
+    /// 01 loaded for load files.
+    pub lifecycle: u8,
+    /// Privilege bytes. Cards with one-byte privileges leave the others zero.
+    pub privileges: [u8; 3],
+    /// Load file of an application.
+    pub load_file: Option<Vec<u8, MAX_AID_LEN>>,
+}
+
+impl RegistryEntry {
+    /// Parse the value of an E3 registry entry.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let aid = |t: tlv::Tlv<'_>| Vec::from_slice(t.value).ok();
+        let mut privileges = [0; 3];
+        if let Some(p) = tlv::find(data, TAG_PRIVILEGES) {
+            privileges.get_mut(..p.value.len())?.copy_from_slice(p.value);
+        }
+        Some(Self {
+            aid: aid(tlv::find(data, TAG_AID)?)?,
+            lifecycle: *tlv::find(data, TAG_LIFECYCLE)?.value.first()?,
+            privileges,
+            load_file: match tlv::find(data, TAG_LOAD_FILE_AID) {
+                Some(t) => Some(aid(t)?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// A GlobalPlatform card.
+pub struct Gp<T: Reader> {
+    reader: T,
+    session: Option<Session>,
+}
+
+impl<T: Reader> Gp<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader, session: None }
+    }
+
+    /// Get the underlying ISO-DEP reader, for sending other commands. These bypass the
+    /// secure channel, and break its MAC chaining.
+    pub fn reader(&mut self) -> &mut T {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    /// Whether a secure channel is open.
+    pub fn is_authenticated(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Select a security domain, usually [`ISD_AID`]. This closes the secure channel.
+    pub async fn select(&mut self, aid: &[u8]) -> Result<(), Error<T::Error>> {
+        self.session = None;
+        let mut rx = [0; RX_BUF_LEN];
+        let cmd = Command::new(CLA_ISO, INS_SELECT, 0x04, 0x00)
+            .with_data(aid)
+            .with_le(apdu::MAX_SHORT_LE);
+        apdu::command(&mut self.reader, &cmd, &mut rx).await?;
+        Ok(())
+    }
+
+    /// Open an SCP03 secure channel with key set `kvn`, 0 for the first available one.
+    pub async fn open_secure_channel(
+        &mut self,
+        rng: &mut impl CryptoRngCore,
+        keys: &Keys,
+        kvn: u8,
+        level: SecurityLevel,
+    ) -> Result<(), Error<T::Error>> {
+        self.session = None;
+        let mut host_challenge = [0; CHALLENGE_LEN];
+        rng.fill_bytes(&mut host_challenge);
+
+        let mut rx = [0; RX_BUF_LEN];
+        let cmd = Command::new(CLA_GP, INS_INITIALIZE_UPDATE, kvn, 0x00)
+            .with_data(&host_challenge)
+            .with_le(apdu::MAX_SHORT_LE);
+        let resp = apdu::command(&mut self.reader, &cmd, &mut rx).await?;
+        if resp.len() != INITIALIZE_UPDATE_LEN && resp.len() != INITIALIZE_UPDATE_LEN + SEQUENCE_COUNTER_LEN {
+            warn!("bad INITIALIZE UPDATE response {:02x}", Bytes(resp));
+            return Err(Error::Protocol);
+        }
+        let (key_info, rest) = resp[10..].split_at(3);
+        if key_info[1] != SCP03 {
+            warn!("key set {:02x} uses SCP{:02x}", key_info[0], key_info[1]);
+            return Err(Error::AuthFailed);
+        }
+        let (card_challenge, rest) = rest.split_at(CHALLENGE_LEN);
+        let card_cryptogram = &rest[..CRYPTOGRAM_LEN];
+
+        let handshake = Handshake::new(keys, &host_challenge, unwrap!(card_challenge.try_into()));
+        if handshake.card_cryptogram != card_cryptogram {
+            debug!("card cryptogram mismatch");
+            return Err(Error::AuthFailed);
+        }
+
+        self.session = Some(handshake.session);
+        let cmd = Command::new(CLA_GP, INS_EXTERNAL_AUTHENTICATE, level.0, 0x00).with_data(&handshake.host_cryptogram);
+        match self.command(&cmd, &mut rx).await {
+            Ok(_) => {}
+            // The card rejected the host cryptogram.
+            Err(Error::SecureChannel) => return Err(Error::AuthFailed),
+            Err(Error::Status(StatusWord::VERIFICATION_FAILED)) => {
+                self.session = None;
+                return Err(Error::AuthFailed);
+            }
+            Err(e) => {
+                self.session = None;
+                return Err(e);
+            }
+        }
+        if let Some(session) = &mut self.session {
+            session.set_level(level);
+        }
+        Ok(())
+    }
+
+    /// List a part of the registry.
+    pub async fn get_status(&mut self, scope: Scope) -> Result<Vec<RegistryEntry, MAX_REGISTRY_ENTRIES>, Error<T::Error>> {
+        let mut entries = Vec::new();
+        let mut rx = [0; RX_BUF_LEN];
+        let mut p2 = GET_STATUS_FIRST;
+        loop {
+            // Search criteria: all AIDs.
+            let cmd = Command::new(CLA_GP, INS_GET_STATUS, scope as u8, p2)
+                .with_data(&[TAG_AID.0 as u8, 0x00])
+                .with_le(apdu::MAX_SHORT_LE);
+            let resp = self.transceive(&cmd, &mut rx).await?;
+            match resp.sw {
+                StatusWord::OK | SW_MORE_DATA => {}
+                StatusWord::REFERENCED_DATA_NOT_FOUND => return Ok(entries),
+                sw => return Err(Error::Status(sw)),
+            }
+            for entry in tlv::iter(resp.data) {
+                let entry = entry.map_err(|_| Error::Protocol)?;
+                if entry.tag != TAG_REGISTRY_ENTRY {
+                    continue;
+                }
+                let entry = RegistryEntry::parse(entry.value).ok_or(Error::Protocol)?;
+                entries.push(entry).map_err(|_| Error::TooBig)?;
+            }
+            if resp.sw != SW_MORE_DATA {
+                return Ok(entries);
+            }
+            p2 = GET_STATUS_NEXT;
+        }
+    }
+
+    /// Delete an application or load file, and with `related` the applications of a load
+    /// file too.
+    pub async fn delete(&mut self, aid: &[u8], related: bool) -> Result<(), Error<T::Error>> {
+        let mut data = tlv::Encoder::<{ 2 + MAX_AID_LEN }>::new();
+        data.push(TAG_AID, aid).map_err(|_| Error::TooBig)?;
+        let p2 = if related { DELETE_RELATED } else { 0x00 };
+        let cmd = Command::new(CLA_GP, INS_DELETE, 0x00, p2)
+            .with_data(data.as_bytes())
+            .with_le(apdu::MAX_SHORT_LE);
+        let mut rx = [0; RX_BUF_LEN];
+        self.command(&cmd, &mut rx).await?;
+        Ok(())
+    }
+
+    /// INSTALL [for load]: prepare loading a load file, associated with a security domain.
+    pub async fn install_for_load(&mut self, load_file: &[u8], security_domain: &[u8]) -> Result<(), Error<T::Error>> {
+        let mut data = Vec::<u8, { 5 + 2 * MAX_AID_LEN }>::new();
+        push_lv(&mut data, load_file)?;
+        push_lv(&mut data, security_domain)?;
+        // No load file hash, load parameters or token.
+        push_lv(&mut data, &[])?;
+        push_lv(&mut data, &[])?;
+        push_lv(&mut data, &[])?;
+        let cmd = Command::new(CLA_GP, INS_INSTALL, INSTALL_FOR_LOAD, 0x00)
+            .with_data(&data)
+            .with_le(apdu::MAX_SHORT_LE);
+        let mut rx = [0; RX_BUF_LEN];
+        self.command(&cmd, &mut rx).await?;
+        Ok(())
+    }
+
+    /// Load a load file, the concatenated CAP file components for Java Card, after
+    /// [`install_for_load`](Self::install_for_load).
+    pub async fn load(&mut self, load_file: &[u8]) -> Result<(), Error<T::Error>> {
+        let (len, len_len) = tlv::encode_length(load_file.len()).map_err(|_| Error::TooBig)?;
+        let mut header = Vec::<u8, 8>::new();
+        unwrap!(header.push(TAG_LOAD_FILE_DATA));
+        unwrap!(header.extend_from_slice(&len[..len_len]));
+
+        let total = header.len() + load_file.len();
+        let blocks = total.div_ceil(MAX_COMMAND_DATA);
+        let blocks = u8::try_from(blocks - 1).map_err(|_| Error::TooBig)?;
+        let mut rx = [0; RX_BUF_LEN];
+        let mut rest = load_file;
+        for n in 0..=blocks {
+            let mut block = Vec::<u8, MAX_COMMAND_DATA>::new();
+            if n == 0 {
+                unwrap!(block.extend_from_slice(&header));
+            }
+            let (chunk, tail) = rest.split_at(rest.len().min(MAX_COMMAND_DATA - block.len()));
+            unwrap!(block.extend_from_slice(chunk));
+            rest = tail;
+
+            let p1 = if n == blocks { LOAD_LAST_BLOCK } else { 0x00 };
+            let cmd = Command::new(CLA_GP, INS_LOAD, p1, n)
+                .with_data(&block)
+                .with_le(apdu::MAX_SHORT_LE);
+            self.command(&cmd, &mut rx).await?;
+        }
+        Ok(())
+    }
+
+    /// INSTALL [for install and make selectable]: create an application from a module of a
+    /// loaded load file. `params` are the application specific parameters, passed to the
+    /// applet's `install` method.
+    pub async fn install(
+        &mut self,
+        load_file: &[u8],
+        module: &[u8],
+        application: &[u8],
+        privileges: &[u8],
+        params: &[u8],
+    ) -> Result<(), Error<T::Error>> {
+        let mut install_params = tlv::Encoder::<MAX_COMMAND_DATA>::new();
+        install_params
+            .push(TAG_INSTALL_PARAMETERS, params)
+            .map_err(|_| Error::TooBig)?;
+
+        let mut data = Vec::<u8, MAX_COMMAND_DATA>::new();
+        push_lv(&mut data, load_file)?;
+        push_lv(&mut data, module)?;
+        push_lv(&mut data, application)?;
+        push_lv(&mut data, privileges)?;
+        push_lv(&mut data, install_params.as_bytes())?;
+        // No token.
+        push_lv(&mut data, &[])?;
+        let cmd = Command::new(CLA_GP, INS_INSTALL, INSTALL_FOR_INSTALL_AND_MAKE_SELECTABLE, 0x00)
+            .with_data(&data)
+            .with_le(apdu::MAX_SHORT_LE);
+        let mut rx = [0; RX_BUF_LEN];
+        self.command(&cmd, &mut rx).await?;
+        Ok(())
+    }
+
+    async fn command<'r>(&mut self, cmd: &Command<'_>, rx: &'r mut [u8]) -> Result<&'r [u8], Error<T::Error>> {
+        Ok(self.transceive(cmd, rx).await?.ok()?)
+    }
+
+    /// Send a command, through the secure channel if open.
+    async fn transceive<'r>(&mut self, cmd: &Command<'_>, rx: &'r mut [u8]) -> Result<Response<'r>, Error<T::Error>> {
+        let Some(session) = &mut self.session else {
+            return Ok(apdu::transceive(&mut self.reader, cmd, rx).await?);
+        };
+
+        let (cla, data) = session.wrap(cmd).ok_or(Error::TooBig)?;
+        let wrapped = Command {
+            cla,
+            data: &data,
+            ..*cmd
+        };
+        let mut raw = [0; RX_BUF_LEN];
+        let resp = apdu::transceive(&mut self.reader, &wrapped, &mut raw).await?;
+
+        if resp.sw == StatusWord::SECURITY_STATUS_NOT_SATISFIED {
+            // The card closes the secure channel on a wrong C-MAC.
+            debug!("secure channel closed by the card");
+            self.session = None;
+            return Err(Error::SecureChannel);
+        }
+        match session.unwrap(resp.data, resp.sw, rx) {
+            Some(n) => Ok(Response {
+                data: &rx[..n],
+                sw: resp.sw,
+            }),
+            None => {
+                self.session = None;
+                Err(Error::SecureChannel)
+            }
+        }
+    }
+}
+
+/// Append a length-value field, with a one-byte length.
+fn push_lv<E, const N: usize>(buf: &mut Vec<u8, N>, value: &[u8]) -> Result<(), Error<E>> {
+    let len = u8::try_from(value.len()).map_err(|_| Error::TooBig)?;
+    buf.push(len).map_err(|_| Error::TooBig)?;
+    buf.extend_from_slice(value).map_err(|_| Error::TooBig)
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::test_util::{FixedRng, mock};
+
+    const KEYS: Keys = Keys {
+        enc: DEFAULT_KEY,
+        mac: DEFAULT_KEY,
+    };
+    const HOST_CHALLENGE: &[u8] = &hex!("0102030405060708");
+
+    #[tokio::test]
+    async fn test_get_status() {
+        // Full security level: encrypted commands and responses, with C-MAC and R-MAC.
+        let r = mock!(
+            "00A4040008A00000015100000000" => "6F108408A000000151000000A5049F6501FF9000",
+            "8050000008010203040506070800" => "000102030405060708093003701122334455667788C9FEDCE39B144A9000002A9000",
+            "8482330010DE473C9D1F1A1BD89728218A38DE1FD4" => "9000",
+            "84F2400218B2ACBCDB4335C47CF63E86326F2381A0CFCE48B3FA59D02C00"
+                => "275E3967B9A3038D622A899D61300145137749C99E6B25944B2615129618E2F7C3767C90AD8CE7916310",
+            "84F2400318297C1B1AA78AD74FAB30C24291E3B002644455DA8E3343D400"
+                => "767C4C390F09C10D7E7A82FEAB4A5A420444A49C7E4131C0F36BE6F39F7C823B2263099D7BE4E5179000",
+            "84F2200218B055890B1BB9F763301A3F84AA8EBB9679AC06945ABE0FD600" => "6A88",
+            // Wrong R-MAC.
+            "84E4000018706C857AD2CB68DCD3BD21718A3D3B739FDEA3CB5D8C1F7700"
+                => "12485F51CFE9C370F74C10C9EB670C687B0E34FAB51289D59000",
+        );
+        let mut gp = Gp::new(r);
+        gp.select(ISD_AID).await.unwrap();
+        gp.open_secure_channel(
+            &mut FixedRng(HOST_CHALLENGE),
+            &KEYS,
+            0x00,
+            SecurityLevel::C_MAC_C_DEC_R_MAC_R_ENC,
+        )
+        .await
+        .unwrap();
+        assert!(gp.is_authenticated());
+
+        let apps = gp.get_status(Scope::Applications).await.unwrap();
+        assert_eq!(apps.len(), 2);
+        assert_eq!(apps[0].aid, ISD_AID);
+        assert_eq!(apps[0].lifecycle, 0x0F);
+        assert_eq!(apps[0].privileges, hex!("9EFE80"));
+        assert_eq!(apps[0].load_file, None);
+        assert_eq!(apps[1].aid, hex!("A0000006472F0001"));
+        assert_eq!(apps[1].lifecycle, 0x07);
+        assert_eq!(apps[1].privileges, hex!("000000"));
+        assert_eq!(apps[1].load_file.as_deref(), Some(&hex!("A0000006472F00")[..]));
+
+        assert!(gp.get_status(Scope::LoadFiles).await.unwrap().is_empty());
+
+        assert!(matches!(
+            gp.delete(&hex!("A0000006472F0001"), false).await,
+            Err(Error::SecureChannel)
+        ));
+        assert!(!gp.is_authenticated());
+        gp.into_inner().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_load_install() {
+        let r = mock!(
+            "8050000008010203040506070800" => "000102030405060708093003701122334455667788C9FEDCE39B144A909000",
+            "8482030010DE473C9D1F1A1BD84438AF2E3CDEBDC8" => "9000",
+            "84E6020028A734FA3CA36E5974C4DBFE7BDEB57D0840664FCE12D9883911EDA8FF3C51D66640F95C453569B3E800" => "009000",
+            "84E80000F8D43647B124248767BFC48B88F7D5BB873F8A2E4829F2894977EB621E9448B5513845C010F4FD49E30CF0EF0B7DBE0CEE8E15B49F96FBB788ACA3915A123A9EE7982653C335E5B3E649E763BE45DEE50319EA6E49261BAA69E2DF7FA11D0D0142966E96A909B20C87B7E601974D15C09B80D79233A2650768ECDC2E2A00E883D0B0A195CF92AF5595DCBE06F003BECE771E71DE7562E1344CCCFDAD6CB48C6F2BD090342E69DB54BAC9D6F3F87656B0B10416EE7A79A63178C78AAB7EEB28C468B3621072C8F0F843EA343BE35169FB5AD2A5E42B3C60BCEE8907C09277CF58EA4AE70B4CE4F9FE9C612CFADBD3424116C772D788C77DF9F900"
+                => "009000",
+            "84E88001580D3BCC2F23DC093A67F440B95A52F4AE77BD8BA5B40D1FC6757B964684907545B6EEEA90A30DD494EB3F42FEC9DEE9872368A19E98D348BBF32636ABBDABF68FF4E5FF11E6355FE177FC0A27BF4851BAD34857F711C0B45300"
+                => "009000",
+            "84E60C00381985B43495C56B1287C82F8C3D5C485E9BEE1AB49CCFDBC940EC877AF09B335FA993F8DB5514394C3C942EC34B5CC85C63D87BE1EBA55EA200"
+                => "009000",
+            "84E400801807E95B13AD05FADB972F2CA281E61A3FFD7F23EC32AB114B00" => "009000",
+        );
+        let mut gp = Gp::new(r);
+        gp.open_secure_channel(&mut FixedRng(HOST_CHALLENGE), &KEYS, 0x00, SecurityLevel::C_MAC_C_DEC)
+            .await
+            .unwrap();
+
+        let package = hex!("A0000006472F00");
+        let applet = hex!("A0000006472F0001");
+        let cap: std::vec::Vec<u8> = (0..300).map(|i| i as u8).collect();
+        gp.install_for_load(&package, ISD_AID).await.unwrap();
+        gp.load(&cap).await.unwrap();
+        gp.install(&package, &applet, &applet, &[0x00], &hex!("0102")).await.unwrap();
+        gp.delete(&package, true).await.unwrap();
+        gp.into_inner().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_wrong_keys() {
+        let r = mock!(
+            "8050000008010203040506070800" => "000102030405060708093003701122334455667788C9FEDCE39B144A909000",
+        );
+        let mut gp = Gp::new(r);
+        let keys = Keys {
+            enc: DEFAULT_KEY,
+            mac: [0; 16],
+        };
+        let res = gp
+            .open_secure_channel(&mut FixedRng(HOST_CHALLENGE), &keys, 0x00, SecurityLevel::C_MAC)
+            .await;
+        assert!(matches!(res, Err(Error::AuthFailed)));
+        assert!(!gp.is_authenticated());
+        gp.into_inner().assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_host_cryptogram_rejected() {
+        let r = mock!(
+            "8050000008010203040506070800" => "000102030405060708093003701122334455667788C9FEDCE39B144A909000",
+            "8482030010DE473C9D1F1A1BD84438AF2E3CDEBDC8" => "6300",
+        );
+        let mut gp = Gp::new(r);
+        let res = gp
+            .open_secure_channel(&mut FixedRng(HOST_CHALLENGE), &KEYS, 0x00, SecurityLevel::C_MAC_C_DEC)
+            .await;
+        assert!(matches!(res, Err(Error::AuthFailed)));
+        assert!(!gp.is_authenticated());
+        gp.into_inner().assert_done();
+    }
+}
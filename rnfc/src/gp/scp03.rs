@@ -0,0 +1,193 @@
+//! GlobalPlatform SCP03, Amendment D: AES-128 session keys, cryptograms, and command and
+//! response protection.
+//!
+//! Every command carries a C-MAC over the MAC chaining value, the header and the data, and
+//! the full CMAC becomes the next chaining value. With C-DECRYPTION the data is first
+//! encrypted, with an IV from the encryption counter. R-MAC covers the response data and
+//! status word, chained from the C-MAC of the command.
+
+use heapless::Vec;
+
+use crate::apdu::{Command, StatusWord};
+use crate::crypto::{Cipher, pad};
+
+/// Derivation constants.
+const DERIVE_CARD_CRYPTOGRAM: u8 = 0x00;
+const DERIVE_HOST_CRYPTOGRAM: u8 = 0x01;
+const DERIVE_S_ENC: u8 = 0x04;
+const DERIVE_S_MAC: u8 = 0x06;
+const DERIVE_S_RMAC: u8 = 0x07;
+
+/// Secure messaging CLA bit.
+const CLA_SECURE: u8 = 0x04;
+
+pub(crate) const MAC_LEN: usize = 8;
+pub(crate) const CRYPTOGRAM_LEN: usize = 8;
+pub(crate) const CHALLENGE_LEN: usize = 8;
+/// Longest command data that fits in a protected short APDU, with padding and the C-MAC.
+pub(crate) const MAX_COMMAND_DATA: usize = 255 - 16 - MAC_LEN;
+
+/// Security level of a secure channel, P1 of EXTERNAL AUTHENTICATE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SecurityLevel(pub u8);
+
+impl SecurityLevel {
+    pub const C_MAC: Self = Self(0x01);
+    pub const C_MAC_C_DEC: Self = Self(0x03);
+    pub const C_MAC_R_MAC: Self = Self(0x11);
+    pub const C_MAC_C_DEC_R_MAC: Self = Self(0x13);
+    pub const C_MAC_C_DEC_R_MAC_R_ENC: Self = Self(0x33);
+
+    pub fn c_dec(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    pub fn r_mac(&self) -> bool {
+        self.0 & 0x10 != 0
+    }
+
+    pub fn r_enc(&self) -> bool {
+        self.0 & 0x20 != 0
+    }
+}
+
+/// Static AES-128 keys of a key set.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Keys {
+    pub enc: [u8; 16],
+    pub mac: [u8; 16],
+}
+
+/// NIST SP 800-108 KDF in counter mode with AES-CMAC, for up to 128 bits.
+fn kdf(key: &[u8; 16], constant: u8, context: &[u8; 2 * CHALLENGE_LEN], bits: u16) -> [u8; 16] {
+    let mut data = [0; 32];
+    // Label: 11 zero bytes and the constant, then the separator zero byte.
+    data[11] = constant;
+    data[13..15].copy_from_slice(&bits.to_be_bytes());
+    data[15] = 0x01;
+    data[16..].copy_from_slice(context);
+    Cipher::aes(key).cmac(&[0; 16], &data)
+}
+
+/// Card and host cryptograms, and the session keys.
+pub(crate) struct Handshake {
+    pub card_cryptogram: [u8; CRYPTOGRAM_LEN],
+    pub host_cryptogram: [u8; CRYPTOGRAM_LEN],
+    pub session: Session,
+}
+
+impl Handshake {
+    pub fn new(keys: &Keys, host_challenge: &[u8; CHALLENGE_LEN], card_challenge: &[u8; CHALLENGE_LEN]) -> Self {
+        let mut context = [0; 2 * CHALLENGE_LEN];
+        context[..CHALLENGE_LEN].copy_from_slice(host_challenge);
+        context[CHALLENGE_LEN..].copy_from_slice(card_challenge);
+
+        let s_enc = kdf(&keys.enc, DERIVE_S_ENC, &context, 128);
+        let s_mac = kdf(&keys.mac, DERIVE_S_MAC, &context, 128);
+        let s_rmac = kdf(&keys.mac, DERIVE_S_RMAC, &context, 128);
+        let cryptogram = |constant| unwrap!(kdf(&s_mac, constant, &context, 64)[..CRYPTOGRAM_LEN].try_into());
+        Self {
+            card_cryptogram: cryptogram(DERIVE_CARD_CRYPTOGRAM),
+            host_cryptogram: cryptogram(DERIVE_HOST_CRYPTOGRAM),
+            session: Session {
+                enc: Cipher::aes(&s_enc),
+                mac: Cipher::aes(&s_mac),
+                rmac: Cipher::aes(&s_rmac),
+                chaining: [0; 16],
+                counter: 0,
+                level: SecurityLevel::C_MAC,
+            },
+        }
+    }
+}
+
+/// An open secure channel.
+pub(crate) struct Session {
+    enc: Cipher,
+    mac: Cipher,
+    rmac: Cipher,
+    /// MAC chaining value: the full CMAC of the last command.
+    chaining: [u8; 16],
+    /// Encryption counter, incremented for every command once C-DECRYPTION is on.
+    counter: u32,
+    level: SecurityLevel,
+}
+
+impl Session {
+    /// Switch to the security level of EXTERNAL AUTHENTICATE, once the card accepted it.
+    pub fn set_level(&mut self, level: SecurityLevel) {
+        self.level = level;
+    }
+
+    fn counter_block(&self, first: u8) -> [u8; 16] {
+        let mut iv = [0; 16];
+        iv[0] = first;
+        iv[12..].copy_from_slice(&self.counter.to_be_bytes());
+        self.enc.encrypt_block(&mut iv);
+        iv
+    }
+
+    /// Protect a command, returning the CLA byte and the data field with the C-MAC.
+    pub fn wrap(&mut self, cmd: &Command<'_>) -> Option<(u8, Vec<u8, 255>)> {
+        let cla = cmd.cla | CLA_SECURE;
+        let mut data = Vec::<u8, 255>::new();
+        if self.level.c_dec() {
+            self.counter = self.counter.wrapping_add(1);
+            if !cmd.data.is_empty() {
+                data.extend_from_slice(cmd.data).ok()?;
+                pad(&mut data, 16)?;
+                let mut iv = self.counter_block(0x00);
+                self.enc.cbc_encrypt(&mut iv, &mut data);
+            }
+        } else {
+            data.extend_from_slice(cmd.data).ok()?;
+        }
+        let lc = u8::try_from(data.len() + MAC_LEN).ok()?;
+
+        let mut input = Vec::<u8, { 16 + 5 + 255 }>::new();
+        input.extend_from_slice(&self.chaining).ok()?;
+        input.extend_from_slice(&[cla, cmd.ins, cmd.p1, cmd.p2, lc]).ok()?;
+        input.extend_from_slice(&data).ok()?;
+        self.chaining = self.mac.cmac(&[0; 16], &input);
+        data.extend_from_slice(&self.chaining[..MAC_LEN]).ok()?;
+        Some((cla, data))
+    }
+
+    /// Check and decrypt a response into `out`, returning the plain data length. `None` if
+    /// the R-MAC is wrong or the response is malformed.
+    pub fn unwrap(&mut self, data: &[u8], sw: StatusWord, out: &mut [u8]) -> Option<usize> {
+        // Error responses carry no R-MAC, only the status word.
+        let protected = sw.is_ok() || sw.is_warning();
+        let data = match self.level.r_mac() && protected {
+            true => {
+                let (data, mac) = data.split_at_checked(data.len().checked_sub(MAC_LEN)?)?;
+                let mut input = Vec::<u8, { 16 + 256 + 2 }>::new();
+                input.extend_from_slice(&self.chaining).ok()?;
+                input.extend_from_slice(data).ok()?;
+                input.extend_from_slice(&sw.0.to_be_bytes()).ok()?;
+                let expected = self.rmac.cmac(&[0; 16], &input);
+                if mac.iter().zip(&expected).fold(0, |acc, (a, b)| acc | (a ^ b)) != 0 {
+                    warn!("R-MAC mismatch");
+                    return None;
+                }
+                data
+            }
+            false => data,
+        };
+
+        let out = out.get_mut(..data.len())?;
+        out.copy_from_slice(data);
+        if self.level.r_enc() && protected && !data.is_empty() {
+            if !data.len().is_multiple_of(16) {
+                return None;
+            }
+            let mut iv = self.counter_block(0x80);
+            self.enc.cbc_decrypt(&mut iv, out);
+            let n = out.iter().rposition(|&b| b != 0x00)?;
+            (out[n] == 0x80).then_some(n)
+        } else {
+            Some(data.len())
+        }
+    }
+}
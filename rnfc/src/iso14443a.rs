@@ -1,28 +1,10 @@
 use heapless::Vec;
-use rnfc_traits::iso14443a::{Reader, UID_MAX_LEN};
+use rnfc_traits::iso14443a::{BitRate, RawFrame, Reader, UID_MAX_LEN};
 use rnfc_traits::iso14443a_ll as ll;
 use rnfc_traits::iso14443a_ll::{Frame, Reader as LLReader};
 
 use crate::fmt::Bytes;
 
-macro_rules! retry {
-    ($tries:literal, $expr:expr) => {{
-        let mut tries = $tries;
-        loop {
-            let r = $expr;
-            if let Ok(r) = r {
-                break Ok(r);
-            }
-
-            tries -= 1;
-
-            if tries == 0 {
-                break r;
-            }
-        }
-    }};
-}
-
 pub struct Poller<T: LLReader> {
     reader: T,
 }
@@ -34,6 +16,15 @@ pub enum Error<T> {
     Protocol,
 }
 
+impl<T: ll::Error> ll::Error for Error<T> {
+    fn kind(&self) -> ll::ErrorKind {
+        match self {
+            Self::Lower(l) => l.kind(),
+            Self::Protocol => ll::ErrorKind::Corruption,
+        }
+    }
+}
+
 impl<T: ll::Error> Error<T> {
     fn is_soft(&self) -> bool {
         match self {
@@ -119,7 +110,7 @@ impl<T: LLReader> Poller<T> {
         }
 
         let bcc = uid[0] ^ uid[1] ^ uid[2] ^ uid[3];
-        if bcc as u8 != rx[6] {
+        if bcc != rx[6] {
             debug!("bad BCC");
             return Err(Error::Protocol);
         }
@@ -220,7 +211,7 @@ impl<T: LLReader> Poller<T> {
             sak = retry!(4, self.transceive_select(cl as u8, uid_part).await)?;
         }
 
-        debug!("Got card! uid={} atqa={} sak={:02}", Bytes(&uid), Bytes(&atqa), sak);
+        debug!("Got card! uid={} atqa={} sak={:02}", Bytes(uid), Bytes(&atqa), sak);
 
         Ok(Card {
             reader: &mut self.reader,
@@ -294,6 +285,17 @@ impl<T: LLReader> Poller<T> {
     }
 }
 
+/// Compute the CRC_A of `data`, in transmission order.
+pub fn crc_a(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0x6363;
+    for &b in data {
+        let b = b ^ crc as u8;
+        let b = b ^ (b << 4);
+        crc = (crc >> 8) ^ ((b as u16) << 8) ^ ((b as u16) << 3) ^ ((b as u16) >> 4);
+    }
+    crc.to_le_bytes()
+}
+
 pub struct Card<'d, T: LLReader> {
     reader: &'d mut T,
 
@@ -303,17 +305,22 @@ pub struct Card<'d, T: LLReader> {
 }
 
 impl<'d, T: LLReader + 'd> Reader for Card<'d, T> {
-    type Error = T::Error;
+    type Error = Error<T::Error>;
 
     async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
         let opts = Frame::Standard { timeout_1fc };
-        let res = self.reader.transceive(tx, rx, opts).await?;
+        let res = self.reader.transceive(tx, rx, opts).await.map_err(Error::Lower)?;
         if res % 8 != 0 {
-            panic!("last byte was not complete!");
+            debug!("last byte was not complete: {} bits", res);
+            return Err(Error::Protocol);
         }
         Ok(res / 8)
     }
 
+    async fn transceive_raw(&mut self, tx: &[u8], rx: &mut [u8], opts: RawFrame) -> Result<usize, Self::Error> {
+        self.reader.transceive(tx, rx, Frame::Raw(opts)).await.map_err(Error::Lower)
+    }
+
     fn uid(&self) -> &[u8] {
         &self.uid
     }
@@ -325,4 +332,99 @@ impl<'d, T: LLReader + 'd> Reader for Card<'d, T> {
     fn sak(&self) -> u8 {
         self.sak
     }
+
+    fn supports_bit_rate(&self, bit_rate: BitRate) -> bool {
+        self.reader.supports_bit_rate(bit_rate)
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        self.reader.set_bit_rate(tx, rx).await.map_err(Error::Lower)
+    }
+
+    async fn delay(&mut self, delay_1fc: u32) {
+        self.reader.delay(delay_1fc).await
+    }
+
+    fn supports_reselect(&self) -> bool {
+        true
+    }
+
+    async fn reselect(&mut self, uid: &[u8]) -> Result<(), Self::Error> {
+        let mut poller = Poller::new(&mut *self.reader);
+        let card = poller.select_by_id(uid).await?;
+        let (atqa, sak) = (card.atqa, card.sak);
+        self.uid = card.uid.clone();
+        self.atqa = atqa;
+        self.sak = sak;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use rnfc_traits::iso14443a_ll::ErrorKind;
+
+    use super::*;
+
+    /// Expected tx bytes, and response bytes with their length in bits.
+    type Exchange = (&'static [u8], &'static [u8], usize);
+
+    struct MockReader {
+        expected: Vec<Exchange>,
+        pos: usize,
+    }
+
+    impl LLReader for MockReader {
+        type Error = ErrorKind;
+
+        async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], _: Frame) -> Result<usize, Self::Error> {
+            let (expected_tx, expected_rx, bits) = self.expected[self.pos];
+            assert_eq!(tx, expected_tx);
+            self.pos += 1;
+            rx[..expected_rx.len()].copy_from_slice(expected_rx);
+            Ok(bits)
+        }
+    }
+
+    #[test]
+    fn test_crc_a() {
+        assert_eq!(crc_a(&[0x00, 0x00]), [0xA0, 0x1E]);
+        assert_eq!(crc_a(&[0x12, 0x34]), [0x26, 0xCF]);
+        assert_eq!(crc_a(&[0x50, 0x00]), [0x57, 0xCD]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_partial_bytes() {
+        let mut mock = MockReader {
+            expected: vec![
+                (&[], &[0x44, 0x00], 16),
+                (&[0x93, 0x70, 0x88, 0x04, 0x11, 0x22, 0xbf], &[0x04], 8),
+                (&[0x95, 0x70, 0x33, 0x44, 0x55, 0x66, 0x44], &[0x00], 8),
+                // WRITE answered with a 4-bit ACK
+                (&[0xa2, 0x04, 0x01, 0x02, 0x03, 0x04], &[0x0a], 4),
+                (&[0xa2, 0x04, 0x01, 0x02, 0x03, 0x04], &[0x0a], 4),
+            ],
+            pos: 0,
+        };
+        let mut poller = Poller::new(&mut mock);
+        let mut card = poller
+            .select_by_id(&[0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66])
+            .await
+            .unwrap();
+
+        let tx = [0xa2, 0x04, 0x01, 0x02, 0x03, 0x04];
+        let mut rx = [0; 1];
+        let res = card.transceive(&tx, &mut rx, 65536).await;
+        assert!(matches!(res, Err(Error::Protocol)));
+
+        let opts = RawFrame {
+            rx_crc: false,
+            ..RawFrame::standard(65536, tx.len())
+        };
+        let bits = card.transceive_raw(&tx, &mut rx, opts).await.unwrap();
+        assert_eq!(bits, 4);
+        assert_eq!(rx[0] & 0x0f, 0x0a);
+    }
 }
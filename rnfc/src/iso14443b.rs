@@ -0,0 +1,456 @@
+use heapless::Vec;
+use rnfc_traits::iso14443b::{APPLICATION_DATA_LEN, PROTOCOL_INFO_MAX_LEN, PUPI_LEN, Reader};
+use rnfc_traits::iso14443b_ll as ll;
+use rnfc_traits::iso14443b_ll::{Frame, Reader as LLReader};
+
+use crate::fmt::Bytes;
+
+/// Anticollision prefix byte, used in REQB/WUPB and Slot-MARKER.
+const APF: u8 = 0x05;
+const CMD_ATTRIB: u8 = 0x1D;
+const CMD_HLTB: u8 = 0x50;
+const ATQB_HEADER: u8 = 0x50;
+
+/// AFI value that all cards answer to.
+const AFI_ALL: u8 = 0x00;
+
+/// Slot counts tried during anticollision, as the `N` exponent: 1, 4 and 16 slots.
+const SLOTS_EXP: [u8; 3] = [0, 2, 4];
+
+/// FSDI sent in ATTRIB. 8 means we can receive frames up to 256 bytes.
+const FSDI: u8 = 8;
+
+pub struct Poller<T: LLReader> {
+    reader: T,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<T> {
+    Lower(T),
+    Protocol,
+}
+
+impl<T: ll::Error> Error<T> {
+    fn is_soft(&self) -> bool {
+        match self {
+            Self::Lower(l) => l.kind() == ll::ErrorKind::Timeout,
+            Self::Protocol => true,
+        }
+    }
+
+    fn is_collision(&self) -> bool {
+        match self {
+            Self::Lower(l) => l.kind() == ll::ErrorKind::Corruption,
+            Self::Protocol => false,
+        }
+    }
+}
+
+/// Parsed protocol info from an ATQB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProtocolInfo {
+    /// Bit rates supported by the card.
+    pub bit_rate_capability: u8,
+    /// Max frame size the card can receive, encoded.
+    pub fsci: u8,
+    pub protocol_type: u8,
+    /// Frame waiting time integer.
+    pub fwi: u8,
+    /// Application data coding.
+    pub adc: u8,
+    /// Frame options: bit 1 = NAD supported, bit 0 = CID supported.
+    pub fo: u8,
+    /// Start-up frame guard time integer. Only present in extended ATQB, 0 otherwise.
+    pub sfgi: u8,
+}
+
+impl ProtocolInfo {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 3 {
+            return None;
+        }
+
+        Some(Self {
+            bit_rate_capability: data[0],
+            fsci: data[1] >> 4,
+            protocol_type: data[1] & 0x0F,
+            fwi: data[2] >> 4,
+            adc: (data[2] >> 2) & 0x03,
+            fo: data[2] & 0x03,
+            sfgi: data.get(3).map(|b| b >> 4).unwrap_or(0),
+        })
+    }
+
+    /// Whether the card is compliant with ISO/IEC 14443-4.
+    pub fn supports_iso_dep(&self) -> bool {
+        self.protocol_type & 0x01 != 0
+    }
+
+    pub fn supports_cid(&self) -> bool {
+        self.fo & 0x01 != 0
+    }
+
+    pub fn supports_nad(&self) -> bool {
+        self.fo & 0x02 != 0
+    }
+
+    /// Frame Waiting Time, in units of 1/Fc
+    pub fn fwt_1fc(&self) -> u32 {
+        // FWT = (256 x 16 / fc) x 2^FWI. FWI=15 is RFU, treat it as 4.
+        let fwi = if self.fwi == 15 { 4 } else { self.fwi };
+        (256 * 16) << fwi
+    }
+
+    /// Start-up frame guard time, in units of 1/Fc
+    pub fn sfgt_1fc(&self) -> u32 {
+        // SFGT = (256 x 16 / fc) x 2^SFGI. SFGI=15 is RFU, treat it as 0.
+        let sfgi = if self.sfgi == 15 { 0 } else { self.sfgi };
+        (256 * 16) << sfgi
+    }
+}
+
+struct Atqb {
+    pupi: [u8; PUPI_LEN],
+    application_data: [u8; APPLICATION_DATA_LEN],
+    protocol_info: Vec<u8, PROTOCOL_INFO_MAX_LEN>,
+}
+
+impl<T: LLReader> Poller<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader }
+    }
+
+    async fn transceive_atqb(&mut self, tx: &[u8], opts: Frame) -> Result<Atqb, Error<T::Error>> {
+        let mut rx = [0; 13];
+        let n = self.reader.transceive(tx, &mut rx, opts).await.map_err(Error::Lower)?;
+        if n != 12 && n != 13 {
+            debug!("ATQB wrong length: {} bytes", n);
+            return Err(Error::Protocol);
+        }
+        if rx[0] != ATQB_HEADER {
+            debug!("ATQB wrong header: {:02x}", rx[0]);
+            return Err(Error::Protocol);
+        }
+
+        Ok(Atqb {
+            pupi: rx[1..5].try_into().unwrap(),
+            application_data: rx[5..9].try_into().unwrap(),
+            protocol_info: Vec::from_slice(&rx[9..n]).unwrap(),
+        })
+    }
+
+    async fn transceive_reqb(&mut self, wakeup: bool, slots_exp: u8) -> Result<Atqb, Error<T::Error>> {
+        let param = (wakeup as u8) << 3 | slots_exp;
+        let tx = [APF, AFI_ALL, param];
+        let opts = if wakeup { Frame::WupB } else { Frame::ReqB };
+        self.transceive_atqb(&tx, opts).await
+    }
+
+    async fn transceive_slot_marker(&mut self, slot: u8) -> Result<Atqb, Error<T::Error>> {
+        let tx = [(slot - 1) << 4 | APF];
+        self.transceive_atqb(&tx, Frame::SlotMarker { slot }).await
+    }
+
+    async fn transceive_attrib(&mut self, atqb: &Atqb) -> Result<(), Error<T::Error>> {
+        let info = ProtocolInfo::parse(&atqb.protocol_info).ok_or(Error::Protocol)?;
+
+        let mut tx = [0; 9];
+        tx[0] = CMD_ATTRIB;
+        tx[1..5].copy_from_slice(&atqb.pupi);
+        tx[5] = 0x00; // Param 1: default TR0, TR1, SOF and EOF
+        tx[6] = FSDI; // Param 2: 106kbps both directions
+        tx[7] = info.protocol_type & 0x01; // Param 3
+        tx[8] = 0x00; // Param 4: CID 0
+
+        let mut rx = [0; 1];
+        let opts = Frame::Standard {
+            timeout_1fc: info.fwt_1fc(),
+        };
+        let n = self.reader.transceive(&tx, &mut rx, opts).await.map_err(Error::Lower)?;
+        if n != 1 {
+            debug!("ATTRIB response wrong length: {} bytes", n);
+            return Err(Error::Protocol);
+        }
+        if rx[0] & 0x0F != 0 {
+            debug!("ATTRIB response wrong CID: {:02x}", rx[0]);
+            return Err(Error::Protocol);
+        }
+        Ok(())
+    }
+
+    async fn transceive_hltb(&mut self, pupi: [u8; PUPI_LEN]) -> Result<(), Error<T::Error>> {
+        let mut tx = [0; 5];
+        tx[0] = CMD_HLTB;
+        tx[1..].copy_from_slice(&pupi);
+        let mut rx = [0; 1];
+        let opts = Frame::Standard { timeout_1fc: 65536 };
+        let n = self.reader.transceive(&tx, &mut rx, opts).await.map_err(Error::Lower)?;
+        if n != 1 || rx[0] != 0x00 {
+            debug!("HLTB bad response");
+            return Err(Error::Protocol);
+        }
+        Ok(())
+    }
+
+    /// Run slotted anticollision until a card accepted by `want` answers.
+    ///
+    /// The number of slots is increased while collisions are detected.
+    async fn anticoll(&mut self, wakeup: bool, mut want: impl FnMut(&Atqb) -> bool) -> Result<Atqb, Error<T::Error>> {
+        let mut last_err = Error::Protocol;
+
+        for slots_exp in SLOTS_EXP {
+            let mut collision = false;
+
+            for slot in 1..=(1u8 << slots_exp) {
+                let res = match slot {
+                    1 => self.transceive_reqb(wakeup, slots_exp).await,
+                    _ => self.transceive_slot_marker(slot).await,
+                };
+
+                match res {
+                    Ok(atqb) if want(&atqb) => return Ok(atqb),
+                    Ok(_) => {}
+                    Err(e) if e.is_collision() => {
+                        debug!("anticoll: collision in slot {}", slot);
+                        collision = true;
+                        last_err = e;
+                    }
+                    Err(e) if e.is_soft() => last_err = e,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if !collision {
+                break;
+            }
+        }
+
+        Err(last_err)
+    }
+
+    fn card(&mut self, atqb: Atqb) -> Card<'_, T> {
+        debug!(
+            "Got card! pupi={} app_data={} protocol_info={}",
+            Bytes(&atqb.pupi),
+            Bytes(&atqb.application_data),
+            Bytes(&atqb.protocol_info)
+        );
+
+        Card {
+            reader: &mut self.reader,
+            pupi: atqb.pupi,
+            application_data: atqb.application_data,
+            protocol_info: atqb.protocol_info,
+        }
+    }
+
+    pub async fn select_any(&mut self) -> Result<Card<'_, T>, Error<T::Error>> {
+        let atqb = retry!(4, self.anticoll(true, |_| true).await)?;
+        retry!(4, self.transceive_attrib(&atqb).await)?;
+        Ok(self.card(atqb))
+    }
+
+    pub async fn select_by_id(&mut self, pupi: &[u8]) -> Result<Card<'_, T>, Error<T::Error>> {
+        if pupi.len() != PUPI_LEN {
+            debug!("Invalid PUPI length {}", pupi.len());
+            return Err(Error::Protocol);
+        }
+
+        let atqb = retry!(4, self.anticoll(true, |atqb| atqb.pupi == pupi).await)?;
+        retry!(4, self.transceive_attrib(&atqb).await)?;
+        Ok(self.card(atqb))
+    }
+
+    /// Search for all cards in the field, and return a list of their PUPIs.
+    /// You can connect to one with [`Self::select_by_id`].
+    pub async fn search<const N: usize>(&mut self) -> Result<Vec<[u8; PUPI_LEN], N>, Error<T::Error>> {
+        let mut res: Vec<[u8; PUPI_LEN], N> = Vec::new();
+
+        for _ in 0..(N * 4) {
+            // Halted cards don't answer REQB, so each round finds a new card.
+            let atqb = match self.anticoll(false, |_| true).await {
+                Ok(x) => x,
+                Err(e) if e.is_soft() || e.is_collision() => break,
+                Err(e) => return Err(e),
+            };
+
+            debug!("Got card! pupi={}", Bytes(&atqb.pupi));
+            let _ = self.transceive_hltb(atqb.pupi).await;
+
+            if !res.contains(&atqb.pupi) {
+                res.push(atqb.pupi).unwrap();
+                if res.is_full() {
+                    break;
+                }
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+pub struct Card<'d, T: LLReader> {
+    reader: &'d mut T,
+
+    pupi: [u8; PUPI_LEN],
+    application_data: [u8; APPLICATION_DATA_LEN],
+    protocol_info: Vec<u8, PROTOCOL_INFO_MAX_LEN>,
+}
+
+impl<'d, T: LLReader + 'd> Reader for Card<'d, T> {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        let opts = Frame::Standard { timeout_1fc };
+        self.reader.transceive(tx, rx, opts).await
+    }
+
+    fn pupi(&self) -> [u8; PUPI_LEN] {
+        self.pupi
+    }
+
+    fn application_data(&self) -> [u8; APPLICATION_DATA_LEN] {
+        self.application_data
+    }
+
+    fn protocol_info(&self) -> &[u8] {
+        &self.protocol_info
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use rnfc_traits::iso14443b::Reader as _;
+    use rnfc_traits::iso14443b_ll::ErrorKind;
+
+    use super::*;
+
+    type Exchange = (&'static [u8], Result<&'static [u8], ErrorKind>);
+
+    struct MockReader {
+        expected: Vec<Exchange>,
+        pos: usize,
+    }
+
+    macro_rules! mock {
+        (@res $rx:literal) => {
+            Ok(&hex_literal::hex!($rx))
+        };
+        (@res timeout) => {
+            Err(ErrorKind::Timeout)
+        };
+        (@res collision) => {
+            Err(ErrorKind::Corruption)
+        };
+        ($($tx:literal => $rx:tt,)*) => {
+            MockReader {
+                expected: vec![
+                    $((&hex_literal::hex!($tx), mock!(@res $rx)),)*
+                ],
+                pos: 0,
+            }
+        };
+    }
+
+    impl LLReader for MockReader {
+        type Error = ErrorKind;
+
+        async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], _: Frame) -> Result<usize, Self::Error> {
+            if self.pos >= self.expected.len() {
+                panic!("unexpected transceive!\n         got: {:02x?}", tx);
+            }
+
+            let (expected_tx, expected_rx) = self.expected[self.pos];
+            if tx != expected_tx {
+                panic!(
+                    "unexpected tx!\n    expected: {:02x?}\n         got: {:02x?}",
+                    expected_tx, tx
+                );
+            }
+
+            self.pos += 1;
+            match expected_rx {
+                Ok(expected_rx) => {
+                    rx[..expected_rx.len()].copy_from_slice(expected_rx);
+                    Ok(expected_rx.len())
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_protocol_info() {
+        let info = ProtocolInfo::parse(&[0x00, 0x81, 0x71]).unwrap();
+        assert_eq!(info.fsci, 8);
+        assert!(info.supports_iso_dep());
+        assert!(info.supports_cid());
+        assert!(!info.supports_nad());
+        assert_eq!(info.fwt_1fc(), (256 * 16) << 7);
+        assert_eq!(info.sfgt_1fc(), 256 * 16);
+
+        let info = ProtocolInfo::parse(&[0x00, 0x81, 0x71, 0x20]).unwrap();
+        assert_eq!(info.sfgt_1fc(), (256 * 16) << 2);
+
+        assert_eq!(ProtocolInfo::parse(&[0x00, 0x81]), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_select_any_single() {
+        let mut mock = mock!(
+            "05 00 08" => "50 11 22 33 44 aa bb cc dd 00 81 71",
+            "1d 11 22 33 44 00 08 01 00" => "10",
+        );
+        let mut poller = Poller::new(&mut mock);
+        let card = poller.select_any().await.unwrap();
+        assert_eq!(card.pupi(), [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(card.application_data(), [0xaa, 0xbb, 0xcc, 0xdd]);
+        assert_eq!(card.protocol_info(), &[0x00, 0x81, 0x71]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_select_any_collision() {
+        let mut mock = mock!(
+            "05 00 08" => collision,
+            "05 00 0a" => timeout,
+            "15" => "50 11 22 33 44 aa bb cc dd 00 81 71",
+            "1d 11 22 33 44 00 08 01 00" => "00",
+        );
+        let mut poller = Poller::new(&mut mock);
+        let card = poller.select_any().await.unwrap();
+        assert_eq!(card.pupi(), [0x11, 0x22, 0x33, 0x44]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_select_by_id() {
+        let mut mock = mock!(
+            "05 00 08" => collision,
+            "05 00 0a" => "50 11 22 33 44 aa bb cc dd 00 81 71",
+            "15" => timeout,
+            "25" => "50 55 66 77 88 aa bb cc dd 00 81 71",
+            "1d 55 66 77 88 00 08 01 00" => "00",
+        );
+        let mut poller = Poller::new(&mut mock);
+        let card = poller.select_by_id(&[0x55, 0x66, 0x77, 0x88]).await.unwrap();
+        assert_eq!(card.pupi(), [0x55, 0x66, 0x77, 0x88]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_search() {
+        let mut mock = mock!(
+            "05 00 00" => collision,
+            "05 00 02" => "50 11 22 33 44 aa bb cc dd 00 81 71",
+            "50 11 22 33 44" => "00",
+            "05 00 00" => "50 55 66 77 88 aa bb cc dd 00 81 71",
+            "50 55 66 77 88" => "00",
+            "05 00 00" => timeout,
+        );
+        let mut poller = Poller::new(&mut mock);
+        let cards = poller.search::<4>().await.unwrap();
+        assert_eq!(&cards[..], &[[0x11, 0x22, 0x33, 0x44], [0x55, 0x66, 0x77, 0x88]]);
+    }
+}
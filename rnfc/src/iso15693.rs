@@ -0,0 +1,709 @@
+use heapless::Vec;
+use rnfc_traits::iso15693 as ll;
+use rnfc_traits::iso15693::{Error as _, Frame, Reader as LLReader, UID_LEN};
+
+use crate::fmt::Bytes;
+
+// Request flags
+const FLAG_DATA_RATE: u8 = 0x02;
+const FLAG_INVENTORY: u8 = 0x04;
+// ... when the inventory flag is not set
+const FLAG_SELECT: u8 = 0x10;
+const FLAG_ADDRESS: u8 = 0x20;
+// ... when the inventory flag is set
+const FLAG_AFI: u8 = 0x10;
+const FLAG_ONE_SLOT: u8 = 0x20;
+
+// Response flags
+const FLAG_ERROR: u8 = 0x01;
+
+const CMD_INVENTORY: u8 = 0x01;
+const CMD_STAY_QUIET: u8 = 0x02;
+const CMD_READ_SINGLE_BLOCK: u8 = 0x20;
+const CMD_WRITE_SINGLE_BLOCK: u8 = 0x21;
+const CMD_READ_MULTIPLE_BLOCKS: u8 = 0x23;
+const CMD_WRITE_MULTIPLE_BLOCKS: u8 = 0x24;
+const CMD_SELECT: u8 = 0x25;
+const CMD_RESET_TO_READY: u8 = 0x26;
+const CMD_GET_SYSTEM_INFO: u8 = 0x2B;
+
+/// Response waiting time: t1 max (4384/fc) plus margin.
+const FWT_1FC: u32 = 4916;
+/// Response waiting time for writes, 20ms.
+const FWT_WRITE_1FC: u32 = 271_200;
+
+/// Max number of pending collisions during inventory.
+const MAX_COLLISIONS: usize = 32;
+
+const FRAME_MAX_LEN: usize = 256;
+
+pub struct Poller<T: LLReader> {
+    reader: T,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<T> {
+    Lower(T),
+    Protocol,
+    /// The tag returned an error code.
+    Status {
+        code: u8,
+    },
+    /// The request doesn't fit in a frame.
+    TooBig,
+    /// No blocks, or the data length isn't a whole number of blocks.
+    InvalidLength,
+}
+
+impl<T: ll::Error> Error<T> {
+    fn is_soft(&self) -> bool {
+        match self {
+            Self::Lower(l) => l.kind() == ll::ErrorKind::Timeout,
+            Self::Protocol => true,
+            Self::Status { .. } | Self::TooBig | Self::InvalidLength => false,
+        }
+    }
+
+    fn is_collision(&self) -> bool {
+        match self {
+            Self::Lower(l) => l.kind() == ll::ErrorKind::Corruption,
+            _ => false,
+        }
+    }
+}
+
+/// Number of slots used in INVENTORY.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Slots {
+    One,
+    Sixteen,
+}
+
+impl Slots {
+    /// Longest INVENTORY mask. With 16 slots, the slot number takes the 4 UID bits after it.
+    fn max_mask_len(self) -> u8 {
+        match self {
+            Self::One => 64,
+            Self::Sixteen => 60,
+        }
+    }
+}
+
+/// A tag found during inventory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Target {
+    /// UID, LSB first as transmitted over the air.
+    pub uid: [u8; UID_LEN],
+    pub dsfid: u8,
+}
+
+impl<T: LLReader> Poller<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader }
+    }
+
+    async fn transceive_inventory(
+        &mut self,
+        afi: Option<u8>,
+        slots: Slots,
+        mask: u64,
+        mask_len: u8,
+        slot: u8,
+    ) -> Result<Target, Error<T::Error>> {
+        let mut rx = [0; 10];
+        let n = if slot == 0 {
+            let mut flags = FLAG_DATA_RATE | FLAG_INVENTORY;
+            if afi.is_some() {
+                flags |= FLAG_AFI;
+            }
+            if slots == Slots::One {
+                flags |= FLAG_ONE_SLOT;
+            }
+
+            let mut tx = [0; 13];
+            tx[0] = flags;
+            tx[1] = CMD_INVENTORY;
+            let mut pos = 2;
+            if let Some(afi) = afi {
+                tx[pos] = afi;
+                pos += 1;
+            }
+            tx[pos] = mask_len;
+            pos += 1;
+            let mask_bytes = (mask_len as usize).div_ceil(8);
+            tx[pos..][..mask_bytes].copy_from_slice(&mask.to_le_bytes()[..mask_bytes]);
+            pos += mask_bytes;
+
+            let opts = Frame::Standard { timeout_1fc: FWT_1FC };
+            self.reader.transceive(&tx[..pos], &mut rx, opts).await
+        } else {
+            let opts = Frame::Eof { timeout_1fc: FWT_1FC };
+            self.reader.transceive(&[], &mut rx, opts).await
+        }
+        .map_err(Error::Lower)?;
+
+        if n != 10 || rx[0] & FLAG_ERROR != 0 {
+            debug!("INVENTORY bad response: {}", Bytes(&rx[..n]));
+            return Err(Error::Protocol);
+        }
+
+        Ok(Target {
+            dsfid: rx[1],
+            uid: rx[2..10].try_into().unwrap(),
+        })
+    }
+
+    /// Find all tags in the field with INVENTORY, resolving collisions with the mask.
+    pub async fn inventory<const N: usize>(
+        &mut self,
+        afi: Option<u8>,
+        slots: Slots,
+    ) -> Result<Vec<Target, N>, Error<T::Error>> {
+        let mut res: Vec<Target, N> = Vec::new();
+
+        // Masks still to be searched.
+        let mut pending: Vec<(u64, u8), MAX_COLLISIONS> = Vec::new();
+        pending.push((0, 0)).unwrap();
+
+        while let Some((mask, mask_len)) = pending.pop() {
+            let slot_count = match slots {
+                Slots::One => 1,
+                Slots::Sixteen => 16,
+            };
+
+            for slot in 0..slot_count {
+                match self.transceive_inventory(afi, slots, mask, mask_len, slot).await {
+                    Ok(target) => {
+                        debug!("Got tag! uid={} dsfid={:02x}", Bytes(&target.uid), target.dsfid);
+                        if !res.contains(&target) && res.push(target).is_err() {
+                            return Ok(res);
+                        }
+                    }
+                    Err(e) if e.is_collision() => {
+                        debug!("inventory: collision in slot {} mask_len {}", slot, mask_len);
+
+                        let (children, step): (&[u64], u8) = match slots {
+                            Slots::One => (&[0, 1], 1),
+                            Slots::Sixteen => (&[slot as u64], 4),
+                        };
+                        if mask_len + step > slots.max_mask_len() {
+                            debug!("inventory: collision with full mask");
+                            return Err(Error::Protocol);
+                        }
+                        for &c in children {
+                            if pending.push((mask | c << mask_len, mask_len + step)).is_err() {
+                                warn!("inventory: too many collisions, some tags will be missed");
+                            }
+                        }
+                    }
+                    Err(e) if e.is_soft() => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    pub async fn select_any(&mut self) -> Result<Card<'_, T>, Error<T::Error>> {
+        let targets = retry!(
+            4,
+            match self.inventory::<1>(None, Slots::Sixteen).await {
+                Ok(targets) if targets.is_empty() => {
+                    debug!("no tags found");
+                    Err(Error::Protocol)
+                }
+                r => r,
+            }
+        )?;
+        Ok(self.card(targets[0]))
+    }
+
+    /// Check the tag with this UID is present with an inventory masked with the full UID,
+    /// and return it in addressed mode.
+    pub async fn select_by_id(&mut self, uid: &[u8]) -> Result<Card<'_, T>, Error<T::Error>> {
+        let Ok(uid) = <[u8; UID_LEN]>::try_from(uid) else {
+            debug!("Invalid UID length {}", uid.len());
+            return Err(Error::Protocol);
+        };
+
+        let mask = u64::from_le_bytes(uid);
+        let target = retry!(4, self.transceive_inventory(None, Slots::One, mask, 64, 0).await)?;
+        if target.uid != uid {
+            debug!("inventory: got wrong UID {}", Bytes(&target.uid));
+            return Err(Error::Protocol);
+        }
+        Ok(self.card(target))
+    }
+
+    fn card(&mut self, target: Target) -> Card<'_, T> {
+        Card {
+            reader: &mut self.reader,
+            uid: target.uid,
+            dsfid: target.dsfid,
+            mode: Mode::Addressed,
+        }
+    }
+}
+
+/// How requests to a tag are addressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Requests carry no UID, all tags in the field execute them.
+    NonAddressed,
+    /// Requests carry the UID of the tag.
+    Addressed,
+    /// Requests carry no UID, only the tag in the Selected state executes them.
+    Selected,
+}
+
+/// Parsed GET SYSTEM INFO response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SystemInfo {
+    /// UID, LSB first as transmitted over the air.
+    pub uid: [u8; UID_LEN],
+    pub dsfid: Option<u8>,
+    pub afi: Option<u8>,
+    pub block_count: Option<u16>,
+    pub block_size: Option<u8>,
+    pub ic_reference: Option<u8>,
+}
+
+pub struct Card<'d, T: LLReader> {
+    reader: &'d mut T,
+
+    uid: [u8; UID_LEN],
+    dsfid: u8,
+    mode: Mode,
+}
+
+impl<'d, T: LLReader + 'd> Card<'d, T> {
+    pub fn uid(&self) -> [u8; UID_LEN] {
+        self.uid
+    }
+
+    pub fn dsfid(&self) -> u8 {
+        self.dsfid
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Set the addressing mode for following requests.
+    ///
+    /// This doesn't send anything to the tag. To enter [`Mode::Selected`] use [`Self::select`] instead.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Write the request flags, command and UID if addressed. Returns the position after them.
+    fn build_request(&self, tx: &mut [u8], cmd: u8, mode: Mode) -> usize {
+        tx[0] = FLAG_DATA_RATE
+            | match mode {
+                Mode::NonAddressed => 0,
+                Mode::Addressed => FLAG_ADDRESS,
+                Mode::Selected => FLAG_SELECT,
+            };
+        tx[1] = cmd;
+        if mode == Mode::Addressed {
+            tx[2..10].copy_from_slice(&self.uid);
+            10
+        } else {
+            2
+        }
+    }
+
+    /// Send a request and check the response flags. Returns the response length, including the flags.
+    async fn request(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Error<T::Error>> {
+        let opts = Frame::Standard { timeout_1fc };
+        let n = self.reader.transceive(tx, rx, opts).await.map_err(Error::Lower)?;
+        if n == 0 {
+            debug!("empty response");
+            return Err(Error::Protocol);
+        }
+        if rx[0] & FLAG_ERROR != 0 {
+            if n < 2 {
+                debug!("error response without code");
+                return Err(Error::Protocol);
+            }
+            debug!("error code {:02x}", rx[1]);
+            return Err(Error::Status { code: rx[1] });
+        }
+        Ok(n)
+    }
+
+    /// Put the tag in the Quiet state, it will then only answer requests in addressed mode.
+    pub async fn stay_quiet(&mut self) -> Result<(), Error<T::Error>> {
+        let mut tx = [0; 10];
+        let n = self.build_request(&mut tx, CMD_STAY_QUIET, Mode::Addressed);
+
+        // There's no response to STAY QUIET.
+        let mut rx = [0; 2];
+        let opts = Frame::Standard { timeout_1fc: FWT_1FC };
+        match self.reader.transceive(&tx[..n], &mut rx, opts).await {
+            Err(e) if e.kind() == ll::ErrorKind::Timeout => Ok(()),
+            Err(e) => Err(Error::Lower(e)),
+            Ok(_) => {
+                debug!("unexpected response to STAY QUIET");
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Put the tag in the Selected state, and switch to [`Mode::Selected`].
+    pub async fn select(&mut self) -> Result<(), Error<T::Error>> {
+        let mut tx = [0; 10];
+        let n = self.build_request(&mut tx, CMD_SELECT, Mode::Addressed);
+        let mut rx = [0; 2];
+        self.request(&tx[..n], &mut rx, FWT_1FC).await?;
+        self.mode = Mode::Selected;
+        Ok(())
+    }
+
+    /// Put the tag back in the Ready state. If it was selected, switch to [`Mode::Addressed`].
+    pub async fn reset_to_ready(&mut self) -> Result<(), Error<T::Error>> {
+        let mut tx = [0; 10];
+        let n = self.build_request(&mut tx, CMD_RESET_TO_READY, self.mode);
+        let mut rx = [0; 2];
+        self.request(&tx[..n], &mut rx, FWT_1FC).await?;
+        if self.mode == Mode::Selected {
+            self.mode = Mode::Addressed;
+        }
+        Ok(())
+    }
+
+    /// Read a block. Returns the block size.
+    pub async fn read_single_block(&mut self, block: u8, data: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        let mut tx = [0; 11];
+        let n = self.build_request(&mut tx, CMD_READ_SINGLE_BLOCK, self.mode);
+        tx[n] = block;
+
+        let mut rx = [0; FRAME_MAX_LEN];
+        let rx_len = self.request(&tx[..n + 1], &mut rx, FWT_1FC).await?;
+        let res = &rx[1..rx_len];
+        if res.len() > data.len() {
+            debug!("read: buffer too small");
+            return Err(Error::Protocol);
+        }
+        data[..res.len()].copy_from_slice(res);
+        Ok(res.len())
+    }
+
+    /// Write a block. `data` must be exactly the block size.
+    pub async fn write_single_block(&mut self, block: u8, data: &[u8]) -> Result<(), Error<T::Error>> {
+        if data.is_empty() {
+            return Err(Error::InvalidLength);
+        }
+
+        let mut tx = [0; FRAME_MAX_LEN];
+        let n = self.build_request(&mut tx, CMD_WRITE_SINGLE_BLOCK, self.mode);
+        if n + 1 + data.len() > FRAME_MAX_LEN {
+            return Err(Error::TooBig);
+        }
+        tx[n] = block;
+        tx[n + 1..][..data.len()].copy_from_slice(data);
+
+        let mut rx = [0; 2];
+        self.request(&tx[..n + 1 + data.len()], &mut rx, FWT_WRITE_1FC).await?;
+        Ok(())
+    }
+
+    /// Read `count` blocks starting at `first`. Returns the total number of bytes read.
+    pub async fn read_multiple_blocks(&mut self, first: u8, count: u8, data: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        if count == 0 {
+            return Err(Error::InvalidLength);
+        }
+
+        let mut tx = [0; 12];
+        let n = self.build_request(&mut tx, CMD_READ_MULTIPLE_BLOCKS, self.mode);
+        tx[n] = first;
+        tx[n + 1] = count - 1;
+
+        let mut rx = [0; FRAME_MAX_LEN];
+        let rx_len = self.request(&tx[..n + 2], &mut rx, FWT_1FC).await?;
+        let res = &rx[1..rx_len];
+        if res.len() > data.len() || res.len() % count as usize != 0 {
+            debug!("read multiple: bad response length {}", res.len());
+            return Err(Error::Protocol);
+        }
+        data[..res.len()].copy_from_slice(res);
+        Ok(res.len())
+    }
+
+    /// Write `count` blocks starting at `first`. `data` must be exactly `count` times the block size.
+    pub async fn write_multiple_blocks(&mut self, first: u8, count: u8, data: &[u8]) -> Result<(), Error<T::Error>> {
+        if count == 0 || data.is_empty() || !data.len().is_multiple_of(count as usize) {
+            return Err(Error::InvalidLength);
+        }
+
+        let mut tx = [0; FRAME_MAX_LEN];
+        let n = self.build_request(&mut tx, CMD_WRITE_MULTIPLE_BLOCKS, self.mode);
+        if n + 2 + data.len() > FRAME_MAX_LEN {
+            return Err(Error::TooBig);
+        }
+        tx[n] = first;
+        tx[n + 1] = count - 1;
+        tx[n + 2..][..data.len()].copy_from_slice(data);
+
+        let mut rx = [0; 2];
+        self.request(&tx[..n + 2 + data.len()], &mut rx, FWT_WRITE_1FC).await?;
+        Ok(())
+    }
+
+    pub async fn get_system_info(&mut self) -> Result<SystemInfo, Error<T::Error>> {
+        let mut tx = [0; 10];
+        let n = self.build_request(&mut tx, CMD_GET_SYSTEM_INFO, self.mode);
+
+        let mut rx = [0; 32];
+        let rx_len = self.request(&tx[..n], &mut rx, FWT_1FC).await?;
+        let res = &rx[..rx_len];
+        if res.len() < 10 {
+            debug!("system info too short");
+            return Err(Error::Protocol);
+        }
+
+        let info_flags = res[1];
+        let mut info = SystemInfo {
+            uid: res[2..10].try_into().unwrap(),
+            dsfid: None,
+            afi: None,
+            block_count: None,
+            block_size: None,
+            ic_reference: None,
+        };
+
+        let mut rest = &res[10..];
+        let mut take = |len: usize| {
+            if rest.len() < len {
+                debug!("system info too short");
+                return Err(Error::Protocol);
+            }
+            let (a, b) = rest.split_at(len);
+            rest = b;
+            Ok(a)
+        };
+
+        if info_flags & 0x01 != 0 {
+            info.dsfid = Some(take(1)?[0]);
+        }
+        if info_flags & 0x02 != 0 {
+            info.afi = Some(take(1)?[0]);
+        }
+        if info_flags & 0x04 != 0 {
+            let mem = take(2)?;
+            info.block_count = Some(mem[0] as u16 + 1);
+            info.block_size = Some((mem[1] & 0x1F) + 1);
+        }
+        if info_flags & 0x08 != 0 {
+            info.ic_reference = Some(take(1)?[0]);
+        }
+
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use rnfc_traits::iso15693::ErrorKind;
+
+    use super::*;
+
+    type Exchange = (&'static [u8], Result<&'static [u8], ErrorKind>);
+
+    struct MockReader {
+        expected: Vec<Exchange>,
+        pos: usize,
+    }
+
+    macro_rules! mock {
+        (@res $rx:literal) => {
+            Ok(&hex_literal::hex!($rx))
+        };
+        (@res timeout) => {
+            Err(ErrorKind::Timeout)
+        };
+        (@res collision) => {
+            Err(ErrorKind::Corruption)
+        };
+        ($($tx:literal => $rx:tt,)*) => {
+            MockReader {
+                expected: vec![
+                    $((&hex_literal::hex!($tx), mock!(@res $rx)),)*
+                ],
+                pos: 0,
+            }
+        };
+    }
+
+    impl LLReader for MockReader {
+        type Error = ErrorKind;
+
+        async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], _: Frame) -> Result<usize, Self::Error> {
+            if self.pos >= self.expected.len() {
+                panic!("unexpected transceive!\n         got: {:02x?}", tx);
+            }
+
+            let (expected_tx, expected_rx) = self.expected[self.pos];
+            if tx != expected_tx {
+                panic!(
+                    "unexpected tx!\n    expected: {:02x?}\n         got: {:02x?}",
+                    expected_tx, tx
+                );
+            }
+
+            self.pos += 1;
+            match expected_rx {
+                Ok(expected_rx) => {
+                    rx[..expected_rx.len()].copy_from_slice(expected_rx);
+                    Ok(expected_rx.len())
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_inventory_16_slots() {
+        let mut mock = mock!(
+            "06 01 00" => timeout,
+            "" => "00 00 11 22 33 44 55 66 07 e0",
+            "" => timeout,
+            "" => collision,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            // resolve the collision in slot 3
+            "06 01 04 03" => timeout,
+            "" => "00 00 13 22 33 44 55 66 07 e0",
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => "00 00 53 22 33 44 55 66 07 e0",
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+            "" => timeout,
+        );
+        let mut poller = Poller::new(&mut mock);
+        let tags = poller.inventory::<8>(None, Slots::Sixteen).await.unwrap();
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[0].uid, hex_literal::hex!("11 22 33 44 55 66 07 e0"));
+        assert_eq!(tags[1].uid, hex_literal::hex!("13 22 33 44 55 66 07 e0"));
+        assert_eq!(tags[2].uid, hex_literal::hex!("53 22 33 44 55 66 07 e0"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_inventory_full_mask() {
+        /// Answers every slot with a collision, keeping the longest mask requested.
+        struct Colliding {
+            max_mask_len: u8,
+        }
+
+        impl LLReader for Colliding {
+            type Error = ErrorKind;
+
+            async fn transceive(&mut self, tx: &[u8], _: &mut [u8], _: Frame) -> Result<usize, Self::Error> {
+                if let [_, _, mask_len, ..] = tx {
+                    self.max_mask_len = self.max_mask_len.max(*mask_len);
+                }
+                Err(ErrorKind::Corruption)
+            }
+        }
+
+        let mut reader = Colliding { max_mask_len: 0 };
+        let mut poller = Poller::new(&mut reader);
+        let res = poller.inventory::<8>(None, Slots::Sixteen).await;
+        assert!(matches!(res, Err(Error::Protocol)));
+        assert_eq!(reader.max_mask_len, 60);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_inventory_1_slot() {
+        let mut mock = mock!(
+            "36 01 00 00" => collision,
+            "36 01 00 01 01" => "00 00 11 22 33 44 55 66 07 e0",
+            "36 01 00 01 00" => "00 00 10 22 33 44 55 66 07 e0",
+        );
+        let mut poller = Poller::new(&mut mock);
+        let tags = poller.inventory::<8>(Some(0), Slots::One).await.unwrap();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].uid, hex_literal::hex!("11 22 33 44 55 66 07 e0"));
+        assert_eq!(tags[1].uid, hex_literal::hex!("10 22 33 44 55 66 07 e0"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_card() {
+        let mut mock = mock!(
+            "26 01 40 11 22 33 44 55 66 07 e0" => "00 00 11 22 33 44 55 66 07 e0",
+            "22 2b 11 22 33 44 55 66 07 e0" => "00 0f 11 22 33 44 55 66 07 e0 00 00 3f 03 01",
+            "22 20 11 22 33 44 55 66 07 e0 05" => "00 01 02 03 04",
+            "22 25 11 22 33 44 55 66 07 e0" => "00",
+            "12 21 05 aa bb cc dd" => "00",
+            "12 23 00 01" => "00 01 02 03 04 05 06 07 08",
+            "12 24 09 01 01 02 03 04 05 06 07 08" => "01 12",
+            "12 26" => "00",
+            "22 02 11 22 33 44 55 66 07 e0" => timeout,
+        );
+        let mut poller = Poller::new(&mut mock);
+        let mut card = poller
+            .select_by_id(&hex_literal::hex!("11 22 33 44 55 66 07 e0"))
+            .await
+            .unwrap();
+
+        let info = card.get_system_info().await.unwrap();
+        assert_eq!(info.dsfid, Some(0));
+        assert_eq!(info.afi, Some(0));
+        assert_eq!(info.block_count, Some(64));
+        assert_eq!(info.block_size, Some(4));
+        assert_eq!(info.ic_reference, Some(1));
+
+        let mut data = [0; 8];
+        assert_eq!(card.read_single_block(5, &mut data).await.unwrap(), 4);
+        assert_eq!(data[..4], [1, 2, 3, 4]);
+
+        card.select().await.unwrap();
+        assert_eq!(card.mode(), Mode::Selected);
+        card.write_single_block(5, &[0xaa, 0xbb, 0xcc, 0xdd]).await.unwrap();
+        assert_eq!(card.read_multiple_blocks(0, 2, &mut data).await.unwrap(), 8);
+        assert_eq!(data, [1, 2, 3, 4, 5, 6, 7, 8]);
+        let res = card.write_multiple_blocks(9, 2, &[1, 2, 3, 4, 5, 6, 7, 8]).await;
+        assert!(matches!(res, Err(Error::Status { code: 0x12 })));
+        // Bad arguments fail before anything is sent.
+        let res = card.read_multiple_blocks(0, 0, &mut data).await;
+        assert!(matches!(res, Err(Error::InvalidLength)));
+        let res = card.write_multiple_blocks(9, 3, &[1, 2, 3, 4, 5, 6, 7, 8]).await;
+        assert!(matches!(res, Err(Error::InvalidLength)));
+        let res = card.write_multiple_blocks(9, 64, &[0; 256]).await;
+        assert!(matches!(res, Err(Error::TooBig)));
+        let res = card.write_single_block(5, &[0; 256]).await;
+        assert!(matches!(res, Err(Error::TooBig)));
+        let res = card.write_single_block(5, &[]).await;
+        assert!(matches!(res, Err(Error::InvalidLength)));
+        card.reset_to_ready().await.unwrap();
+        assert_eq!(card.mode(), Mode::Addressed);
+        card.stay_quiet().await.unwrap();
+    }
+}
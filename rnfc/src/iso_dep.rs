@@ -1,38 +1,229 @@
+use heapless::Vec;
 use rnfc_traits::iso_dep::Reader as IsoDepReader;
-use rnfc_traits::iso14443a::{Error as _, Reader as Iso14443aReader};
-use rnfc_traits::iso14443a_ll::ErrorKind;
+use rnfc_traits::iso14443a::{Reader as Iso14443aReader, UID_MAX_LEN};
+use rnfc_traits::iso14443a_ll::{BitRate, Error as _, ErrorKind};
+use rnfc_traits::iso14443b::Reader as Iso14443bReader;
+
+use crate::iso14443b::ProtocolInfo;
 
 pub const ATS_MAX_LEN: usize = 32; // TODO??
 
 const FSC_MAX: usize = 256;
 const FSC_MAX_WITHOUT_CRC: usize = FSC_MAX - 2;
 
+/// PCB bits: a CID byte follows, and in I-blocks a NAD byte.
+const PCB_CID: u8 = 0x08;
+const PCB_NAD: u8 = 0x04;
+
+/// ATS TC1 bits: the card supports CID, NAD.
+const TC1_CID: u8 = 0x02;
+const TC1_NAD: u8 = 0x01;
+
+/// Highest CID, 15 is reserved for future use.
+pub const CID_MAX: u8 = 14;
+
+/// ATS TA(1) bits: only the same bit rate in both directions, and a reserved bit which
+/// invalidates the others when set.
+const TA1_SAME_D: u8 = 0x80;
+const TA1_RFU: u8 = 0x08;
+
+/// PPSS, with the CID in the lower bits, and PPS0 announcing PPS1.
+const PPSS: u8 = 0xD0;
+const PPS0_PPS1: u8 = 0x11;
+
 pub struct IsoDepA<T: Iso14443aReader> {
     card: T,
 
-    /// Max frame size we can send to the card, including header and crc.
-    /// Ex: if header is 1 byte (no CID/NAD) then max INF field size is FSC-3.
-    fsc: usize,
+    /// Kept to activate the card again the same way, see [`IsoDepA::reactivate`].
+    config: Config,
 
-    /// Start-up frame guard time, in units of 1/Fc
-    #[allow(unused)] // TODO implement support in lower layers.
-    sfgt_1fc: u32,
+    /// UID to select the card again by, if the reader can.
+    uid: Vec<u8, UID_MAX_LEN>,
 
-    /// Framr Waiting Time, in units of 1/Fc
-    fwt_1fc: u32,
+    ats: Ats,
 
-    /// Block count spin bit: 0 or 1
-    block_num: u8,
+    protocol: Protocol,
+
+    /// Bit rates negotiated with PPS: reader to card, card to reader.
+    bit_rate: (BitRate, BitRate),
+
+    /// The reader may be at another card's bit rate, after [`IsoDepA::from_parts`].
+    restore_bit_rate: bool,
+}
+
+pub struct IsoDepB<T: Iso14443bReader> {
+    card: T,
+
+    protocol: Protocol,
+}
+
+/// Activation parameters of an ISO-DEP card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// Card identifier, up to [`CID_MAX`], to keep several cards active on the same field.
+    /// Activation fails with [`Error::InvalidCid`] above it.
+    /// Sent in every block, unless the card doesn't support CID.
+    pub cid: Option<u8>,
+    /// Node address, sent in the first I-block of each command if the card supports NAD.
+    pub nad: Option<u8>,
+    /// Highest bit rate to negotiate with PPS. The highest one supported by both the card
+    /// and the reader is used.
+    pub max_bit_rate: BitRate,
+    /// What to do when the card stops answering.
+    pub recovery: Recovery,
+}
+
+/// Recovery policy, when an exchange fails with [`Error::Communication`] after all retries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Recovery {
+    /// Fail with [`Error::Communication`].
+    None,
+    /// Activate the card again with [`IsoDepA::reactivate`], if the reader can select it
+    /// again, and fail with [`Error::Reactivated`] so the caller starts its session over.
+    Reactivate,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cid: None,
+            nad: None,
+            max_bit_rate: BitRate::Kbps848,
+            recovery: Recovery::None,
+        }
+    }
+}
+
+/// How to check that a card is still in the field, without disturbing the exchange in
+/// progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PresenceCheck {
+    /// Send an R(NAK), which the card answers with an R(ACK) for its last block.
+    Nak,
+    /// Send an I-block without data. The card answers with an I-block, usually an error
+    /// status word, and the block numbers move on as with any exchange. For cards that
+    /// don't answer R(NAK) properly.
+    EmptyIBlock,
+}
+
+/// Protocol state of an activated Type A card, without its reader.
+///
+/// Cards with a CID ignore blocks for other CIDs, so a session can go on through the
+/// reader of another card on the same field: take it apart with [`IsoDepA::into_parts`],
+/// select and activate the next card, then put it back with [`IsoDepA::from_parts`].
+/// [`Recovery::Reactivate`] selects the session's card again by its UID, whichever card the
+/// reader was selected for.
+pub struct Session {
+    config: Config,
+    uid: Vec<u8, UID_MAX_LEN>,
+    ats: Ats,
+    protocol: Protocol,
+    bit_rate: (BitRate, BitRate),
+}
+
+/// Answer to select of a Type A card, ISO/IEC 14443-4 clause 5.2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ats {
+    /// Frame size for proximity card integer, 2 (32 bytes) without T0.
+    pub fsci: u8,
+    /// Bit rates supported, TA(1).
+    pub ta: Option<u8>,
+    /// Frame waiting time and start-up frame guard time integers, TB(1).
+    pub tb: Option<u8>,
+    /// Protocol options, TC(1): CID and NAD support.
+    pub tc: Option<u8>,
+    /// Historical bytes, often identifying the chip and its operating system.
+    pub historical_bytes: Vec<u8, ATS_MAX_LEN>,
+}
+
+impl Ats {
+    /// Parse an ATS, starting with its length byte TL.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (&tl, rest) = data.split_first()?;
+        if tl as usize != data.len() {
+            return None;
+        }
+        let Some((&t0, mut rest)) = rest.split_first() else {
+            return Some(Self {
+                fsci: 2,
+                ta: None,
+                tb: None,
+                tc: None,
+                historical_bytes: Vec::new(),
+            });
+        };
+
+        let mut interface_byte = |bit: u8| match t0 & bit {
+            0 => Some(None),
+            _ => {
+                let (&b, r) = rest.split_first()?;
+                rest = r;
+                Some(Some(b))
+            }
+        };
+        let ta = interface_byte(0x10)?;
+        let tb = interface_byte(0x20)?;
+        let tc = interface_byte(0x40)?;
+        Some(Self {
+            fsci: t0 & 0x0F,
+            ta,
+            tb,
+            tc,
+            historical_bytes: Vec::from_slice(rest).ok()?,
+        })
+    }
+
+    /// Frame waiting time integer, 4 without TB(1).
+    pub fn fwi(&self) -> u8 {
+        self.tb.map_or(4, |tb| tb >> 4)
+    }
+
+    /// Start-up frame guard time integer, 0 without TB(1).
+    pub fn sfgi(&self) -> u8 {
+        self.tb.map_or(0, |tb| tb & 0x0F)
+    }
+
+    /// Frame waiting time, in units of 1/fc.
+    pub fn fwt_1fc(&self) -> u32 {
+        // FWT = (256 x 16 / fc) x 2^FWI
+        (256 * 16) << self.fwi()
+    }
+
+    /// Start-up frame guard time, in units of 1/fc.
+    pub fn sfgt_1fc(&self) -> u32 {
+        // SFGT = (256 x 16 / fc) x 2^SFGI
+        (256 * 16) << self.sfgi()
+    }
+
+    /// Without TC(1), the card supports CID but not NAD.
+    pub fn supports_cid(&self) -> bool {
+        self.tc.unwrap_or(TC1_CID) & TC1_CID != 0
+    }
+
+    pub fn supports_nad(&self) -> bool {
+        self.tc.unwrap_or(TC1_CID) & TC1_NAD != 0
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<E> {
+    /// Error from the card below: the Type A card, or the Type B card of an [`IsoDepB`].
     Iso14443a(E),
     Protocol,
     Communication,
     TxFrameTooBig,
     RxFrameTooBig,
+    /// [`Config::cid`] is above [`CID_MAX`].
+    InvalidCid,
+    /// The card stopped answering and was activated again, see [`Recovery::Reactivate`]:
+    /// the command may or may not have been executed, and the card's state, such as the
+    /// selected application or authentication, is lost.
+    Reactivated,
 }
 
 // Divide by 2 so it fits in u8, saving some space
@@ -48,107 +239,192 @@ const FS_DIV_2_TABLE: [u8; 9] = [
     128, // 256 / 2
 ];
 
+fn fsc_from_fsci<E>(fsci: usize) -> Result<usize, Error<E>> {
+    if fsci >= FS_DIV_2_TABLE.len() {
+        warn!("FSCI too high");
+        return Err(Error::Protocol);
+    }
+    Ok(FS_DIV_2_TABLE[fsci] as usize * 2)
+}
+
 const RATS_TIMEOUT_1FC: u32 = 65536;
 
-impl<T: Iso14443aReader> IsoDepA<T>
+/// Bit rates in increasing order, by DRI and DSI.
+const BIT_RATES: [BitRate; 4] = [BitRate::Kbps106, BitRate::Kbps212, BitRate::Kbps424, BitRate::Kbps848];
+
+/// Highest bit rate allowed by the TA(1) bits of one direction, the reader and the config.
+fn pick_bit_rate(supported: u8, max: BitRate, reader_supports: impl Fn(BitRate) -> bool) -> u8 {
+    (1..BIT_RATES.len())
+        .rev()
+        .find(|&i| supported & 1 << (i - 1) != 0 && BIT_RATES[i] <= max && reader_supports(BIT_RATES[i]))
+        .unwrap_or(0) as u8
+}
+
+/// Lower layer of the block transmission protocol: an activated Type A or Type B card.
+trait Transport {
+    type Error: rnfc_traits::iso14443a_ll::Error + crate::fmt::Format;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error>;
+}
+
+struct TransportA<'a, T>(&'a mut T);
+
+impl<'a, T: Iso14443aReader> Transport for TransportA<'a, T>
 where
     T::Error: crate::fmt::Format,
 {
-    pub async fn new(mut card: T) -> Result<Self, Error<T::Error>> {
-        // RATS
-        let req = [0xe0, 0x80];
-        let mut res = [0; ATS_MAX_LEN];
-        let mut retries = 0;
-        let res_len = loop {
-            match card.transceive(&req, &mut res, RATS_TIMEOUT_1FC).await {
-                Ok(len) => break len,
-                Err(e) => {
-                    warn!("isodep: Trx RATS failed: {:?}", e);
-                    match e.kind() {
-                        ErrorKind::Timeout | ErrorKind::Corruption => {
-                            retries += 1;
-                            if retries >= 4 {
-                                return Err(Error::Communication);
-                            }
-                        }
-                        _ => return Err(Error::Iso14443a(e)),
-                    }
-                }
-            }
-        };
-        let ats = &res[..res_len];
-
-        let mut fsci = 2;
-        let mut sfgi = 0;
-        let mut fwi = 4;
-
-        if ats.len() >= 2 {
-            let t0 = ats[1];
-            // format byte present.
-            fsci = (t0 & 0xF) as usize;
-            if t0 & 0x20 != 0 {
-                let tb_idx = if t0 & 0x10 != 0 { 3 } else { 2 };
-                if let Some(tb) = ats.get(tb_idx) {
-                    sfgi = tb & 0x0f;
-                    fwi = tb >> 4;
-                }
-            }
-        }
+    type Error = T::Error;
 
-        if fsci >= FS_DIV_2_TABLE.len() {
-            warn!("FSCI too high");
-            return Err(Error::Protocol);
-        }
-        let fsc = FS_DIV_2_TABLE[fsci] as usize * 2;
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        self.0.transceive(tx, rx, timeout_1fc).await
+    }
+}
 
-        // SFGT = (256 x 16 / fc) x 2^SFGI
-        let sfgt_1fc = (256 * 16) << sfgi;
-        // FWT = (256 x 16 / fc) x 2^FWI
-        let fwt_1fc = (256 * 16) << fwi;
+struct TransportB<'a, T>(&'a mut T);
 
-        debug!("fsc= {}, sfgt={}/fc, fwt={}/fc", fsc, sfgt_1fc, fwt_1fc);
+impl<'a, T: Iso14443bReader> Transport for TransportB<'a, T>
+where
+    T::Error: crate::fmt::Format,
+{
+    type Error = T::Error;
 
-        Ok(Self {
-            card,
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        self.0.transceive(tx, rx, timeout_1fc).await
+    }
+}
+
+/// Half-duplex block transmission protocol (ISO/IEC 14443-4 clause 7), common to Type A and Type B.
+struct Protocol {
+    /// Max frame size we can send to the card, including header and crc.
+    /// Ex: if header is 1 byte (no CID/NAD) then max INF field size is FSC-3.
+    fsc: usize,
+
+    /// Framr Waiting Time, in units of 1/Fc
+    fwt_1fc: u32,
+
+    /// Block count spin bit: 0 or 1
+    block_num: u8,
+
+    /// Card identifier, if the card was activated with one and supports it.
+    cid: Option<u8>,
+
+    /// Node address, if the card supports it.
+    nad: Option<u8>,
+}
+
+impl Protocol {
+    fn new(fsc: usize, fwt_1fc: u32) -> Self {
+        Self {
             fsc,
-            sfgt_1fc,
             fwt_1fc,
             block_num: 0,
-        })
+            cid: None,
+            nad: None,
+        }
     }
 
-    pub fn inner(&self) -> &T {
-        &self.card
+    /// Write the PCB and the CID and NAD bytes, returning the prologue length.
+    fn prologue(&self, pcb: u8, buf: &mut [u8], with_nad: bool) -> usize {
+        let mut n = 1;
+        buf[0] = pcb;
+        if let Some(cid) = self.cid {
+            buf[0] |= PCB_CID;
+            buf[n] = cid;
+            n += 1;
+        }
+        if let (true, Some(nad)) = (with_nad, self.nad) {
+            buf[0] |= PCB_NAD;
+            buf[n] = nad;
+            n += 1;
+        }
+        n
     }
 
-    pub fn inner_mut(&mut self) -> &mut T {
-        &mut self.card
+    /// Parse the prologue of a received block, returning the PCB without the CID and NAD
+    /// bits, and the prologue length.
+    fn parse_prologue(&self, rx: &[u8]) -> Option<(u8, usize)> {
+        let pcb = rx[0];
+        let mut n = 1;
+        if pcb & PCB_CID != 0 {
+            // The upper bits are the power level indication.
+            let cid = *rx.get(n)? & 0x0F;
+            if Some(cid) != self.cid {
+                warn!("isodep: got block for CID {}", cid);
+                return None;
+            }
+            n += 1;
+        }
+        // I-block with NAD. The NAD isn't checked: the card sends the addresses swapped.
+        if pcb & 0xC0 == 0x00 && pcb & PCB_NAD != 0 {
+            n += 1;
+        }
+        if rx.len() < n {
+            return None;
+        }
+        Some((pcb & !(PCB_CID | PCB_NAD), n))
     }
 
-    pub async fn deselect(&mut self) -> Result<(), Error<T::Error>> {
-        let tx_buf = [0xC2];
-        let mut rx_buf = [0; 1];
+    async fn deselect<T: Transport>(&mut self, card: &mut T) -> Result<(), Error<T::Error>> {
+        let mut tx_buf = [0; 2];
+        let mut rx_buf = [0; 2];
 
-        let rx_len = self
-            .card
-            .transceive(&tx_buf, &mut rx_buf, self.fwt_1fc)
+        let tx_len = self.prologue(0xC2, &mut tx_buf, false);
+        let rx_len = card
+            .transceive(&tx_buf[..tx_len], &mut rx_buf, self.fwt_1fc)
             .await
             .map_err(Error::Iso14443a)?;
-        if rx_len != 1 || rx_buf != [0xC2] {
+        if rx_len == 0 || self.parse_prologue(&rx_buf[..rx_len]) != Some((0xC2, rx_len)) {
             return Err(Error::Protocol);
         }
 
         Ok(())
     }
-}
 
-impl<T: Iso14443aReader> IsoDepReader for IsoDepA<T>
-where
-    T::Error: crate::fmt::Format,
-{
-    type Error = Error<T::Error>;
+    async fn presence_check<T: Transport>(&mut self, card: &mut T, method: PresenceCheck) -> Result<(), Error<T::Error>> {
+        if method == PresenceCheck::EmptyIBlock {
+            let mut rx = [0; FSC_MAX];
+            return self.transceive(card, &[], &mut rx).await.map(|_| ());
+        }
+
+        let mut tx_buf = [0; 2];
+        let mut rx_buf = [0; 2];
+
+        let tx_len = self.prologue(0xb2 | self.block_num, &mut tx_buf, false);
+        let mut retries = 0;
+        let rx_len = loop {
+            match card.transceive(&tx_buf[..tx_len], &mut rx_buf, self.fwt_1fc).await {
+                Ok(rx_len) => break rx_len,
+                Err(e) => {
+                    warn!("isodep: presence check got error {:?}", e);
+                    match e.kind() {
+                        ErrorKind::Timeout | ErrorKind::Corruption => {
+                            retries += 1;
+                            if retries >= 3 {
+                                return Err(Error::Communication);
+                            }
+                        }
+                        _ => return Err(Error::Iso14443a(e)),
+                    }
+                }
+            }
+        };
+
+        // The card's block number is the one of its last I-block, not ours.
+        let ack = 0xa2 | (self.block_num ^ 1);
+        if rx_len == 0 || self.parse_prologue(&rx_buf[..rx_len]) != Some((ack, rx_len)) {
+            warn!("isodep: bad presence check response");
+            return Err(Error::Protocol);
+        }
+
+        Ok(())
+    }
 
-    async fn transceive(&mut self, mut tx: &[u8], mut rx: &mut [u8]) -> Result<usize, Self::Error> {
+    async fn transceive<T: Transport>(
+        &mut self,
+        card: &mut T,
+        mut tx: &[u8],
+        mut rx: &mut [u8],
+    ) -> Result<usize, Error<T::Error>> {
         let mut tx_buf = [0; FSC_MAX_WITHOUT_CRC];
         let mut rx_buf = [0; FSC_MAX_WITHOUT_CRC];
 
@@ -160,7 +436,9 @@ where
         }
         let mut send = Send::Data;
 
-        let max_n = self.fsc - 3;
+        // Room for the NAD is kept in all blocks, though only the first one of a chain has it.
+        let max_n = self.fsc - 3 - self.cid.is_some() as usize - self.nad.is_some() as usize;
+        let tx_total = tx.len();
         let mut rx_total = 0;
         let mut rx_chaining = false;
         let mut retries = 0;
@@ -171,27 +449,22 @@ where
                 Send::Data => {
                     let n = tx.len().min(max_n);
                     let more_blocks = n != tx.len();
-                    tx_buf[0] = 0x02 | self.block_num | (more_blocks as u8) << 4;
-                    tx_buf[1..][..n].copy_from_slice(&tx[..n]);
-                    1 + n
+                    let pcb = 0x02 | self.block_num | (more_blocks as u8) << 4;
+                    let h = self.prologue(pcb, &mut tx_buf, tx.len() == tx_total);
+                    tx_buf[h..][..n].copy_from_slice(&tx[..n]);
+                    h + n
                 }
                 Send::Wtx(mul) => {
                     fwt *= mul as u32;
-                    tx_buf[0] = 0xF2;
-                    tx_buf[1] = mul;
-                    2
-                }
-                Send::Ack => {
-                    tx_buf[0] = 0xa2 | self.block_num;
-                    1
-                }
-                Send::Nak => {
-                    tx_buf[0] = 0xb2 | self.block_num;
-                    1
+                    let h = self.prologue(0xF2, &mut tx_buf, false);
+                    tx_buf[h] = mul;
+                    h + 1
                 }
+                Send::Ack => self.prologue(0xa2 | self.block_num, &mut tx_buf, false),
+                Send::Nak => self.prologue(0xb2 | self.block_num, &mut tx_buf, false),
             };
 
-            let res = self.card.transceive(&tx_buf[..tx_len], &mut rx_buf, fwt).await;
+            let res = card.transceive(&tx_buf[..tx_len], &mut rx_buf, fwt).await;
 
             send = match res {
                 Err(e) => {
@@ -218,16 +491,19 @@ where
 
                     retries = 0;
 
-                    let rx_pcb = rx_buf[0]; // protocol control byte (aka header)
+                    // protocol control byte (aka header), and the prologue length.
+                    let Some((rx_pcb, h)) = self.parse_prologue(&rx_buf[..rx_len]) else {
+                        return Err(Error::Protocol);
+                    };
                     match rx_pcb {
                         // I-block
                         0x02 | 0x03 | 0x12 | 0x13 => {
-                            let rx_inf_len = rx_len - 1;
+                            let rx_inf_len = rx_len - h;
                             if rx_inf_len > rx.len() {
                                 return Err(Error::RxFrameTooBig);
                             }
 
-                            rx[..rx_inf_len].copy_from_slice(&rx_buf[1..][..rx_inf_len]);
+                            rx[..rx_inf_len].copy_from_slice(&rx_buf[h..][..rx_inf_len]);
                             rx = &mut rx[rx_inf_len..];
                             rx_total += rx_inf_len;
 
@@ -258,11 +534,11 @@ where
                         }
                         // S-block Waiting Time Extension - WTX
                         0xF2 => {
-                            if rx_len != 2 {
+                            if rx_len != h + 1 {
                                 warn!("isodep: invalid S(WTX) len {}", rx_len);
                                 return Err(Error::Protocol);
                             }
-                            Send::Wtx(rx_buf[1] & 0x3F)
+                            Send::Wtx(rx_buf[h] & 0x3F)
                         }
                         _ => {
                             warn!("unknown rx pcb {:02x}", rx_pcb);
@@ -275,20 +551,341 @@ where
     }
 }
 
+impl<T: Iso14443aReader> IsoDepA<T>
+where
+    T::Error: crate::fmt::Format,
+{
+    /// Activate ISO-DEP on a selected card, without CID nor NAD.
+    pub async fn new(card: T) -> Result<Self, Error<T::Error>> {
+        Self::with_config(card, Config::default()).await
+    }
+
+    /// Activate ISO-DEP on a selected card, with a CID and NAD.
+    ///
+    /// The CID and NAD are dropped if the card doesn't support them, check with
+    /// [`cid`](Self::cid) before activating other cards.
+    pub async fn with_config(mut card: T, config: Config) -> Result<Self, Error<T::Error>> {
+        let (ats, protocol, bit_rate) = Self::activate(&mut card, &config).await?;
+        let uid = match card.supports_reselect() {
+            true => unwrap!(Vec::from_slice(card.uid())),
+            false => Vec::new(),
+        };
+
+        Ok(Self {
+            card,
+            config,
+            uid,
+            ats,
+            protocol,
+            bit_rate,
+            restore_bit_rate: false,
+        })
+    }
+
+    /// RATS and PPS, returning the ATS, the protocol state and the bit rates.
+    async fn activate(card: &mut T, config: &Config) -> Result<(Ats, Protocol, (BitRate, BitRate)), Error<T::Error>> {
+        if let Some(cid) = config.cid
+            && cid > CID_MAX
+        {
+            warn!("isodep: invalid CID {}", cid);
+            return Err(Error::InvalidCid);
+        }
+
+        // RATS: FSDI for 256 bytes, and the CID.
+        let req = [0xe0, 0x80 | config.cid.unwrap_or(0)];
+        let mut res = [0; ATS_MAX_LEN];
+        let mut retries = 0;
+        let res_len = loop {
+            match card.transceive(&req, &mut res, RATS_TIMEOUT_1FC).await {
+                Ok(len) => break len,
+                Err(e) => {
+                    warn!("isodep: Trx RATS failed: {:?}", e);
+                    match e.kind() {
+                        ErrorKind::Timeout | ErrorKind::Corruption => {
+                            retries += 1;
+                            if retries >= 4 {
+                                return Err(Error::Communication);
+                            }
+                        }
+                        _ => return Err(Error::Iso14443a(e)),
+                    }
+                }
+            }
+        };
+        let Some(ats) = Ats::parse(&res[..res_len]) else {
+            warn!("isodep: malformed ATS");
+            return Err(Error::Protocol);
+        };
+
+        let fsc = fsc_from_fsci(ats.fsci as usize)?;
+        let sfgt_1fc = ats.sfgt_1fc();
+        let fwt_1fc = ats.fwt_1fc();
+
+        debug!("fsc= {}, sfgt={}/fc, fwt={}/fc", fsc, sfgt_1fc, fwt_1fc);
+
+        let mut protocol = Protocol::new(fsc, fwt_1fc);
+        if ats.supports_cid() {
+            protocol.cid = config.cid;
+        } else if config.cid.is_some() {
+            warn!("isodep: card doesn't support CID");
+        }
+        if ats.supports_nad() {
+            protocol.nad = config.nad;
+        } else if config.nad.is_some() {
+            warn!("isodep: card doesn't support NAD");
+        }
+
+        // The card may not listen until the start-up frame guard time has elapsed, with
+        // SFGI 0 meaning it doesn't need one.
+        if ats.sfgi() != 0 {
+            card.delay(sfgt_1fc).await;
+        }
+
+        let bit_rate = Self::pps(card, &protocol, ats.ta.unwrap_or(0), config.max_bit_rate).await?;
+
+        Ok((ats, protocol, bit_rate))
+    }
+
+    /// Negotiate the highest bit rates allowed by the card's TA(1), and switch the reader to
+    /// them.
+    async fn pps(card: &mut T, protocol: &Protocol, ta: u8, max: BitRate) -> Result<(BitRate, BitRate), Error<T::Error>> {
+        let mut dri = 0;
+        let mut dsi = 0;
+        if ta & TA1_RFU == 0 {
+            let supports = |b| card.supports_bit_rate(b);
+            if ta & TA1_SAME_D != 0 {
+                dri = pick_bit_rate(ta & (ta >> 4) & 0x07, max, supports);
+                dsi = dri;
+            } else {
+                dri = pick_bit_rate(ta & 0x07, max, supports);
+                dsi = pick_bit_rate(ta >> 4 & 0x07, max, supports);
+            }
+        }
+        if dri == 0 && dsi == 0 {
+            return Ok((BitRate::Kbps106, BitRate::Kbps106));
+        }
+
+        let ppss = PPSS | protocol.cid.unwrap_or(0);
+        let req = [ppss, PPS0_PPS1, dsi << 2 | dri];
+        let mut res = [0; 1];
+        match card.transceive(&req, &mut res, protocol.fwt_1fc).await {
+            Ok(1) if res[0] == ppss => {}
+            Ok(_) => {
+                warn!("isodep: bad PPS response");
+                return Err(Error::Protocol);
+            }
+            Err(e) => {
+                warn!("isodep: Trx PPS failed: {:?}", e);
+                return Err(match e.kind() {
+                    ErrorKind::Timeout | ErrorKind::Corruption => Error::Communication,
+                    _ => Error::Iso14443a(e),
+                });
+            }
+        }
+
+        let bit_rate = (BIT_RATES[dri as usize], BIT_RATES[dsi as usize]);
+        debug!("isodep: bit rate {:?}", bit_rate);
+        card.set_bit_rate(bit_rate.0, bit_rate.1).await.map_err(Error::Iso14443a)?;
+        Ok(bit_rate)
+    }
+
+    /// Take the session apart, to use the reader for other cards.
+    pub fn into_parts(self) -> (T, Session) {
+        let session = Session {
+            config: self.config,
+            uid: self.uid,
+            ats: self.ats,
+            protocol: self.protocol,
+            bit_rate: self.bit_rate,
+        };
+        (self.card, session)
+    }
+
+    /// Go on with a session from [`into_parts`](Self::into_parts), with a reader on the
+    /// same field. The reader switches to the bit rate of the card on the next exchange.
+    pub fn from_parts(card: T, session: Session) -> Self {
+        Self {
+            card,
+            config: session.config,
+            uid: session.uid,
+            ats: session.ats,
+            protocol: session.protocol,
+            bit_rate: session.bit_rate,
+            restore_bit_rate: true,
+        }
+    }
+
+    /// The ATS received on activation.
+    pub fn ats(&self) -> &Ats {
+        &self.ats
+    }
+
+    /// Bit rates in use: reader to card, card to reader.
+    pub fn bit_rate(&self) -> (BitRate, BitRate) {
+        self.bit_rate
+    }
+
+    async fn restore_bit_rate(&mut self) -> Result<(), Error<T::Error>> {
+        if self.restore_bit_rate {
+            let (tx, rx) = self.bit_rate;
+            self.card.set_bit_rate(tx, rx).await.map_err(Error::Iso14443a)?;
+            self.restore_bit_rate = false;
+        }
+        Ok(())
+    }
+
+    /// The CID in use, `None` if activated without one or the card doesn't support it.
+    pub fn cid(&self) -> Option<u8> {
+        self.protocol.cid
+    }
+
+    /// The NAD in use, `None` if activated without one or the card doesn't support it.
+    pub fn nad(&self) -> Option<u8> {
+        self.protocol.nad
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.card
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.card
+    }
+
+    pub async fn deselect(&mut self) -> Result<(), Error<T::Error>> {
+        self.restore_bit_rate().await?;
+        self.protocol.deselect(&mut TransportA(&mut self.card)).await
+    }
+
+    /// Check that the card is still in the field. Fails with [`Error::Communication`] if
+    /// it doesn't answer.
+    pub async fn presence_check(&mut self, method: PresenceCheck) -> Result<(), Error<T::Error>> {
+        self.restore_bit_rate().await?;
+        self.protocol.presence_check(&mut TransportA(&mut self.card), method).await
+    }
+
+    /// Activate the card again after it stopped answering: DESELECT in case it still
+    /// listens, select it again by UID, then RATS and PPS with the same configuration.
+    /// Fails with [`Error::Protocol`] if the reader can't select the card again.
+    ///
+    /// Everything the card kept for the session, such as the selected application or
+    /// authentication, is lost.
+    pub async fn reactivate(&mut self) -> Result<(), Error<T::Error>> {
+        if !self.card.supports_reselect() {
+            warn!("isodep: reader can't select the card again");
+            return Err(Error::Protocol);
+        }
+        if let Err(e) = self.deselect().await {
+            debug!("isodep: deselect before reactivation failed: {:?}", e);
+        }
+        self.card.reselect(&self.uid).await.map_err(Error::Iso14443a)?;
+        let (ats, protocol, bit_rate) = Self::activate(&mut self.card, &self.config).await?;
+        self.ats = ats;
+        self.protocol = protocol;
+        self.bit_rate = bit_rate;
+        self.restore_bit_rate = false;
+        Ok(())
+    }
+}
+
+impl<T: Iso14443aReader> IsoDepReader for IsoDepA<T>
+where
+    T::Error: crate::fmt::Format,
+{
+    type Error = Error<T::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Self::Error> {
+        self.restore_bit_rate().await?;
+        let res = self.protocol.transceive(&mut TransportA(&mut self.card), tx, rx).await;
+        match res {
+            Err(Error::Communication) if self.config.recovery == Recovery::Reactivate && self.card.supports_reselect() => {
+                warn!("isodep: card lost, reactivating");
+                self.reactivate().await?;
+                Err(Error::Reactivated)
+            }
+            res => res,
+        }
+    }
+}
+
+impl<T: Iso14443bReader> IsoDepB<T>
+where
+    T::Error: crate::fmt::Format,
+{
+    /// Start ISO-DEP on a Type B card. The card must already be activated with ATTRIB,
+    /// protocol parameters are taken from the ATQB protocol info.
+    pub fn new(card: T) -> Result<Self, Error<T::Error>> {
+        let Some(info) = ProtocolInfo::parse(card.protocol_info()) else {
+            warn!("protocol info too short");
+            return Err(Error::Protocol);
+        };
+        if !info.supports_iso_dep() {
+            warn!("card is not ISO/IEC 14443-4 compliant");
+            return Err(Error::Protocol);
+        }
+
+        let fsc = fsc_from_fsci(info.fsci as usize)?;
+        let fwt_1fc = info.fwt_1fc();
+
+        debug!("fsc= {}, fwt={}/fc", fsc, fwt_1fc);
+
+        Ok(Self {
+            card,
+            protocol: Protocol::new(fsc, fwt_1fc),
+        })
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.card
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.card
+    }
+
+    pub async fn deselect(&mut self) -> Result<(), Error<T::Error>> {
+        self.protocol.deselect(&mut TransportB(&mut self.card)).await
+    }
+
+    /// Check that the card is still in the field. Fails with [`Error::Communication`] if
+    /// it doesn't answer.
+    pub async fn presence_check(&mut self, method: PresenceCheck) -> Result<(), Error<T::Error>> {
+        self.protocol.presence_check(&mut TransportB(&mut self.card), method).await
+    }
+}
+
+impl<T: Iso14443bReader> IsoDepReader for IsoDepB<T>
+where
+    T::Error: crate::fmt::Format,
+{
+    type Error = Error<T::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Self::Error> {
+        self.protocol.transceive(&mut TransportB(&mut self.card), tx, rx).await
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::vec::Vec;
 
     use hex_literal::hex;
     use rnfc_traits::iso_dep::Reader;
-    use rnfc_traits::iso14443a::Reader as Iso14443aReader;
-    use rnfc_traits::iso14443a_ll::ErrorKind;
+    use rnfc_traits::iso14443a::{RawFrame, Reader as Iso14443aReader};
+    use rnfc_traits::iso14443a_ll::{ErrorKind, Frame, Reader as LLReader};
+    use rnfc_traits::iso14443b::Reader as Iso14443bReader;
 
     use super::*;
+    use crate::iso14443a::Poller;
+
+    type Exchange = (&'static [u8], Result<&'static [u8], ErrorKind>);
 
     struct MockReader {
-        expected: Vec<(&'static [u8], Result<&'static [u8], ErrorKind>)>,
+        expected: Vec<Exchange>,
         pos: usize,
+        max_bit_rate: BitRate,
+        bit_rate: (BitRate, BitRate),
+        delays: Vec<u32>,
     }
 
     macro_rules! mock {
@@ -304,6 +901,9 @@ mod test {
                     $((&hex_literal::hex!($tx), mock!(@res $rx)),)*
                 ],
                 pos: 0,
+                max_bit_rate: BitRate::Kbps106,
+                bit_rate: (BitRate::Kbps106, BitRate::Kbps106),
+                delays: vec![],
             }
         };
     }
@@ -334,6 +934,10 @@ mod test {
             }
         }
 
+        async fn transceive_raw(&mut self, _: &[u8], _: &mut [u8], _: RawFrame) -> Result<usize, Self::Error> {
+            todo!()
+        }
+
         fn atqa(&self) -> [u8; 2] {
             todo!()
         }
@@ -345,6 +949,49 @@ mod test {
         fn uid(&self) -> &[u8] {
             todo!()
         }
+
+        fn supports_bit_rate(&self, bit_rate: BitRate) -> bool {
+            bit_rate <= self.max_bit_rate
+        }
+
+        async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+            self.bit_rate = (tx, rx);
+            Ok(())
+        }
+
+        async fn delay(&mut self, delay_1fc: u32) {
+            self.delays.push(delay_1fc);
+        }
+    }
+
+    // For the `Poller`, which needs whole frames in bits.
+    impl LLReader for MockReader {
+        type Error = ErrorKind;
+
+        async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], _: Frame) -> Result<usize, Self::Error> {
+            Iso14443aReader::transceive(self, tx, rx, 0).await.map(|n| n * 8)
+        }
+    }
+
+    impl Iso14443bReader for MockReader {
+        type Error = ErrorKind;
+
+        async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+            Iso14443aReader::transceive(self, tx, rx, timeout_1fc).await
+        }
+
+        fn pupi(&self) -> [u8; 4] {
+            todo!()
+        }
+
+        fn application_data(&self) -> [u8; 4] {
+            todo!()
+        }
+
+        fn protocol_info(&self) -> &[u8] {
+            // FSCI=5 (64 bytes), ISO-DEP, FWI=7, CID supported.
+            &[0x00, 0x51, 0x71]
+        }
     }
 
     macro_rules! trx {
@@ -373,36 +1020,352 @@ mod test {
             "e0 80" => "01",
         );
         let x = IsoDepA::new(mock).await.unwrap();
-        assert_eq!(x.fsc, 32);
-        assert_eq!(x.sfgt_1fc, 256 * 16);
-        assert_eq!(x.fwt_1fc, 256 * 16 * 16);
+        assert_eq!(x.protocol.fsc, 32);
+        assert_eq!(x.ats.sfgt_1fc(), 256 * 16);
+        assert_eq!(x.protocol.fwt_1fc, 256 * 16 * 16);
 
         // T0 present, nothing else.
         let mock = mock!(
             "e0 80" => "02 05",
         );
         let x = IsoDepA::new(mock).await.unwrap();
-        assert_eq!(x.fsc, 64);
-        assert_eq!(x.sfgt_1fc, 256 * 16);
-        assert_eq!(x.fwt_1fc, 256 * 16 * 16);
+        assert_eq!(x.protocol.fsc, 64);
+        assert_eq!(x.ats.sfgt_1fc(), 256 * 16);
+        assert_eq!(x.protocol.fwt_1fc, 256 * 16 * 16);
 
         // TA not present, TB present
         let mock = mock!(
             "e0 80" => "05 67 81 02 80",
         );
         let x = IsoDepA::new(mock).await.unwrap();
-        assert_eq!(x.fsc, 128);
-        assert_eq!(x.sfgt_1fc, 8192);
-        assert_eq!(x.fwt_1fc, 1048576);
+        assert_eq!(x.protocol.fsc, 128);
+        assert_eq!(x.ats.sfgt_1fc(), 8192);
+        assert_eq!(x.protocol.fwt_1fc, 1048576);
 
         // TA present, TB present
         let mock = mock!(
             "e0 80" => "06 77 77 81 02 80",
         );
         let x = IsoDepA::new(mock).await.unwrap();
-        assert_eq!(x.fsc, 128);
-        assert_eq!(x.sfgt_1fc, 8192);
-        assert_eq!(x.fwt_1fc, 1048576);
+        assert_eq!(x.protocol.fsc, 128);
+        assert_eq!(x.ats.sfgt_1fc(), 8192);
+        assert_eq!(x.protocol.fwt_1fc, 1048576);
+    }
+
+    #[test]
+    fn test_ats() {
+        // TA, TB and TC present, then historical bytes.
+        let ats = Ats::parse(&hex!("0e 78 80 b1 02 4a 43 4f 50 33 31 56 32 32")).unwrap();
+        assert_eq!(ats.fsci, 8);
+        assert_eq!(ats.ta, Some(0x80));
+        assert_eq!(ats.tb, Some(0xb1));
+        assert_eq!(ats.tc, Some(0x02));
+        assert_eq!(ats.historical_bytes, b"JCOP31V22");
+        assert_eq!(ats.fwi(), 11);
+        assert_eq!(ats.sfgi(), 1);
+        assert!(ats.supports_cid());
+        assert!(!ats.supports_nad());
+
+        // Only TL.
+        let ats = Ats::parse(&hex!("01")).unwrap();
+        assert_eq!(ats.fsci, 2);
+        assert_eq!(ats.fwi(), 4);
+        assert!(ats.historical_bytes.is_empty());
+
+        // TL doesn't match the length.
+        assert_eq!(Ats::parse(&hex!("03 05")), None);
+        // TB announced by T0 but missing.
+        assert_eq!(Ats::parse(&hex!("02 25")), None);
+        assert_eq!(Ats::parse(&[]), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_init_sfgt() {
+        // SFGI=4: the reader waits before the first block.
+        let mock = mock!(
+            "e0 80" => "05 70 80 44 02",
+            "02 12 34" => "02 56 78",
+        );
+        let mut x = IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.card.delays, [(256 * 16) << 4]);
+        trx!(x, "12 34" => "56 78");
+        assert_eq!(x.ats().historical_bytes, []);
+
+        // SFGI=0: no guard time needed.
+        let mock = mock!(
+            "e0 80" => "03 20 40",
+        );
+        let x = IsoDepA::new(mock).await.unwrap();
+        assert!(x.card.delays.is_empty());
+
+        // Malformed ATS.
+        let mock = mock!(
+            "e0 80" => "03 70 80",
+        );
+        assert!(matches!(IsoDepA::new(mock).await, Err(Error::Protocol)));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_init_cid() {
+        // TC1 = 02: CID supported, NAD not.
+        let mock = mock!(
+            "e0 83" => "06 77 77 81 02 80",
+        );
+        let config = Config {
+            cid: Some(3),
+            nad: Some(0x10),
+            ..Default::default()
+        };
+        let x = IsoDepA::with_config(mock, config).await.unwrap();
+        assert_eq!(x.cid(), Some(3));
+        assert_eq!(x.nad(), None);
+
+        // TB absent, TC1 = 00: neither.
+        let mock = mock!(
+            "e0 83" => "04 58 77 00",
+        );
+        let x = IsoDepA::with_config(mock, config).await.unwrap();
+        assert_eq!(x.cid(), None);
+        assert_eq!(x.nad(), None);
+
+        // TC1 absent: CID supported by default.
+        let mock = mock!(
+            "e0 83" => "02 05",
+        );
+        let x = IsoDepA::with_config(mock, config).await.unwrap();
+        assert_eq!(x.cid(), Some(3));
+        assert_eq!(x.nad(), None);
+
+        // CID 15 is reserved, nothing is sent.
+        let mock = mock!();
+        let config = Config {
+            cid: Some(15),
+            ..Default::default()
+        };
+        assert!(matches!(IsoDepA::with_config(mock, config).await, Err(Error::InvalidCid)));
+    }
+
+    // Two cards active on the same field, with CID 1 and 2. The second one supports NAD.
+    #[test_log::test(tokio::test)]
+    async fn test_cid_two_cards() {
+        let mock = mock!(
+            "e0 81" => "06 77 77 81 02 80",
+            "e0 82" => "06 77 77 81 03 80",
+            "0a 01 12 34" => "0a 01 56 78",
+            "0e 02 10 aa bb" => "0e 02 01 cc dd",
+            "0b 01 55 66" => "fa 01 01",
+            "fa 01 01" => "0b 01 77 88",
+            // PICC chaining, the NAD only in the first block.
+            "0f 02 10 99" => "1f 02 01 00 11",
+            "aa 02" => "0a 02 22",
+            "ca 01" => "ca 01",
+            // Block from the wrong card.
+            "0f 02 10 01" => "0b 01 00",
+        );
+        let cid = |cid, nad| Config {
+            cid: Some(cid),
+            nad,
+            ..Default::default()
+        };
+        let a = IsoDepA::with_config(mock, cid(1, Some(0x10))).await.unwrap();
+        assert_eq!(a.nad(), None);
+        let (mock, a) = a.into_parts();
+        let b = IsoDepA::with_config(mock, cid(2, Some(0x10))).await.unwrap();
+        let (mock, b) = b.into_parts();
+
+        let mut a = IsoDepA::from_parts(mock, a);
+        trx!(a, "12 34" => "56 78");
+        let (mock, a) = a.into_parts();
+        let mut b = IsoDepA::from_parts(mock, b);
+        trx!(b, "aa bb" => "cc dd");
+        let (mock, b) = b.into_parts();
+        let mut a = IsoDepA::from_parts(mock, a);
+        trx!(a, "55 66" => "77 88");
+        let (mock, a) = a.into_parts();
+        let mut b = IsoDepA::from_parts(mock, b);
+        trx!(b, "99" => "00 11 22");
+        let (mock, b) = b.into_parts();
+        let mut a = IsoDepA::from_parts(mock, a);
+        a.deselect().await.unwrap();
+        let (mock, _) = a.into_parts();
+        let mut b = IsoDepA::from_parts(mock, b);
+        trx!(b, "01" => Error::Protocol);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_pps() {
+        // TA(1) = 77: up to 848 kbps in both directions, the reader up to 424.
+        let mut mock = mock!(
+            "e0 80" => "06 77 77 81 02 80",
+            "d0 11 0a" => "d0",
+            "02 12 34" => "02 56 78",
+        );
+        mock.max_bit_rate = BitRate::Kbps424;
+        let mut x = IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.bit_rate(), (BitRate::Kbps424, BitRate::Kbps424));
+        assert_eq!(x.inner().bit_rate, (BitRate::Kbps424, BitRate::Kbps424));
+        trx!(x, "12 34" => "56 78");
+
+        // TA(1) = 90: same bit rate in both directions, 212 kbps card to reader only, so no
+        // PPS. Then TA(1) = 03, reader to card only, capped by the config, with CID 2.
+        let mut mock = mock!(
+            "e0 80" => "05 78 90 81 02",
+            "e0 82" => "05 78 03 81 02",
+            "d2 11 01" => "d2",
+        );
+        mock.max_bit_rate = BitRate::Kbps848;
+        let x = IsoDepA::new(&mut mock).await.unwrap();
+        assert_eq!(x.bit_rate(), (BitRate::Kbps106, BitRate::Kbps106));
+        let config = Config {
+            cid: Some(2),
+            max_bit_rate: BitRate::Kbps212,
+            ..Default::default()
+        };
+        let x = IsoDepA::with_config(&mut mock, config).await.unwrap();
+        assert_eq!(x.bit_rate(), (BitRate::Kbps212, BitRate::Kbps106));
+        assert_eq!(mock.bit_rate, (BitRate::Kbps212, BitRate::Kbps106));
+    }
+
+    // A session resumed on a reader at another card's bit rate switches it back.
+    #[test_log::test(tokio::test)]
+    async fn test_pps_two_cards() {
+        let mut mock = mock!(
+            "e0 81" => "06 78 77 81 02 80",
+            "d1 11 0f" => "d1",
+            "e0 82" => "02 08",
+            "0a 02 12 34" => "0a 02 56 78",
+            "0a 01 aa bb" => "0a 01 cc dd",
+        );
+        mock.max_bit_rate = BitRate::Kbps848;
+        let cid = |cid| Config {
+            cid: Some(cid),
+            ..Default::default()
+        };
+        let (mock, a) = IsoDepA::with_config(mock, cid(1)).await.unwrap().into_parts();
+        assert_eq!(mock.bit_rate, (BitRate::Kbps848, BitRate::Kbps848));
+        // The reader is switched back to 106 kbps by the WUPA of the next selection.
+        let mut mock = mock;
+        mock.bit_rate = (BitRate::Kbps106, BitRate::Kbps106);
+        let (mock, b) = IsoDepA::with_config(mock, cid(2)).await.unwrap().into_parts();
+
+        let mut b = IsoDepA::from_parts(mock, b);
+        trx!(b, "12 34" => "56 78");
+        let (mock, _) = b.into_parts();
+        let mut a = IsoDepA::from_parts(mock, a);
+        trx!(a, "aa bb" => "cc dd");
+        assert_eq!(a.inner().bit_rate, (BitRate::Kbps848, BitRate::Kbps848));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_presence_check() {
+        let mock = mock!(
+            "e0 80" => "01",
+            "b2" => "a3",
+            "02 12 34" => "02 56 78",
+            "b3" => timeout,
+            "b3" => "a2",
+            "03" => "03 67 00",
+            "b2" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+        );
+        let mut x = IsoDepA::new(mock).await.unwrap();
+        x.presence_check(PresenceCheck::Nak).await.unwrap();
+        trx!(x, "12 34" => "56 78");
+        x.presence_check(PresenceCheck::Nak).await.unwrap();
+        x.presence_check(PresenceCheck::EmptyIBlock).await.unwrap();
+        // Card gone.
+        assert_eq!(x.presence_check(PresenceCheck::Nak).await, Err(Error::Communication));
+    }
+
+    // The card stops answering, and is selected again by UID and activated.
+    #[test_log::test(tokio::test)]
+    async fn test_reactivate() {
+        let mut mock = mock!(
+            "" => "04 00",
+            "93 70 01 02 03 04 04" => "20",
+            "e0 80" => "01",
+            "02 12 34" => "c2",
+            "02 12 34" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+            "b2" => timeout,
+            "c2" => timeout,
+            "" => "04 00",
+            "93 70 01 02 03 04 04" => "20",
+            "e0 80" => "01",
+            "02 aa bb" => "02 cc dd",
+        );
+        let mut poller = Poller::new(&mut mock);
+        let card = poller.select_by_id(&hex!("01 02 03 04")).await.unwrap();
+        let config = Config {
+            recovery: Recovery::Reactivate,
+            ..Default::default()
+        };
+        let mut x = IsoDepA::with_config(card, config).await.unwrap();
+
+        // A card that answers, even wrongly, isn't reactivated.
+        let mut buf = [0; 16];
+        let res = x.transceive(&hex!("12 34"), &mut buf).await;
+        assert!(matches!(res, Err(Error::Protocol)));
+
+        let res = x.transceive(&hex!("12 34"), &mut buf).await;
+        assert!(matches!(res, Err(Error::Reactivated)));
+        trx!(x, "aa bb" => "cc dd");
+    }
+
+    // A session resumed on another card's reader selects its own card again, by its UID.
+    #[test_log::test(tokio::test)]
+    async fn test_reactivate_from_parts() {
+        let mut mock = mock!(
+            "" => "04 00",
+            "93 70 01 02 03 04 04" => "20",
+            "e0 81" => "04 68 81 02",
+            "" => "04 00",
+            "93 70 05 06 07 08 0c" => "20",
+            "e0 82" => "04 68 81 02",
+            "0a 01 12 34" => timeout,
+            "ba 01" => timeout,
+            "ba 01" => timeout,
+            "ba 01" => timeout,
+            "ba 01" => timeout,
+            "ba 01" => timeout,
+            "ba 01" => timeout,
+            "ba 01" => timeout,
+            "ba 01" => timeout,
+            "ba 01" => timeout,
+            "ca 01" => timeout,
+            "" => "04 00",
+            "93 70 01 02 03 04 04" => "20",
+            "e0 81" => "04 68 81 02",
+            "0a 01 aa bb" => "0a 01 cc dd",
+        );
+        let config = |cid| Config {
+            cid: Some(cid),
+            recovery: Recovery::Reactivate,
+            ..Default::default()
+        };
+        let a = {
+            let mut poller = Poller::new(&mut mock);
+            let card = poller.select_by_id(&hex!("01 02 03 04")).await.unwrap();
+            IsoDepA::with_config(card, config(1)).await.unwrap().into_parts().1
+        };
+        let mut poller = Poller::new(&mut mock);
+        let card = poller.select_by_id(&hex!("05 06 07 08")).await.unwrap();
+        let (card, _) = IsoDepA::with_config(card, config(2)).await.unwrap().into_parts();
+
+        let mut a = IsoDepA::from_parts(card, a);
+        let mut buf = [0; 16];
+        let res = a.transceive(&hex!("12 34"), &mut buf).await;
+        assert!(matches!(res, Err(Error::Reactivated)));
+        assert_eq!(a.inner().uid(), hex!("01 02 03 04"));
+        trx!(a, "aa bb" => "cc dd");
     }
 
     // B.2.1 Exchange of I-blocks. Scenario 1
@@ -445,7 +1408,7 @@ mod test {
             "03 aa bb" => "03 cc dd",
         );
         let x = &mut IsoDepA::new(mock).await.unwrap();
-        x.fsc = 10;
+        x.protocol.fsc = 10;
         trx!(x, "00 11 22 33 44 55 66 77 88 99 aa bb cc dd ee ff" => "cc dd");
         trx!(x, "aa bb" => "cc dd");
     }
@@ -461,7 +1424,7 @@ mod test {
             "03 aa bb" => "03 cc dd",
         );
         let x = &mut IsoDepA::new(mock).await.unwrap();
-        x.fsc = 10;
+        x.protocol.fsc = 10;
         trx!(x, "12 34" => "00 11 22 33 44 55 66 77 88 99 aa bb cc dd ee ff");
         trx!(x, "aa bb" => "cc dd");
     }
@@ -619,7 +1582,7 @@ mod test {
             "03 aa bb" => "03 cc dd",
         );
         let x = &mut IsoDepA::new(mock).await.unwrap();
-        x.fsc = 10;
+        x.protocol.fsc = 10;
         trx!(x, "00 11 22 33 44 55 66 77 88 99 aa bb cc dd ee ff" => "cc dd");
         trx!(x, "aa bb" => "cc dd");
     }
@@ -637,7 +1600,7 @@ mod test {
             "03 aa bb" => "03 cc dd",
         );
         let x = &mut IsoDepA::new(mock).await.unwrap();
-        x.fsc = 10;
+        x.protocol.fsc = 10;
         trx!(x, "00 11 22 33 44 55 66 77 88 99 aa bb cc dd ee ff" => "cc dd");
         trx!(x, "aa bb" => "cc dd");
     }
@@ -655,7 +1618,7 @@ mod test {
             "03 aa bb" => "03 cc dd",
         );
         let x = &mut IsoDepA::new(mock).await.unwrap();
-        x.fsc = 10;
+        x.protocol.fsc = 10;
         trx!(x, "00 11 22 33 44 55 66 77 88 99 aa bb cc dd ee ff" => "cc dd");
         trx!(x, "aa bb" => "cc dd");
     }
@@ -672,7 +1635,7 @@ mod test {
             "03 aa bb" => "03 cc dd",
         );
         let x = &mut IsoDepA::new(mock).await.unwrap();
-        x.fsc = 10;
+        x.protocol.fsc = 10;
         trx!(x, "12 34" => "00 11 22 33 44 55 66 77 88 99 aa bb cc dd ee ff");
         trx!(x, "aa bb" => "cc dd");
     }
@@ -702,7 +1665,42 @@ mod test {
             "b2" => timeout,
         );
         let x = &mut IsoDepA::new(mock).await.unwrap();
-        x.fsc = 10;
+        x.protocol.fsc = 10;
         trx!(x, "12 34" => Error::Communication);
     }
+
+    #[test_log::test(tokio::test)]
+    async fn test_b_init() {
+        let mock = mock!();
+        let x = IsoDepB::new(mock).unwrap();
+        assert_eq!(x.protocol.fsc, 64);
+        assert_eq!(x.protocol.fwt_1fc, (256 * 16) << 7);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_b_exchange_iblocks() {
+        let mock = mock!(
+            "02 12 34" => "02 56 78",
+            "03 aa bb" => "f2 c1",
+            "f2 01" => "03 cc dd",
+            "c2" => "c2",
+        );
+        let x = &mut IsoDepB::new(mock).unwrap();
+        trx!(x, "12 34" => "56 78");
+        trx!(x, "aa bb" => "cc dd");
+        x.deselect().await.unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_b_picc_chaining() {
+        let mock = mock!(
+            "02 12 34" => "12 00 11 22 33 44 55 66",
+            "a3" => timeout,
+            "a3" => "13 77 88 99 aa bb cc dd",
+            "a2" => "02 ee ff",
+        );
+        let x = &mut IsoDepB::new(mock).unwrap();
+        x.protocol.fsc = 10;
+        trx!(x, "12 34" => "00 11 22 33 44 55 66 77 88 99 aa bb cc dd ee ff");
+    }
 }
@@ -4,7 +4,45 @@
 // This must go FIRST so that other mods see its macros.
 mod fmt;
 
+macro_rules! retry {
+    ($tries:literal, $expr:expr) => {{
+        let mut tries = $tries;
+        loop {
+            let r = $expr;
+            if let Ok(r) = r {
+                break Ok(r);
+            }
+
+            tries -= 1;
+
+            if tries == 0 {
+                break r;
+            }
+        }
+    }};
+}
+
 pub use rnfc_traits as traits;
 
+pub mod apdu;
+pub(crate) mod crypto;
+pub mod ctap;
+pub mod desfire;
+pub mod emrtd;
+pub mod emv;
+pub mod gp;
 pub mod iso14443a;
+pub mod iso14443b;
+pub mod iso15693;
 pub mod iso_dep;
+pub mod mifare_classic;
+pub mod ndef;
+pub mod nfcf;
+pub mod ntag;
+pub mod ntag424;
+pub mod t2t;
+pub mod t4t;
+#[cfg(test)]
+mod test_util;
+pub mod tlv;
+pub mod ultralight_c;
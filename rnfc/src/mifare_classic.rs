@@ -0,0 +1,856 @@
+//! MIFARE Classic 1K/4K authentication and block access.
+//!
+//! After authentication, all frames are encrypted with [`Crypto1`], parity bits included, so
+//! this needs a reader able to send and receive frames with caller-provided parity through
+//! [`transceive_raw`](Reader::transceive_raw). A [`Card`](crate::iso14443a::Card) on top of any
+//! [`iso14443a_ll::Reader`](rnfc_traits::iso14443a_ll::Reader) supporting raw frames works.
+
+use rand_core::CryptoRngCore;
+use rnfc_traits::iso14443a::{Error as _, RawFrame, Reader};
+use rnfc_traits::iso14443a_ll::ErrorKind;
+
+use self::access::Trailer;
+use self::crypto1::{Crypto1, odd_parity, prng_successor};
+use crate::iso14443a::crc_a;
+
+pub mod access;
+pub mod crypto1;
+pub mod value;
+
+pub const BLOCK_SIZE: usize = 16;
+
+pub type Key = [u8; 6];
+
+const CMD_AUTH_A: u8 = 0x60;
+const CMD_AUTH_B: u8 = 0x61;
+const CMD_READ: u8 = 0x30;
+const CMD_WRITE: u8 = 0xA0;
+const CMD_HALT: u8 = 0x50;
+
+const ACK: u8 = 0x0A;
+
+const TIMEOUT_1FC: u32 = 65536;
+const WRITE_TIMEOUT_1FC: u32 = 131072;
+
+/// Longest frame we send or receive: a block and its CRC, with a parity bit after each byte.
+const MAX_FRAME: usize = ((BLOCK_SIZE + 2) * 9).div_ceil(8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KeyType {
+    A,
+    B,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The card sent a malformed response, or one with bad CRC or parity.
+    Protocol,
+    /// The card answered with a NAK. The value is the 4-bit NAK code.
+    Nak(u8),
+    /// Authentication failed, most likely because of a wrong key.
+    AuthFailed,
+    /// The command needs authenticating to the sector first.
+    NotAuthenticated,
+    /// The sector trailer has invalid access bits.
+    InvalidTrailer,
+    /// The block is not in the value block format.
+    InvalidValue,
+}
+
+/// Sector of a block. Sectors 0-31 have 4 blocks, sectors 32-39 of 4K cards have 16.
+pub fn sector_of(block: u8) -> u8 {
+    match block {
+        0..128 => block / 4,
+        _ => 32 + (block - 128) / 16,
+    }
+}
+
+/// First block of a sector.
+pub fn first_block(sector: u8) -> u8 {
+    match sector {
+        0..32 => sector * 4,
+        _ => 128 + (sector - 32) * 16,
+    }
+}
+
+/// Number of blocks in a sector.
+pub fn block_count(sector: u8) -> u8 {
+    match sector {
+        0..32 => 4,
+        _ => 16,
+    }
+}
+
+/// Sector trailer of a sector, its last block, holding the keys and access bits.
+pub fn trailer(sector: u8) -> u8 {
+    first_block(sector) + (block_count(sector) - 1)
+}
+
+/// A MIFARE Classic card.
+///
+/// The card can be authenticated to one sector at a time. Authenticating while already
+/// authenticated runs the nested authentication, encrypted with the current session.
+/// Errors in an authenticated session end it, the card must then be reselected.
+pub struct Classic<T: Reader> {
+    card: T,
+    /// UID bytes used in authentication: all of a 4-byte UID, the last 4 of a 7-byte one.
+    cuid: u32,
+    cipher: Option<Crypto1>,
+}
+
+impl<T: Reader> Classic<T> {
+    pub fn new(card: T) -> Self {
+        let uid = card.uid();
+        let mut cuid = 0;
+        for &b in &uid[uid.len().saturating_sub(4)..] {
+            cuid = cuid << 8 | b as u32;
+        }
+        Self {
+            card,
+            cuid,
+            cipher: None,
+        }
+    }
+
+    /// Get the underlying card, for sending other commands.
+    pub fn card(&mut self) -> &mut T {
+        &mut self.card
+    }
+
+    pub fn into_inner(self) -> T {
+        self.card
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Authenticate to the sector of `block`.
+    ///
+    /// The reader nonce is taken from `rng`.
+    pub async fn authenticate(
+        &mut self,
+        rng: &mut impl CryptoRngCore,
+        block: u8,
+        key_type: KeyType,
+        key: &Key,
+    ) -> Result<(), Error<T::Error>> {
+        let cmd = match key_type {
+            KeyType::A => CMD_AUTH_A,
+            KeyType::B => CMD_AUTH_B,
+        };
+        let cmd = [cmd, block];
+        let mut cipher = Crypto1::new(key);
+
+        let nt = match self.cipher.is_some() {
+            false => {
+                let opts = RawFrame {
+                    rx_crc: false,
+                    ..RawFrame::standard(TIMEOUT_1FC, cmd.len())
+                };
+                let mut rx = [0; 4];
+                let bits = self.card.transceive_raw(&cmd, &mut rx, opts).await.map_err(Error::Lower)?;
+                if bits == 4 {
+                    debug!("AUTH: NAK {:x}", rx[0] & 0x0F);
+                    return Err(Error::Nak(rx[0] & 0x0F));
+                }
+                if bits != 32 {
+                    warn!("AUTH: bad response, {} bits", bits);
+                    return Err(Error::Protocol);
+                }
+                let nt = u32::from_be_bytes(rx);
+                cipher.word(self.cuid ^ nt, false);
+                nt
+            }
+            true => {
+                // Nested: the command is encrypted with the current session, the card nonce
+                // with the new key. The parity bits of the nonce can't be checked.
+                let mut raw = [0; MAX_FRAME];
+                let res = self.transceive_encrypted(&cmd, &mut raw, TIMEOUT_1FC).await;
+                self.cipher = None;
+                let bits = res?;
+                if bits != 36 {
+                    warn!("nested AUTH: bad response, {} bits", bits);
+                    return Err(Error::Protocol);
+                }
+                let mut nt_enc = 0;
+                for i in 0..4 {
+                    nt_enc = nt_enc << 8 | raw_byte(&raw, i) as u32;
+                }
+                cipher.word(nt_enc ^ self.cuid, true) ^ nt_enc
+            }
+        };
+        trace!("AUTH: nt {:08x}", nt);
+
+        // The reader nonce is shifted into the cipher, the reader answer isn't.
+        let nr = rng.next_u32().to_be_bytes();
+        let ar = prng_successor(nt, 64).to_be_bytes();
+        let mut frame = RawBuf::new();
+        for b in nr {
+            frame.push_encrypted(&mut cipher, b, true);
+        }
+        for b in ar {
+            frame.push_encrypted(&mut cipher, b, false);
+        }
+
+        let opts = RawFrame {
+            timeout_1fc: TIMEOUT_1FC,
+            tx_bits: frame.bits,
+            tx_crc: false,
+            rx_crc: false,
+            parity: false,
+        };
+        let mut raw = [0; MAX_FRAME];
+        let bits = match self.card.transceive_raw(frame.as_bytes(), &mut raw, opts).await {
+            Ok(bits) => bits,
+            // The card stays silent if the reader answer is wrong.
+            Err(e) if e.kind() == ErrorKind::Timeout => {
+                debug!("AUTH: no answer from card, wrong key?");
+                return Err(Error::AuthFailed);
+            }
+            Err(e) => return Err(Error::Lower(e)),
+        };
+        if bits != 36 {
+            warn!("AUTH: bad card answer, {} bits", bits);
+            return Err(Error::Protocol);
+        }
+        let mut at = [0; 4];
+        for (i, b) in at.iter_mut().enumerate() {
+            *b = decrypt_byte(&mut cipher, &raw, i).ok_or(Error::Protocol)?;
+        }
+        if u32::from_be_bytes(at) != prng_successor(nt, 96) {
+            warn!("AUTH: bad card answer");
+            return Err(Error::AuthFailed);
+        }
+
+        self.cipher = Some(cipher);
+        Ok(())
+    }
+
+    /// READ: read one block.
+    pub async fn read(&mut self, block: u8) -> Result<[u8; BLOCK_SIZE], Error<T::Error>> {
+        let mut rx = [0; BLOCK_SIZE];
+        match self.transceive(&[CMD_READ, block], &mut rx, TIMEOUT_1FC).await? {
+            Response::Data(BLOCK_SIZE) => Ok(rx),
+            r => self.unexpected(r),
+        }
+    }
+
+    /// WRITE: write one block.
+    ///
+    /// Writes to a sector trailer are refused if the access bits are inconsistent, which
+    /// would make the sector unusable forever.
+    pub async fn write(&mut self, block: u8, data: &[u8; BLOCK_SIZE]) -> Result<(), Error<T::Error>> {
+        if block == trailer(sector_of(block)) && Trailer::decode(data).is_none() {
+            warn!("refusing to write trailer {} with inconsistent access bits", block);
+            return Err(Error::InvalidTrailer);
+        }
+        self.transceive_ack(&[CMD_WRITE, block], TIMEOUT_1FC).await?;
+        self.transceive_ack(data, WRITE_TIMEOUT_1FC).await
+    }
+
+    /// HALT: end the session and put the card in the HALT state.
+    pub async fn halt(&mut self) -> Result<(), Error<T::Error>> {
+        let res = self.transceive_silent(&[CMD_HALT, 0x00]).await;
+        self.cipher = None;
+        res
+    }
+
+    /// Send an encrypted command answered by an ACK.
+    pub(crate) async fn transceive_ack(&mut self, tx: &[u8], timeout_1fc: u32) -> Result<(), Error<T::Error>> {
+        match self.transceive(tx, &mut [], timeout_1fc).await? {
+            Response::Ack => Ok(()),
+            r => self.unexpected(r),
+        }
+    }
+
+    /// Send an encrypted command with CRC, and decrypt the response.
+    pub(crate) async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<Response, Error<T::Error>> {
+        let mut raw = [0; MAX_FRAME];
+        let res = match self.transceive_encrypted(tx, &mut raw, timeout_1fc).await {
+            Ok(bits) => self.decrypt_response(&raw, bits, rx),
+            Err(e) => Err(e),
+        };
+        if res.is_err() {
+            self.cipher = None;
+        }
+        res
+    }
+
+    /// Send an encrypted command the card only answers in case of error, with a NAK.
+    pub(crate) async fn transceive_silent(&mut self, tx: &[u8]) -> Result<(), Error<T::Error>> {
+        let mut raw = [0; MAX_FRAME];
+        let res = match self.transceive_encrypted(tx, &mut raw, TIMEOUT_1FC).await {
+            Err(Error::Lower(e)) if e.kind() == ErrorKind::Timeout => return Ok(()),
+            Ok(bits) => match self.decrypt_response(&raw, bits, &mut []) {
+                Ok(r) => self.unexpected(r),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        self.cipher = None;
+        res
+    }
+
+    fn decrypt_response(&mut self, raw: &[u8], bits: usize, rx: &mut [u8]) -> Result<Response, Error<T::Error>> {
+        let cipher = self.cipher.as_mut().ok_or(Error::NotAuthenticated)?;
+
+        if bits == 4 {
+            let mut nibble = 0;
+            for i in 0..4 {
+                nibble |= (raw_bit(raw, i) ^ cipher.bit(0, false)) << i;
+            }
+            return match nibble {
+                ACK => Ok(Response::Ack),
+                nak => {
+                    debug!("NAK {:x}", nak);
+                    Err(Error::Nak(nak))
+                }
+            };
+        }
+
+        if !bits.is_multiple_of(9) || bits / 9 < 3 || bits / 9 - 2 > rx.len() {
+            warn!("bad response, {} bits", bits);
+            return Err(Error::Protocol);
+        }
+        let n = bits / 9 - 2;
+        let mut crc = [0; 2];
+        for i in 0..n + 2 {
+            let b = decrypt_byte(cipher, raw, i).ok_or_else(|| {
+                warn!("bad parity");
+                Error::Protocol
+            })?;
+            match i < n {
+                true => rx[i] = b,
+                false => crc[i - n] = b,
+            }
+        }
+        if crc != crc_a(&rx[..n]) {
+            warn!("bad CRC");
+            return Err(Error::Protocol);
+        }
+        Ok(Response::Data(n))
+    }
+
+    /// Encrypt and send `tx` with its CRC, and return the raw response bits.
+    async fn transceive_encrypted(
+        &mut self,
+        tx: &[u8],
+        raw: &mut [u8; MAX_FRAME],
+        timeout_1fc: u32,
+    ) -> Result<usize, Error<T::Error>> {
+        let Some(cipher) = self.cipher.as_mut() else {
+            return Err(Error::NotAuthenticated);
+        };
+
+        let mut frame = RawBuf::new();
+        for &b in tx.iter().chain(&crc_a(tx)) {
+            frame.push_encrypted(cipher, b, false);
+        }
+        let opts = RawFrame {
+            timeout_1fc,
+            tx_bits: frame.bits,
+            tx_crc: false,
+            rx_crc: false,
+            parity: false,
+        };
+        self.card
+            .transceive_raw(frame.as_bytes(), raw, opts)
+            .await
+            .map_err(Error::Lower)
+    }
+
+    fn unexpected<R>(&mut self, r: Response) -> Result<R, Error<T::Error>> {
+        warn!("unexpected response {:?}", r);
+        self.cipher = None;
+        Err(Error::Protocol)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Response {
+    Ack,
+    /// Data of the given length, CRC stripped.
+    Data(usize),
+}
+
+/// Bit stream of bytes each followed by its parity bit, LSB first.
+struct RawBuf {
+    buf: [u8; MAX_FRAME],
+    bits: usize,
+}
+
+impl RawBuf {
+    fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME],
+            bits: 0,
+        }
+    }
+
+    fn push_bit(&mut self, bit: u8) {
+        self.buf[self.bits / 8] |= (bit & 1) << (self.bits % 8);
+        self.bits += 1;
+    }
+
+    /// Encrypt `b`, shifting it into the cipher if `feed` is set, and push it with its
+    /// encrypted parity bit.
+    fn push_encrypted(&mut self, cipher: &mut Crypto1, b: u8, feed: bool) {
+        let ks = cipher.byte(if feed { b } else { 0 }, false);
+        let enc = b ^ ks;
+        for i in 0..8 {
+            self.push_bit(enc >> i);
+        }
+        self.push_bit(odd_parity(b) ^ cipher.peek());
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.bits.div_ceil(8)]
+    }
+}
+
+fn raw_bit(raw: &[u8], i: usize) -> u8 {
+    raw[i / 8] >> (i % 8) & 1
+}
+
+/// Byte `i` of a raw bit stream, without its parity bit.
+fn raw_byte(raw: &[u8], i: usize) -> u8 {
+    (0..8).fold(0, |b, j| b | raw_bit(raw, i * 9 + j) << j)
+}
+
+/// Decrypt byte `i` of a raw bit stream and check its parity.
+fn decrypt_byte(cipher: &mut Crypto1, raw: &[u8], i: usize) -> Option<u8> {
+    let b = raw_byte(raw, i) ^ cipher.byte(0, false);
+    let parity = raw_bit(raw, i * 9 + 8) ^ cipher.peek();
+    (parity == odd_parity(b)).then_some(b)
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use hex_literal::hex;
+
+    use super::access::AccessBits;
+    use super::value::Value;
+    use super::*;
+
+    const UID: [u8; 4] = hex!("9c599b32");
+
+    enum State {
+        Idle,
+        /// Card nonce sent, waiting for the reader's.
+        Auth {
+            cipher: Crypto1,
+            nt: u32,
+            sector: u8,
+        },
+        Authenticated {
+            cipher: Crypto1,
+            sector: u8,
+        },
+        /// WRITE or value command acknowledged, waiting for the data or operand.
+        Pending {
+            cipher: Crypto1,
+            sector: u8,
+            cmd: u8,
+            block: u8,
+        },
+    }
+
+    /// Simulated MIFARE Classic 1K with the same keys in all sectors.
+    struct MockCard {
+        blocks: [[u8; BLOCK_SIZE]; 64],
+        key_a: Key,
+        key_b: Key,
+        nt: u32,
+        state: State,
+        transfer_buffer: Option<i32>,
+    }
+
+    impl MockCard {
+        fn new() -> Self {
+            let mut blocks = [[0; BLOCK_SIZE]; 64];
+            for (i, b) in blocks.iter_mut().enumerate() {
+                b[0] = i as u8;
+            }
+            Self {
+                blocks,
+                key_a: hex!("A0A1A2A3A4A5"),
+                key_b: hex!("B0B1B2B3B4B5"),
+                nt: 0x01200145,
+                state: State::Idle,
+                transfer_buffer: None,
+            }
+        }
+
+        /// Pick the card nonce, and load the key for AUTH.
+        fn start_auth(&mut self, cmd: &[u8]) -> (Crypto1, u32, u8) {
+            let key = match cmd[0] {
+                CMD_AUTH_A => &self.key_a,
+                CMD_AUTH_B => &self.key_b,
+                c => panic!("unexpected command {:02x}", c),
+            };
+            let mut cipher = Crypto1::new(key);
+            self.nt = prng_successor(self.nt, 1000);
+            let nt_enc = cipher.word(u32::from_be_bytes(UID) ^ self.nt, false) ^ self.nt;
+            (cipher, nt_enc, sector_of(cmd[1]))
+        }
+
+        /// Respond to an encrypted command. Returns the number of raw bits of the response.
+        fn command(
+            &mut self,
+            cmd: &[u8],
+            mut cipher: Crypto1,
+            sector: u8,
+            pending: Option<(u8, u8)>,
+            rx: &mut [u8],
+        ) -> Result<usize, ErrorKind> {
+            let mut frame = RawBuf::new();
+            let nibble = |cipher: &mut Crypto1, frame: &mut RawBuf, n: u8| {
+                for i in 0..4 {
+                    frame.push_bit((n >> i) ^ cipher.bit(0, false));
+                }
+            };
+
+            match (pending, cmd) {
+                (Some((CMD_WRITE, block)), data) => {
+                    let data = data.try_into().unwrap();
+                    if block == trailer(sector) {
+                        // A real card would block the sector.
+                        assert!(Trailer::decode(data).is_some());
+                    }
+                    self.blocks[block as usize] = *data;
+                    nibble(&mut cipher, &mut frame, ACK);
+                    self.state = State::Authenticated { cipher, sector };
+                }
+                (Some((op, block)), operand) => {
+                    let operand = i32::from_le_bytes(operand.try_into().unwrap());
+                    let Some(v) = value::Value::decode(&self.blocks[block as usize]) else {
+                        nibble(&mut cipher, &mut frame, 0x4);
+                        self.state = State::Idle;
+                        rx[0] = frame.buf[0];
+                        return Ok(frame.bits);
+                    };
+                    self.transfer_buffer = Some(match op {
+                        0xC0 => v.value - operand,
+                        0xC1 => v.value + operand,
+                        _ => v.value,
+                    });
+                    self.state = State::Authenticated { cipher, sector };
+                    return Err(ErrorKind::Timeout);
+                }
+                (_, [0xC0..=0xC2, block]) if sector_of(*block) == sector => {
+                    nibble(&mut cipher, &mut frame, ACK);
+                    self.state = State::Pending {
+                        cipher,
+                        sector,
+                        cmd: cmd[0],
+                        block: *block,
+                    };
+                }
+                (_, [0xB0, block]) if sector_of(*block) == sector && self.transfer_buffer.is_some() => {
+                    let value = self.transfer_buffer.take().unwrap();
+                    let addr = self.blocks[*block as usize][12];
+                    self.blocks[*block as usize] = value::Value { value, addr }.encode();
+                    nibble(&mut cipher, &mut frame, ACK);
+                    self.state = State::Authenticated { cipher, sector };
+                }
+                (_, [CMD_READ, block]) if sector_of(*block) == sector => {
+                    let data = self.blocks[*block as usize];
+                    for b in data.iter().chain(&crc_a(&data)) {
+                        frame.push_encrypted(&mut cipher, *b, false);
+                    }
+                    self.state = State::Authenticated { cipher, sector };
+                }
+                (_, [CMD_WRITE, block]) if sector_of(*block) == sector => {
+                    nibble(&mut cipher, &mut frame, ACK);
+                    self.state = State::Pending {
+                        cipher,
+                        sector,
+                        cmd: CMD_WRITE,
+                        block: *block,
+                    };
+                }
+                (_, [CMD_AUTH_A | CMD_AUTH_B, _]) => {
+                    let (new, nt_enc, sector) = self.start_auth(cmd);
+                    for b in nt_enc.to_be_bytes() {
+                        for i in 0..8 {
+                            frame.push_bit(b >> i);
+                        }
+                        frame.push_bit(odd_parity(b));
+                    }
+                    self.state = State::Auth {
+                        cipher: new,
+                        nt: self.nt,
+                        sector,
+                    };
+                }
+                _ => {
+                    nibble(&mut cipher, &mut frame, 0x4);
+                    self.state = State::Idle;
+                }
+            }
+            rx[..frame.as_bytes().len()].copy_from_slice(frame.as_bytes());
+            Ok(frame.bits)
+        }
+
+        /// Decrypt a command in an authenticated session, and respond to it.
+        fn encrypted(
+            &mut self,
+            tx: &[u8],
+            opts: RawFrame,
+            mut cipher: Crypto1,
+            sector: u8,
+            pending: Option<(u8, u8)>,
+            rx: &mut [u8],
+        ) -> Result<usize, ErrorKind> {
+            let mut cmd = Vec::new();
+            for i in 0..opts.tx_bits / 9 {
+                cmd.push(decrypt_byte(&mut cipher, tx, i).ok_or(ErrorKind::Timeout)?);
+            }
+            let (cmd, crc) = cmd.split_at(cmd.len() - 2);
+            assert_eq!(crc, crc_a(cmd));
+            if cmd == [CMD_HALT, 0x00] {
+                return Err(ErrorKind::Timeout);
+            }
+            self.command(cmd, cipher, sector, pending, rx)
+        }
+    }
+
+    impl Reader for MockCard {
+        type Error = ErrorKind;
+
+        async fn transceive(&mut self, _tx: &[u8], _rx: &mut [u8], _timeout_1fc: u32) -> Result<usize, Self::Error> {
+            panic!("unexpected standard frame")
+        }
+
+        async fn transceive_raw(&mut self, tx: &[u8], rx: &mut [u8], opts: RawFrame) -> Result<usize, Self::Error> {
+            if opts.parity {
+                // Plain AUTH.
+                assert!(matches!(self.state, State::Idle));
+                assert!(opts.tx_crc && !opts.rx_crc);
+                let (cipher, _, sector) = self.start_auth(tx);
+                self.state = State::Auth {
+                    cipher,
+                    nt: self.nt,
+                    sector,
+                };
+                rx[..4].copy_from_slice(&self.nt.to_be_bytes());
+                return Ok(32);
+            }
+
+            assert!(!opts.tx_crc && !opts.rx_crc);
+            assert_eq!(opts.tx_bits % 9, 0);
+            match core::mem::replace(&mut self.state, State::Idle) {
+                State::Idle => Err(ErrorKind::Timeout),
+                State::Auth { mut cipher, nt, sector } => {
+                    assert_eq!(opts.tx_bits, 72);
+                    let mut nr_enc = 0;
+                    for i in 0..4 {
+                        nr_enc = nr_enc << 8 | raw_byte(tx, i) as u32;
+                    }
+                    cipher.word(nr_enc, true);
+                    let mut ar = [0; 4];
+                    for (i, b) in ar.iter_mut().enumerate() {
+                        *b = decrypt_byte(&mut cipher, tx, i + 4).ok_or(ErrorKind::Timeout)?;
+                    }
+                    if u32::from_be_bytes(ar) != prng_successor(nt, 64) {
+                        return Err(ErrorKind::Timeout);
+                    }
+
+                    let mut frame = RawBuf::new();
+                    for b in prng_successor(nt, 96).to_be_bytes() {
+                        frame.push_encrypted(&mut cipher, b, false);
+                    }
+                    rx[..frame.as_bytes().len()].copy_from_slice(frame.as_bytes());
+                    self.state = State::Authenticated { cipher, sector };
+                    Ok(frame.bits)
+                }
+                State::Authenticated { cipher, sector } => self.encrypted(tx, opts, cipher, sector, None, rx),
+                State::Pending {
+                    cipher,
+                    sector,
+                    cmd,
+                    block,
+                } => self.encrypted(tx, opts, cipher, sector, Some((cmd, block)), rx),
+            }
+        }
+
+        fn uid(&self) -> &[u8] {
+            &UID
+        }
+
+        fn atqa(&self) -> [u8; 2] {
+            [0x04, 0x00]
+        }
+
+        fn sak(&self) -> u8 {
+            0x08
+        }
+    }
+
+    /// Returns the same bytes over and over.
+    struct FixedRng;
+
+    impl rand_core::RngCore for FixedRng {
+        fn next_u32(&mut self) -> u32 {
+            0x12345678
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            0x12345678_12345678
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(0x55);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl rand_core::CryptoRng for FixedRng {}
+
+    #[test]
+    fn test_sectors() {
+        assert_eq!(sector_of(0), 0);
+        assert_eq!(sector_of(127), 31);
+        assert_eq!(sector_of(128), 32);
+        assert_eq!(sector_of(255), 39);
+        assert_eq!(first_block(39), 240);
+        assert_eq!(trailer(1), 7);
+        assert_eq!(trailer(32), 143);
+        assert_eq!(trailer(39), 255);
+    }
+
+    #[tokio::test]
+    async fn test_read_write() {
+        let mut card = MockCard::new();
+        let mut c = Classic::new(&mut card);
+
+        c.authenticate(&mut FixedRng, 4, KeyType::A, &hex!("A0A1A2A3A4A5"))
+            .await
+            .unwrap();
+        assert!(c.is_authenticated());
+        assert_eq!(c.read(5).await.unwrap()[0], 5);
+
+        let data = hex!("00112233445566778899AABBCCDDEEFF");
+        c.write(6, &data).await.unwrap();
+        assert_eq!(c.read(6).await.unwrap(), data);
+
+        c.halt().await.unwrap();
+        assert!(!c.is_authenticated());
+        assert_eq!(card.blocks[6], data);
+    }
+
+    #[tokio::test]
+    async fn test_nested() {
+        let mut card = MockCard::new();
+        let mut c = Classic::new(&mut card);
+
+        c.authenticate(&mut FixedRng, 0, KeyType::A, &hex!("A0A1A2A3A4A5"))
+            .await
+            .unwrap();
+        assert_eq!(c.read(1).await.unwrap()[0], 1);
+
+        // Blocks of other sectors need authenticating again.
+        assert!(matches!(c.read(9).await, Err(Error::Nak(4))));
+        assert!(!c.is_authenticated());
+        assert!(matches!(c.read(9).await, Err(Error::NotAuthenticated)));
+
+        c.authenticate(&mut FixedRng, 0, KeyType::B, &hex!("B0B1B2B3B4B5"))
+            .await
+            .unwrap();
+        c.authenticate(&mut FixedRng, 9, KeyType::B, &hex!("B0B1B2B3B4B5"))
+            .await
+            .unwrap();
+        assert_eq!(c.read(9).await.unwrap()[0], 9);
+        c.authenticate(&mut FixedRng, 63, KeyType::A, &hex!("A0A1A2A3A4A5"))
+            .await
+            .unwrap();
+        assert_eq!(c.read(62).await.unwrap()[0], 62);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_key() {
+        let mut card = MockCard::new();
+        let mut c = Classic::new(&mut card);
+
+        assert!(matches!(
+            c.authenticate(&mut FixedRng, 4, KeyType::A, &hex!("FFFFFFFFFFFF")).await,
+            Err(Error::AuthFailed)
+        ));
+        assert!(!c.is_authenticated());
+
+        // Wrong key in a nested authentication.
+        c.authenticate(&mut FixedRng, 4, KeyType::A, &hex!("A0A1A2A3A4A5"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            c.authenticate(&mut FixedRng, 8, KeyType::B, &hex!("A0A1A2A3A4A5")).await,
+            Err(Error::AuthFailed)
+        ));
+        assert!(!c.is_authenticated());
+    }
+
+    #[tokio::test]
+    async fn test_trailer() {
+        let mut card = MockCard::new();
+        let mut c = Classic::new(&mut card);
+        c.authenticate(&mut FixedRng, 4, KeyType::A, &hex!("A0A1A2A3A4A5"))
+            .await
+            .unwrap();
+
+        // Inconsistent access bits are refused before sending anything.
+        let bad = hex!("A0A1A2A3A4A5 FF0781 00 B0B1B2B3B4B5");
+        assert!(matches!(c.write(7, &bad).await, Err(Error::InvalidTrailer)));
+        assert!(c.is_authenticated());
+
+        let trailer = Trailer {
+            key_a: hex!("A0A1A2A3A4A5"),
+            access: AccessBits {
+                data: [0b000, 0b000, 0b110],
+                trailer: 0b011,
+            },
+            gpb: 0x69,
+            key_b: hex!("B0B1B2B3B4B5"),
+        };
+        c.write(7, &trailer.encode().unwrap()).await.unwrap();
+        assert_eq!(card.blocks[7], hex!("A0A1A2A3A4A5 3B 47 8C 69 B0B1B2B3B4B5"));
+    }
+
+    #[tokio::test]
+    async fn test_value() {
+        let mut card = MockCard::new();
+        let mut c = Classic::new(&mut card);
+        c.authenticate(&mut FixedRng, 8, KeyType::B, &hex!("B0B1B2B3B4B5"))
+            .await
+            .unwrap();
+
+        c.write_value(8, &Value { value: 100, addr: 9 }).await.unwrap();
+        c.decrement(8, 30).await.unwrap();
+        // Not written until TRANSFER.
+        assert_eq!(c.read_value(8).await.unwrap().value, 100);
+        c.transfer(8).await.unwrap();
+        assert_eq!(c.read_value(8).await.unwrap(), Value { value: 70, addr: 9 });
+
+        c.increment(8, 5).await.unwrap();
+        c.transfer(8).await.unwrap();
+        // Back up to block 9.
+        c.restore(8).await.unwrap();
+        c.transfer(9).await.unwrap();
+        assert_eq!(c.read_value(9).await.unwrap().value, 75);
+
+        // Block 10 is not a value block.
+        assert!(matches!(c.read_value(10).await, Err(Error::InvalidValue)));
+        assert!(matches!(c.increment(10, 1).await, Err(Error::Nak(4))));
+        assert!(!c.is_authenticated());
+    }
+}
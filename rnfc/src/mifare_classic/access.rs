@@ -0,0 +1,298 @@
+//! Sector trailers and their access bits.
+//!
+//! The access bits are stored twice in a trailer, once inverted. If the two copies don't
+//! match, the card blocks the whole sector forever, so trailers are only encoded from
+//! valid conditions, and [`Classic::write`](super::Classic::write) refuses inconsistent ones.
+
+use super::{BLOCK_SIZE, Key};
+
+/// Which keys allow an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Keys {
+    Never,
+    KeyA,
+    KeyB,
+    KeyAOrB,
+}
+
+/// Operations allowed on a data block.
+///
+/// Decrement also covers RESTORE and TRANSFER.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DataPermissions {
+    pub read: Keys,
+    pub write: Keys,
+    pub increment: Keys,
+    pub decrement: Keys,
+}
+
+/// Operations allowed on a sector trailer. Key A can never be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TrailerPermissions {
+    pub key_a_write: Keys,
+    pub access_read: Keys,
+    pub access_write: Keys,
+    pub key_b_read: Keys,
+    pub key_b_write: Keys,
+}
+
+/// Access conditions of a sector, as 3-bit values with C1 as the MSB and C3 as the LSB.
+///
+/// Sectors with 4 blocks have a condition per data block. The 16-block sectors of 4K cards
+/// have one per group of 5 data blocks, see [`group_of`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AccessBits {
+    pub data: [u8; 3],
+    pub trailer: u8,
+}
+
+impl AccessBits {
+    /// Factory configuration: data blocks fully accessible with key A or B, and trailer
+    /// writable with key A. Encodes to `FF 07 80`.
+    pub const TRANSPORT: Self = Self {
+        data: [0b000; 3],
+        trailer: 0b001,
+    };
+
+    /// Decode bytes 6-8 of a sector trailer. Returns `None` if the bits are inconsistent.
+    pub fn decode(bytes: &[u8; 3]) -> Option<Self> {
+        let c1 = bytes[1] >> 4;
+        let c2 = bytes[2] & 0x0F;
+        let c3 = bytes[2] >> 4;
+        if bytes[0] & 0x0F != !c1 & 0x0F || bytes[0] >> 4 != !c2 & 0x0F || bytes[1] & 0x0F != !c3 & 0x0F {
+            return None;
+        }
+
+        let cond = |i: usize| (c1 >> i & 1) << 2 | (c2 >> i & 1) << 1 | (c3 >> i & 1);
+        Some(Self {
+            data: [cond(0), cond(1), cond(2)],
+            trailer: cond(3),
+        })
+    }
+
+    /// Encode to bytes 6-8 of a sector trailer. Returns `None` if a condition is not 3 bits.
+    pub fn encode(&self) -> Option<[u8; 3]> {
+        let (mut c1, mut c2, mut c3) = (0, 0, 0);
+        for (i, &c) in self.data.iter().chain([&self.trailer]).enumerate() {
+            if c > 0b111 {
+                return None;
+            }
+            c1 |= (c >> 2 & 1) << i;
+            c2 |= (c >> 1 & 1) << i;
+            c3 |= (c & 1) << i;
+        }
+        Some([(!c2 & 0x0F) << 4 | (!c1 & 0x0F), c1 << 4 | (!c3 & 0x0F), c3 << 4 | c2])
+    }
+
+    /// Permissions of data block or group `i`.
+    ///
+    /// Operations allowed with key B are not possible if key B is readable, see
+    /// [`key_b_readable`](Self::key_b_readable).
+    pub fn data_permissions(&self, i: usize) -> DataPermissions {
+        use Keys::*;
+        let (read, write, increment, decrement) = match self.data[i] & 0b111 {
+            0b000 => (KeyAOrB, KeyAOrB, KeyAOrB, KeyAOrB),
+            0b010 => (KeyAOrB, Never, Never, Never),
+            0b100 => (KeyAOrB, KeyB, Never, Never),
+            0b110 => (KeyAOrB, KeyB, KeyB, KeyAOrB),
+            0b001 => (KeyAOrB, Never, Never, KeyAOrB),
+            0b011 => (KeyB, KeyB, Never, Never),
+            0b101 => (KeyB, Never, Never, Never),
+            _ => (Never, Never, Never, Never),
+        };
+        DataPermissions {
+            read,
+            write,
+            increment,
+            decrement,
+        }
+    }
+
+    /// Permissions of the sector trailer.
+    pub fn trailer_permissions(&self) -> TrailerPermissions {
+        use Keys::*;
+        let (key_a_write, access_read, access_write, key_b_read, key_b_write) = match self.trailer & 0b111 {
+            0b000 => (KeyA, KeyA, Never, KeyA, KeyA),
+            0b010 => (Never, KeyA, Never, KeyA, Never),
+            0b100 => (KeyB, KeyAOrB, Never, Never, KeyB),
+            0b110 => (Never, KeyAOrB, Never, Never, Never),
+            0b001 => (KeyA, KeyA, KeyA, KeyA, KeyA),
+            0b011 => (KeyB, KeyAOrB, KeyB, Never, KeyB),
+            0b101 => (Never, KeyAOrB, KeyB, Never, Never),
+            _ => (Never, KeyAOrB, Never, Never, Never),
+        };
+        TrailerPermissions {
+            key_a_write,
+            access_read,
+            access_write,
+            key_b_read,
+            key_b_write,
+        }
+    }
+
+    /// Whether key B can be read. It then can't be used for authentication, and the
+    /// bytes are usable as data.
+    pub fn key_b_readable(&self) -> bool {
+        self.trailer_permissions().key_b_read != Keys::Never
+    }
+}
+
+/// Index of the access condition of a block, 3 for the trailer.
+pub fn group_of(block: u8) -> usize {
+    match block {
+        0..128 => block as usize % 4,
+        _ => (block as usize - 128) % 16 / 5,
+    }
+}
+
+/// Contents of a sector trailer.
+///
+/// Key A always reads back as zeros. Key B too, unless it's readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Trailer {
+    pub key_a: Key,
+    pub access: AccessBits,
+    /// General purpose byte, free for the application.
+    pub gpb: u8,
+    pub key_b: Key,
+}
+
+impl Trailer {
+    /// Decode a sector trailer. Returns `None` if the access bits are inconsistent.
+    pub fn decode(block: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        let access = AccessBits::decode(unwrap!(block[6..9].try_into()))?;
+        Some(Self {
+            key_a: unwrap!(block[0..6].try_into()),
+            access,
+            gpb: block[9],
+            key_b: unwrap!(block[10..16].try_into()),
+        })
+    }
+
+    /// Encode a sector trailer. Returns `None` if the access conditions are invalid.
+    pub fn encode(&self) -> Option<[u8; BLOCK_SIZE]> {
+        let mut block = [0; BLOCK_SIZE];
+        block[0..6].copy_from_slice(&self.key_a);
+        block[6..9].copy_from_slice(&self.access.encode()?);
+        block[9] = self.gpb;
+        block[10..16].copy_from_slice(&self.key_b);
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let cases = [
+            (hex!("FF 07 80"), AccessBits::TRANSPORT),
+            (
+                hex!("78 77 88"),
+                AccessBits {
+                    data: [0b100; 3],
+                    trailer: 0b011,
+                },
+            ),
+            (
+                hex!("08 77 8F"),
+                AccessBits {
+                    data: [0b110; 3],
+                    trailer: 0b011,
+                },
+            ),
+            (
+                hex!("00 F0 FF"),
+                AccessBits {
+                    data: [0b111; 3],
+                    trailer: 0b111,
+                },
+            ),
+            (
+                hex!("A5 A6 95"),
+                AccessBits {
+                    data: [0b011, 0b100, 0b010],
+                    trailer: 0b101,
+                },
+            ),
+        ];
+        for (bytes, access) in cases {
+            assert_eq!(AccessBits::decode(&bytes), Some(access));
+            assert_eq!(access.encode(), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn test_inconsistent() {
+        assert_eq!(AccessBits::decode(&hex!("FF 07 81")), None);
+        assert_eq!(AccessBits::decode(&hex!("00 00 00")), None);
+        assert_eq!(AccessBits::decode(&hex!("FF FF FF")), None);
+        let bad = AccessBits {
+            data: [0, 8, 0],
+            trailer: 1,
+        };
+        assert_eq!(bad.encode(), None);
+
+        // All encodable conditions decode back to themselves.
+        for i in 0..1u16 << 12 {
+            let c = |n: u16| (i >> (n * 3) & 7) as u8;
+            let access = AccessBits {
+                data: [c(0), c(1), c(2)],
+                trailer: c(3),
+            };
+            assert_eq!(AccessBits::decode(&access.encode().unwrap()), Some(access));
+        }
+    }
+
+    #[test]
+    fn test_permissions() {
+        let transport = AccessBits::TRANSPORT;
+        assert!(transport.key_b_readable());
+        assert_eq!(transport.data_permissions(0).write, Keys::KeyAOrB);
+        assert_eq!(transport.trailer_permissions().access_write, Keys::KeyA);
+
+        let access = AccessBits::decode(&hex!("08 77 8F")).unwrap();
+        assert!(!access.key_b_readable());
+        assert_eq!(
+            access.data_permissions(1),
+            DataPermissions {
+                read: Keys::KeyAOrB,
+                write: Keys::KeyB,
+                increment: Keys::KeyB,
+                decrement: Keys::KeyAOrB,
+            }
+        );
+        assert_eq!(access.trailer_permissions().key_a_write, Keys::KeyB);
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(group_of(4), 0);
+        assert_eq!(group_of(7), 3);
+        assert_eq!(group_of(128), 0);
+        assert_eq!(group_of(133), 1);
+        assert_eq!(group_of(142), 2);
+        assert_eq!(group_of(143), 3);
+        assert_eq!(group_of(255), 3);
+    }
+
+    #[test]
+    fn test_trailer() {
+        let block = hex!("000000000000 FF0780 69 FFFFFFFFFFFF");
+        let trailer = Trailer::decode(&block).unwrap();
+        assert_eq!(trailer.access, AccessBits::TRANSPORT);
+        assert_eq!(trailer.gpb, 0x69);
+        assert_eq!(trailer.key_b, [0xFF; 6]);
+        assert_eq!(trailer.encode(), Some(block));
+
+        assert_eq!(Trailer::decode(&hex!("000000000000 FF0781 69 FFFFFFFFFFFF")), None);
+    }
+}
@@ -0,0 +1,171 @@
+//! Crypto1 stream cipher used by MIFARE Classic.
+//!
+//! Written from the description in "Dismantling MIFARE Classic" (Garcia et al., ESORICS
+//! 2008): a 48-bit LFSR, and a two-layer nonlinear filter over its 20 odd-numbered bits.
+
+/// Taps of the LFSR feedback, x0 ^ x5 ^ x9 ^ ... ^ x43.
+const FEEDBACK: u64 = 1
+    | 1 << 5
+    | 1 << 9
+    | 1 << 10
+    | 1 << 12
+    | 1 << 14
+    | 1 << 15
+    | 1 << 17
+    | 1 << 19
+    | 1 << 24
+    | 1 << 25
+    | 1 << 27
+    | 1 << 29
+    | 1 << 35
+    | 1 << 39
+    | 1 << 41
+    | 1 << 42
+    | 1 << 43;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Crypto1 {
+    /// LFSR cells x0..x47 in bits 0..47. Bits shift towards x0, new bits enter at x47.
+    state: u64,
+}
+
+impl Crypto1 {
+    /// Load a 6-byte key into the LFSR, in transmission order: key bit `i` goes to cell `xi`.
+    pub fn new(key: &[u8; 6]) -> Self {
+        let mut k = [0; 8];
+        k[..6].copy_from_slice(key);
+        Self {
+            state: u64::from_le_bytes(k),
+        }
+    }
+
+    /// Keystream bit for the current state, without clocking the LFSR.
+    ///
+    /// This is the bit used to encrypt the parity bit of the byte just processed.
+    pub fn peek(&self) -> u8 {
+        filter(self.state)
+    }
+
+    /// Clock the LFSR once, shifting in `input`. Returns the keystream bit.
+    ///
+    /// If `encrypted` is set, `input` is ciphertext, and the plaintext bit is shifted in instead.
+    pub fn bit(&mut self, input: u8, encrypted: bool) -> u8 {
+        let ret = filter(self.state);
+        let mut feedin = (self.state & FEEDBACK).count_ones() as u8 & 1;
+        feedin ^= input & 1;
+        feedin ^= ret & encrypted as u8;
+        self.state = self.state >> 1 | (feedin as u64) << 47;
+        ret
+    }
+
+    /// Clock the LFSR 8 times, shifting in `input` LSB first. Returns the keystream byte.
+    pub fn byte(&mut self, input: u8, encrypted: bool) -> u8 {
+        let mut ret = 0;
+        for i in 0..8 {
+            ret |= self.bit(input >> i, encrypted) << i;
+        }
+        ret
+    }
+
+    /// Clock the LFSR 32 times, shifting in `input` as 4 big endian bytes, each LSB first.
+    /// Returns the keystream, in the same order.
+    pub fn word(&mut self, input: u32, encrypted: bool) -> u32 {
+        let mut ret = 0;
+        for i in 0..32 {
+            ret |= (self.bit((input >> (i ^ 24)) as u8, encrypted) as u32) << (i ^ 24);
+        }
+        ret
+    }
+}
+
+/// Advance the tag's 16-bit nonce PRNG `n` steps. Nonces are in transmission order, big endian.
+pub fn prng_successor(x: u32, n: u32) -> u32 {
+    let mut x = x.swap_bytes();
+    for _ in 0..n {
+        x = x >> 1 | (x >> 16 ^ x >> 18 ^ x >> 19 ^ x >> 21) << 31;
+    }
+    x.swap_bytes()
+}
+
+/// Odd parity bit of a byte, as sent on the air.
+pub fn odd_parity(b: u8) -> u8 {
+    (b.count_ones() as u8 & 1) ^ 1
+}
+
+/// Output filter: `fc` over `fa`/`fb` applied to x9, x11, ..., x47, four cells each.
+fn filter(s: u64) -> u8 {
+    let x = |i: u32| (s >> i) as u8 & 1;
+    fc(
+        fa(x(9), x(11), x(13), x(15)),
+        fb(x(17), x(19), x(21), x(23)),
+        fb(x(25), x(27), x(29), x(31)),
+        fa(x(33), x(35), x(37), x(39)),
+        fb(x(41), x(43), x(45), x(47)),
+    )
+}
+
+fn fa(y0: u8, y1: u8, y2: u8, y3: u8) -> u8 {
+    ((y0 | y1) ^ (y0 & y3)) ^ (y2 & ((y0 ^ y1) | y3))
+}
+
+fn fb(y0: u8, y1: u8, y2: u8, y3: u8) -> u8 {
+    ((y0 & y1) | y2) ^ ((y0 ^ y1) & (y2 | y3))
+}
+
+fn fc(y0: u8, y1: u8, y2: u8, y3: u8, y4: u8) -> u8 {
+    (y0 | ((y1 | y4) & (y3 ^ y4))) ^ ((y0 ^ (y1 & y3)) & ((y2 ^ y3) | (y1 & y4)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Authentication traced between a reader and a card with key FFFFFFFFFFFF,
+    // the example of the mfkey64 key recovery tool.
+    const UID: u32 = 0x9c599b32;
+    const NT: u32 = 0x82a4166c;
+    const NR_ENC: u32 = 0xa1e458ce;
+    const AR_ENC: u32 = 0x6eea41e0;
+    const AT_ENC: u32 = 0x5cadf439;
+
+    #[test]
+    fn test_auth_trace() {
+        // Card side: decrypt the reader's answers and check them.
+        let mut c = Crypto1::new(&[0xFF; 6]);
+        c.word(UID ^ NT, false);
+        c.word(NR_ENC, true);
+        let ks2 = c.word(0, false);
+        assert_eq!(AR_ENC ^ ks2, prng_successor(NT, 64));
+        let ks3 = c.word(0, false);
+        assert_eq!(AT_ENC ^ ks3, prng_successor(NT, 96));
+    }
+
+    #[test]
+    fn test_encrypt_reader_nonce() {
+        // Recover the plaintext reader nonce, then check encrypting it again gives the trace.
+        let mut c = Crypto1::new(&[0xFF; 6]);
+        c.word(UID ^ NT, false);
+        let mut d = c;
+        let nr = d.word(NR_ENC, true) ^ NR_ENC;
+
+        let ks1 = c.word(nr, false);
+        assert_eq!(nr ^ ks1, NR_ENC);
+        assert_eq!(c, d);
+    }
+
+    #[test]
+    fn test_prng() {
+        assert_eq!(prng_successor(NT, 0), NT);
+        assert_eq!(prng_successor(prng_successor(NT, 32), 32), prng_successor(NT, 64));
+        // The PRNG has a period of 65535.
+        assert_eq!(prng_successor(NT, 65535), NT);
+    }
+
+    #[test]
+    fn test_parity() {
+        assert_eq!(odd_parity(0x00), 1);
+        assert_eq!(odd_parity(0x01), 0);
+        assert_eq!(odd_parity(0x93), 1);
+    }
+}
@@ -0,0 +1,127 @@
+//! Value blocks and the commands operating on them.
+//!
+//! A value block holds a signed 32-bit value stored three times, once inverted, and an
+//! address byte free for the application, typically pointing to a backup block.
+//!
+//! INCREMENT, DECREMENT and RESTORE load the result in the card's transfer buffer, which
+//! TRANSFER writes to a block. Until then, the block is unchanged.
+
+use rnfc_traits::iso14443a::Reader;
+
+use super::{BLOCK_SIZE, Classic, Error, Response, TIMEOUT_1FC, WRITE_TIMEOUT_1FC};
+
+const CMD_DECREMENT: u8 = 0xC0;
+const CMD_INCREMENT: u8 = 0xC1;
+const CMD_RESTORE: u8 = 0xC2;
+const CMD_TRANSFER: u8 = 0xB0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Value {
+    pub value: i32,
+    pub addr: u8,
+}
+
+impl Value {
+    /// Decode a value block. Returns `None` if the block is not in the value block format.
+    pub fn decode(block: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        let value = unwrap!(block[0..4].try_into());
+        let inverted: [u8; 4] = unwrap!(block[4..8].try_into());
+        if block[8..12] != value || inverted.map(|b| !b) != value {
+            return None;
+        }
+        let addr = block[12];
+        if block[13] != !addr || block[14] != addr || block[15] != !addr {
+            return None;
+        }
+        Some(Self {
+            value: i32::from_le_bytes(value),
+            addr,
+        })
+    }
+
+    pub fn encode(&self) -> [u8; BLOCK_SIZE] {
+        let value = self.value.to_le_bytes();
+        let mut block = [0; BLOCK_SIZE];
+        block[0..4].copy_from_slice(&value);
+        block[4..8].copy_from_slice(&value.map(|b| !b));
+        block[8..12].copy_from_slice(&value);
+        block[12..16].copy_from_slice(&[self.addr, !self.addr, self.addr, !self.addr]);
+        block
+    }
+}
+
+impl<T: Reader> Classic<T> {
+    /// Read and decode a value block.
+    pub async fn read_value(&mut self, block: u8) -> Result<Value, Error<T::Error>> {
+        let data = self.read(block).await?;
+        Value::decode(&data).ok_or(Error::InvalidValue)
+    }
+
+    /// Format a block as a value block.
+    pub async fn write_value(&mut self, block: u8, value: &Value) -> Result<(), Error<T::Error>> {
+        self.write(block, &value.encode()).await
+    }
+
+    /// INCREMENT: load the value of `block` plus `amount` in the transfer buffer.
+    pub async fn increment(&mut self, block: u8, amount: u32) -> Result<(), Error<T::Error>> {
+        self.value_op(CMD_INCREMENT, block, amount).await
+    }
+
+    /// DECREMENT: load the value of `block` minus `amount` in the transfer buffer.
+    pub async fn decrement(&mut self, block: u8, amount: u32) -> Result<(), Error<T::Error>> {
+        self.value_op(CMD_DECREMENT, block, amount).await
+    }
+
+    /// RESTORE: load the value of `block` in the transfer buffer, to copy it to another block.
+    pub async fn restore(&mut self, block: u8) -> Result<(), Error<T::Error>> {
+        self.value_op(CMD_RESTORE, block, 0).await
+    }
+
+    /// TRANSFER: write the transfer buffer to `block`.
+    pub async fn transfer(&mut self, block: u8) -> Result<(), Error<T::Error>> {
+        self.transceive_ack(&[CMD_TRANSFER, block], WRITE_TIMEOUT_1FC).await
+    }
+
+    async fn value_op(&mut self, cmd: u8, block: u8, operand: u32) -> Result<(), Error<T::Error>> {
+        match self.transceive(&[cmd, block], &mut [], TIMEOUT_1FC).await? {
+            Response::Ack => {}
+            r => return self.unexpected(r),
+        }
+        // The operand is only answered if the operation fails, for example on a block
+        // not in the value block format.
+        self.transceive_silent(&operand.to_le_bytes()).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let cases = [
+            (hex!("64000000 9BFFFFFF 64000000 05FA05FA"), 100, 5),
+            (hex!("FFFFFFFF 00000000 FFFFFFFF 00FF00FF"), -1, 0),
+            (hex!("00000080 FFFFFF7F 00000080 7F807F80"), i32::MIN, 0x7F),
+        ];
+        for (block, value, addr) in cases {
+            let v = Value { value, addr };
+            assert_eq!(v.encode(), block);
+            assert_eq!(Value::decode(&block), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(Value::decode(&[0; 16]), None);
+        // Bad inverted copy.
+        assert_eq!(Value::decode(&hex!("64000000 9BFFFFFE 64000000 05FA05FA")), None);
+        // Bad third copy.
+        assert_eq!(Value::decode(&hex!("64000000 9BFFFFFF 65000000 05FA05FA")), None);
+        // Bad address.
+        assert_eq!(Value::decode(&hex!("64000000 9BFFFFFF 64000000 05FA06FA")), None);
+    }
+}
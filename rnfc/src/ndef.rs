@@ -0,0 +1,910 @@
+//! NDEF message parsing and building.
+//!
+//! [`Message::parse`] validates a whole message up front, after which its records can be
+//! iterated without copying. Chunked records are presented as a single [`Record`] whose
+//! [`Payload`] spans all the chunks.
+//!
+//! [`MessageBuilder`] serializes records into a `heapless` buffer, taking care of the
+//! MB/ME/SR/IL flags.
+//!
+//! The well-known URI, Text and Smart Poster types live in the [`uri`], [`text`] and
+//! [`smart_poster`] submodules, which also add `push_*` methods to [`MessageBuilder`].
+//! MIME media and external type records are handled here.
+
+use heapless::Vec;
+
+pub mod smart_poster;
+pub mod text;
+pub mod uri;
+
+const FLAG_MB: u8 = 0x80;
+const FLAG_ME: u8 = 0x40;
+const FLAG_CF: u8 = 0x20;
+const FLAG_SR: u8 = 0x10;
+const FLAG_IL: u8 = 0x08;
+const TNF_MASK: u8 = 0x07;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Data ended in the middle of a record.
+    Truncated,
+    /// The first record doesn't have the MB flag set.
+    MissingMessageBegin,
+    /// A record other than the first has the MB flag set.
+    UnexpectedMessageBegin,
+    /// Data ended at a record boundary, but no record had the ME flag set.
+    MissingMessageEnd,
+    /// There's data after the record with the ME flag set.
+    TrailingData,
+    /// Chunked record doesn't follow the chunking rules.
+    InvalidChunk,
+    /// Record fields are inconsistent with its TNF, or too long to encode.
+    InvalidRecord,
+    /// The output buffer is too small.
+    BufferTooSmall,
+    /// The record doesn't have the TNF and type expected by the typed record parser.
+    UnexpectedType,
+    /// The payload of a typed record is malformed.
+    InvalidPayload,
+}
+
+/// Type Name Format, the 3-bit field that says how to interpret the record type.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Tnf {
+    /// No type, id or payload.
+    Empty = 0,
+    /// NFC Forum well-known type, for example `U` or `T`.
+    WellKnown = 1,
+    /// Media type as defined in RFC 2046, for example `text/plain`.
+    Media = 2,
+    /// Absolute URI as defined in RFC 3986.
+    AbsoluteUri = 3,
+    /// NFC Forum external type, for example `example.com:mytype`.
+    External = 4,
+    /// Unknown payload type. The type field must be empty.
+    Unknown = 5,
+    /// Used in the middle and terminating chunks of a chunked record.
+    Unchanged = 6,
+    /// Reserved, parsers should treat it like `Unknown`.
+    Reserved = 7,
+}
+
+impl Tnf {
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & TNF_MASK {
+            0 => Self::Empty,
+            1 => Self::WellKnown,
+            2 => Self::Media,
+            3 => Self::AbsoluteUri,
+            4 => Self::External,
+            5 => Self::Unknown,
+            6 => Self::Unchanged,
+            _ => Self::Reserved,
+        }
+    }
+}
+
+/// A single record as it appears on the wire. Chunked records consist of several of these.
+struct Chunk<'a> {
+    header: u8,
+    ty: &'a [u8],
+    id: &'a [u8],
+    payload: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    /// Parse the chunk at the start of `data`. Returns it with the number of bytes it occupies.
+    fn parse(data: &'a [u8]) -> Result<(Self, usize), Error> {
+        let header = *data.first().ok_or(Error::Truncated)?;
+        let type_len = *data.get(1).ok_or(Error::Truncated)? as usize;
+        let mut pos = 2;
+
+        let payload_len = if header & FLAG_SR != 0 {
+            let len = *data.get(pos).ok_or(Error::Truncated)?;
+            pos += 1;
+            len as usize
+        } else {
+            let len: [u8; 4] = data
+                .get(pos..pos + 4)
+                .and_then(|b| b.try_into().ok())
+                .ok_or(Error::Truncated)?;
+            pos += 4;
+            usize::try_from(u32::from_be_bytes(len)).map_err(|_| Error::Truncated)?
+        };
+
+        let id_len = if header & FLAG_IL != 0 {
+            let len = *data.get(pos).ok_or(Error::Truncated)?;
+            pos += 1;
+            len as usize
+        } else {
+            0
+        };
+
+        let mut take = |len: usize| {
+            let end = pos.checked_add(len).ok_or(Error::Truncated)?;
+            let field = data.get(pos..end).ok_or(Error::Truncated)?;
+            pos = end;
+            Ok(field)
+        };
+        let ty = take(type_len)?;
+        let id = take(id_len)?;
+        let payload = take(payload_len)?;
+
+        Ok((Self { header, ty, id, payload }, pos))
+    }
+
+    fn tnf(&self) -> Tnf {
+        Tnf::from_bits(self.header)
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.header & flag != 0
+    }
+}
+
+/// Parse the (possibly chunked) record at the start of `data`.
+///
+/// Returns the record, the number of bytes it occupies and whether it has the ME flag.
+fn parse_record(data: &[u8], first: bool) -> Result<(Record<'_>, usize, bool), Error> {
+    let (chunk, first_len) = Chunk::parse(data)?;
+    let mut pos = first_len;
+
+    if chunk.flag(FLAG_MB) != first {
+        return Err(match first {
+            true => Error::MissingMessageBegin,
+            false => Error::UnexpectedMessageBegin,
+        });
+    }
+
+    match chunk.tnf() {
+        Tnf::Unchanged => return Err(Error::InvalidChunk),
+        Tnf::Empty if !chunk.ty.is_empty() || !chunk.id.is_empty() || !chunk.payload.is_empty() => {
+            return Err(Error::InvalidRecord);
+        }
+        Tnf::Unknown if !chunk.ty.is_empty() => return Err(Error::InvalidRecord),
+        _ => {}
+    }
+
+    let mut len = chunk.payload.len();
+    let mut last = chunk.flag(FLAG_ME);
+
+    if chunk.flag(FLAG_CF) {
+        // The ME flag goes in the terminating chunk.
+        if last {
+            return Err(Error::InvalidChunk);
+        }
+
+        loop {
+            let (c, n) = Chunk::parse(&data[pos..])?;
+            if c.flag(FLAG_MB) {
+                return Err(Error::UnexpectedMessageBegin);
+            }
+            if c.tnf() != Tnf::Unchanged || !c.ty.is_empty() || c.flag(FLAG_IL) {
+                return Err(Error::InvalidChunk);
+            }
+
+            pos += n;
+            len = len.checked_add(c.payload.len()).ok_or(Error::InvalidChunk)?;
+
+            if !c.flag(FLAG_CF) {
+                last = c.flag(FLAG_ME);
+                break;
+            }
+            if c.flag(FLAG_ME) {
+                return Err(Error::InvalidChunk);
+            }
+        }
+    }
+
+    Ok((
+        Record {
+            tnf: chunk.tnf(),
+            ty: chunk.ty,
+            id: chunk.id,
+            payload: Payload {
+                first: chunk.payload,
+                rest: &data[first_len..pos],
+                len,
+            },
+        },
+        pos,
+        last,
+    ))
+}
+
+/// A validated NDEF message, borrowing its bytes.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// Validate `data` as a complete NDEF message.
+    ///
+    /// `data` must end exactly at the end of the record with the ME flag.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let mut pos = 0;
+        let mut first = true;
+        loop {
+            if pos == data.len() && !first {
+                return Err(Error::MissingMessageEnd);
+            }
+            let (_, n, last) = parse_record(&data[pos..], first)?;
+            pos += n;
+            first = false;
+            if last {
+                break;
+            }
+        }
+
+        if pos != data.len() {
+            return Err(Error::TrailingData);
+        }
+
+        Ok(Self { data })
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn records(&self) -> Records<'a> {
+        Records {
+            data: self.data,
+            first: true,
+        }
+    }
+}
+
+/// Iterator over the records of a [`Message`].
+#[derive(Debug, Clone)]
+pub struct Records<'a> {
+    data: &'a [u8],
+    first: bool,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        // The message was validated in `Message::parse`, so this can't fail.
+        let (record, n, _) = parse_record(self.data, self.first).ok()?;
+        self.data = &self.data[n..];
+        self.first = false;
+        Some(record)
+    }
+}
+
+/// A record of a [`Message`]. For chunked records, the type and id come from the first chunk.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Record<'a> {
+    pub tnf: Tnf,
+    pub ty: &'a [u8],
+    pub id: &'a [u8],
+    pub payload: Payload<'a>,
+}
+
+impl<'a> Record<'a> {
+    /// Check whether this is an NFC Forum well-known type record of type `ty`.
+    pub fn is_well_known(&self, ty: &[u8]) -> bool {
+        self.tnf == Tnf::WellKnown && self.ty == ty
+    }
+
+    /// Get the media type of a [`Tnf::Media`] record, for example `text/plain`.
+    pub fn media_type(&self) -> Option<&'a str> {
+        match self.tnf {
+            Tnf::Media => core::str::from_utf8(self.ty).ok(),
+            _ => None,
+        }
+    }
+
+    /// Get the domain and type of a [`Tnf::External`] record. For `example.com:mytype`
+    /// this returns `("example.com", "mytype")`.
+    pub fn external_type(&self) -> Option<(&'a str, &'a str)> {
+        match self.tnf {
+            Tnf::External => core::str::from_utf8(self.ty).ok()?.split_once(':'),
+            _ => None,
+        }
+    }
+
+    /// Get the payload as a single slice, for the typed record parsers.
+    ///
+    /// Chunked typed records are rejected with [`Error::InvalidPayload`]. Copy the payload
+    /// with [`Payload::to_vec`] and parse that instead if you need to handle them.
+    fn typed_payload(&self, tnf: Tnf, ty: &[u8]) -> Result<&'a [u8], Error> {
+        if self.tnf != tnf || self.ty != ty {
+            return Err(Error::UnexpectedType);
+        }
+        self.payload.as_slice().ok_or(Error::InvalidPayload)
+    }
+}
+
+/// Payload of a [`Record`], which may be split across several chunks.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Payload<'a> {
+    /// Payload of the first chunk.
+    first: &'a [u8],
+    /// Middle and terminating chunks, still encoded.
+    rest: &'a [u8],
+    /// Total payload length.
+    len: usize,
+}
+
+impl<'a> Payload<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        !self.rest.is_empty()
+    }
+
+    /// Get the payload as a single slice. Returns `None` if the record is chunked.
+    pub fn as_slice(&self) -> Option<&'a [u8]> {
+        match self.is_chunked() {
+            true => None,
+            false => Some(self.first),
+        }
+    }
+
+    /// Iterate the payload chunk by chunk.
+    pub fn chunks(&self) -> PayloadChunks<'a> {
+        PayloadChunks {
+            first: Some(self.first),
+            rest: self.rest,
+        }
+    }
+
+    /// Copy the payload into `buf`, returning its length.
+    pub fn copy_to(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let buf = buf.get_mut(..self.len).ok_or(Error::BufferTooSmall)?;
+        let mut pos = 0;
+        for c in self.chunks() {
+            buf[pos..][..c.len()].copy_from_slice(c);
+            pos += c.len();
+        }
+        Ok(pos)
+    }
+
+    /// Copy the payload into a new `heapless::Vec`.
+    pub fn to_vec<const N: usize>(&self) -> Result<Vec<u8, N>, Error> {
+        let mut res = Vec::new();
+        for c in self.chunks() {
+            res.extend_from_slice(c).map_err(|_| Error::BufferTooSmall)?;
+        }
+        Ok(res)
+    }
+}
+
+/// Iterator over the chunks of a [`Payload`].
+#[derive(Debug, Clone)]
+pub struct PayloadChunks<'a> {
+    first: Option<&'a [u8]>,
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for PayloadChunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (c, n) = Chunk::parse(self.rest).ok()?;
+        self.rest = &self.rest[n..];
+        Some(c.payload)
+    }
+}
+
+/// Serializes records into an NDEF message.
+///
+/// The MB and ME flags are kept up to date on every push, so the buffer is a valid
+/// message as soon as one record has been added.
+pub struct MessageBuilder<const N: usize> {
+    buf: Vec<u8, N>,
+    /// Offset of the header of the last chunk written, which carries the ME flag.
+    end: Option<usize>,
+}
+
+impl<const N: usize> Default for MessageBuilder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> MessageBuilder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            end: None,
+        }
+    }
+
+    /// Append a record.
+    pub fn push(&mut self, tnf: Tnf, ty: &[u8], id: &[u8], payload: &[u8]) -> Result<(), Error> {
+        self.push_with(tnf, ty, id, payload.len(), |w| w.write(payload))
+    }
+
+    /// Append a record, copying it from a parsed message. Chunked records are merged.
+    pub fn push_record(&mut self, record: &Record<'_>) -> Result<(), Error> {
+        self.push_with(record.tnf, record.ty, record.id, record.payload.len(), |w| {
+            record.payload.chunks().try_for_each(|c| w.write(c))
+        })
+    }
+
+    /// Append a record whose payload is written by `f`, which must write exactly `payload_len` bytes.
+    pub fn push_with(
+        &mut self,
+        tnf: Tnf,
+        ty: &[u8],
+        id: &[u8],
+        payload_len: usize,
+        f: impl FnOnce(&mut PayloadWriter<'_, N>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.transaction(|this| {
+            validate(tnf, ty, id, payload_len)?;
+            let header = this.write_header(tnf as u8, ty, id, payload_len)?;
+            let mut w = PayloadWriter {
+                buf: &mut this.buf,
+                remaining: payload_len,
+            };
+            f(&mut w)?;
+            if w.remaining != 0 {
+                return Err(Error::InvalidRecord);
+            }
+            Ok(header)
+        })
+    }
+
+    /// Append a MIME media record, for example with type `text/plain` or `application/json`.
+    pub fn push_media(&mut self, media_type: &str, payload: &[u8]) -> Result<(), Error> {
+        let valid = media_type
+            .split_once('/')
+            .is_some_and(|(t, s)| !t.is_empty() && !s.is_empty())
+            && media_type.bytes().all(|b| b.is_ascii_graphic());
+        if !valid {
+            return Err(Error::InvalidRecord);
+        }
+        self.push(Tnf::Media, media_type.as_bytes(), &[], payload)
+    }
+
+    /// Append an NFC Forum external type record. `ty` has the form `domain:type`,
+    /// without the `urn:nfc:ext:` prefix.
+    pub fn push_external(&mut self, ty: &str, payload: &[u8]) -> Result<(), Error> {
+        let valid =
+            ty.split_once(':').is_some_and(|(d, t)| !d.is_empty() && !t.is_empty()) && ty.bytes().all(|b| b.is_ascii_graphic());
+        if !valid {
+            return Err(Error::InvalidRecord);
+        }
+        self.push(Tnf::External, ty.as_bytes(), &[], payload)
+    }
+
+    /// Append a record split into chunks of at most `chunk_len` bytes of payload.
+    pub fn push_chunked(&mut self, tnf: Tnf, ty: &[u8], id: &[u8], payload: &[u8], chunk_len: usize) -> Result<(), Error> {
+        if chunk_len == 0 {
+            return Err(Error::InvalidChunk);
+        }
+        if payload.len() <= chunk_len {
+            return self.push(tnf, ty, id, payload);
+        }
+
+        self.transaction(|this| {
+            validate(tnf, ty, id, payload.len())?;
+
+            let mut chunks = payload.chunks(chunk_len).peekable();
+            let mut header = FLAG_CF | tnf as u8;
+            let mut ty = ty;
+            let mut id = id;
+            let mut last = 0;
+            while let Some(c) = chunks.next() {
+                if chunks.peek().is_none() {
+                    header &= !FLAG_CF;
+                }
+                last = this.write_header(header, ty, id, c.len())?;
+                this.write(c)?;
+                header = (header & FLAG_CF) | Tnf::Unchanged as u8;
+                ty = &[];
+                id = &[];
+            }
+            Ok(last)
+        })
+    }
+
+    /// Run `f`, which writes a record and returns the offset of its last header.
+    /// On failure, the buffer is restored to what it was.
+    fn transaction(&mut self, f: impl FnOnce(&mut Self) -> Result<usize, Error>) -> Result<(), Error> {
+        let len = self.buf.len();
+        match f(self) {
+            Ok(header) => {
+                if let Some(end) = self.end {
+                    self.buf[end] &= !FLAG_ME;
+                }
+                self.buf[header] |= FLAG_ME;
+                self.end = Some(header);
+                Ok(())
+            }
+            Err(e) => {
+                self.buf.truncate(len);
+                Err(e)
+            }
+        }
+    }
+
+    /// Write a chunk header, returning its offset. MB, SR and IL are set as needed.
+    fn write_header(&mut self, mut header: u8, ty: &[u8], id: &[u8], payload_len: usize) -> Result<usize, Error> {
+        let pos = self.buf.len();
+        if pos == 0 {
+            header |= FLAG_MB;
+        }
+        if payload_len <= 0xFF {
+            header |= FLAG_SR;
+        }
+        if !id.is_empty() {
+            header |= FLAG_IL;
+        }
+
+        self.write(&[header, ty.len() as u8])?;
+        if payload_len <= 0xFF {
+            self.write(&[payload_len as u8])?;
+        } else {
+            self.write(&(payload_len as u32).to_be_bytes())?;
+        }
+        if !id.is_empty() {
+            self.write(&[id.len() as u8])?;
+        }
+        self.write(ty)?;
+        self.write(id)?;
+        Ok(pos)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.buf.extend_from_slice(data).map_err(|_| Error::BufferTooSmall)
+    }
+
+    /// Get the message bytes so far. Empty if no record was pushed.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Finish the message. If no record was pushed, this is an empty NDEF message,
+    /// which consists of a single record with TNF [`Tnf::Empty`].
+    pub fn finish(mut self) -> Result<Vec<u8, N>, Error> {
+        if self.end.is_none() {
+            self.push(Tnf::Empty, &[], &[], &[])?;
+        }
+        Ok(self.buf)
+    }
+}
+
+/// Writes the payload of a record, see [`MessageBuilder::push_with`].
+pub struct PayloadWriter<'a, const N: usize> {
+    buf: &'a mut Vec<u8, N>,
+    remaining: usize,
+}
+
+impl<'a, const N: usize> PayloadWriter<'a, N> {
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.len() > self.remaining {
+            return Err(Error::InvalidRecord);
+        }
+        self.buf.extend_from_slice(data).map_err(|_| Error::BufferTooSmall)?;
+        self.remaining -= data.len();
+        Ok(())
+    }
+}
+
+/// Check the fields of a record about to be written.
+fn validate(tnf: Tnf, ty: &[u8], id: &[u8], payload_len: usize) -> Result<(), Error> {
+    let valid = match tnf {
+        Tnf::Unchanged => false,
+        Tnf::Empty => ty.is_empty() && id.is_empty() && payload_len == 0,
+        Tnf::Unknown => ty.is_empty(),
+        _ => true,
+    };
+    if !valid || ty.len() > 0xFF || id.len() > 0xFF || u32::try_from(payload_len).is_err() {
+        return Err(Error::InvalidRecord);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn records(data: &[u8]) -> std::vec::Vec<Record<'_>> {
+        Message::parse(data).unwrap().records().collect()
+    }
+
+    #[test]
+    fn test_parse_short_record() {
+        // URI record for "https://example.com" with MB, ME and SR set.
+        let data = hex!("D1 01 0C 55 04 6578616d706c652e636f6d");
+        let r = records(&data);
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].tnf, Tnf::WellKnown);
+        assert_eq!(r[0].ty, b"U");
+        assert_eq!(r[0].id, b"");
+        assert_eq!(r[0].payload.as_slice(), Some(&hex!("04 6578616d706c652e636f6d")[..]));
+    }
+
+    #[test]
+    fn test_parse_multiple_records() {
+        // Media record with an id, followed by a long external record without SR.
+        let data = hex!(
+            "9A 0A 03 01 746578742f706c61696e 41 686869"
+            "44 03 00000002 613a62 0102"
+        );
+        let r = records(&data);
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].tnf, Tnf::Media);
+        assert_eq!(r[0].ty, b"text/plain");
+        assert_eq!(r[0].id, b"A");
+        assert_eq!(r[0].payload.as_slice(), Some(&b"hhi"[..]));
+        assert_eq!(r[1].tnf, Tnf::External);
+        assert_eq!(r[1].ty, b"a:b");
+        assert_eq!(r[1].payload.as_slice(), Some(&hex!("0102")[..]));
+    }
+
+    #[test]
+    fn test_parse_all_tnfs() {
+        let data = hex!(
+            "90 00 00"
+            "11 01 00 54"
+            "12 01 00 78"
+            "13 01 00 78"
+            "14 01 00 78"
+            "15 00 01 AA"
+            "57 00 01 BB"
+        );
+        let tnfs: std::vec::Vec<_> = records(&data).iter().map(|r| r.tnf).collect();
+        assert_eq!(
+            tnfs,
+            [
+                Tnf::Empty,
+                Tnf::WellKnown,
+                Tnf::Media,
+                Tnf::AbsoluteUri,
+                Tnf::External,
+                Tnf::Unknown,
+                Tnf::Reserved
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_chunked() {
+        let data = hex!(
+            "B2 0A 03 746578742f706c61696e 616263"
+            "36 00 02 6465"
+            "16 00 01 66"
+            "51 01 00 54"
+        );
+        let r = records(&data);
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].tnf, Tnf::Media);
+        assert_eq!(r[0].ty, b"text/plain");
+        assert!(r[0].payload.is_chunked());
+        assert_eq!(r[0].payload.as_slice(), None);
+        assert_eq!(r[0].payload.len(), 6);
+        let chunks: std::vec::Vec<_> = r[0].payload.chunks().collect();
+        assert_eq!(chunks, [&b"abc"[..], b"de", b"f"]);
+        assert_eq!(r[0].payload.to_vec::<6>().unwrap(), b"abcdef");
+        assert_eq!(r[0].payload.to_vec::<5>(), Err(Error::BufferTooSmall));
+        let mut buf = [0; 8];
+        assert_eq!(r[0].payload.copy_to(&mut buf), Ok(6));
+        assert_eq!(&buf[..6], b"abcdef");
+        assert_eq!(r[1].ty, b"T");
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let cases: &[(&[u8], Error)] = &[
+            (&[], Error::Truncated),
+            (&hex!("D1"), Error::Truncated),
+            (&hex!("D1 01"), Error::Truncated),
+            (&hex!("C1 01 00 00"), Error::Truncated),
+            (&hex!("D1 01 05 55 01"), Error::Truncated),
+            (&hex!("D9 01 00 02 55 01"), Error::Truncated),
+            (&hex!("C1 01 FF FF FF FF 55"), Error::Truncated),
+            // MB/ME
+            (&hex!("51 01 00 55"), Error::MissingMessageBegin),
+            (&hex!("91 01 00 55"), Error::MissingMessageEnd),
+            (&hex!("91 01 00 55 D1 01 00 55"), Error::UnexpectedMessageBegin),
+            (&hex!("D1 01 00 55 00"), Error::TrailingData),
+            // Chunking
+            (&hex!("D6 00 00"), Error::InvalidChunk),
+            (&hex!("F1 01 00 55"), Error::InvalidChunk),
+            (&hex!("B1 01 00 55"), Error::Truncated),
+            (&hex!("B1 01 00 55 51 00 00"), Error::InvalidChunk),
+            (&hex!("B1 01 00 55 56 01 00 55"), Error::InvalidChunk),
+            (&hex!("B1 01 00 55 5E 00 00 01 41"), Error::InvalidChunk),
+            (&hex!("B1 01 00 55 76 00 00"), Error::InvalidChunk),
+            (&hex!("B1 01 00 55 D6 00 00"), Error::UnexpectedMessageBegin),
+            (&hex!("B1 01 00 55 16 00 00"), Error::MissingMessageEnd),
+            // TNF consistency
+            (&hex!("D0 00 01 00"), Error::InvalidRecord),
+            (&hex!("D0 01 00 55"), Error::InvalidRecord),
+            (&hex!("D5 01 00 55"), Error::InvalidRecord),
+        ];
+        for (data, err) in cases {
+            assert_eq!(Message::parse(data).err(), Some(*err), "data: {:02x?}", data);
+        }
+    }
+
+    #[test]
+    fn test_parse_truncations_and_mutations() {
+        let data = hex!(
+            "B2 0A 03 746578742f706c61696e 616263"
+            "36 00 02 6465"
+            "16 00 01 66"
+            "19 01 02 01 54 49 0102"
+            "44 03 00000002 613a62 0102"
+        );
+        Message::parse(&data).unwrap();
+
+        for len in 0..data.len() {
+            assert!(Message::parse(&data[..len]).is_err(), "len {}", len);
+        }
+
+        // Any input must either fail or yield records consistent with the input.
+        for i in 0..data.len() {
+            for bit in 0..8 {
+                let mut d = data;
+                d[i] ^= 1 << bit;
+                if let Ok(m) = Message::parse(&d) {
+                    for r in m.records() {
+                        assert_eq!(r.payload.chunks().map(|c| c.len()).sum::<usize>(), r.payload.len());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let mut b = MessageBuilder::<64>::new();
+        b.push(Tnf::WellKnown, b"U", b"", &hex!("04 6578616d706c652e636f6d")).unwrap();
+        assert_eq!(b.as_bytes(), hex!("D1 01 0C 55 04 6578616d706c652e636f6d"));
+
+        b.push(Tnf::Media, b"text/plain", b"A", b"hhi").unwrap();
+        assert_eq!(
+            b.finish().unwrap(),
+            hex!(
+                "91 01 0C 55 04 6578616d706c652e636f6d"
+                "5A 0A 03 01 746578742f706c61696e 41 686869"
+            )
+        );
+    }
+
+    #[test]
+    fn test_build_empty() {
+        let b = MessageBuilder::<8>::new();
+        assert_eq!(b.finish().unwrap(), hex!("D0 00 00"));
+    }
+
+    #[test]
+    fn test_build_long_record() {
+        let payload = [0x42; 300];
+        let mut b = MessageBuilder::<320>::new();
+        b.push(Tnf::Unknown, b"", b"", &payload).unwrap();
+        let data = b.finish().unwrap();
+        assert_eq!(data[..6], hex!("C5 00 0000012C"));
+
+        let r = records(&data);
+        assert_eq!(r[0].payload.as_slice(), Some(&payload[..]));
+    }
+
+    #[test]
+    fn test_build_chunked() {
+        let mut b = MessageBuilder::<64>::new();
+        b.push_chunked(Tnf::Media, b"text/plain", b"", b"abcdef", 3).unwrap();
+        b.push_chunked(Tnf::WellKnown, b"T", b"", b"", 3).unwrap();
+        assert_eq!(
+            b.as_bytes(),
+            hex!(
+                "B2 0A 03 746578742f706c61696e 616263"
+                "16 00 03 646566"
+                "51 01 00 54"
+            )
+        );
+
+        let data = b.finish().unwrap();
+        let r = records(&data);
+        assert_eq!(r[0].payload.to_vec::<8>().unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let mut b = MessageBuilder::<8>::new();
+        assert_eq!(b.push(Tnf::Unchanged, b"", b"", b""), Err(Error::InvalidRecord));
+        assert_eq!(b.push(Tnf::Empty, b"", b"", b"x"), Err(Error::InvalidRecord));
+        assert_eq!(b.push(Tnf::Unknown, b"x", b"", b""), Err(Error::InvalidRecord));
+        assert_eq!(b.push(Tnf::WellKnown, &[0x55; 256], b"", b""), Err(Error::InvalidRecord));
+        assert_eq!(b.push_chunked(Tnf::WellKnown, b"U", b"", b"abc", 0), Err(Error::InvalidChunk));
+
+        // A failed push leaves the message untouched.
+        b.push(Tnf::WellKnown, b"U", b"", b"ab").unwrap();
+        assert_eq!(b.push(Tnf::WellKnown, b"U", b"", b"abc"), Err(Error::BufferTooSmall));
+        assert_eq!(b.as_bytes(), hex!("D1 01 02 55 6162"));
+    }
+
+    #[test]
+    fn test_media_and_external() {
+        let mut b = MessageBuilder::<64>::new();
+        b.push_media("application/json", b"{}").unwrap();
+        b.push_external("example.com:thing", &[0x01]).unwrap();
+        assert_eq!(b.push_media("json", b""), Err(Error::InvalidRecord));
+        assert_eq!(b.push_media("text/ plain", b""), Err(Error::InvalidRecord));
+        assert_eq!(b.push_external("thing", b""), Err(Error::InvalidRecord));
+        assert_eq!(b.push_external(":thing", b""), Err(Error::InvalidRecord));
+        let data = b.finish().unwrap();
+        assert_eq!(
+            data,
+            hex!(
+                "92 10 02 6170706c69636174696f6e2f6a736f6e 7b7d"
+                "54 11 01 6578616d706c652e636f6d3a7468696e67 01"
+            )
+        );
+
+        let r = records(&data);
+        assert_eq!(r[0].media_type(), Some("application/json"));
+        assert_eq!(r[0].external_type(), None);
+        assert_eq!(r[1].media_type(), None);
+        assert_eq!(r[1].external_type(), Some(("example.com", "thing")));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = hex!(
+            "B2 0A 03 746578742f706c61696e 616263"
+            "36 00 02 6465"
+            "16 00 01 66"
+            "19 01 02 01 54 49 0102"
+            "44 03 00000002 613a62 0102"
+        );
+
+        let mut b = MessageBuilder::<64>::new();
+        for r in Message::parse(&data).unwrap().records() {
+            b.push_record(&r).unwrap();
+        }
+        let out = b.finish().unwrap();
+
+        // Chunks get merged, and the long record gets the short format.
+        assert_eq!(
+            out,
+            hex!(
+                "92 0A 06 746578742f706c61696e 616263646566"
+                "19 01 02 01 54 49 0102"
+                "54 03 02 613a62 0102"
+            )
+        );
+
+        let a: std::vec::Vec<_> = Message::parse(&data).unwrap().records().collect();
+        let b: std::vec::Vec<_> = Message::parse(&out).unwrap().records().collect();
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(&b) {
+            assert_eq!(a.tnf, b.tnf);
+            assert_eq!(a.ty, b.ty);
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.payload.to_vec::<16>().unwrap(), b.payload.to_vec::<16>().unwrap());
+        }
+    }
+}
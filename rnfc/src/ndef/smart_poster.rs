@@ -0,0 +1,208 @@
+//! NFC Forum Smart Poster record type (`Sp`).
+//!
+//! The payload of a Smart Poster is itself an NDEF message, containing exactly one URI
+//! record and optional title, action, size, type and icon records. Build it with a
+//! [`MessageBuilder`] using [`push_uri`](MessageBuilder::push_uri),
+//! [`push_text`](MessageBuilder::push_text) for titles and the local record methods
+//! below, then wrap it with [`MessageBuilder::push_smart_poster`].
+
+use super::text::Text;
+use super::uri::Uri;
+use super::{Error, Message, MessageBuilder, Record, Tnf};
+
+/// Record type of Smart Poster records.
+pub const TYPE: &[u8] = b"Sp";
+/// Local record type of the recommended action.
+pub const TYPE_ACTION: &[u8] = b"act";
+/// Local record type of the size of the referenced object.
+pub const TYPE_SIZE: &[u8] = b"s";
+/// Local record type of the MIME type of the referenced object.
+pub const TYPE_TYPE: &[u8] = b"t";
+
+/// Recommended action for the URI.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Action {
+    /// Do the action: open a browser, send the SMS, call the number...
+    Do = 0,
+    /// Save for later.
+    Save = 1,
+    /// Open for editing.
+    Open = 2,
+}
+
+/// A decoded Smart Poster record.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SmartPoster<'a> {
+    /// The nested message, for access to records not decoded here, such as icons.
+    pub message: Message<'a>,
+    pub uri: Uri<'a>,
+    /// Recommended action. `None` if absent or set to a reserved value.
+    pub action: Option<Action>,
+    /// Size in bytes of the object the URI refers to.
+    pub size: Option<u32>,
+    /// MIME type of the object the URI refers to.
+    pub target_type: Option<&'a str>,
+}
+
+impl<'a> SmartPoster<'a> {
+    /// Parse the payload of a Smart Poster record.
+    pub fn parse(payload: &'a [u8]) -> Result<Self, Error> {
+        let message = Message::parse(payload)?;
+
+        let mut uri = None;
+        let mut action = None;
+        let mut size = None;
+        let mut target_type = None;
+        for r in message.records() {
+            if r.is_well_known(super::uri::TYPE) {
+                if uri.is_some() {
+                    return Err(Error::InvalidPayload);
+                }
+                uri = Some(Uri::from_record(&r)?);
+            } else if r.is_well_known(TYPE_ACTION) {
+                action = match local_payload(&r)? {
+                    [0] => Some(Action::Do),
+                    [1] => Some(Action::Save),
+                    [2] => Some(Action::Open),
+                    [_] => None,
+                    _ => return Err(Error::InvalidPayload),
+                };
+            } else if r.is_well_known(TYPE_SIZE) {
+                let s = local_payload(&r)?.try_into().map_err(|_| Error::InvalidPayload)?;
+                size = Some(u32::from_be_bytes(s));
+            } else if r.is_well_known(TYPE_TYPE) {
+                let t = core::str::from_utf8(local_payload(&r)?).map_err(|_| Error::InvalidPayload)?;
+                target_type = Some(t);
+            }
+        }
+
+        Ok(Self {
+            message,
+            uri: uri.ok_or(Error::InvalidPayload)?,
+            action,
+            size,
+            target_type,
+        })
+    }
+
+    /// Parse a Smart Poster record.
+    pub fn from_record(record: &Record<'a>) -> Result<Self, Error> {
+        Self::parse(record.typed_payload(Tnf::WellKnown, TYPE)?)
+    }
+
+    /// Iterate the titles, there may be one per language. Malformed titles are skipped.
+    pub fn titles(&self) -> impl Iterator<Item = Text<'a>> + 'a {
+        self.message.records().filter_map(|r| Text::from_record(&r).ok())
+    }
+}
+
+fn local_payload<'a>(record: &Record<'a>) -> Result<&'a [u8], Error> {
+    record.payload.as_slice().ok_or(Error::InvalidPayload)
+}
+
+impl<const N: usize> MessageBuilder<N> {
+    /// Append a Smart Poster record wrapping `message`.
+    pub fn push_smart_poster(&mut self, message: Message<'_>) -> Result<(), Error> {
+        self.push(Tnf::WellKnown, TYPE, &[], message.as_bytes())
+    }
+
+    /// Append the recommended action, inside a Smart Poster message.
+    pub fn push_action(&mut self, action: Action) -> Result<(), Error> {
+        self.push(Tnf::WellKnown, TYPE_ACTION, &[], &[action as u8])
+    }
+
+    /// Append the size of the referenced object, inside a Smart Poster message.
+    pub fn push_size(&mut self, size: u32) -> Result<(), Error> {
+        self.push(Tnf::WellKnown, TYPE_SIZE, &[], &size.to_be_bytes())
+    }
+
+    /// Append the MIME type of the referenced object, inside a Smart Poster message.
+    pub fn push_target_type(&mut self, media_type: &str) -> Result<(), Error> {
+        self.push(Tnf::WellKnown, TYPE_TYPE, &[], media_type.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_build_and_parse() {
+        let mut sp = MessageBuilder::<80>::new();
+        sp.push_uri("https://nfc-forum.org").unwrap();
+        sp.push_text("en", "NFC").unwrap();
+        sp.push_text("fr", "CCP").unwrap();
+        sp.push_action(Action::Open).unwrap();
+        sp.push_size(1234).unwrap();
+        sp.push_target_type("text/html").unwrap();
+        let sp = sp.finish().unwrap();
+
+        let mut b = MessageBuilder::<96>::new();
+        b.push_smart_poster(Message::parse(&sp).unwrap()).unwrap();
+        let data = b.finish().unwrap();
+        assert_eq!(
+            data,
+            hex!(
+                "D1 02 42 5370"
+                "91 01 0E 55 04 6e66632d666f72756d2e6f7267"
+                "11 01 06 54 02 656e 4e4643"
+                "11 01 06 54 02 6672 434350"
+                "11 03 01 616374 02"
+                "11 01 04 73 000004d2"
+                "51 01 09 74 746578742f68746d6c"
+            )
+        );
+
+        let r = Message::parse(&data).unwrap().records().next().unwrap();
+        let sp = SmartPoster::from_record(&r).unwrap();
+        assert_eq!(sp.uri, Uri::new("https://nfc-forum.org"));
+        assert_eq!(sp.action, Some(Action::Open));
+        assert_eq!(sp.size, Some(1234));
+        assert_eq!(sp.target_type, Some("text/html"));
+        let titles: std::vec::Vec<_> = sp.titles().map(|t| (t.language, t.as_str().unwrap())).collect();
+        assert_eq!(titles, [("en", "NFC"), ("fr", "CCP")]);
+    }
+
+    #[test]
+    fn test_parse_minimal() {
+        let sp = SmartPoster::parse(&hex!("D1 01 04 55 03 612e62")).unwrap();
+        assert_eq!(sp.uri, Uri::new("http://a.b"));
+        assert_eq!(sp.action, None);
+        assert_eq!(sp.size, None);
+        assert_eq!(sp.target_type, None);
+        assert_eq!(sp.titles().count(), 0);
+
+        // Reserved action values are ignored.
+        let sp = SmartPoster::parse(&hex!("91 01 01 55 00 51 03 01 616374 07")).unwrap();
+        assert_eq!(sp.action, None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // No URI.
+        assert_eq!(
+            SmartPoster::parse(&hex!("D1 01 03 54 00 6869")).err(),
+            Some(Error::InvalidPayload)
+        );
+        // Two URIs.
+        assert_eq!(
+            SmartPoster::parse(&hex!("91 01 01 55 00 51 01 01 55 00")).err(),
+            Some(Error::InvalidPayload)
+        );
+        // Bad size length.
+        assert_eq!(
+            SmartPoster::parse(&hex!("91 01 01 55 00 51 01 02 73 0000")).err(),
+            Some(Error::InvalidPayload)
+        );
+        // Malformed nested message.
+        assert_eq!(
+            SmartPoster::parse(&hex!("91 01 01 55 00")).err(),
+            Some(Error::MissingMessageEnd)
+        );
+    }
+}
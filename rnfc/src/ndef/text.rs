@@ -0,0 +1,233 @@
+//! NFC Forum Text record type (`T`).
+
+use core::char::{DecodeUtf16, REPLACEMENT_CHARACTER, decode_utf16};
+use core::iter::Map;
+use core::slice::ChunksExact;
+
+use heapless::String;
+
+use super::{Error, MessageBuilder, Record, Tnf};
+
+/// Record type of Text records.
+pub const TYPE: &[u8] = b"T";
+
+const STATUS_UTF16: u8 = 0x80;
+const STATUS_LANG_LEN_MASK: u8 = 0x3F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Encoding {
+    Utf8,
+    /// UTF-16, big endian unless the text starts with a little endian byte order mark.
+    Utf16,
+}
+
+/// A decoded Text record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Text<'a> {
+    pub encoding: Encoding,
+    /// IANA language code, for example `en` or `en-US`.
+    pub language: &'a str,
+    /// Encoded text, validated when parsing.
+    data: &'a [u8],
+}
+
+impl<'a> Text<'a> {
+    /// Parse the payload of a Text record.
+    pub fn parse(payload: &'a [u8]) -> Result<Self, Error> {
+        let (&status, rest) = payload.split_first().ok_or(Error::InvalidPayload)?;
+        let lang_len = (status & STATUS_LANG_LEN_MASK) as usize;
+        if rest.len() < lang_len {
+            return Err(Error::InvalidPayload);
+        }
+        let (language, data) = rest.split_at(lang_len);
+        if !language.is_ascii() {
+            return Err(Error::InvalidPayload);
+        }
+        // Can't fail, the language code is ASCII.
+        let language = core::str::from_utf8(language).map_err(|_| Error::InvalidPayload)?;
+
+        let encoding = match status & STATUS_UTF16 != 0 {
+            false => Encoding::Utf8,
+            true => Encoding::Utf16,
+        };
+        let this = Self {
+            encoding,
+            language,
+            data,
+        };
+
+        let valid = match encoding {
+            Encoding::Utf8 => core::str::from_utf8(data).is_ok(),
+            Encoding::Utf16 => data.len() % 2 == 0 && this.utf16().all(|c| c.is_ok()),
+        };
+        if !valid {
+            return Err(Error::InvalidPayload);
+        }
+
+        Ok(this)
+    }
+
+    /// Parse a Text record.
+    pub fn from_record(record: &Record<'a>) -> Result<Self, Error> {
+        Self::parse(record.typed_payload(Tnf::WellKnown, TYPE)?)
+    }
+
+    /// Get the text without copying. Only possible for UTF-8 records.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self.encoding {
+            Encoding::Utf8 => core::str::from_utf8(self.data).ok(),
+            Encoding::Utf16 => None,
+        }
+    }
+
+    /// Iterate the characters of the text, in either encoding.
+    pub fn chars(&self) -> Chars<'a> {
+        match self.encoding {
+            Encoding::Utf8 => Chars(CharsInner::Utf8(self.as_str().unwrap_or_default().chars())),
+            Encoding::Utf16 => Chars(CharsInner::Utf16(self.utf16())),
+        }
+    }
+
+    /// Copy the text into a `heapless::String`, converting it to UTF-8.
+    pub fn to_string<const N: usize>(&self) -> Result<String<N>, Error> {
+        let mut res = String::new();
+        for c in self.chars() {
+            res.push(c).map_err(|_| Error::BufferTooSmall)?;
+        }
+        Ok(res)
+    }
+
+    fn utf16(&self) -> Utf16Chars<'a> {
+        let (data, unit): (_, fn(&[u8]) -> u16) = match self.data {
+            [0xFE, 0xFF, rest @ ..] => (rest, |b| u16::from_be_bytes([b[0], b[1]])),
+            [0xFF, 0xFE, rest @ ..] => (rest, |b| u16::from_le_bytes([b[0], b[1]])),
+            data => (data, |b| u16::from_be_bytes([b[0], b[1]])),
+        };
+        decode_utf16(data.chunks_exact(2).map(unit))
+    }
+}
+
+type Utf16Chars<'a> = DecodeUtf16<Map<ChunksExact<'a, u8>, fn(&[u8]) -> u16>>;
+
+/// Iterator over the characters of a [`Text`].
+pub struct Chars<'a>(CharsInner<'a>);
+
+enum CharsInner<'a> {
+    Utf8(core::str::Chars<'a>),
+    Utf16(Utf16Chars<'a>),
+}
+
+impl<'a> Iterator for Chars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match &mut self.0 {
+            CharsInner::Utf8(c) => c.next(),
+            // Validated when parsing, so the replacement character never shows up.
+            CharsInner::Utf16(c) => c.next().map(|c| c.unwrap_or(REPLACEMENT_CHARACTER)),
+        }
+    }
+}
+
+fn status(language: &str, encoding: Encoding) -> Result<u8, Error> {
+    if language.len() > STATUS_LANG_LEN_MASK as usize || !language.is_ascii() {
+        return Err(Error::InvalidPayload);
+    }
+    let flag = match encoding {
+        Encoding::Utf8 => 0,
+        Encoding::Utf16 => STATUS_UTF16,
+    };
+    Ok(flag | language.len() as u8)
+}
+
+impl<const N: usize> MessageBuilder<N> {
+    /// Append a UTF-8 Text record.
+    pub fn push_text(&mut self, language: &str, text: &str) -> Result<(), Error> {
+        let status = status(language, Encoding::Utf8)?;
+        let len = 1 + language.len() + text.len();
+        self.push_with(Tnf::WellKnown, TYPE, &[], len, |w| {
+            w.write(&[status])?;
+            w.write(language.as_bytes())?;
+            w.write(text.as_bytes())
+        })
+    }
+
+    /// Append a UTF-16 Text record. The text is written big endian, without byte order mark.
+    pub fn push_text_utf16(&mut self, language: &str, text: &str) -> Result<(), Error> {
+        let status = status(language, Encoding::Utf16)?;
+        let len = 1 + language.len() + text.encode_utf16().count() * 2;
+        self.push_with(Tnf::WellKnown, TYPE, &[], len, |w| {
+            w.write(&[status])?;
+            w.write(language.as_bytes())?;
+            text.encode_utf16().try_for_each(|u| w.write(&u.to_be_bytes()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::ndef::Message;
+
+    #[test]
+    fn test_utf8() {
+        let mut b = MessageBuilder::<32>::new();
+        b.push_text("en", "Hello").unwrap();
+        let data = b.finish().unwrap();
+        assert_eq!(data, hex!("D1 01 08 54 02 656e 48656c6c6f"));
+
+        let r = Message::parse(&data).unwrap().records().next().unwrap();
+        let t = Text::from_record(&r).unwrap();
+        assert_eq!(t.encoding, Encoding::Utf8);
+        assert_eq!(t.language, "en");
+        assert_eq!(t.as_str(), Some("Hello"));
+        assert_eq!(t.to_string::<5>().unwrap(), "Hello");
+        assert_eq!(t.to_string::<4>(), Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn test_utf16() {
+        let mut b = MessageBuilder::<32>::new();
+        b.push_text_utf16("fr", "Dé😀").unwrap();
+        let data = b.finish().unwrap();
+        assert_eq!(data, hex!("D1 01 0B 54 82 6672 0044 00e9 d83d de00"));
+
+        let r = Message::parse(&data).unwrap().records().next().unwrap();
+        let t = Text::from_record(&r).unwrap();
+        assert_eq!(t.encoding, Encoding::Utf16);
+        assert_eq!(t.language, "fr");
+        assert_eq!(t.as_str(), None);
+        assert_eq!(t.to_string::<16>().unwrap(), "Dé😀");
+    }
+
+    #[test]
+    fn test_utf16_bom() {
+        let t = Text::parse(&hex!("82 656e FEFF 0048 0069")).unwrap();
+        assert_eq!(t.to_string::<8>().unwrap(), "Hi");
+        let t = Text::parse(&hex!("82 656e FFFE 4800 6900")).unwrap();
+        assert_eq!(t.to_string::<8>().unwrap(), "Hi");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Text::parse(&[]), Err(Error::InvalidPayload));
+        // Language code longer than the payload.
+        assert_eq!(Text::parse(&hex!("05 656e")), Err(Error::InvalidPayload));
+        // Non-ASCII language code.
+        assert_eq!(Text::parse(&hex!("01 e9")), Err(Error::InvalidPayload));
+        // Invalid UTF-8.
+        assert_eq!(Text::parse(&hex!("02 656e FF")), Err(Error::InvalidPayload));
+        // Odd length UTF-16.
+        assert_eq!(Text::parse(&hex!("82 656e 0048 00")), Err(Error::InvalidPayload));
+        // Unpaired surrogate.
+        assert_eq!(Text::parse(&hex!("82 656e d83d 0048")), Err(Error::InvalidPayload));
+
+        let mut b = MessageBuilder::<128>::new();
+        assert_eq!(b.push_text(&"x".repeat(64), ""), Err(Error::InvalidPayload));
+        assert_eq!(b.push_text("é", ""), Err(Error::InvalidPayload));
+    }
+}
@@ -0,0 +1,181 @@
+//! NFC Forum URI record type (`U`).
+
+use core::fmt;
+
+use heapless::Vec;
+
+use super::{Error, MessageBuilder, Record, Tnf};
+
+/// Record type of URI records.
+pub const TYPE: &[u8] = b"U";
+
+/// URI identifier codes. Index `n` is the prefix abbreviated by code `n`.
+pub const PREFIXES: [&str; 36] = [
+    "",
+    "http://www.",
+    "https://www.",
+    "http://",
+    "https://",
+    "tel:",
+    "mailto:",
+    "ftp://anonymous:anonymous@",
+    "ftp://ftp.",
+    "ftps://",
+    "sftp://",
+    "smb://",
+    "nfs://",
+    "ftp://",
+    "dav://",
+    "news:",
+    "telnet://",
+    "imap:",
+    "rtsp://",
+    "urn:",
+    "pop:",
+    "sip:",
+    "sips:",
+    "tftp:",
+    "btspp://",
+    "btl2cap://",
+    "btgoep://",
+    "tcpobex://",
+    "irdaobex://",
+    "file://",
+    "urn:epc:id:",
+    "urn:epc:tag:",
+    "urn:epc:pat:",
+    "urn:epc:raw:",
+    "urn:epc:",
+    "urn:nfc:",
+];
+
+/// A URI, split into an abbreviated prefix and the rest.
+///
+/// `Display` prints the full URI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Uri<'a> {
+    /// URI identifier code, an index into [`PREFIXES`].
+    pub code: u8,
+    pub rest: &'a str,
+}
+
+impl<'a> Uri<'a> {
+    /// Split `uri`, abbreviating the longest prefix in [`PREFIXES`].
+    pub fn new(uri: &'a str) -> Self {
+        let mut res = Self { code: 0, rest: uri };
+        for (code, prefix) in PREFIXES.iter().enumerate().skip(1) {
+            if let Some(rest) = uri.strip_prefix(prefix)
+                && rest.len() < res.rest.len()
+            {
+                res = Self { code: code as u8, rest };
+            }
+        }
+        res
+    }
+
+    /// Parse the payload of a URI record.
+    pub fn parse(payload: &'a [u8]) -> Result<Self, Error> {
+        let (&code, rest) = payload.split_first().ok_or(Error::InvalidPayload)?;
+        if code as usize >= PREFIXES.len() {
+            return Err(Error::InvalidPayload);
+        }
+        let rest = core::str::from_utf8(rest).map_err(|_| Error::InvalidPayload)?;
+        Ok(Self { code, rest })
+    }
+
+    /// Parse a URI record.
+    pub fn from_record(record: &Record<'a>) -> Result<Self, Error> {
+        Self::parse(record.typed_payload(Tnf::WellKnown, TYPE)?)
+    }
+
+    pub fn prefix(&self) -> &'static str {
+        PREFIXES[self.code as usize]
+    }
+
+    /// Length of the full URI.
+    pub fn len(&self) -> usize {
+        self.prefix().len() + self.rest.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> fmt::Display for Uri<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.prefix())?;
+        f.write_str(self.rest)
+    }
+}
+
+impl<const N: usize> MessageBuilder<N> {
+    /// Append a URI record, abbreviating the URI prefix.
+    pub fn push_uri(&mut self, uri: &str) -> Result<(), Error> {
+        let uri = Uri::new(uri);
+        self.push_with(Tnf::WellKnown, TYPE, &[], 1 + uri.rest.len(), |w| {
+            w.write(&[uri.code])?;
+            w.write(uri.rest.as_bytes())
+        })
+    }
+}
+
+/// Build a message with a single URI record, ready to be written to a tag.
+pub fn message<const N: usize>(uri: &str) -> Result<Vec<u8, N>, Error> {
+    let mut b = MessageBuilder::new();
+    b.push_uri(uri)?;
+    b.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::ToString;
+
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::ndef::Message;
+
+    #[test]
+    fn test_abbreviate() {
+        assert_eq!(Uri::new("https://www.nxp.com").code, 0x02);
+        assert_eq!(Uri::new("https://www.nxp.com").rest, "nxp.com");
+        assert_eq!(Uri::new("https://nxp.com").code, 0x04);
+        assert_eq!(Uri::new("tel:+1234").code, 0x05);
+        assert_eq!(Uri::new("ftp://ftp.example.com").code, 0x08);
+        assert_eq!(Uri::new("urn:epc:id:sgtin:1").code, 0x1E);
+        assert_eq!(Uri::new("urn:epc:foo").code, 0x22);
+        assert_eq!(Uri::new("urn:isbn:1").code, 0x13);
+        assert_eq!(Uri::new("geo:1,2").code, 0x00);
+        assert_eq!(Uri::new("").code, 0x00);
+    }
+
+    #[test]
+    fn test_message() {
+        let data: Vec<u8, 32> = message("https://www.nxp.com").unwrap();
+        assert_eq!(data, hex!("D1 01 08 55 02 6e78702e636f6d"));
+
+        let m = Message::parse(&data).unwrap();
+        let r = m.records().next().unwrap();
+        let uri = Uri::from_record(&r).unwrap();
+        assert_eq!(uri.code, 0x02);
+        assert_eq!(uri.rest, "nxp.com");
+        assert_eq!(uri.len(), 19);
+        assert_eq!(uri.to_string(), "https://www.nxp.com");
+
+        assert_eq!(message::<8>("https://www.nxp.com"), Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Uri::parse(&[]), Err(Error::InvalidPayload));
+        assert_eq!(Uri::parse(&hex!("24 61")), Err(Error::InvalidPayload));
+        assert_eq!(Uri::parse(&hex!("00 FF")), Err(Error::InvalidPayload));
+        assert_eq!(Uri::parse(&hex!("23")).unwrap().to_string(), "urn:nfc:");
+
+        let data = hex!("D1 01 01 54 00");
+        let r = Message::parse(&data).unwrap().records().next().unwrap();
+        assert_eq!(Uri::from_record(&r), Err(Error::UnexpectedType));
+    }
+}
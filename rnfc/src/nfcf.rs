@@ -0,0 +1,489 @@
+use heapless::Vec;
+use rnfc_traits::nfcf as ll;
+use rnfc_traits::nfcf::{Frame, IDM_LEN, PMM_LEN, Reader as LLReader};
+
+use crate::fmt::Bytes;
+
+const CMD_SENSF_REQ: u8 = 0x00;
+const RES_SENSF_RES: u8 = 0x01;
+const CMD_READ_WITHOUT_ENCRYPTION: u8 = 0x06;
+const RES_READ_WITHOUT_ENCRYPTION: u8 = 0x07;
+const CMD_WRITE_WITHOUT_ENCRYPTION: u8 = 0x08;
+const RES_WRITE_WITHOUT_ENCRYPTION: u8 = 0x09;
+
+/// SENSF_REQ request code asking the cards to include their system code.
+const RC_SYSTEM_CODE: u8 = 0x01;
+
+/// System code that all cards answer to.
+pub const SYSTEM_CODE_ANY: u16 = 0xFFFF;
+/// System code of NFC Forum Type 3 Tags.
+pub const SYSTEM_CODE_NDEF: u16 = 0x12FC;
+
+/// Service code to read the NDEF data of a Type 3 Tag.
+pub const SERVICE_CODE_NDEF_READ: u16 = 0x000B;
+/// Service code to write the NDEF data of a Type 3 Tag.
+pub const SERVICE_CODE_NDEF_WRITE: u16 = 0x0009;
+
+pub const BLOCK_LEN: usize = 16;
+
+/// Max blocks in a single read or write, so that the frame fits in 255 bytes. Writes may
+/// fit fewer, with the data in the request.
+pub const MAX_BLOCKS: usize = 15;
+
+const FRAME_MAX_LEN: usize = 255;
+
+/// Response time unit, 256 x 16 / fc.
+const T_UNIT_1FC: u32 = 256 * 16;
+
+pub struct Poller<T: LLReader> {
+    reader: T,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<T> {
+    Lower(T),
+    Protocol,
+    /// The card returned an error status.
+    Status {
+        flag1: u8,
+        flag2: u8,
+    },
+    /// Too many blocks, or the command doesn't fit in a frame.
+    TooBig,
+    /// The data length isn't the number of blocks times [`BLOCK_LEN`].
+    InvalidLength,
+}
+
+impl<T: ll::Error> Error<T> {
+    fn is_soft(&self) -> bool {
+        match self {
+            Self::Lower(l) => l.kind() == ll::ErrorKind::Timeout,
+            Self::Protocol => true,
+            Self::Status { .. } | Self::TooBig | Self::InvalidLength => false,
+        }
+    }
+}
+
+/// A card found during polling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Target {
+    pub idm: [u8; IDM_LEN],
+    pub pmm: [u8; PMM_LEN],
+    /// Only present if the card included it in SENSF_RES.
+    pub system_code: Option<u16>,
+}
+
+impl<T: LLReader> Poller<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader }
+    }
+
+    /// Send SENSF_REQ, and collect the responses from all time slots.
+    async fn transceive_sensf_req<const N: usize>(
+        &mut self,
+        system_code: u16,
+        slots: u8,
+        res: &mut Vec<Target, N>,
+    ) -> Result<(), Error<T::Error>> {
+        let sc = system_code.to_be_bytes();
+        let tx = [6, CMD_SENSF_REQ, sc[0], sc[1], RC_SYSTEM_CODE, slots - 1];
+
+        let mut rx = [0; FRAME_MAX_LEN];
+        let opts = Frame::SensfReq { slots };
+        let n = self.reader.transceive(&tx, &mut rx, opts).await.map_err(Error::Lower)?;
+
+        let mut rx = &rx[..n];
+        while !rx.is_empty() {
+            let len = rx[0] as usize;
+            if len < 18 || len > rx.len() {
+                debug!("SENSF_RES wrong length: {} bytes", len);
+                return Err(Error::Protocol);
+            }
+            let frame = &rx[..len];
+            rx = &rx[len..];
+
+            if frame[1] != RES_SENSF_RES {
+                debug!("SENSF_RES wrong response code: {:02x}", frame[1]);
+                return Err(Error::Protocol);
+            }
+
+            let target = Target {
+                idm: frame[2..10].try_into().unwrap(),
+                pmm: frame[10..18].try_into().unwrap(),
+                system_code: frame.get(18..20).map(|x| u16::from_be_bytes(x.try_into().unwrap())),
+            };
+            debug!("Got card! idm={} pmm={}", Bytes(&target.idm), Bytes(&target.pmm));
+
+            if !res.iter().any(|t| t.idm == target.idm) && res.push(target).is_err() {
+                break;
+            }
+        }
+
+        if n == 0 {
+            // All slots empty, or all responses corrupted by collisions.
+            debug!("SENSF_REQ: no responses");
+            return Err(Error::Protocol);
+        }
+
+        Ok(())
+    }
+
+    fn card(&mut self, target: Target) -> Card<'_, T> {
+        Card {
+            reader: &mut self.reader,
+            idm: target.idm,
+            pmm: target.pmm,
+            system_code: target.system_code,
+        }
+    }
+
+    pub async fn select_any(&mut self, system_code: u16) -> Result<Card<'_, T>, Error<T::Error>> {
+        let mut res: Vec<Target, 1> = Vec::new();
+        retry!(4, self.transceive_sensf_req(system_code, 1, &mut res).await)?;
+        Ok(self.card(res[0]))
+    }
+
+    pub async fn select_by_id(&mut self, system_code: u16, idm: &[u8]) -> Result<Card<'_, T>, Error<T::Error>> {
+        if idm.len() != IDM_LEN {
+            debug!("Invalid IDm length {}", idm.len());
+            return Err(Error::Protocol);
+        }
+
+        for _ in 0..4 {
+            let mut res: Vec<Target, 16> = Vec::new();
+            match self.transceive_sensf_req(system_code, 16, &mut res).await {
+                Ok(()) => {}
+                Err(e) if e.is_soft() => continue,
+                Err(e) => return Err(e),
+            }
+
+            if let Some(target) = res.iter().find(|t| t.idm == idm) {
+                let target = *target;
+                return Ok(self.card(target));
+            }
+        }
+
+        debug!("card {} not found", Bytes(idm));
+        Err(Error::Protocol)
+    }
+
+    /// Search for all cards in the field that match `system_code`.
+    /// You can connect to one with [`Self::select_by_id`].
+    pub async fn search<const N: usize>(&mut self, system_code: u16) -> Result<Vec<Target, N>, Error<T::Error>> {
+        let mut res = Vec::new();
+
+        // Cards pick a random time slot on each SENSF_REQ. Repeat until no new card answers,
+        // so that cards that collided in a previous round get another chance.
+        for _ in 0..4 {
+            let found = res.len();
+            match self.transceive_sensf_req(system_code, 16, &mut res).await {
+                Ok(()) => {}
+                Err(e) if e.is_soft() => break,
+                Err(e) => return Err(e),
+            }
+            if res.len() == found || res.is_full() {
+                break;
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+/// Check the block count, and that `data_len` bytes are exactly these blocks.
+fn check_blocks<E>(blocks: &[u16], data_len: usize) -> Result<(), Error<E>> {
+    if blocks.len() > MAX_BLOCKS {
+        return Err(Error::TooBig);
+    }
+    if data_len != blocks.len() * BLOCK_LEN {
+        return Err(Error::InvalidLength);
+    }
+    Ok(())
+}
+
+pub struct Card<'d, T: LLReader> {
+    reader: &'d mut T,
+
+    idm: [u8; IDM_LEN],
+    pmm: [u8; PMM_LEN],
+    system_code: Option<u16>,
+}
+
+impl<'d, T: LLReader + 'd> Card<'d, T> {
+    pub fn idm(&self) -> [u8; IDM_LEN] {
+        self.idm
+    }
+
+    pub fn pmm(&self) -> [u8; PMM_LEN] {
+        self.pmm
+    }
+
+    pub fn system_code(&self) -> Option<u16> {
+        self.system_code
+    }
+
+    /// Maximum response time for a command, from the PMm byte at `index`.
+    fn timeout_1fc(&self, index: usize, blocks: usize) -> u32 {
+        // T = T_unit x 4^E x ((B + 1) x n + (A + 1))
+        let mrti = self.pmm[index];
+        let a = (mrti & 0x07) as u32;
+        let b = ((mrti >> 3) & 0x07) as u32;
+        let e = (mrti >> 6) as u32;
+        (T_UNIT_1FC << (2 * e)) * ((b + 1) * blocks as u32 + (a + 1))
+    }
+
+    /// Write the command header and block list, return the position after it.
+    fn build_blocks_cmd(&self, tx: &mut [u8], cmd: u8, service_code: u16, blocks: &[u16]) -> usize {
+        tx[1] = cmd;
+        tx[2..10].copy_from_slice(&self.idm);
+        tx[10] = 1; // number of services
+        tx[11..13].copy_from_slice(&service_code.to_le_bytes());
+        tx[13] = blocks.len() as u8;
+
+        let mut pos = 14;
+        for &block in blocks {
+            if block <= 0xFF {
+                // 2-byte block list element, service code list order 0.
+                tx[pos] = 0x80;
+                tx[pos + 1] = block as u8;
+                pos += 2;
+            } else {
+                // 3-byte block list element, service code list order 0.
+                tx[pos] = 0x00;
+                tx[pos + 1..pos + 3].copy_from_slice(&block.to_le_bytes());
+                pos += 3;
+            }
+        }
+        pos
+    }
+
+    /// Check the response header and status flags, return the data after them.
+    fn check_response<'a>(&self, rx: &'a [u8], code: u8) -> Result<&'a [u8], Error<T::Error>> {
+        if rx.len() < 12 || rx[0] as usize != rx.len() || rx[1] != code || rx[2..10] != self.idm {
+            debug!("bad response: {}", Bytes(rx));
+            return Err(Error::Protocol);
+        }
+        let (flag1, flag2) = (rx[10], rx[11]);
+        if flag1 != 0x00 {
+            debug!("error status: {:02x} {:02x}", flag1, flag2);
+            return Err(Error::Status { flag1, flag2 });
+        }
+        Ok(&rx[12..])
+    }
+
+    /// Read blocks from a service that doesn't require authentication.
+    ///
+    /// `data` must be exactly `blocks.len() * BLOCK_LEN` bytes long.
+    pub async fn read_without_encryption(
+        &mut self,
+        service_code: u16,
+        blocks: &[u16],
+        data: &mut [u8],
+    ) -> Result<(), Error<T::Error>> {
+        check_blocks(blocks, data.len())?;
+
+        let mut tx = [0; FRAME_MAX_LEN];
+        let tx_len = self.build_blocks_cmd(&mut tx, CMD_READ_WITHOUT_ENCRYPTION, service_code, blocks);
+        tx[0] = tx_len as u8;
+
+        let mut rx = [0; FRAME_MAX_LEN];
+        let opts = Frame::Standard {
+            timeout_1fc: self.timeout_1fc(5, blocks.len()),
+        };
+        let n = self
+            .reader
+            .transceive(&tx[..tx_len], &mut rx, opts)
+            .await
+            .map_err(Error::Lower)?;
+
+        let res = self.check_response(&rx[..n], RES_READ_WITHOUT_ENCRYPTION)?;
+        if res.is_empty() || res[0] as usize != blocks.len() || res.len() != 1 + data.len() {
+            debug!("read: wrong block count");
+            return Err(Error::Protocol);
+        }
+        data.copy_from_slice(&res[1..]);
+        Ok(())
+    }
+
+    /// Write blocks to a service that doesn't require authentication.
+    ///
+    /// `data` must be exactly `blocks.len() * BLOCK_LEN` bytes long.
+    pub async fn write_without_encryption(
+        &mut self,
+        service_code: u16,
+        blocks: &[u16],
+        data: &[u8],
+    ) -> Result<(), Error<T::Error>> {
+        check_blocks(blocks, data.len())?;
+
+        let mut tx = [0; FRAME_MAX_LEN];
+        let pos = self.build_blocks_cmd(&mut tx, CMD_WRITE_WITHOUT_ENCRYPTION, service_code, blocks);
+        let tx_len = pos + data.len();
+        if tx_len > FRAME_MAX_LEN {
+            debug!("write: frame too long, {} bytes", tx_len);
+            return Err(Error::TooBig);
+        }
+        tx[pos..tx_len].copy_from_slice(data);
+        tx[0] = tx_len as u8;
+
+        let mut rx = [0; FRAME_MAX_LEN];
+        let opts = Frame::Standard {
+            timeout_1fc: self.timeout_1fc(6, blocks.len()),
+        };
+        let n = self
+            .reader
+            .transceive(&tx[..tx_len], &mut rx, opts)
+            .await
+            .map_err(Error::Lower)?;
+
+        self.check_response(&rx[..n], RES_WRITE_WITHOUT_ENCRYPTION)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use rnfc_traits::nfcf::{BitRate, ErrorKind};
+
+    use super::*;
+
+    type Exchange = (&'static [u8], Result<&'static [u8], ErrorKind>);
+
+    struct MockReader {
+        expected: Vec<Exchange>,
+        pos: usize,
+    }
+
+    macro_rules! mock {
+        (@res $rx:literal) => {
+            Ok(&hex_literal::hex!($rx))
+        };
+        (@res timeout) => {
+            Err(ErrorKind::Timeout)
+        };
+        ($($tx:literal => $rx:tt,)*) => {
+            MockReader {
+                expected: vec![
+                    $((&hex_literal::hex!($tx), mock!(@res $rx)),)*
+                ],
+                pos: 0,
+            }
+        };
+    }
+
+    impl LLReader for MockReader {
+        type Error = ErrorKind;
+
+        async fn set_bit_rate(&mut self, _: BitRate) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], _: Frame) -> Result<usize, Self::Error> {
+            if self.pos >= self.expected.len() {
+                panic!("unexpected transceive!\n         got: {:02x?}", tx);
+            }
+
+            let (expected_tx, expected_rx) = self.expected[self.pos];
+            if tx != expected_tx {
+                panic!(
+                    "unexpected tx!\n    expected: {:02x?}\n         got: {:02x?}",
+                    expected_tx, tx
+                );
+            }
+
+            self.pos += 1;
+            match expected_rx {
+                Ok(expected_rx) => {
+                    rx[..expected_rx.len()].copy_from_slice(expected_rx);
+                    Ok(expected_rx.len())
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_search() {
+        let mut mock = mock!(
+            "06 00 ff ff 01 0f" => "14 01 0102030405060708 1122334455667788 12fc  12 01 a1a2a3a4a5a6a7a8 1122334455667788",
+            "06 00 ff ff 01 0f" => "14 01 0102030405060708 1122334455667788 12fc",
+        );
+        let mut poller = Poller::new(&mut mock);
+        let cards = poller.search::<4>(SYSTEM_CODE_ANY).await.unwrap();
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].idm, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(cards[0].system_code, Some(0x12fc));
+        assert_eq!(cards[1].idm, [0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8]);
+        assert_eq!(cards[1].system_code, None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_read_write() {
+        let mut mock = mock!(
+            "06 00 12 fc 01 00" => timeout,
+            "06 00 12 fc 01 00" => "14 01 0102030405060708 0120220427674eff 12fc",
+            "12 06 0102030405060708 01 0b00 02 8000 8001" => "2d 07 0102030405060708 00 00 02 10000000000000000000000000000000 20000000000000000000000000000000",
+            "21 08 0102030405060708 01 0900 01 00 0001 30000000000000000000000000000000" => "0c 09 0102030405060708 00 00",
+            "10 06 0102030405060708 01 0b00 01 8005" => "0c 07 0102030405060708 01 a2",
+        );
+        let mut poller = Poller::new(&mut mock);
+        let mut card = poller.select_any(SYSTEM_CODE_NDEF).await.unwrap();
+        assert_eq!(card.idm(), [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut data = [0; 32];
+        card.read_without_encryption(SERVICE_CODE_NDEF_READ, &[0, 1], &mut data)
+            .await
+            .unwrap();
+        assert_eq!(data[0], 0x10);
+        assert_eq!(data[16], 0x20);
+
+        let mut data = [0; 16];
+        data[0] = 0x30;
+        card.write_without_encryption(SERVICE_CODE_NDEF_WRITE, &[0x100], &data)
+            .await
+            .unwrap();
+
+        let res = card.read_without_encryption(SERVICE_CODE_NDEF_READ, &[5], &mut data).await;
+        assert!(matches!(
+            res,
+            Err(Error::Status {
+                flag1: 0x01,
+                flag2: 0xa2
+            })
+        ));
+
+        // Bad arguments fail before anything is sent.
+        let res = card.read_without_encryption(SERVICE_CODE_NDEF_READ, &[0, 1], &mut data).await;
+        assert!(matches!(res, Err(Error::InvalidLength)));
+        let res = card
+            .read_without_encryption(SERVICE_CODE_NDEF_READ, &[0; 16], &mut [0; 256])
+            .await;
+        assert!(matches!(res, Err(Error::TooBig)));
+        let res = card
+            .write_without_encryption(SERVICE_CODE_NDEF_WRITE, &[0; 16], &[0; 256])
+            .await;
+        assert!(matches!(res, Err(Error::TooBig)));
+        // 15 blocks with 3-byte block list elements don't fit in a frame.
+        let res = card
+            .write_without_encryption(SERVICE_CODE_NDEF_WRITE, &[0x100; 15], &[0; 240])
+            .await;
+        assert!(matches!(res, Err(Error::TooBig)));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut mock = mock!();
+        let card = Card {
+            reader: &mut mock,
+            idm: [0; 8],
+            pmm: hex_literal::hex!("0120220427674eff"),
+            system_code: None,
+        };
+        // PMm[5] = 0x67: E=1, B=4, A=7
+        assert_eq!(card.timeout_1fc(5, 2), T_UNIT_1FC * 4 * (5 * 2 + 8));
+    }
+}
@@ -0,0 +1,565 @@
+//! NXP NTAG21x and MIFARE Ultralight EV1 commands.
+//!
+//! These are Type 2 Tags with extra commands for product identification, password
+//! protection, counters and originality signatures. Plain memory access and NDEF are
+//! handled by [`t2t`](crate::t2t).
+
+use rnfc_traits::iso14443a::{RawFrame, Reader};
+
+use crate::fmt::Bytes;
+use crate::iso14443a::crc_a;
+use crate::t2t;
+
+const CMD_GET_VERSION: u8 = 0x60;
+const CMD_FAST_READ: u8 = 0x3A;
+const CMD_PWD_AUTH: u8 = 0x1B;
+const CMD_READ_CNT: u8 = 0x39;
+const CMD_INCR_CNT: u8 = 0xA5;
+const CMD_READ_SIG: u8 = 0x3C;
+const CMD_CHECK_TEARING_EVENT: u8 = 0x3E;
+
+const TIMEOUT_1FC: u32 = 65536;
+
+/// Max pages per FAST_READ exchange. Longer reads are split.
+const FAST_READ_MAX_PAGES: usize = 15;
+
+/// Value of CHECK_TEARING_EVENT's response if there was no tearing event.
+const NO_TEARING: u8 = 0xBD;
+
+/// Counter of NTAG21x incremented on each NFC read, if enabled with `NFC_CNT_EN`.
+pub const NFC_COUNTER: u8 = 2;
+
+pub const SIGNATURE_LEN: usize = 32;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    Protocol,
+    /// The tag answered with a NAK. The value is the 4-bit NAK code.
+    Nak(u8),
+    /// PWD_AUTH succeeded, but the PACK didn't match the expected one.
+    PackMismatch,
+    /// The product is unknown, so the configuration pages can't be found.
+    UnknownProduct,
+}
+
+impl<E> From<t2t::Error<E>> for Error<E> {
+    fn from(e: t2t::Error<E>) -> Self {
+        match e {
+            t2t::Error::Lower(e) => Self::Lower(e),
+            t2t::Error::Nak(n) => Self::Nak(n),
+            _ => Self::Protocol,
+        }
+    }
+}
+
+/// GET_VERSION response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    /// 0x04 for NXP.
+    pub vendor_id: u8,
+    /// 0x03 for MIFARE Ultralight, 0x04 for NTAG.
+    pub product_type: u8,
+    pub product_subtype: u8,
+    pub major_version: u8,
+    pub minor_version: u8,
+    /// Encoded user memory size: bits 7-1 are `n` for a size between 2^n and 2^(n+1) bytes,
+    /// bit 0 is set if the size is not exactly 2^n.
+    pub storage_size: u8,
+    /// 0x03 for ISO 14443-3.
+    pub protocol_type: u8,
+}
+
+impl Version {
+    pub fn parse(data: &[u8; 8]) -> Self {
+        Self {
+            vendor_id: data[1],
+            product_type: data[2],
+            product_subtype: data[3],
+            major_version: data[4],
+            minor_version: data[5],
+            storage_size: data[6],
+            protocol_type: data[7],
+        }
+    }
+
+    /// Identify the product. Returns `None` for unknown products.
+    pub fn product(&self) -> Option<Product> {
+        if self.vendor_id != 0x04 || self.protocol_type != 0x03 {
+            return None;
+        }
+        match (self.product_type, self.major_version, self.storage_size) {
+            (0x03, 0x01, 0x0B) => Some(Product::UltralightEv1Mf0ul11),
+            (0x03, 0x01, 0x0E) => Some(Product::UltralightEv1Mf0ul21),
+            (0x04, 0x01, 0x0B) => Some(Product::Ntag210),
+            (0x04, 0x01, 0x0E) => Some(Product::Ntag212),
+            (0x04, 0x01, 0x0F) => Some(Product::Ntag213),
+            (0x04, 0x01, 0x11) => Some(Product::Ntag215),
+            (0x04, 0x01, 0x13) => Some(Product::Ntag216),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Product {
+    UltralightEv1Mf0ul11,
+    UltralightEv1Mf0ul21,
+    Ntag210,
+    Ntag212,
+    Ntag213,
+    Ntag215,
+    Ntag216,
+}
+
+impl Product {
+    /// Total number of pages.
+    pub fn pages(&self) -> u8 {
+        self.cfg0_page() + 4
+    }
+
+    /// Page of the first configuration page, CFG0. It's followed by CFG1, PWD and PACK.
+    pub fn cfg0_page(&self) -> u8 {
+        match self {
+            Self::UltralightEv1Mf0ul11 | Self::Ntag210 => 0x10,
+            Self::UltralightEv1Mf0ul21 | Self::Ntag212 => 0x25,
+            Self::Ntag213 => 0x29,
+            Self::Ntag215 => 0x83,
+            Self::Ntag216 => 0xE3,
+        }
+    }
+
+    /// Ultralight EV1 has three counters with INCR_CNT and CHECK_TEARING_EVENT,
+    /// NTAG21x only has the NFC counter.
+    pub fn is_ultralight_ev1(&self) -> bool {
+        matches!(self, Self::UltralightEv1Mf0ul11 | Self::UltralightEv1Mf0ul21)
+    }
+}
+
+/// Contents of the CFG0 and CFG1 configuration pages.
+///
+/// Bits without an accessor are kept as read, so reading, modifying and writing back
+/// doesn't change them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub cfg0: [u8; 4],
+    pub cfg1: [u8; 4],
+}
+
+const ACCESS_PROT: u8 = 0x80;
+const ACCESS_CFGLCK: u8 = 0x40;
+const ACCESS_NFC_CNT_EN: u8 = 0x10;
+const ACCESS_NFC_CNT_PWD_PROT: u8 = 0x08;
+const ACCESS_AUTHLIM_MASK: u8 = 0x07;
+
+impl Config {
+    /// First page protected by the password. Pages from AUTH0 on need PWD_AUTH.
+    /// A value past the last page disables password protection.
+    pub fn auth0(&self) -> u8 {
+        self.cfg0[3]
+    }
+
+    pub fn set_auth0(&mut self, page: u8) {
+        self.cfg0[3] = page;
+    }
+
+    /// If set, reads from AUTH0 on need the password too, not only writes.
+    pub fn prot(&self) -> bool {
+        self.cfg1[0] & ACCESS_PROT != 0
+    }
+
+    pub fn set_prot(&mut self, val: bool) {
+        self.set_access_bit(ACCESS_PROT, val);
+    }
+
+    /// If set, the configuration pages are permanently locked. Can't be cleared.
+    pub fn cfglck(&self) -> bool {
+        self.cfg1[0] & ACCESS_CFGLCK != 0
+    }
+
+    pub fn set_cfglck(&mut self, val: bool) {
+        self.set_access_bit(ACCESS_CFGLCK, val);
+    }
+
+    /// NTAG21x only: enable the NFC counter.
+    pub fn nfc_cnt_en(&self) -> bool {
+        self.cfg1[0] & ACCESS_NFC_CNT_EN != 0
+    }
+
+    pub fn set_nfc_cnt_en(&mut self, val: bool) {
+        self.set_access_bit(ACCESS_NFC_CNT_EN, val);
+    }
+
+    /// NTAG21x only: READ_CNT needs the password.
+    pub fn nfc_cnt_pwd_prot(&self) -> bool {
+        self.cfg1[0] & ACCESS_NFC_CNT_PWD_PROT != 0
+    }
+
+    pub fn set_nfc_cnt_pwd_prot(&mut self, val: bool) {
+        self.set_access_bit(ACCESS_NFC_CNT_PWD_PROT, val);
+    }
+
+    /// Limit of failed password attempts is 2^AUTHLIM. 0 disables the limit.
+    pub fn authlim(&self) -> u8 {
+        self.cfg1[0] & ACCESS_AUTHLIM_MASK
+    }
+
+    pub fn set_authlim(&mut self, val: u8) {
+        self.cfg1[0] = (self.cfg1[0] & !ACCESS_AUTHLIM_MASK) | (val & ACCESS_AUTHLIM_MASK);
+    }
+
+    fn set_access_bit(&mut self, bit: u8, val: bool) {
+        match val {
+            true => self.cfg1[0] |= bit,
+            false => self.cfg1[0] &= !bit,
+        }
+    }
+}
+
+/// An NTAG21x or Ultralight EV1 tag, identified with GET_VERSION.
+pub struct Ntag<T: Reader> {
+    card: T,
+    version: Version,
+}
+
+impl<T: Reader> Ntag<T> {
+    /// Identify the tag with GET_VERSION.
+    pub async fn new(card: T) -> Result<Self, Error<T::Error>> {
+        let mut card = card;
+        let mut rx = [0; 8];
+        let n = transceive(&mut card, &[CMD_GET_VERSION], &mut rx, TIMEOUT_1FC).await?;
+        if n != 8 {
+            warn!("GET_VERSION: bad response length {}", n);
+            return Err(Error::Protocol);
+        }
+        let version = Version::parse(&rx);
+        debug!("version: {:?} product: {:?}", version, version.product());
+
+        Ok(Self { card, version })
+    }
+
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    pub fn product(&self) -> Option<Product> {
+        self.version.product()
+    }
+
+    /// Get the underlying card, for sending other commands.
+    pub fn card(&mut self) -> &mut T {
+        &mut self.card
+    }
+
+    pub fn into_inner(self) -> T {
+        self.card
+    }
+
+    /// READ: read 4 pages starting at `page`.
+    pub async fn read(&mut self, page: u8) -> Result<[u8; t2t::READ_LEN], Error<T::Error>> {
+        Ok(t2t::read(&mut self.card, page).await?)
+    }
+
+    /// WRITE: write one page.
+    pub async fn write(&mut self, page: u8, data: &[u8; t2t::PAGE_SIZE]) -> Result<(), Error<T::Error>> {
+        Ok(t2t::write(&mut self.card, page, data).await?)
+    }
+
+    /// FAST_READ: read pages `start` to `end` inclusive into `buf`.
+    pub async fn fast_read(&mut self, start: u8, end: u8, buf: &mut [u8]) -> Result<(), Error<T::Error>> {
+        if end < start || buf.len() < (end - start + 1) as usize * t2t::PAGE_SIZE {
+            return Err(Error::Protocol);
+        }
+
+        let mut page = start as usize;
+        for chunk in buf.chunks_mut(FAST_READ_MAX_PAGES * t2t::PAGE_SIZE) {
+            if page > end as usize {
+                break;
+            }
+            let last = (page + FAST_READ_MAX_PAGES - 1).min(end as usize);
+            let len = (last - page + 1) * t2t::PAGE_SIZE;
+
+            let mut rx = [0; FAST_READ_MAX_PAGES * t2t::PAGE_SIZE];
+            let n = transceive(&mut self.card, &[CMD_FAST_READ, page as u8, last as u8], &mut rx, TIMEOUT_1FC).await?;
+            if n != len {
+                warn!("FAST_READ: bad response length {}", n);
+                return Err(Error::Protocol);
+            }
+            chunk[..len].copy_from_slice(&rx[..len]);
+            page = last + 1;
+        }
+        Ok(())
+    }
+
+    /// PWD_AUTH: authenticate with the 32-bit password.
+    ///
+    /// The tag answers with its PACK, which is checked against `expected_pack` so the
+    /// reader can tell genuine tags from ones accepting any password.
+    pub async fn pwd_auth(&mut self, pwd: &[u8; 4], expected_pack: &[u8; 2]) -> Result<(), Error<T::Error>> {
+        let mut tx = [0; 5];
+        tx[0] = CMD_PWD_AUTH;
+        tx[1..].copy_from_slice(pwd);
+        let mut rx = [0; 2];
+        let n = transceive(&mut self.card, &tx, &mut rx, TIMEOUT_1FC).await?;
+        if n != 2 {
+            warn!("PWD_AUTH: bad response length {}", n);
+            return Err(Error::Protocol);
+        }
+        if rx != *expected_pack {
+            warn!("PWD_AUTH: PACK mismatch, got {:02x}", Bytes(&rx));
+            return Err(Error::PackMismatch);
+        }
+        Ok(())
+    }
+
+    /// READ_CNT: read a 24-bit one-way counter. NTAG21x only has [`NFC_COUNTER`].
+    pub async fn read_cnt(&mut self, counter: u8) -> Result<u32, Error<T::Error>> {
+        let mut rx = [0; 3];
+        let n = transceive(&mut self.card, &[CMD_READ_CNT, counter], &mut rx, TIMEOUT_1FC).await?;
+        if n != 3 {
+            warn!("READ_CNT: bad response length {}", n);
+            return Err(Error::Protocol);
+        }
+        Ok(u32::from_le_bytes([rx[0], rx[1], rx[2], 0]))
+    }
+
+    /// INCR_CNT: increment a 24-bit one-way counter. Ultralight EV1 only.
+    pub async fn incr_cnt(&mut self, counter: u8, value: u32) -> Result<(), Error<T::Error>> {
+        let [a, b, c, _] = value.to_le_bytes();
+        Ok(t2t::transceive_ack(&mut self.card, &[CMD_INCR_CNT, counter, a, b, c, 0x00]).await?)
+    }
+
+    /// READ_SIG: read the 32-byte ECC originality signature of the UID.
+    pub async fn read_sig(&mut self) -> Result<[u8; SIGNATURE_LEN], Error<T::Error>> {
+        let mut rx = [0; SIGNATURE_LEN];
+        let n = transceive(&mut self.card, &[CMD_READ_SIG, 0x00], &mut rx, TIMEOUT_1FC).await?;
+        if n != SIGNATURE_LEN {
+            warn!("READ_SIG: bad response length {}", n);
+            return Err(Error::Protocol);
+        }
+        Ok(rx)
+    }
+
+    /// CHECK_TEARING_EVENT: check whether the last write to a counter was torn.
+    /// Ultralight EV1 only. Returns true if there was a tearing event.
+    pub async fn check_tearing_event(&mut self, counter: u8) -> Result<bool, Error<T::Error>> {
+        let mut rx = [0; 1];
+        let n = transceive(&mut self.card, &[CMD_CHECK_TEARING_EVENT, counter], &mut rx, TIMEOUT_1FC).await?;
+        if n != 1 {
+            warn!("CHECK_TEARING_EVENT: bad response length {}", n);
+            return Err(Error::Protocol);
+        }
+        Ok(rx[0] != NO_TEARING)
+    }
+
+    /// Read the CFG0 and CFG1 configuration pages.
+    pub async fn read_config(&mut self) -> Result<Config, Error<T::Error>> {
+        let page = self.cfg0_page()?;
+        let data = self.read(page).await?;
+        let mut config = Config {
+            cfg0: [0; 4],
+            cfg1: [0; 4],
+        };
+        config.cfg0.copy_from_slice(&data[0..4]);
+        config.cfg1.copy_from_slice(&data[4..8]);
+        Ok(config)
+    }
+
+    /// Write the CFG0 and CFG1 configuration pages.
+    ///
+    /// CFG1 is written first so that setting AUTH0 comes last, when enabling protection.
+    pub async fn write_config(&mut self, config: &Config) -> Result<(), Error<T::Error>> {
+        let page = self.cfg0_page()?;
+        self.write(page + 1, &config.cfg1).await?;
+        self.write(page, &config.cfg0).await
+    }
+
+    /// Write the password and the PACK the tag answers PWD_AUTH with.
+    ///
+    /// These pages can't be read back. Set AUTH0 with [`write_config`](Self::write_config)
+    /// afterwards to enable protection.
+    pub async fn set_password(&mut self, pwd: &[u8; 4], pack: &[u8; 2]) -> Result<(), Error<T::Error>> {
+        let page = self.cfg0_page()?;
+        self.write(page + 2, pwd).await?;
+        self.write(page + 3, &[pack[0], pack[1], 0x00, 0x00]).await
+    }
+
+    fn cfg0_page(&self) -> Result<u8, Error<T::Error>> {
+        Ok(self.product().ok_or(Error::UnknownProduct)?.cfg0_page())
+    }
+}
+
+/// Transceive a command whose response is either data with CRC, or a 4-bit NAK.
+/// Returns the length of the data.
+pub(crate) async fn transceive<T: Reader>(
+    card: &mut T,
+    tx: &[u8],
+    rx: &mut [u8],
+    timeout_1fc: u32,
+) -> Result<usize, Error<T::Error>> {
+    // Receive without CRC check so we can tell NAKs apart, and check it ourselves.
+    let opts = RawFrame {
+        rx_crc: false,
+        ..RawFrame::standard(timeout_1fc, tx.len())
+    };
+    let mut buf = [0; FAST_READ_MAX_PAGES * t2t::PAGE_SIZE + 2];
+    let buf = &mut buf[..rx.len() + 2];
+    let bits = card.transceive_raw(tx, buf, opts).await.map_err(Error::Lower)?;
+    if bits == 4 {
+        debug!("NAK {:x}", buf[0] & 0x0F);
+        return Err(Error::Nak(buf[0] & 0x0F));
+    }
+    if bits % 8 != 0 || bits < 24 {
+        warn!("bad response: {} bits", bits);
+        return Err(Error::Protocol);
+    }
+
+    let n = bits / 8 - 2;
+    if crc_a(&buf[..n]) != buf[n..n + 2] {
+        warn!("bad CRC");
+        return Err(Error::Protocol);
+    }
+    rx[..n].copy_from_slice(&buf[..n]);
+    Ok(n)
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::test_util::mock;
+
+    #[test]
+    fn test_version() {
+        let cases = [
+            (hex!("00 04 04 02 01 00 0F 03"), Some(Product::Ntag213)),
+            (hex!("00 04 04 02 01 00 11 03"), Some(Product::Ntag215)),
+            (hex!("00 04 04 02 01 00 13 03"), Some(Product::Ntag216)),
+            (hex!("00 04 03 01 01 00 0B 03"), Some(Product::UltralightEv1Mf0ul11)),
+            (hex!("00 04 03 01 01 00 0E 03"), Some(Product::UltralightEv1Mf0ul21)),
+            // Not NXP.
+            (hex!("00 05 04 02 01 00 0F 03"), None),
+        ];
+        for (data, product) in cases {
+            assert_eq!(Version::parse(&data).product(), product);
+        }
+        assert_eq!(Product::Ntag213.pages(), 45);
+        assert_eq!(Product::Ntag216.pages(), 231);
+        assert_eq!(Product::UltralightEv1Mf0ul11.pages(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_fast_read() {
+        let mut r = mock!(
+            "60" => "00 04 04 02 01 00 11 03",
+            "3A 00 0E" => "
+                04112233 44556677 88990000 E1103E00
+                00000000 00000000 00000000 00000000
+                00000000 00000000 00000000 00000000
+                00000000 00000000 0000AB01
+            ",
+            "3A 0F 10" => "CDEF0000 000000FF",
+        );
+        let mut tag = Ntag::new(&mut r).await.unwrap();
+        assert_eq!(tag.product(), Some(Product::Ntag215));
+
+        let mut buf = [0; 17 * 4];
+        tag.fast_read(0, 16, &mut buf).await.unwrap();
+        assert_eq!(buf[12..16], hex!("E1103E00"));
+        assert_eq!(buf[58..62], hex!("AB01CDEF"));
+        assert_eq!(buf[64..68], hex!("000000FF"));
+
+        // Buffer too small.
+        assert!(matches!(tag.fast_read(0, 1, &mut buf[..7]).await, Err(Error::Protocol)));
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_pwd_auth() {
+        let mut r = mock!(
+            "60" => "00 04 04 02 01 00 0F 03",
+            "1B 11223344" => "AABB",
+            "1B 11223344" => "CCDD",
+            "1B 00000000" => "00",
+        );
+        let mut tag = Ntag::new(&mut r).await.unwrap();
+        tag.pwd_auth(&hex!("11223344"), &hex!("AABB")).await.unwrap();
+        assert!(matches!(
+            tag.pwd_auth(&hex!("11223344"), &hex!("AABB")).await,
+            Err(Error::PackMismatch)
+        ));
+        assert!(matches!(
+            tag.pwd_auth(&hex!("00000000"), &hex!("AABB")).await,
+            Err(Error::Nak(0))
+        ));
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_counters() {
+        let mut r = mock!(
+            "60" => "00 04 03 01 01 00 0B 03",
+            "39 00" => "030201",
+            "A5 00 05000000" => "0A",
+            "A5 01 FFFFFF00" => "04",
+            "3E 00" => "BD",
+            "3E 01" => "12",
+            "3C 00" => "
+                0102030405060708090A0B0C0D0E0F10
+                1112131415161718191A1B1C1D1E1F20
+            ",
+        );
+        let mut tag = Ntag::new(&mut r).await.unwrap();
+        assert_eq!(tag.read_cnt(0).await.unwrap(), 0x010203);
+        tag.incr_cnt(0, 5).await.unwrap();
+        assert!(matches!(tag.incr_cnt(1, 0xFFFFFF).await, Err(Error::Nak(4))));
+        assert!(!tag.check_tearing_event(0).await.unwrap());
+        assert!(tag.check_tearing_event(1).await.unwrap());
+        assert_eq!(tag.read_sig().await.unwrap()[31], 0x20);
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_config() {
+        let mut r = mock!(
+            "60" => "00 04 04 02 01 00 0F 03",
+            "30 29" => "04000000 00050000 00000000 00000000",
+            "A2 2B 12345678" => "0A",
+            "A2 2C 9ABC0000" => "0A",
+            "A2 2A 9D050000" => "0A",
+            "A2 29 04000010" => "0A",
+        );
+        let mut tag = Ntag::new(&mut r).await.unwrap();
+
+        let mut config = tag.read_config().await.unwrap();
+        assert_eq!(config.auth0(), 0x00);
+        assert!(!config.prot());
+        assert_eq!(config.authlim(), 0);
+
+        tag.set_password(&hex!("12345678"), &hex!("9ABC")).await.unwrap();
+        config.set_auth0(0x10);
+        config.set_prot(true);
+        config.set_nfc_cnt_en(true);
+        config.set_nfc_cnt_pwd_prot(true);
+        config.set_authlim(5);
+        assert_eq!(config.cfg1, hex!("9D050000"));
+        tag.write_config(&config).await.unwrap();
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_unknown_product() {
+        let mut r = mock!(
+            "60" => "00 04 04 02 05 00 0F 03",
+        );
+        let mut tag = Ntag::new(&mut r).await.unwrap();
+        assert_eq!(tag.product(), None);
+        assert!(matches!(tag.read_config().await, Err(Error::UnknownProduct)));
+        r.assert_done();
+    }
+}
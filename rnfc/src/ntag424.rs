@@ -0,0 +1,591 @@
+//! NXP NTAG 424 DNA: Secure Dynamic Messaging (SDM) configuration and secure file access.
+//!
+//! The tag is a Type 4 Tag with a subset of the DESFire EV2 command set, so this builds on
+//! [`Desfire`] for AuthenticateEV2First and its secure messaging. The NDEF application is
+//! selected with ISOSelectFile, like [`t4t`](crate::t4t) does.
+//!
+//! ISOReadBinary and ISOUpdateBinary use the same secure messaging as the native commands,
+//! with the INS as command code and P1-P2, plus Le for ISOReadBinary, as command header.
+//!
+//! See [`sun`] to verify the SUN messages the tag generates, on the server side.
+
+pub mod sun;
+
+use rand_core::CryptoRngCore;
+use rnfc_traits::iso_dep::Reader;
+
+use crate::desfire::session::{Buf, Request};
+use crate::desfire::{self, AccessRights, CommMode, Desfire, Key};
+use crate::fmt::Bytes;
+use crate::t4t;
+
+/// File number of the Capability Container file, E103.
+pub const CC_FILE: u8 = 0x01;
+/// File number of the NDEF file, E104.
+pub const NDEF_FILE: u8 = 0x02;
+/// File number of the proprietary file, E105.
+pub const PROPRIETARY_FILE: u8 = 0x03;
+
+const CMD_READ_SIG: u8 = 0x3C;
+const CMD_GET_FILE_SETTINGS: u8 = 0xF5;
+const CMD_CHANGE_FILE_SETTINGS: u8 = 0x5F;
+
+const INS_READ_BINARY: u8 = 0xB0;
+const INS_UPDATE_BINARY: u8 = 0xD6;
+
+const SW_OK: u16 = 0x9000;
+
+const FILE_OPTION_SDM: u8 = 0x40;
+
+const SDM_UID: u8 = 0x80;
+const SDM_READ_CTR: u8 = 0x40;
+const SDM_READ_CTR_LIMIT: u8 = 0x20;
+const SDM_ENC_FILE_DATA: u8 = 0x10;
+const SDM_ASCII: u8 = 0x01;
+
+/// Biggest chunk in a single ISOReadBinary or ISOUpdateBinary, leaving room for secure
+/// messaging in short APDUs.
+const MAX_CHUNK: usize = 128;
+/// Highest offset that fits in P1-P2.
+const MAX_OFFSET: usize = 0x7FFF;
+
+/// Length of the originality signature.
+pub const SIGNATURE_LEN: usize = 56;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The tag sent a malformed response.
+    Protocol,
+    /// The tag answered with an error status word, ISO (`6982`) or native (`91AE`). Any
+    /// session is dropped.
+    Status(u16),
+    /// The tag's authentication response doesn't match: it doesn't know the key.
+    AuthFailed,
+    /// The MAC of a response is wrong. The session is dropped.
+    Integrity,
+    /// The data doesn't fit in a message or in the buffer.
+    TooBig,
+    /// The command needs authentication first.
+    NotAuthenticated,
+}
+
+impl<E> From<desfire::Error<E>> for Error<E> {
+    fn from(e: desfire::Error<E>) -> Self {
+        match e {
+            desfire::Error::Lower(e) => Self::Lower(e),
+            desfire::Error::Protocol | desfire::Error::InvalidKey | desfire::Error::InvalidKeyCount => Self::Protocol,
+            desfire::Error::Status(s) => Self::Status(0x9100 | s as u16),
+            desfire::Error::AuthFailed => Self::AuthFailed,
+            desfire::Error::Integrity => Self::Integrity,
+            desfire::Error::TooBig => Self::TooBig,
+            desfire::Error::NotAuthenticated => Self::NotAuthenticated,
+        }
+    }
+}
+
+impl<E> From<t4t::Error<E>> for Error<E> {
+    fn from(e: t4t::Error<E>) -> Self {
+        match e {
+            t4t::Error::Lower(e) => Self::Lower(e),
+            t4t::Error::Status(sw) => Self::Status(sw.0),
+            t4t::Error::TooBig => Self::TooBig,
+            t4t::Error::Protocol | t4t::Error::AccessDenied | t4t::Error::Unsupported => Self::Protocol,
+        }
+    }
+}
+
+/// Secure Dynamic Messaging settings of a file.
+///
+/// Access rights are key numbers 0-4, [`AccessRights::FREE`] or [`AccessRights::NEVER`].
+/// Offsets are in bytes from the start of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SdmSettings {
+    pub uid_mirror: bool,
+    pub read_ctr_mirror: bool,
+    /// Mirror data as ASCII hex, as needed in URLs, instead of binary.
+    pub ascii: bool,
+    /// Access right for reading the SDM read counter with GetFileCounters.
+    pub ctr_ret: u8,
+    /// Access right for PICCData: a key encrypts it, free mirrors UID and counter in plain.
+    pub meta_read: u8,
+    /// Key for the SUN MAC and encrypted file data, or never for no MAC.
+    pub file_read: u8,
+    /// UID offset, with plain mirroring.
+    pub uid_offset: u32,
+    /// Read counter offset, with plain mirroring.
+    pub read_ctr_offset: u32,
+    /// Encrypted PICCData offset, with a `meta_read` key.
+    pub picc_data_offset: u32,
+    /// Start of the data covered by the SUN MAC.
+    pub mac_input_offset: u32,
+    /// Offset and length of the encrypted part of the file, if any.
+    pub enc_data: Option<(u32, u32)>,
+    pub mac_offset: u32,
+    /// Number of reads after which the tag stops mirroring, if limited.
+    pub read_ctr_limit: Option<u32>,
+}
+
+impl SdmSettings {
+    fn options(&self) -> u8 {
+        let mut options = 0;
+        if self.uid_mirror {
+            options |= SDM_UID;
+        }
+        if self.read_ctr_mirror {
+            options |= SDM_READ_CTR;
+        }
+        if self.read_ctr_limit.is_some() {
+            options |= SDM_READ_CTR_LIMIT;
+        }
+        if self.enc_data.is_some() {
+            options |= SDM_ENC_FILE_DATA;
+        }
+        if self.ascii {
+            options |= SDM_ASCII;
+        }
+        options
+    }
+
+    /// Encode as in ChangeFileSettings, from SDMOptions on. Returns the length.
+    pub fn encode(&self, buf: &mut [u8; 32]) -> usize {
+        buf[0] = self.options();
+        buf[1] = 0xF0 | self.ctr_ret & 0x0F;
+        buf[2] = (self.meta_read & 0x0F) << 4 | self.file_read & 0x0F;
+        let mut len = 3;
+        let mut push = |v: u32| {
+            buf[len..len + 3].copy_from_slice(&v.to_le_bytes()[..3]);
+            len += 3;
+        };
+
+        if self.meta_read == AccessRights::FREE {
+            if self.uid_mirror {
+                push(self.uid_offset);
+            }
+            if self.read_ctr_mirror {
+                push(self.read_ctr_offset);
+            }
+        } else if self.meta_read != AccessRights::NEVER {
+            push(self.picc_data_offset);
+        }
+        if self.file_read != AccessRights::NEVER {
+            push(self.mac_input_offset);
+            if let Some((offset, length)) = self.enc_data {
+                push(offset);
+                push(length);
+            }
+            push(self.mac_offset);
+        }
+        if let Some(limit) = self.read_ctr_limit {
+            push(limit);
+        }
+        len
+    }
+
+    /// Parse from SDMOptions on.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let options = *data.first()?;
+        let access = data.get(1..3)?;
+        let mut s = Self {
+            uid_mirror: options & SDM_UID != 0,
+            read_ctr_mirror: options & SDM_READ_CTR != 0,
+            ascii: options & SDM_ASCII != 0,
+            ctr_ret: access[0] & 0x0F,
+            meta_read: access[1] >> 4,
+            file_read: access[1] & 0x0F,
+            uid_offset: 0,
+            read_ctr_offset: 0,
+            picc_data_offset: 0,
+            mac_input_offset: 0,
+            enc_data: None,
+            mac_offset: 0,
+            read_ctr_limit: None,
+        };
+
+        let mut rest = &data[3..];
+        let mut pop = || {
+            let v = rest.get(..3)?;
+            rest = &rest[3..];
+            Some(u32::from_le_bytes([v[0], v[1], v[2], 0]))
+        };
+        if s.meta_read == AccessRights::FREE {
+            if s.uid_mirror {
+                s.uid_offset = pop()?;
+            }
+            if s.read_ctr_mirror {
+                s.read_ctr_offset = pop()?;
+            }
+        } else if s.meta_read != AccessRights::NEVER {
+            s.picc_data_offset = pop()?;
+        }
+        if s.file_read != AccessRights::NEVER {
+            s.mac_input_offset = pop()?;
+            if options & SDM_ENC_FILE_DATA != 0 {
+                s.enc_data = Some((pop()?, pop()?));
+            }
+            s.mac_offset = pop()?;
+        }
+        if options & SDM_READ_CTR_LIMIT != 0 {
+            s.read_ctr_limit = Some(pop()?);
+        }
+        Some(s)
+    }
+}
+
+/// Response to GetFileSettings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FileSettings {
+    pub comm_mode: CommMode,
+    pub access: AccessRights,
+    pub size: u32,
+    pub sdm: Option<SdmSettings>,
+}
+
+impl FileSettings {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        // Only standard data files.
+        if *data.first()? != 0x00 {
+            return None;
+        }
+        let v = data.get(1..7)?;
+        let option = v[0];
+        let comm_mode = match option & 0x03 {
+            1 => CommMode::Mac,
+            3 => CommMode::Full,
+            _ => CommMode::Plain,
+        };
+        let sdm = match option & FILE_OPTION_SDM {
+            0 => None,
+            _ => Some(SdmSettings::parse(&data[7..])?),
+        };
+        Some(Self {
+            comm_mode,
+            access: AccessRights::decode([v[1], v[2]]),
+            size: u32::from_le_bytes([v[3], v[4], v[5], 0]),
+            sdm,
+        })
+    }
+}
+
+/// An NTAG 424 DNA tag with the NDEF application selected.
+pub struct Ntag424<T: Reader> {
+    card: Desfire<T>,
+}
+
+impl<T: Reader> Ntag424<T> {
+    /// Select the NDEF application.
+    pub async fn new(reader: T) -> Result<Self, Error<T::Error>> {
+        let mut card = Desfire::new(reader);
+        t4t::select_ndef_application(card.reader()).await?;
+        card.iso_selected();
+        Ok(Self { card })
+    }
+
+    /// Get the underlying DESFire card, for native commands such as ReadData or GetCardUID.
+    pub fn desfire(&mut self) -> &mut Desfire<T> {
+        &mut self.card
+    }
+
+    pub fn into_inner(self) -> T {
+        self.card.into_inner()
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.card.is_authenticated()
+    }
+
+    /// Authenticate with AuthenticateEV2First. Keys 0 to 4 exist, 0 is the master key.
+    pub async fn authenticate(
+        &mut self,
+        rng: &mut impl CryptoRngCore,
+        key_no: u8,
+        key: &[u8; 16],
+    ) -> Result<(), Error<T::Error>> {
+        Ok(self.card.authenticate_ev2_first(rng, key_no, &Key::Aes(*key)).await?)
+    }
+
+    /// Change key `key_no`, which needs authentication with key 0.
+    ///
+    /// Changing a key other than key 0 needs its current value in `old_key`. Changing key 0
+    /// ends the session.
+    pub async fn change_key(
+        &mut self,
+        key_no: u8,
+        new_key: &[u8; 16],
+        version: u8,
+        old_key: &[u8; 16],
+    ) -> Result<(), Error<T::Error>> {
+        Ok(self
+            .card
+            .change_key(key_no, &Key::Aes(*new_key), version, &Key::Aes(*old_key))
+            .await?)
+    }
+
+    pub async fn get_file_settings(&mut self, file: u8) -> Result<FileSettings, Error<T::Error>> {
+        let mut buf = [0; 64];
+        let header = [file];
+        let req = Request::new(CMD_GET_FILE_SETTINGS, &header, &[], CommMode::Mac);
+        let n = self.card.exchange(&req, &mut buf).await?;
+        FileSettings::parse(&buf[..n]).ok_or_else(|| {
+            warn!("malformed file settings");
+            Error::Protocol
+        })
+    }
+
+    /// Change the communication mode, access rights and SDM settings of a file. Needs
+    /// authentication with the key of the change access right.
+    pub async fn change_file_settings(
+        &mut self,
+        file: u8,
+        comm_mode: CommMode,
+        access: AccessRights,
+        sdm: Option<&SdmSettings>,
+    ) -> Result<(), Error<T::Error>> {
+        if !self.card.is_authenticated() {
+            return Err(Error::NotAuthenticated);
+        }
+        let mut data = [0; 35];
+        data[0] = comm_mode as u8;
+        data[1..3].copy_from_slice(&access.encode());
+        let mut len = 3;
+        if let Some(sdm) = sdm {
+            data[0] |= FILE_OPTION_SDM;
+            len += sdm.encode(unwrap!((&mut data[3..]).try_into()));
+        }
+        let header = [file];
+        let req = Request::new(CMD_CHANGE_FILE_SETTINGS, &header, &data[..len], CommMode::Full);
+        self.card.exchange(&req, &mut []).await?;
+        Ok(())
+    }
+
+    /// Read the NXP originality signature: an ECDSA signature of the UID, on the secp224r1
+    /// curve. Enciphered when authenticated.
+    pub async fn read_sig(&mut self) -> Result<[u8; SIGNATURE_LEN], Error<T::Error>> {
+        let mut sig = [0; SIGNATURE_LEN];
+        let req = Request::new(CMD_READ_SIG, &[0x00], &[], CommMode::Full);
+        match self.card.exchange(&req, &mut sig).await? {
+            SIGNATURE_LEN => Ok(sig),
+            _ => {
+                warn!("malformed signature");
+                Err(Error::Protocol)
+            }
+        }
+    }
+
+    /// Select a file in the NDEF application by ISO file ID, such as E104 for the NDEF file.
+    pub async fn select_file(&mut self, file_id: u16) -> Result<(), Error<T::Error>> {
+        Ok(t4t::select_file(self.card.reader(), file_id).await?)
+    }
+
+    /// ISOReadBinary from the selected file into `buf`, with the file's communication mode.
+    pub async fn read_binary(&mut self, offset: usize, buf: &mut [u8], mode: CommMode) -> Result<(), Error<T::Error>> {
+        if offset + buf.len() > MAX_OFFSET + 1 {
+            return Err(Error::TooBig);
+        }
+        for (i, c) in buf.chunks_mut(MAX_CHUNK).enumerate() {
+            let [p1, p2] = ((offset + i * MAX_CHUNK) as u16).to_be_bytes();
+            let header = [p1, p2, c.len() as u8];
+            let req = Request::new(INS_READ_BINARY, &header, &[], mode);
+            let n = self.iso_exchange(&req, c).await?;
+            if n != c.len() {
+                warn!("short read");
+                return Err(Error::Protocol);
+            }
+        }
+        Ok(())
+    }
+
+    /// ISOUpdateBinary `data` in the selected file, with the file's communication mode.
+    pub async fn update_binary(&mut self, offset: usize, data: &[u8], mode: CommMode) -> Result<(), Error<T::Error>> {
+        if offset + data.len() > MAX_OFFSET + 1 {
+            return Err(Error::TooBig);
+        }
+        for (i, c) in data.chunks(MAX_CHUNK).enumerate() {
+            let header = ((offset + i * MAX_CHUNK) as u16).to_be_bytes();
+            let req = Request::new(INS_UPDATE_BINARY, &header, c, mode);
+            self.iso_exchange(&req, &mut []).await?;
+        }
+        Ok(())
+    }
+
+    /// Send an ISO command with secure messaging if authenticated. The header is P1-P2,
+    /// followed by Le if a response is expected.
+    async fn iso_exchange(&mut self, req: &Request<'_>, rx: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        let mut msg = Buf::new();
+        match self.card.session() {
+            Some(session) => session.protect(req, &mut msg).ok_or(Error::TooBig)?,
+            None => {
+                unwrap!(msg.push(req.cmd));
+                msg.extend_from_slice(req.header).map_err(|_| Error::TooBig)?;
+                msg.extend_from_slice(req.data).map_err(|_| Error::TooBig)?;
+            }
+        }
+
+        // INS, P1-P2, then Le if present, then the command data and MAC.
+        let le = req.header.get(2).copied();
+        let body = &msg[1 + req.header.len()..];
+        let mut tx = [0; 6 + MAX_CHUNK + 32];
+        tx[..4].copy_from_slice(&[0x00, msg[0], msg[1], msg[2]]);
+        let mut len = 4;
+        if !body.is_empty() {
+            tx[4] = body.len() as u8;
+            tx[5..][..body.len()].copy_from_slice(body);
+            len += 1 + body.len();
+        }
+        if let Some(le) = le {
+            tx[len] = le;
+            len += 1;
+        }
+
+        debug!("APDU TX: {:02x}", Bytes(&tx[..len]));
+        let mut buf = [0; 258];
+        let res = self.card.reader().transceive(&tx[..len], &mut buf).await;
+        let n = match res {
+            Ok(n) => n,
+            Err(e) => {
+                self.card.end_session();
+                return Err(Error::Lower(e));
+            }
+        };
+        debug!("APDU RX: {:02x}", Bytes(&buf[..n]));
+        if n < 2 {
+            warn!("response too short");
+            self.card.end_session();
+            return Err(Error::Protocol);
+        }
+        let sw = u16::from_be_bytes([buf[n - 2], buf[n - 1]]);
+        if sw != SW_OK {
+            debug!("status {:04x}", sw);
+            self.card.end_session();
+            return Err(Error::Status(sw));
+        }
+
+        let mut resp = Buf::new();
+        resp.extend_from_slice(&buf[..n - 2]).map_err(|_| Error::Protocol)?;
+        if let Some(session) = self.card.session()
+            && session.unprotect(req.mode, &mut resp, rx.len()).is_none()
+        {
+            warn!("response integrity check failed");
+            self.card.end_session();
+            return Err(Error::Integrity);
+        }
+        if resp.len() > rx.len() {
+            warn!("response too long");
+            return Err(Error::Protocol);
+        }
+        rx[..resp.len()].copy_from_slice(&resp);
+        Ok(resp.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::test_util::{FixedRng, mock};
+
+    /// RndA of AN12196.
+    const RND_A: &[u8] = &hex!("13C5DB8A5930439FC3DEF9A4C675360F");
+
+    /// SDM settings of the AN12196 example: encrypted PICCData at 0x20 with key 1, and a MAC
+    /// with key 2 at 0x43 over nothing.
+    const SDM: SdmSettings = SdmSettings {
+        uid_mirror: true,
+        read_ctr_mirror: true,
+        ascii: true,
+        ctr_ret: AccessRights::NEVER,
+        meta_read: 1,
+        file_read: 2,
+        uid_offset: 0,
+        read_ctr_offset: 0,
+        picc_data_offset: 0x20,
+        mac_input_offset: 0x43,
+        enc_data: None,
+        mac_offset: 0x43,
+        read_ctr_limit: None,
+    };
+
+    const ACCESS: AccessRights = AccessRights {
+        read: AccessRights::FREE,
+        write: 0,
+        read_write: 0,
+        change: 0,
+    };
+
+    #[test]
+    fn test_sdm_settings() {
+        let mut buf = [0; 32];
+        let n = SDM.encode(&mut buf);
+        assert_eq!(buf[..n], hex!("C1 FF12 200000 430000 430000"));
+        assert_eq!(SdmSettings::parse(&buf[..n]), Some(SDM));
+
+        let sdm = SdmSettings {
+            meta_read: AccessRights::FREE,
+            picc_data_offset: 0,
+            uid_offset: 0x10,
+            read_ctr_offset: 0x30,
+            enc_data: Some((0x40, 0x20)),
+            read_ctr_limit: Some(1000),
+            ..SDM
+        };
+        let n = sdm.encode(&mut buf);
+        assert_eq!(buf[..n], hex!("F1 FFE2 100000 300000 430000 400000 200000 430000 E80300"));
+        assert_eq!(SdmSettings::parse(&buf[..n]), Some(sdm));
+        assert_eq!(SdmSettings::parse(&buf[..n - 1]), None);
+    }
+
+    #[test]
+    fn test_file_settings() {
+        let s = FileSettings::parse(&hex!("00 40 00E0 000100 C1FF12200000430000430000")).unwrap();
+        assert_eq!(s.comm_mode, CommMode::Plain);
+        assert_eq!(s.access, ACCESS);
+        assert_eq!(s.size, 256);
+        assert_eq!(s.sdm, Some(SDM));
+
+        let s = FileSettings::parse(&hex!("00 03 30E0 800000")).unwrap();
+        assert_eq!(s.comm_mode, CommMode::Full);
+        assert_eq!(s.sdm, None);
+    }
+
+    #[tokio::test]
+    async fn test_secure_messaging() {
+        let mut r = mock!(
+            "00A4040007D276000085010100" => "9000",
+            // AN12196, AuthenticateEV2First with key 0.
+            "9071000002000000" => "A04C124213C186F22399D33AC2A30215 91AF",
+            "90AF00002035C3E05A752E0144BAC0DE51C1F22C56B34408A23D8AEA266CAB947EA8E0118D00" => "3FA64DB5446D1F34CD6EA311167F5E4985B89690C04A05F17FA7AB2F08120663 9100",
+            "905F0000190204AD224A7BC184BD2AD28F534747080879874AA0EFAE76FF00" => "FC222E5F7A542452 9100",
+            "903C000009007F0D0307A1072FF200" => "7EF5A3B46A874C5BE545E77D2B2EA97B624DF67FEBB0145E681C564CD481623343E8C9F1F7B585B21B34A2274D487A6FBB583D57374A59819E261C16A8804AA8 A0D100BE039E7179 9100",
+            "00A4000C02E104" => "9000",
+            "00B0000008037E24B95943051C04" => "0012D101 4931C4EA01F3E67E 9000",
+            "00D600040A0203B38D3F794219B8EE" => "18141200E0420D1C 9000",
+            "00B0000004" => "6982",
+        );
+        let mut tag = Ntag424::new(&mut r).await.unwrap();
+        tag.authenticate(&mut FixedRng(RND_A), 0, &[0; 16]).await.unwrap();
+
+        tag.change_file_settings(NDEF_FILE, CommMode::Plain, ACCESS, Some(&SDM))
+            .await
+            .unwrap();
+
+        let sig = tag.read_sig().await.unwrap();
+        assert!(sig.iter().enumerate().all(|(i, &b)| b == i as u8));
+
+        tag.select_file(0xE104).await.unwrap();
+        let mut buf = [0; 4];
+        tag.read_binary(0, &mut buf, CommMode::Mac).await.unwrap();
+        assert_eq!(buf, hex!("0012D101"));
+        tag.update_binary(4, &hex!("0203"), CommMode::Mac).await.unwrap();
+
+        assert!(matches!(
+            tag.read_binary(0, &mut buf, CommMode::Plain).await,
+            Err(Error::Status(0x6982))
+        ));
+        assert!(!tag.is_authenticated());
+        r.assert_done();
+    }
+}
@@ -0,0 +1,250 @@
+//! Host-side verification of Secure Unique NFC (SUN) messages.
+//!
+//! With Secure Dynamic Messaging (SDM) enabled, the tag mirrors fresh data into the NDEF
+//! file on every read: encrypted PICCData holding the UID and read counter, optionally
+//! encrypted file data, and a MAC over part of the file. These functions check them on the
+//! server receiving the tapped URL, without talking to the tag.
+
+use crate::crypto::Cipher;
+
+const TAG_UID: u8 = 0x80;
+const TAG_READ_CTR: u8 = 0x40;
+const TAG_UID_LEN: u8 = 0x0F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A field is missing, isn't valid hex, or has the wrong length.
+    Malformed,
+    /// The MAC doesn't match: the message wasn't produced by a tag with this key.
+    Mac,
+}
+
+/// UID and read counter mirrored by the tag, decrypted or read in plain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PiccData {
+    pub uid: Option<[u8; 7]>,
+    pub read_ctr: Option<u32>,
+}
+
+impl PiccData {
+    /// Decrypt encrypted PICCData with the SDMMetaRead key.
+    pub fn decrypt(key: &[u8; 16], data: &[u8; 16]) -> Option<Self> {
+        let mut p = *data;
+        Cipher::aes(key).decrypt_block(&mut p);
+
+        let tag = p[0];
+        let mut pos = 1;
+        let mut uid = None;
+        if tag & TAG_UID != 0 {
+            if tag & TAG_UID_LEN != 7 {
+                return None;
+            }
+            uid = Some(unwrap!(p[1..8].try_into()));
+            pos += 7;
+        }
+        let mut read_ctr = None;
+        if tag & TAG_READ_CTR != 0 {
+            read_ctr = Some(u32::from_le_bytes([p[pos], p[pos + 1], p[pos + 2], 0]));
+        }
+        Some(Self { uid, read_ctr })
+    }
+
+    /// Derive the SDM session key for `label`: CMAC of the key over a vector with the
+    /// mirrored UID and read counter.
+    fn session_key(&self, key: &[u8; 16], label: [u8; 2]) -> [u8; 16] {
+        let mut sv = [0; 32];
+        sv[..6].copy_from_slice(&[label[0], label[1], 0x00, 0x01, 0x00, 0x80]);
+        let mut len = 6;
+        if let Some(uid) = &self.uid {
+            sv[len..len + 7].copy_from_slice(uid);
+            len += 7;
+        }
+        if let Some(ctr) = self.read_ctr {
+            sv[len..len + 3].copy_from_slice(&ctr.to_le_bytes()[..3]);
+            len += 3;
+        }
+        Cipher::aes(key).cmac(&[0; 16], &sv[..len.next_multiple_of(16)])
+    }
+}
+
+/// Compute the SUN MAC with the SDMFileRead key over `input`, the file contents from
+/// SDMMACInputOffset up to SDMMACOffset.
+pub fn sdm_mac(key: &[u8; 16], picc: &PiccData, input: &[u8]) -> [u8; 8] {
+    let mac_key = picc.session_key(key, [0x3C, 0xC3]);
+    let mac = Cipher::aes(&mac_key).cmac(&[0; 16], input);
+    core::array::from_fn(|i| mac[i * 2 + 1])
+}
+
+/// Check the SUN MAC, in constant time.
+pub fn verify_mac(key: &[u8; 16], picc: &PiccData, input: &[u8], mac: &[u8; 8]) -> bool {
+    let expected = sdm_mac(key, picc, input);
+    expected.iter().zip(mac).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Decrypt SDMENCFileData in place with the SDMFileRead key. Needs the read counter.
+pub fn decrypt_file_data(key: &[u8; 16], picc: &PiccData, data: &mut [u8]) -> Result<(), Error> {
+    let Some(ctr) = picc.read_ctr else {
+        return Err(Error::Malformed);
+    };
+    if data.is_empty() || !data.len().is_multiple_of(16) {
+        return Err(Error::Malformed);
+    }
+    let cipher = Cipher::aes(&picc.session_key(key, [0xC3, 0x3C]));
+    let mut iv = [0; 16];
+    iv[..3].copy_from_slice(&ctr.to_le_bytes()[..3]);
+    cipher.encrypt_block(&mut iv);
+    cipher.cbc_decrypt(&mut iv, data);
+    Ok(())
+}
+
+/// Names of the URL query parameters holding the mirrored fields, in ASCII encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UrlLayout<'a> {
+    /// Encrypted PICCData.
+    pub picc_data: &'a str,
+    /// Encrypted file data, if mirrored.
+    pub enc_file_data: Option<&'a str>,
+    pub mac: &'a str,
+    /// Parameter whose value starts the MAC input, which runs up to the MAC value. `None`
+    /// if the input is empty, when SDMMACInputOffset equals SDMMACOffset.
+    pub mac_input: Option<&'a str>,
+}
+
+/// A verified SUN message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message {
+    pub picc: PiccData,
+    /// Length of the decrypted file data.
+    pub file_data_len: usize,
+}
+
+/// Verify a tapped URL: decrypt PICCData, check the MAC, and decrypt the file data into
+/// `file_data` if the layout has some.
+///
+/// Callers must still check the read counter is higher than the last one seen for this UID,
+/// to reject replayed URLs.
+pub fn verify_url(
+    url: &str,
+    layout: &UrlLayout<'_>,
+    meta_read_key: &[u8; 16],
+    file_read_key: &[u8; 16],
+    file_data: &mut [u8],
+) -> Result<Message, Error> {
+    let url = url.as_bytes();
+
+    let (_, value) = find_param(url, layout.picc_data).ok_or(Error::Malformed)?;
+    let mut enc_picc = [0; 16];
+    hex_decode(value, &mut enc_picc)?;
+    let picc = PiccData::decrypt(meta_read_key, &enc_picc).ok_or(Error::Malformed)?;
+
+    let (mac_pos, value) = find_param(url, layout.mac).ok_or(Error::Malformed)?;
+    let mut mac = [0; 8];
+    hex_decode(value, &mut mac)?;
+    let input_pos = match layout.mac_input {
+        Some(name) => find_param(url, name).ok_or(Error::Malformed)?.0,
+        None => mac_pos,
+    };
+    if input_pos > mac_pos {
+        return Err(Error::Malformed);
+    }
+    if !verify_mac(file_read_key, &picc, &url[input_pos..mac_pos], &mac) {
+        return Err(Error::Mac);
+    }
+
+    let mut file_data_len = 0;
+    if let Some(name) = layout.enc_file_data {
+        let (_, value) = find_param(url, name).ok_or(Error::Malformed)?;
+        file_data_len = value.len() / 2;
+        let buf = file_data.get_mut(..file_data_len).ok_or(Error::Malformed)?;
+        hex_decode(value, buf)?;
+        decrypt_file_data(file_read_key, &picc, buf)?;
+    }
+
+    Ok(Message { picc, file_data_len })
+}
+
+/// Find a query parameter, returning the position of its value and the value.
+fn find_param<'u>(url: &'u [u8], name: &str) -> Option<(usize, &'u [u8])> {
+    let query = url.iter().position(|&b| b == b'?')?;
+    let mut pos = query + 1;
+    for param in url[pos..].split(|&b| b == b'&') {
+        if let Some(value) = param.strip_prefix(name.as_bytes()).and_then(|v| v.strip_prefix(b"=")) {
+            return Some((pos + name.len() + 1, value));
+        }
+        pos += param.len() + 1;
+    }
+    None
+}
+
+/// Decode hex into `out`, which must be exactly half as long.
+fn hex_decode(hex: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    if hex.len() != out.len() * 2 {
+        return Err(Error::Malformed);
+    }
+    let nibble = |c: u8| match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(Error::Malformed),
+    };
+    for (o, h) in out.iter_mut().zip(hex.chunks_exact(2)) {
+        *o = nibble(h[0])? << 4 | nibble(h[1])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_picc_data() {
+        // AN12196, SUN with encrypted PICCData.
+        let picc = PiccData::decrypt(&[0; 16], &hex!("EF963FF7828658A599F3041510671E88")).unwrap();
+        assert_eq!(picc.uid, Some(hex!("04DE5F1EACC040")));
+        assert_eq!(picc.read_ctr, Some(61));
+        assert_eq!(sdm_mac(&[0; 16], &picc, &[]), hex!("94EED9EE65337086"));
+    }
+
+    #[test]
+    fn test_verify_url() {
+        let layout = UrlLayout {
+            picc_data: "e",
+            enc_file_data: None,
+            mac: "c",
+            mac_input: None,
+        };
+        let url = "https://choose.url.com/ntag424?e=EF963FF7828658A599F3041510671E88&c=94EED9EE65337086";
+        let msg = verify_url(url, &layout, &[0; 16], &[0; 16], &mut []).unwrap();
+        assert_eq!(msg.picc.uid, Some(hex!("04DE5F1EACC040")));
+        assert_eq!(msg.picc.read_ctr, Some(61));
+
+        let url = "https://choose.url.com/ntag424?e=EF963FF7828658A599F3041510671E88&c=94EED9EE65337087";
+        assert_eq!(verify_url(url, &layout, &[0; 16], &[0; 16], &mut []), Err(Error::Mac));
+        let url = "https://choose.url.com/ntag424?e=EF963FF7828658A599F3041510671E&c=94EED9EE65337086";
+        assert_eq!(verify_url(url, &layout, &[0; 16], &[0; 16], &mut []), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn test_verify_url_enc_file_data() {
+        // AN12196, SUN with encrypted PICCData and file data.
+        let layout = UrlLayout {
+            picc_data: "picc_data",
+            enc_file_data: Some("enc"),
+            mac: "cmac",
+            mac_input: Some("enc"),
+        };
+        let url = "https://www.my424dna.com/?picc_data=FD91EC264309878BE6345CBE53BADF40&enc=CEE9A53E3E463EF1F459635736738962&cmac=ECC1E7F6C6C73BF6";
+        let mut data = [0; 32];
+        let msg = verify_url(url, &layout, &[0; 16], &[0; 16], &mut data).unwrap();
+        assert_eq!(msg.picc.uid, Some(hex!("04958CAA5C5E80")));
+        assert_eq!(msg.picc.read_ctr, Some(8));
+        assert_eq!(&data[..msg.file_data_len], b"xxxxxxxxxxxxxxxx");
+    }
+}
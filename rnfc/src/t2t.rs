@@ -0,0 +1,702 @@
+//! NFC Forum Type 2 Tag platform: memory access and NDEF on top of ISO 14443-3A.
+//!
+//! The memory is made of 4-byte pages. Pages 0-2 hold the UID and static lock bytes, page 3
+//! the Capability Container, and the data area with the TLVs starts at page 4. The data area
+//! may contain lock and reserved areas, declared by Lock Control and Memory Control TLVs,
+//! which are skipped when reading and writing the NDEF message.
+//!
+//! Tags bigger than 1 KiB, which need SECTOR_SELECT, are not supported.
+
+use heapless::Vec;
+use rnfc_traits::iso14443a::{RawFrame, Reader};
+
+use crate::fmt::Bytes;
+
+pub const PAGE_SIZE: usize = 4;
+/// Number of bytes returned by READ, 4 pages.
+pub const READ_LEN: usize = 16;
+
+const CMD_READ: u8 = 0x30;
+const CMD_WRITE: u8 = 0xA2;
+const CMD_COMPATIBILITY_WRITE: u8 = 0xA0;
+
+const ACK: u8 = 0x0A;
+
+const READ_TIMEOUT_1FC: u32 = 65536;
+const WRITE_TIMEOUT_1FC: u32 = 131072;
+
+const CC_PAGE: u8 = 3;
+const CC_MAGIC: u8 = 0xE1;
+/// Byte address of the start of the data area.
+const DATA_START: usize = 16;
+/// Highest byte address reachable without SECTOR_SELECT.
+const MEMORY_MAX: usize = 256 * PAGE_SIZE;
+
+const TLV_NULL: u8 = 0x00;
+const TLV_LOCK_CONTROL: u8 = 0x01;
+const TLV_MEMORY_CONTROL: u8 = 0x02;
+const TLV_NDEF: u8 = 0x03;
+const TLV_TERMINATOR: u8 = 0xFE;
+
+/// Max number of Lock Control and Memory Control TLVs we can keep track of.
+const MAX_AREAS: usize = 4;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The tag sent a malformed response, or the TLVs are malformed.
+    Protocol,
+    /// The tag answered with a NAK. The value is the 4-bit NAK code.
+    Nak(u8),
+    /// The Capability Container doesn't have the NDEF magic number.
+    NotFormatted,
+    /// The access conditions in the Capability Container don't allow the operation.
+    AccessDenied,
+    /// The NDEF message doesn't fit in the buffer or the data area.
+    TooBig,
+    /// The tag uses a mapping version or memory size this implementation doesn't support.
+    Unsupported,
+}
+
+/// Parsed Capability Container, from page 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CapabilityContainer {
+    /// Mapping version, major in the high nibble and minor in the low nibble.
+    pub version: u8,
+    /// Size of the data area in bytes.
+    pub data_size: usize,
+    /// Read access condition, 0 means granted.
+    pub read_access: u8,
+    /// Write access condition, 0 means granted and 0xF means read-only.
+    pub write_access: u8,
+}
+
+impl CapabilityContainer {
+    pub fn parse(data: &[u8; 4]) -> Option<Self> {
+        if data[0] != CC_MAGIC {
+            return None;
+        }
+        Some(Self {
+            version: data[1],
+            data_size: data[2] as usize * 8,
+            read_access: data[3] >> 4,
+            write_access: data[3] & 0x0F,
+        })
+    }
+}
+
+/// A lock or reserved area inside the data area, as byte addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Area {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Area {
+    /// Decode the value of a Lock Control or Memory Control TLV.
+    fn parse(tlv_type: u8, value: &[u8; 3]) -> Self {
+        let major_offsets = (value[0] >> 4) as usize;
+        let minor_offsets = (value[0] & 0x0F) as usize;
+        // The high nibble of the page control byte is the lock bit granularity, which we don't need.
+        let major_offset_size = 1usize << (value[2] & 0x0F);
+        let start = major_offsets * major_offset_size + minor_offsets;
+
+        // 0 means 256, in bits for lock areas and bytes for reserved areas.
+        let size = match value[1] {
+            0 => 256,
+            n => n as usize,
+        };
+        let len = match tlv_type {
+            TLV_LOCK_CONTROL => size.div_ceil(8),
+            _ => size,
+        };
+
+        Self { start, end: start + len }
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+}
+
+/// READ: read 4 pages starting at `page`.
+///
+/// Reading past the end of memory wraps around to page 0.
+pub async fn read<T: Reader>(card: &mut T, page: u8) -> Result<[u8; READ_LEN], Error<T::Error>> {
+    let mut rx = [0; READ_LEN];
+    let n = card
+        .transceive(&[CMD_READ, page], &mut rx, READ_TIMEOUT_1FC)
+        .await
+        .map_err(Error::Lower)?;
+    if n != READ_LEN {
+        warn!("READ: bad response length {}", n);
+        return Err(Error::Protocol);
+    }
+    Ok(rx)
+}
+
+/// WRITE: write one page.
+pub async fn write<T: Reader>(card: &mut T, page: u8, data: &[u8; PAGE_SIZE]) -> Result<(), Error<T::Error>> {
+    let mut tx = [0; 2 + PAGE_SIZE];
+    tx[0] = CMD_WRITE;
+    tx[1] = page;
+    tx[2..].copy_from_slice(data);
+    transceive_ack(card, &tx).await
+}
+
+/// COMPATIBILITY WRITE: write one page, with the MIFARE Classic WRITE framing.
+///
+/// The tag takes 16 bytes of data in the second frame, but only writes the first 4.
+pub async fn compatibility_write<T: Reader>(card: &mut T, page: u8, data: &[u8; PAGE_SIZE]) -> Result<(), Error<T::Error>> {
+    transceive_ack(card, &[CMD_COMPATIBILITY_WRITE, page]).await?;
+    let mut tx = [0; 16];
+    tx[..PAGE_SIZE].copy_from_slice(data);
+    transceive_ack(card, &tx).await
+}
+
+/// Send a frame answered with a 4-bit ACK or NAK.
+pub(crate) async fn transceive_ack<T: Reader>(card: &mut T, tx: &[u8]) -> Result<(), Error<T::Error>> {
+    let opts = RawFrame {
+        rx_crc: false,
+        ..RawFrame::standard(WRITE_TIMEOUT_1FC, tx.len())
+    };
+    let mut rx = [0; 1];
+    let bits = card.transceive_raw(tx, &mut rx, opts).await.map_err(Error::Lower)?;
+    if bits != 4 {
+        warn!("expected ACK, got {} bits", bits);
+        return Err(Error::Protocol);
+    }
+    match rx[0] & 0x0F {
+        ACK => Ok(()),
+        nak => {
+            debug!("NAK {:x}", nak);
+            Err(Error::Nak(nak))
+        }
+    }
+}
+
+/// A Type 2 Tag with its Capability Container and TLVs parsed.
+pub struct Tag<T: Reader> {
+    card: T,
+    cc: CapabilityContainer,
+    /// Lock and reserved areas inside the data area.
+    areas: Vec<Area, MAX_AREAS>,
+    /// Byte address of the NDEF TLV, or where to put it if there is none.
+    ndef_tlv: usize,
+    /// Length of the NDEF message, if there's an NDEF TLV.
+    ndef_len: Option<usize>,
+
+    /// Last 4 pages read.
+    cache: Option<(u8, [u8; READ_LEN])>,
+    /// Page being written, flushed when moving to another page.
+    pending: Option<(u8, [u8; PAGE_SIZE])>,
+}
+
+impl<T: Reader> Tag<T> {
+    /// Read the Capability Container and parse the TLVs in the data area.
+    pub async fn new(card: T) -> Result<Self, Error<T::Error>> {
+        let mut this = Self {
+            card,
+            cc: CapabilityContainer {
+                version: 0,
+                data_size: 0,
+                read_access: 0,
+                write_access: 0,
+            },
+            areas: Vec::new(),
+            ndef_tlv: DATA_START,
+            ndef_len: None,
+            cache: None,
+            pending: None,
+        };
+
+        let mut cc = [0; 4];
+        this.read_bytes(CC_PAGE as usize * PAGE_SIZE, &mut cc).await?;
+        debug!("CC: {:02x}", Bytes(&cc));
+        this.cc = CapabilityContainer::parse(&cc).ok_or(Error::NotFormatted)?;
+        if this.cc.version >> 4 != 1 {
+            warn!("unsupported mapping version {:02x}", this.cc.version);
+            return Err(Error::Unsupported);
+        }
+        if this.data_end() > MEMORY_MAX {
+            warn!("tags bigger than 1KiB are not supported");
+            return Err(Error::Unsupported);
+        }
+
+        if this.cc.read_access == 0 {
+            this.parse_tlvs().await?;
+        }
+
+        Ok(this)
+    }
+
+    async fn parse_tlvs(&mut self) -> Result<(), Error<T::Error>> {
+        let mut addr = DATA_START;
+        loop {
+            addr = self.skip_areas(addr);
+            if addr >= self.data_end() {
+                break;
+            }
+
+            let tlv_start = addr;
+            let mut t = [0; 1];
+            addr = self.read_data(addr, &mut t).await?;
+            let t = t[0];
+            if t == TLV_NULL {
+                continue;
+            }
+            if t == TLV_TERMINATOR {
+                addr = tlv_start;
+                break;
+            }
+
+            let mut l = [0; 1];
+            addr = self.read_data(addr, &mut l).await?;
+            let len = match l[0] {
+                0xFF => {
+                    let mut l = [0; 2];
+                    addr = self.read_data(addr, &mut l).await?;
+                    u16::from_be_bytes(l) as usize
+                }
+                l => l as usize,
+            };
+            trace!("TLV {:02x} at {}, len {}", t, tlv_start, len);
+
+            match t {
+                TLV_LOCK_CONTROL | TLV_MEMORY_CONTROL => {
+                    if len != 3 {
+                        warn!("bad control TLV length");
+                        return Err(Error::Protocol);
+                    }
+                    let mut v = [0; 3];
+                    addr = self.read_data(addr, &mut v).await?;
+                    let area = Area::parse(t, &v);
+                    debug!("area: {:?}", area);
+                    // Areas outside the data area don't affect us.
+                    if area.start < self.data_end() && self.areas.push(area).is_err() {
+                        warn!("too many control TLVs");
+                        return Err(Error::Unsupported);
+                    }
+                }
+                TLV_NDEF => {
+                    self.ndef_tlv = tlv_start;
+                    self.ndef_len = Some(len);
+                    return Ok(());
+                }
+                _ => {
+                    // Proprietary or unknown TLV, skip its value.
+                    for _ in 0..len {
+                        addr = self.skip_areas(addr) + 1;
+                    }
+                }
+            }
+        }
+
+        self.ndef_tlv = addr.min(self.data_end());
+        Ok(())
+    }
+
+    pub fn cc(&self) -> &CapabilityContainer {
+        &self.cc
+    }
+
+    /// Lock and reserved areas inside the data area, from the Lock Control and Memory Control TLVs.
+    pub fn areas(&self) -> &[Area] {
+        &self.areas
+    }
+
+    /// Get the underlying card, for sending other commands.
+    pub fn card(&mut self) -> &mut T {
+        self.cache = None;
+        &mut self.card
+    }
+
+    pub fn into_inner(self) -> T {
+        self.card
+    }
+
+    /// Maximum NDEF message length that fits in the data area.
+    pub fn max_ndef_len(&self) -> usize {
+        // T and a 1-byte L, or T and a 3-byte L for messages of 255 bytes or more.
+        let avail = self.available(self.ndef_tlv);
+        let short = avail.saturating_sub(2).min(0xFE);
+        let long = avail.saturating_sub(4);
+        short.max(long)
+    }
+
+    /// Read the NDEF message into `buf`, returning its length.
+    ///
+    /// A length of zero means the tag has no NDEF message.
+    pub async fn read_ndef(&mut self, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        if self.cc.read_access != 0 {
+            return Err(Error::AccessDenied);
+        }
+        let Some(len) = self.ndef_len else {
+            return Ok(0);
+        };
+        if len > buf.len() {
+            return Err(Error::TooBig);
+        }
+
+        let lsize = if len < 0xFF { 1 } else { 3 };
+        let mut addr = self.ndef_tlv;
+        for _ in 0..1 + lsize {
+            addr = self.skip_areas(addr) + 1;
+        }
+        self.read_data(addr, &mut buf[..len]).await?;
+        Ok(len)
+    }
+
+    /// Write `msg` as the NDEF message, replacing the existing one.
+    ///
+    /// Following the NFC Forum procedure, the length is set to zero while the message is
+    /// written, so a tag removed halfway doesn't end up with a corrupted message.
+    pub async fn write_ndef(&mut self, msg: &[u8]) -> Result<(), Error<T::Error>> {
+        if self.cc.write_access != 0 {
+            return Err(Error::AccessDenied);
+        }
+        if msg.len() > self.max_ndef_len() {
+            return Err(Error::TooBig);
+        }
+
+        let (l, lsize): ([u8; 3], usize) = match msg.len() {
+            n if n < 0xFF => ([n as u8, 0, 0], 1),
+            n => ([0xFF, (n >> 8) as u8, n as u8], 3),
+        };
+        let empty: [u8; 3] = match lsize {
+            1 => [0x00, 0, 0],
+            _ => [0xFF, 0, 0],
+        };
+
+        self.ndef_len = None;
+
+        let addr = self.write_data(self.ndef_tlv, &[TLV_NDEF]).await?;
+        let l_addr = addr;
+        let addr = self.write_data(addr, &empty[..lsize]).await?;
+        let addr = self.write_data(addr, msg).await?;
+        if self.available(addr) > 0 {
+            self.write_data(addr, &[TLV_TERMINATOR]).await?;
+        }
+        self.flush().await?;
+
+        self.write_data(l_addr, &l[..lsize]).await?;
+        self.flush().await?;
+
+        self.ndef_len = Some(msg.len());
+        Ok(())
+    }
+
+    fn data_end(&self) -> usize {
+        DATA_START + self.cc.data_size
+    }
+
+    /// Skip lock and reserved areas starting at `addr`.
+    fn skip_areas(&self, mut addr: usize) -> usize {
+        while let Some(a) = self.areas.iter().find(|a| a.contains(addr)) {
+            addr = a.end;
+        }
+        addr
+    }
+
+    /// Number of data bytes from `addr` to the end of the data area, excluding lock and reserved areas.
+    fn available(&self, mut addr: usize) -> usize {
+        let mut n = 0;
+        loop {
+            addr = self.skip_areas(addr);
+            if addr >= self.data_end() {
+                return n;
+            }
+            n += 1;
+            addr += 1;
+        }
+    }
+
+    /// Read data area bytes starting at `addr`, skipping lock and reserved areas.
+    /// Returns the address following the last byte read.
+    async fn read_data(&mut self, mut addr: usize, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        for b in buf {
+            addr = self.skip_areas(addr);
+            if addr >= self.data_end() {
+                warn!("read past the end of the data area");
+                return Err(Error::Protocol);
+            }
+            self.read_bytes(addr, core::slice::from_mut(b)).await?;
+            addr += 1;
+        }
+        Ok(addr)
+    }
+
+    /// Write data area bytes starting at `addr`, skipping lock and reserved areas.
+    /// Returns the address following the last byte written. Call `flush` when done.
+    async fn write_data(&mut self, mut addr: usize, data: &[u8]) -> Result<usize, Error<T::Error>> {
+        for &b in data {
+            addr = self.skip_areas(addr);
+            if addr >= self.data_end() {
+                return Err(Error::TooBig);
+            }
+
+            let page = (addr / PAGE_SIZE) as u8;
+            if self.pending.is_some_and(|(p, _)| p != page) {
+                self.flush().await?;
+            }
+            if self.pending.is_none() {
+                // Read the page first, to keep the bytes we're not writing.
+                let mut buf = [0; PAGE_SIZE];
+                self.read_bytes(page as usize * PAGE_SIZE, &mut buf).await?;
+                self.pending = Some((page, buf));
+            }
+            if let Some((_, buf)) = &mut self.pending {
+                buf[addr % PAGE_SIZE] = b;
+            }
+            addr += 1;
+        }
+        Ok(addr)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error<T::Error>> {
+        let Some((page, data)) = self.pending.take() else {
+            return Ok(());
+        };
+        write(&mut self.card, page, &data).await?;
+        if let Some((start, cache)) = &mut self.cache
+            && (*start..start.saturating_add(4)).contains(&page)
+        {
+            let offs = (page - *start) as usize * PAGE_SIZE;
+            cache[offs..][..PAGE_SIZE].copy_from_slice(&data);
+        }
+        Ok(())
+    }
+
+    /// Read raw memory bytes, going through the cache.
+    async fn read_bytes(&mut self, addr: usize, buf: &mut [u8]) -> Result<(), Error<T::Error>> {
+        for (i, b) in buf.iter_mut().enumerate() {
+            let addr = addr + i;
+            let page = (addr / PAGE_SIZE) as u8;
+            let (start, data) = match self.cache {
+                Some((start, data)) if (start..start.saturating_add(4)).contains(&page) => (start, data),
+                _ => {
+                    let data = read(&mut self.card, page).await?;
+                    self.cache = Some((page, data));
+                    (page, data)
+                }
+            };
+            *b = data[addr - start as usize * PAGE_SIZE];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use hex_literal::hex;
+    use rnfc_traits::iso14443a_ll::ErrorKind;
+
+    use super::*;
+    use crate::ndef;
+
+    /// Simulated Type 2 Tag, answering READ, WRITE and COMPATIBILITY WRITE from its memory.
+    struct MemoryTag {
+        mem: Vec<u8>,
+        /// Page of a COMPATIBILITY WRITE waiting for its data frame.
+        compat_page: Option<usize>,
+    }
+
+    impl MemoryTag {
+        fn new(pages: usize, init: &[u8]) -> Self {
+            let mut mem = vec![0; pages * PAGE_SIZE];
+            mem[..init.len()].copy_from_slice(init);
+            Self { mem, compat_page: None }
+        }
+
+        fn write_page(&mut self, page: usize, data: &[u8]) -> u8 {
+            // Pages 0 and 1 hold the UID and are read-only.
+            if page < 2 || (page + 1) * PAGE_SIZE > self.mem.len() {
+                return 0x0;
+            }
+            self.mem[page * PAGE_SIZE..][..PAGE_SIZE].copy_from_slice(&data[..PAGE_SIZE]);
+            ACK
+        }
+    }
+
+    impl Reader for MemoryTag {
+        type Error = ErrorKind;
+
+        async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], _timeout_1fc: u32) -> Result<usize, Self::Error> {
+            assert_eq!(tx[0], CMD_READ);
+            let pages = self.mem.len() / PAGE_SIZE;
+            for (i, b) in rx[..READ_LEN].iter_mut().enumerate() {
+                let page = (tx[1] as usize + i / PAGE_SIZE) % pages;
+                *b = self.mem[page * PAGE_SIZE + i % PAGE_SIZE];
+            }
+            Ok(READ_LEN)
+        }
+
+        async fn transceive_raw(&mut self, tx: &[u8], rx: &mut [u8], opts: RawFrame) -> Result<usize, Self::Error> {
+            assert_eq!(opts.tx_bits, tx.len() * 8);
+            assert!(opts.tx_crc && !opts.rx_crc && opts.parity);
+
+            rx[0] = match (self.compat_page.take(), tx) {
+                (Some(page), data) if data.len() == 16 => self.write_page(page, data),
+                (None, [CMD_WRITE, page, data @ ..]) if data.len() == PAGE_SIZE => self.write_page(*page as usize, data),
+                (None, [CMD_COMPATIBILITY_WRITE, page]) => {
+                    self.compat_page = Some(*page as usize);
+                    ACK
+                }
+                _ => panic!("unexpected frame {:02x?}", tx),
+            };
+            Ok(4)
+        }
+
+        fn uid(&self) -> &[u8] {
+            &self.mem[..7]
+        }
+
+        fn atqa(&self) -> [u8; 2] {
+            [0x44, 0x00]
+        }
+
+        fn sak(&self) -> u8 {
+            0x00
+        }
+    }
+
+    const HEADER: [u8; 12] = hex!("04 11 22 BF 33 44 55 66 44 48 00 00");
+
+    fn tag_memory(cc: [u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut mem = Vec::new();
+        mem.extend_from_slice(&HEADER);
+        mem.extend_from_slice(&cc);
+        mem.extend_from_slice(data);
+        mem
+    }
+
+    #[tokio::test]
+    async fn test_ntag213() {
+        // Factory state of an NTAG213: Lock Control TLV, empty NDEF TLV and terminator.
+        let mem = tag_memory(hex!("E1 10 12 00"), &hex!("01 03 A0 0C 34 03 00 FE"));
+        let mut card = MemoryTag::new(45, &mem);
+
+        let mut tag = Tag::new(&mut card).await.unwrap();
+        assert_eq!(
+            *tag.cc(),
+            CapabilityContainer {
+                version: 0x10,
+                data_size: 144,
+                read_access: 0,
+                write_access: 0,
+            }
+        );
+        // The dynamic lock bytes are right after the data area.
+        assert_eq!(tag.areas(), &[]);
+        assert_eq!(tag.max_ndef_len(), 144 - 5 - 2);
+
+        let mut buf = [0; 64];
+        assert_eq!(tag.read_ndef(&mut buf).await.unwrap(), 0);
+
+        let msg: heapless::Vec<u8, 32> = ndef::uri::message("https://www.nxp.com").unwrap();
+        tag.write_ndef(&msg).await.unwrap();
+        let n = tag.read_ndef(&mut buf).await.unwrap();
+        assert_eq!(buf[..n], msg);
+        drop(tag);
+
+        assert_eq!(
+            card.mem[16..40],
+            hex!("01 03 A0 0C 34 03 0C D1 01 08 55 02 6e 78 70 2e 63 6f 6d FE 00 00 00 00")
+        );
+
+        // A fresh tag finds the message too.
+        let mut tag = Tag::new(&mut card).await.unwrap();
+        let n = tag.read_ndef(&mut buf).await.unwrap();
+        assert_eq!(buf[..n], msg);
+    }
+
+    #[tokio::test]
+    async fn test_reserved_area() {
+        // 48-byte data area with a Memory Control TLV reserving bytes 24 and 25.
+        let mut mem = tag_memory(hex!("E1 10 06 00"), &hex!("02 03 60 02 02 03 00 FE"));
+        mem.resize(64, 0);
+        mem[24] = 0xAA;
+        mem[25] = 0xBB;
+        let mut card = MemoryTag::new(16, &mem);
+
+        let mut tag = Tag::new(&mut card).await.unwrap();
+        assert_eq!(tag.areas(), &[Area { start: 24, end: 26 }]);
+        // 48 bytes, minus the control TLV, the reserved area and the NDEF T and L.
+        assert_eq!(tag.max_ndef_len(), 48 - 5 - 2 - 2);
+        assert!(matches!(tag.write_ndef(&[0; 40]).await, Err(Error::TooBig)));
+
+        let msg = hex!("D1 01 06 54 02 656e 6869 21");
+        tag.write_ndef(&msg).await.unwrap();
+        let mut buf = [0; 16];
+        let n = tag.read_ndef(&mut buf).await.unwrap();
+        assert_eq!(buf[..n], msg);
+        drop(tag);
+
+        assert_eq!(
+            card.mem[16..40],
+            hex!("02 03 60 02 02 03 0A D1 AA BB 01 06 54 02 65 6e 68 69 21 FE 00 00 00 00")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_long_message() {
+        let mem = tag_memory(hex!("E1 10 3E 00"), &hex!("03 00 FE"));
+        let mut card = MemoryTag::new(4 + 0x3E * 2, &mem);
+
+        let mut tag = Tag::new(&mut card).await.unwrap();
+        assert_eq!(tag.max_ndef_len(), 0x3E * 8 - 4);
+
+        let mut b = ndef::MessageBuilder::<320>::new();
+        b.push(ndef::Tnf::Unknown, b"", b"", &[0x42; 290]).unwrap();
+        let msg = b.finish().unwrap();
+        tag.write_ndef(&msg).await.unwrap();
+
+        let mut buf = [0; 320];
+        let n = tag.read_ndef(&mut buf).await.unwrap();
+        assert_eq!(buf[..n], msg);
+        drop(tag);
+
+        assert_eq!(card.mem[16..20], hex!("03 FF 01 28"));
+        assert_eq!(card.mem[20 + msg.len()], TLV_TERMINATOR);
+    }
+
+    #[tokio::test]
+    async fn test_access() {
+        let mem = tag_memory(hex!("E1 10 06 0F"), &hex!("03 03 D0 00 00 FE"));
+        let mut card = MemoryTag::new(16, &mem);
+        let mut tag = Tag::new(&mut card).await.unwrap();
+        let mut buf = [0; 2];
+        assert!(matches!(tag.read_ndef(&mut buf).await, Err(Error::TooBig)));
+        let mut buf = [0; 3];
+        assert_eq!(tag.read_ndef(&mut buf).await.unwrap(), 3);
+        assert!(matches!(tag.write_ndef(&buf).await, Err(Error::AccessDenied)));
+
+        let mem = tag_memory(hex!("00 00 00 00"), &[]);
+        let mut card = MemoryTag::new(16, &mem);
+        assert!(matches!(Tag::new(&mut card).await, Err(Error::NotFormatted)));
+
+        let mem = tag_memory(hex!("E1 20 06 00"), &[]);
+        let mut card = MemoryTag::new(16, &mem);
+        assert!(matches!(Tag::new(&mut card).await, Err(Error::Unsupported)));
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let mut card = MemoryTag::new(16, &HEADER);
+
+        write(&mut card, 4, &hex!("01020304")).await.unwrap();
+        compatibility_write(&mut card, 5, &hex!("05060708")).await.unwrap();
+        assert!(matches!(write(&mut card, 0, &hex!("00000000")).await, Err(Error::Nak(0))));
+
+        let data = read(&mut card, 4).await.unwrap();
+        assert_eq!(data, hex!("01020304 05060708 00000000 00000000"));
+        // Reads wrap around at the end of memory.
+        let data = read(&mut card, 15).await.unwrap();
+        assert_eq!(data, hex!("00000000 041122BF 33445566 44480000"));
+    }
+}
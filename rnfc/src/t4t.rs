@@ -0,0 +1,493 @@
+//! NFC Forum Type 4 Tag platform: NDEF access on top of ISO-DEP.
+//!
+//! Works with anything implementing [`iso_dep::Reader`](rnfc_traits::iso_dep::Reader), so the
+//! same code runs on [`IsoDepA`](crate::iso_dep::IsoDepA), USB readers and phones.
+//!
+//! Only short APDUs are used, so transfers are limited to 255 bytes per command in each
+//! direction even if the tag's MLe/MLc allow more. NDEF files must fit in 32 KiB, the
+//! maximum offset reachable by READ BINARY and UPDATE BINARY without offset data objects.
+
+use rnfc_traits::iso_dep::Reader;
+
+use crate::apdu::{self, Command, StatusWord};
+use crate::fmt::Bytes;
+
+/// Application ID of the NDEF tag application, version 2.0 and later.
+pub const NDEF_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+/// File ID of the Capability Container.
+pub const CC_FILE_ID: u16 = 0xE103;
+
+/// Access condition byte meaning access is granted without any security.
+pub const ACCESS_GRANTED: u8 = 0x00;
+/// Access condition byte meaning no access is granted at all.
+pub const ACCESS_DENIED: u8 = 0xFF;
+
+const CLA: u8 = 0x00;
+const INS_SELECT: u8 = 0xA4;
+const INS_READ_BINARY: u8 = 0xB0;
+const INS_UPDATE_BINARY: u8 = 0xD6;
+
+const TLV_NDEF_FILE_CONTROL: u8 = 0x04;
+const TLV_EXTENDED_NDEF_FILE_CONTROL: u8 = 0x06;
+
+/// Biggest chunk in a single READ BINARY or UPDATE BINARY with short APDUs.
+const MAX_CHUNK: usize = 255;
+/// Highest offset that fits in P1-P2.
+const MAX_OFFSET: usize = 0x7FFF;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    /// The tag sent a malformed response or Capability Container.
+    Protocol,
+    /// The tag answered with a status word other than 9000.
+    Status(StatusWord),
+    /// The access conditions of the NDEF file don't allow the operation.
+    AccessDenied,
+    /// The NDEF message doesn't fit in the buffer or the NDEF file.
+    TooBig,
+    /// The tag uses a mapping version or file size this implementation doesn't support.
+    Unsupported,
+}
+
+impl<E> From<apdu::Error<E>> for Error<E> {
+    fn from(e: apdu::Error<E>) -> Self {
+        match e {
+            apdu::Error::Lower(e) => Self::Lower(e),
+            apdu::Error::Protocol => Self::Protocol,
+            apdu::Error::Status(sw) => Self::Status(sw),
+            apdu::Error::TooBig => Self::TooBig,
+        }
+    }
+}
+
+/// NDEF File Control TLV from the Capability Container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NdefFileControl {
+    pub file_id: u16,
+    /// Maximum size of the NDEF file, including the length field.
+    pub max_size: u32,
+    pub read_access: u8,
+    pub write_access: u8,
+    /// The file uses a 4-byte ENLEN length field, instead of the 2-byte NLEN.
+    pub extended: bool,
+}
+
+impl NdefFileControl {
+    /// Size of the NLEN or ENLEN field at the start of the NDEF file.
+    pub fn len_size(&self) -> usize {
+        match self.extended {
+            true => 4,
+            false => 2,
+        }
+    }
+}
+
+/// Parsed Capability Container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CapabilityContainer {
+    /// Mapping version, major in the high nibble and minor in the low nibble.
+    pub version: u8,
+    /// Maximum data size that can be read with a single READ BINARY.
+    pub mle: u16,
+    /// Maximum data size that can be sent with a single UPDATE BINARY.
+    pub mlc: u16,
+    pub ndef_file: NdefFileControl,
+}
+
+impl CapabilityContainer {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 15 {
+            return None;
+        }
+
+        // Ignore any TLVs following the NDEF File Control TLV.
+        let cclen = u16::from_be_bytes([data[0], data[1]]) as usize;
+        if cclen < 15 {
+            return None;
+        }
+        let data = &data[..cclen.min(data.len())];
+        let version = data[2];
+        let mle = u16::from_be_bytes([data[3], data[4]]);
+        let mlc = u16::from_be_bytes([data[5], data[6]]);
+
+        let ndef_file = match (data[7], data[8]) {
+            (TLV_NDEF_FILE_CONTROL, 6) => {
+                let v = data.get(9..15)?;
+                NdefFileControl {
+                    file_id: u16::from_be_bytes([v[0], v[1]]),
+                    max_size: u16::from_be_bytes([v[2], v[3]]) as u32,
+                    read_access: v[4],
+                    write_access: v[5],
+                    extended: false,
+                }
+            }
+            (TLV_EXTENDED_NDEF_FILE_CONTROL, 8) => {
+                let v = data.get(9..17)?;
+                NdefFileControl {
+                    file_id: u16::from_be_bytes([v[0], v[1]]),
+                    max_size: u32::from_be_bytes([v[2], v[3], v[4], v[5]]),
+                    read_access: v[6],
+                    write_access: v[7],
+                    extended: true,
+                }
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            version,
+            mle,
+            mlc,
+            ndef_file,
+        })
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.ndef_file.read_access == ACCESS_GRANTED
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.ndef_file.write_access == ACCESS_GRANTED
+    }
+
+    /// Maximum NDEF message size the file can hold.
+    pub fn max_ndef_len(&self) -> usize {
+        (self.ndef_file.max_size as usize).saturating_sub(self.ndef_file.len_size())
+    }
+}
+
+/// A Type 4 Tag with the NDEF application selected.
+pub struct Tag<T: Reader> {
+    reader: T,
+    cc: CapabilityContainer,
+}
+
+impl<T: Reader> Tag<T> {
+    /// Select the NDEF application and read the Capability Container.
+    pub async fn new(reader: T) -> Result<Self, Error<T::Error>> {
+        let mut reader = reader;
+        select_ndef_application(&mut reader).await?;
+        select_file(&mut reader, CC_FILE_ID).await?;
+
+        let mut buf = [0; 17];
+        let n = read_binary(&mut reader, 0, &mut buf[..15]).await?;
+        if n < 15 {
+            warn!("CC too short");
+            return Err(Error::Protocol);
+        }
+        // The extended NDEF File Control TLV makes the CC 2 bytes longer.
+        if buf[7] == TLV_EXTENDED_NDEF_FILE_CONTROL {
+            let n = read_binary(&mut reader, 15, &mut buf[15..]).await?;
+            if n < 2 {
+                warn!("CC too short");
+                return Err(Error::Protocol);
+            }
+        }
+        debug!("CC: {:02x}", Bytes(&buf));
+
+        let Some(cc) = CapabilityContainer::parse(&buf) else {
+            warn!("malformed CC");
+            return Err(Error::Protocol);
+        };
+        debug!("CC: {:?}", cc);
+
+        if !(0x20..0x40).contains(&cc.version) {
+            warn!("unsupported mapping version {:02x}", cc.version);
+            return Err(Error::Unsupported);
+        }
+        if cc.mle == 0 || cc.mlc == 0 {
+            warn!("invalid MLe/MLc");
+            return Err(Error::Protocol);
+        }
+
+        Ok(Self { reader, cc })
+    }
+
+    pub fn cc(&self) -> &CapabilityContainer {
+        &self.cc
+    }
+
+    /// Get the underlying ISO-DEP reader, for sending other commands.
+    pub fn reader(&mut self) -> &mut T {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    /// Read the NDEF message into `buf`, returning its length.
+    ///
+    /// A length of zero means the tag is initialized but contains no NDEF message.
+    pub async fn read_ndef(&mut self, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        if !self.cc.is_readable() {
+            return Err(Error::AccessDenied);
+        }
+
+        let file = self.cc.ndef_file;
+        select_file(&mut self.reader, file.file_id).await?;
+
+        let len_size = file.len_size();
+        let mut nlen = [0; 4];
+        let n = read_binary(&mut self.reader, 0, &mut nlen[..len_size]).await?;
+        if n != len_size {
+            warn!("short NLEN read");
+            return Err(Error::Protocol);
+        }
+        let len = match file.extended {
+            true => u32::from_be_bytes(nlen) as usize,
+            false => u16::from_be_bytes([nlen[0], nlen[1]]) as usize,
+        };
+        debug!("NLEN: {}", len);
+
+        if len > self.cc.max_ndef_len() {
+            warn!("NLEN bigger than the NDEF file");
+            return Err(Error::Protocol);
+        }
+        if len > buf.len() {
+            return Err(Error::TooBig);
+        }
+        if len_size + len > MAX_OFFSET + 1 {
+            return Err(Error::Unsupported);
+        }
+
+        let chunk = (self.cc.mle as usize).min(MAX_CHUNK);
+        let mut pos = 0;
+        while pos < len {
+            let n = (len - pos).min(chunk);
+            let n = read_binary(&mut self.reader, len_size + pos, &mut buf[pos..][..n]).await?;
+            if n == 0 {
+                warn!("empty READ BINARY response");
+                return Err(Error::Protocol);
+            }
+            pos += n;
+        }
+
+        Ok(len)
+    }
+
+    /// Write `msg` as the NDEF message.
+    ///
+    /// Following the NFC Forum procedure, the length is set to zero while the message is
+    /// written, so a tag removed halfway doesn't end up with a corrupted message.
+    pub async fn write_ndef(&mut self, msg: &[u8]) -> Result<(), Error<T::Error>> {
+        if !self.cc.is_writable() {
+            return Err(Error::AccessDenied);
+        }
+        if msg.len() > self.cc.max_ndef_len() {
+            return Err(Error::TooBig);
+        }
+
+        let file = self.cc.ndef_file;
+        let len_size = file.len_size();
+        if len_size + msg.len() > MAX_OFFSET + 1 {
+            return Err(Error::Unsupported);
+        }
+
+        select_file(&mut self.reader, file.file_id).await?;
+
+        update_binary(&mut self.reader, 0, &[0; 4][..len_size]).await?;
+
+        let chunk = (self.cc.mlc as usize).min(MAX_CHUNK);
+        for (i, c) in msg.chunks(chunk).enumerate() {
+            update_binary(&mut self.reader, len_size + i * chunk, c).await?;
+        }
+
+        let len = (msg.len() as u32).to_be_bytes();
+        update_binary(&mut self.reader, 0, &len[4 - len_size..]).await?;
+
+        Ok(())
+    }
+}
+
+pub(crate) async fn select_ndef_application<T: Reader>(reader: &mut T) -> Result<(), Error<T::Error>> {
+    // Le = 00, the response may contain FCI.
+    let cmd = Command::new(CLA, INS_SELECT, 0x04, 0x00)
+        .with_data(&NDEF_AID)
+        .with_le(apdu::MAX_SHORT_LE);
+    let mut rx = [0; apdu::MAX_SHORT_LE + 2];
+    apdu::command(reader, &cmd, &mut rx).await?;
+    Ok(())
+}
+
+pub(crate) async fn select_file<T: Reader>(reader: &mut T, file_id: u16) -> Result<(), Error<T::Error>> {
+    let id = file_id.to_be_bytes();
+    let cmd = Command::new(CLA, INS_SELECT, 0x00, 0x0C).with_data(&id);
+    let mut rx = [0; 2];
+    apdu::command(reader, &cmd, &mut rx).await?;
+    Ok(())
+}
+
+/// READ BINARY into `buf`, whose length is used as Le. Returns the number of bytes read.
+async fn read_binary<T: Reader>(reader: &mut T, offset: usize, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+    assert!(offset <= MAX_OFFSET && buf.len() <= MAX_CHUNK);
+
+    let [hi, lo] = (offset as u16).to_be_bytes();
+    let cmd = Command::new(CLA, INS_READ_BINARY, hi, lo).with_le(buf.len());
+    let mut rx = [0; MAX_CHUNK + 2];
+    let data = apdu::command(reader, &cmd, &mut rx).await?;
+    if data.len() > buf.len() {
+        warn!("READ BINARY returned more data than requested");
+        return Err(Error::Protocol);
+    }
+    buf[..data.len()].copy_from_slice(data);
+    Ok(data.len())
+}
+
+async fn update_binary<T: Reader>(reader: &mut T, offset: usize, data: &[u8]) -> Result<(), Error<T::Error>> {
+    assert!(offset <= MAX_OFFSET && data.len() <= MAX_CHUNK);
+
+    let [hi, lo] = (offset as u16).to_be_bytes();
+    let cmd = Command::new(CLA, INS_UPDATE_BINARY, hi, lo).with_data(data);
+    let mut rx = [0; 2];
+    apdu::command(reader, &cmd, &mut rx).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::ndef;
+    use crate::test_util::mock;
+
+    #[tokio::test]
+    async fn test_read_ndef() {
+        let mut r = mock!(
+            "00A4040007D276000085010100" => "9000",
+            "00A4000C02E103" => "9000",
+            // MLe = 0x000F, MLc = 0x0003
+            "00B000000F" => "000F 20 000F 0003 0406 E104 0100 00 00 9000",
+            "00A4000C02E104" => "9000",
+            "00B0000002" => "0014 9000",
+            "00B000020F" => "D1 01 10 55 04 6578616d706c652e636f 9000",
+            // The tag may return less than requested.
+            "00B0001105" => "6d2f 9000",
+            "00B0001303" => "616263 9000",
+        );
+
+        let mut tag = Tag::new(&mut r).await.unwrap();
+        assert_eq!(
+            *tag.cc(),
+            CapabilityContainer {
+                version: 0x20,
+                mle: 0x0F,
+                mlc: 0x03,
+                ndef_file: NdefFileControl {
+                    file_id: 0xE104,
+                    max_size: 0x100,
+                    read_access: 0x00,
+                    write_access: 0x00,
+                    extended: false,
+                },
+            }
+        );
+
+        let mut buf = [0; 64];
+        let n = tag.read_ndef(&mut buf).await.unwrap();
+        assert_eq!(n, 0x14);
+        let msg = ndef::Message::parse(&buf[..n]).unwrap();
+        let record = msg.records().next().unwrap();
+        let uri = ndef::uri::Uri::from_record(&record).unwrap();
+        assert_eq!(uri.rest, "example.com/abc");
+
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_write_ndef() {
+        let mut r = mock!(
+            "00A4040007D276000085010100" => "9000",
+            "00A4000C02E103" => "9000",
+            // MLe = 0x00FF, MLc = 0x0008
+            "00B000000F" => "000F 20 00FF 0008 0406 E104 0020 00 00 9000",
+            "00A4000C02E104" => "9000",
+            // Clear NLEN, write the message in MLc-sized chunks, then set NLEN.
+            "00D60000020000" => "9000",
+            "00D6000208D1010855026e7870" => "9000",
+            "00D6000A042e636f6d" => "9000",
+            "00D6000002000C" => "9000",
+        );
+
+        let mut tag = Tag::new(&mut r).await.unwrap();
+        let msg: heapless::Vec<u8, 32> = ndef::uri::message("https://www.nxp.com").unwrap();
+        tag.write_ndef(&msg).await.unwrap();
+
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_extended_cc() {
+        let mut r = mock!(
+            "00A4040007D276000085010100" => "9000",
+            "00A4000C02E103" => "9000",
+            "00B000000F" => "0011 30 0100 0100 0608 E104 00010000 9000",
+            "00B0000F02" => "00 FF 9000",
+            "00A4000C02E104" => "9000",
+            "00B0000004" => "00000003 9000",
+            "00B0000403" => "D0 00 00 9000",
+        );
+
+        let mut tag = Tag::new(&mut r).await.unwrap();
+        let cc = *tag.cc();
+        assert_eq!(cc.version, 0x30);
+        assert_eq!(cc.ndef_file.max_size, 0x10000);
+        assert!(cc.ndef_file.extended);
+        assert!(cc.is_readable());
+        assert!(!cc.is_writable());
+        assert_eq!(cc.max_ndef_len(), 0xFFFC);
+
+        let mut buf = [0; 8];
+        assert_eq!(tag.read_ndef(&mut buf).await.unwrap(), 3);
+        assert_eq!(buf[..3], hex!("D0 00 00"));
+
+        assert!(matches!(tag.write_ndef(&buf[..3]).await, Err(Error::AccessDenied)));
+
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        // Not an NDEF tag.
+        let mut r = mock!(
+            "00A4040007D276000085010100" => "6A82",
+        );
+        assert!(matches!(
+            Tag::new(&mut r).await,
+            Err(Error::Status(StatusWord::FILE_NOT_FOUND))
+        ));
+        r.assert_done();
+
+        // Unsupported mapping version.
+        let mut r = mock!(
+            "00A4040007D276000085010100" => "9000",
+            "00A4000C02E103" => "9000",
+            "00B000000F" => "000F 10 000F 0003 0406 E104 0100 00 00 9000",
+        );
+        assert!(matches!(Tag::new(&mut r).await, Err(Error::Unsupported)));
+        r.assert_done();
+
+        let mut r = mock!(
+            "00A4040007D276000085010100" => "9000",
+            "00A4000C02E103" => "9000",
+            "00B000000F" => "000F 20 000F 0003 0406 E104 0010 FF 00 9000",
+            "00A4000C02E104" => "9000",
+            "00B0000002" => "0010 9000",
+        );
+        let mut tag = Tag::new(&mut r).await.unwrap();
+        let mut buf = [0; 32];
+        // Not readable.
+        assert!(matches!(tag.read_ndef(&mut buf).await, Err(Error::AccessDenied)));
+        // Doesn't fit in the 16-byte file.
+        assert!(matches!(tag.write_ndef(&buf[..15]).await, Err(Error::TooBig)));
+        tag.cc.ndef_file.read_access = ACCESS_GRANTED;
+        // NLEN bigger than the file allows.
+        assert!(matches!(tag.read_ndef(&mut buf).await, Err(Error::Protocol)));
+        r.assert_done();
+    }
+}
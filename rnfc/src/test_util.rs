@@ -0,0 +1,145 @@
+//! Test fixtures shared by the application protocol tests.
+
+use std::vec::Vec;
+
+use rand_core::{CryptoRng, RngCore, impls};
+use rnfc_traits::iso_dep::Reader;
+use rnfc_traits::iso14443a::{self, RawFrame};
+use rnfc_traits::iso14443a_ll::ErrorKind;
+
+use crate::iso14443a::crc_a;
+
+/// ISO-DEP or Type A reader that checks the commands sent against a script, answering each
+/// with the scripted response.
+pub(crate) struct MockReader {
+    pub expected: Vec<(&'static [u8], &'static [u8])>,
+    pub pos: usize,
+}
+
+/// Build a [`MockReader`] from `"command" => "response"` pairs in hex.
+macro_rules! mock {
+    ($($tx:literal => $rx:literal,)*) => {
+        $crate::test_util::MockReader {
+            expected: vec![
+                $((&hex_literal::hex!($tx), &hex_literal::hex!($rx)),)*
+            ],
+            pos: 0,
+        }
+    };
+}
+
+pub(crate) use mock;
+
+impl MockReader {
+    /// Check the next command and return its response.
+    pub fn next(&mut self, tx: &[u8]) -> &'static [u8] {
+        let Some((exp_tx, exp_rx)) = self.expected.get(self.pos) else {
+            panic!("unexpected transceive: {:02x?}", tx);
+        };
+        self.pos += 1;
+        assert_eq!(tx, *exp_tx);
+        exp_rx
+    }
+
+    pub fn assert_done(&self) {
+        assert_eq!(self.pos, self.expected.len(), "not all expected exchanges were done");
+    }
+}
+
+impl Reader for MockReader {
+    type Error = ();
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Self::Error> {
+        let exp_rx = self.next(tx);
+        rx[..exp_rx.len()].copy_from_slice(exp_rx);
+        Ok(exp_rx.len())
+    }
+}
+
+/// On raw frames, responses of a single byte below 0x10 are sent as a 4-bit ACK/NAK, others
+/// get a CRC_A.
+impl iso14443a::Reader for MockReader {
+    type Error = ErrorKind;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], _timeout_1fc: u32) -> Result<usize, Self::Error> {
+        let exp_rx = self.next(tx);
+        rx[..exp_rx.len()].copy_from_slice(exp_rx);
+        Ok(exp_rx.len())
+    }
+
+    async fn transceive_raw(&mut self, tx: &[u8], rx: &mut [u8], opts: RawFrame) -> Result<usize, Self::Error> {
+        assert!(opts.tx_crc && !opts.rx_crc && opts.parity);
+        let exp_rx = self.next(tx);
+        if let [nibble @ 0..0x10] = exp_rx {
+            rx[0] = *nibble;
+            return Ok(4);
+        }
+        rx[..exp_rx.len()].copy_from_slice(exp_rx);
+        rx[exp_rx.len()..][..2].copy_from_slice(&crc_a(exp_rx));
+        Ok((exp_rx.len() + 2) * 8)
+    }
+
+    fn uid(&self) -> &[u8] {
+        &[0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]
+    }
+
+    fn atqa(&self) -> [u8; 2] {
+        [0x44, 0x00]
+    }
+
+    fn sak(&self) -> u8 {
+        0x00
+    }
+}
+
+/// Returns the given bytes as random data, repeated as needed.
+pub(crate) struct FixedRng(pub &'static [u8]);
+
+impl RngCore for FixedRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for (d, s) in dest.iter_mut().zip(self.0.iter().cycle()) {
+            *d = *s;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for FixedRng {}
+
+/// Returns the given bytes as random data, in order. Panics when they run out.
+pub(crate) struct ScriptedRng(pub Vec<u8>);
+
+impl RngCore for ScriptedRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let rest = self.0.split_off(dest.len());
+        dest.copy_from_slice(&self.0);
+        self.0 = rest;
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for ScriptedRng {}
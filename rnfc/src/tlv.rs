@@ -0,0 +1,552 @@
+//! BER-TLV parsing and building, as used by ISO 7816-4 data objects.
+//!
+//! [`iter`] walks a sequence of data objects without copying, and [`Tlv::children`] walks the
+//! value of a constructed one. [`find_path`] looks up nested objects, for example the PDOL
+//! inside the FCI template of a SELECT response.
+//!
+//! [`Encoder`] serializes data objects into a `heapless` buffer. Constructed objects are
+//! built with a closure, and their length is filled in afterwards.
+//!
+//! Tags are up to 4 bytes long. Only definite lengths are supported, up to 4 length bytes.
+//! `00` and `FF` bytes between data objects are padding, as allowed by ISO 7816-4.
+
+use heapless::Vec;
+
+const TAG_CONSTRUCTED: u8 = 0x20;
+const TAG_NUMBER_MASK: u8 = 0x1F;
+const TAG_MORE: u8 = 0x80;
+const MAX_TAG_LEN: usize = 4;
+const MAX_LENGTH_BYTES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Data ended in the middle of a data object.
+    Truncated,
+    /// The tag is longer than 4 bytes, or starts with `00` or `FF`.
+    InvalidTag,
+    /// Indefinite length, or a length field longer than 4 bytes.
+    InvalidLength,
+    /// The output buffer is too small.
+    BufferTooSmall,
+}
+
+/// Tag class, from the top 2 bits of the first tag byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Class {
+    Universal,
+    Application,
+    ContextSpecific,
+    Private,
+}
+
+/// A tag, with its bytes as a big endian number: `5F20` is `Tag(0x5F20)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Tag(pub u32);
+
+impl Tag {
+    /// Parse the tag at the start of `data`, returning it and its length.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), Error> {
+        let first = *data.first().ok_or(Error::Truncated)?;
+        if first == 0x00 || first == 0xFF {
+            return Err(Error::InvalidTag);
+        }
+
+        let mut tag = first as u32;
+        if first & TAG_NUMBER_MASK != TAG_NUMBER_MASK {
+            return Ok((Self(tag), 1));
+        }
+        for i in 1..MAX_TAG_LEN {
+            let b = *data.get(i).ok_or(Error::Truncated)?;
+            tag = tag << 8 | b as u32;
+            if b & TAG_MORE == 0 {
+                return Ok((Self(tag), i + 1));
+            }
+        }
+        Err(Error::InvalidTag)
+    }
+
+    fn first_byte(&self) -> u8 {
+        (self.0 >> ((self.encoded_len() - 1) * 8)) as u8
+    }
+
+    /// Number of bytes of the tag.
+    pub fn encoded_len(&self) -> usize {
+        (4 - self.0.leading_zeros() as usize / 8).max(1)
+    }
+
+    pub fn class(&self) -> Class {
+        match self.first_byte() >> 6 {
+            0 => Class::Universal,
+            1 => Class::Application,
+            2 => Class::ContextSpecific,
+            _ => Class::Private,
+        }
+    }
+
+    /// Whether the value of data objects with this tag is a sequence of data objects.
+    pub fn is_constructed(&self) -> bool {
+        self.first_byte() & TAG_CONSTRUCTED != 0
+    }
+
+    fn to_bytes(self) -> ([u8; MAX_TAG_LEN], usize) {
+        let len = self.encoded_len();
+        let mut buf = [0; MAX_TAG_LEN];
+        buf[..len].copy_from_slice(&self.0.to_be_bytes()[MAX_TAG_LEN - len..]);
+        (buf, len)
+    }
+}
+
+/// Parse the length field at the start of `data`, returning the length and the field size.
+fn parse_length(data: &[u8]) -> Result<(usize, usize), Error> {
+    let first = *data.first().ok_or(Error::Truncated)?;
+    if first < 0x80 {
+        return Ok((first as usize, 1));
+    }
+    let n = (first & 0x7F) as usize;
+    if n == 0 || n > MAX_LENGTH_BYTES {
+        return Err(Error::InvalidLength);
+    }
+    let bytes = data.get(1..1 + n).ok_or(Error::Truncated)?;
+    let len = bytes.iter().fold(0u32, |acc, &b| acc << 8 | b as u32);
+    let len = usize::try_from(len).map_err(|_| Error::InvalidLength)?;
+    Ok((len, 1 + n))
+}
+
+/// Encode a length field, in the shortest form.
+pub(crate) fn encode_length(len: usize) -> Result<([u8; 1 + MAX_LENGTH_BYTES], usize), Error> {
+    let len = u32::try_from(len).map_err(|_| Error::InvalidLength)?;
+    let mut buf = [0; 1 + MAX_LENGTH_BYTES];
+    if len < 0x80 {
+        buf[0] = len as u8;
+        return Ok((buf, 1));
+    }
+    let n = 4 - len.leading_zeros() as usize / 8;
+    buf[0] = 0x80 | n as u8;
+    buf[1..][..n].copy_from_slice(&len.to_be_bytes()[4 - n..]);
+    Ok((buf, 1 + n))
+}
+
+/// A data object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Tlv<'a> {
+    pub tag: Tag,
+    pub value: &'a [u8],
+}
+
+impl<'a> Tlv<'a> {
+    /// Parse the data object at the start of `data`, returning it and its encoded length.
+    /// Padding isn't skipped.
+    pub fn parse(data: &'a [u8]) -> Result<(Self, usize), Error> {
+        let (tag, tag_len) = Tag::parse(data)?;
+        let (len, len_len) = parse_length(&data[tag_len..])?;
+        let start = tag_len + len_len;
+        let value = data.get(start..).and_then(|d| d.get(..len)).ok_or(Error::Truncated)?;
+        Ok((Self { tag, value }, start + len))
+    }
+
+    pub fn is_constructed(&self) -> bool {
+        self.tag.is_constructed()
+    }
+
+    /// Iterate the data objects in the value. Empty for primitive data objects.
+    pub fn children(&self) -> Iter<'a> {
+        match self.is_constructed() {
+            true => iter(self.value),
+            false => iter(&[]),
+        }
+    }
+
+    /// Find the first child with `tag`.
+    pub fn find(&self, tag: Tag) -> Option<Tlv<'a>> {
+        find(self.children().data, tag)
+    }
+
+    /// Follow `path` down from this data object's children.
+    pub fn find_path(&self, path: &[Tag]) -> Option<Tlv<'a>> {
+        find_path(self.children().data, path)
+    }
+}
+
+/// Parse only the tag and length at the start of `data`, returning the tag, the value length
+/// and the header length. The value doesn't need to be there, to size a file from its first
+/// bytes.
+pub fn parse_header(data: &[u8]) -> Result<(Tag, usize, usize), Error> {
+    let (tag, tag_len) = Tag::parse(data)?;
+    let (len, len_len) = parse_length(&data[tag_len..])?;
+    Ok((tag, len, tag_len + len_len))
+}
+
+/// Iterate the data objects in `data`.
+pub fn iter(data: &[u8]) -> Iter<'_> {
+    Iter { data }
+}
+
+/// Find the first data object in `data` with `tag`, without looking into constructed ones.
+/// Data objects after a malformed one are not found.
+pub fn find(data: &[u8], tag: Tag) -> Option<Tlv<'_>> {
+    iter(data).map_while(Result::ok).find(|t| t.tag == tag)
+}
+
+/// Find a nested data object: the first one in `data` matching `path[0]`, then in its value
+/// the first one matching `path[1]`, and so on. An empty path finds nothing.
+pub fn find_path<'a>(data: &'a [u8], path: &[Tag]) -> Option<Tlv<'a>> {
+    let (first, rest) = path.split_first()?;
+    let mut tlv = find(data, *first)?;
+    for tag in rest {
+        tlv = tlv.find(*tag)?;
+    }
+    Some(tlv)
+}
+
+/// Iterator over a sequence of data objects, see [`iter`].
+///
+/// Yields an error and stops at the first malformed data object.
+#[derive(Clone)]
+pub struct Iter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iter<'a> {
+    /// The data not iterated yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<Tlv<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.data.iter().position(|&b| b != 0x00 && b != 0xFF)?;
+        match Tlv::parse(&self.data[start..]) {
+            Ok((tlv, n)) => {
+                self.data = &self.data[start + n..];
+                Some(Ok(tlv))
+            }
+            Err(e) => {
+                self.data = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Serializes data objects.
+pub struct Encoder<const N: usize> {
+    buf: Vec<u8, N>,
+}
+
+impl<const N: usize> Default for Encoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Encoder<N> {
+    pub const fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// Append a primitive data object.
+    pub fn push(&mut self, tag: Tag, value: &[u8]) -> Result<(), Error> {
+        self.transaction(|this| {
+            this.write_tag(tag)?;
+            let (len, n) = encode_length(value.len())?;
+            this.write(&len[..n])?;
+            this.write(value)
+        })
+    }
+
+    /// Append a data object with a value written by `f`, usually a constructed one whose
+    /// children are pushed by `f`.
+    pub fn push_with(&mut self, tag: Tag, f: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        self.transaction(|this| {
+            this.write_tag(tag)?;
+            let start = this.buf.len();
+            f(this)?;
+
+            // Move the value to make room for the length.
+            let (len, n) = encode_length(this.buf.len() - start)?;
+            let end = this.buf.len();
+            this.buf.resize(end + n, 0).map_err(|_| Error::BufferTooSmall)?;
+            this.buf.copy_within(start..end, start + n);
+            this.buf[start..][..n].copy_from_slice(&len[..n]);
+            Ok(())
+        })
+    }
+
+    /// Append already encoded data objects, or padding.
+    pub fn push_raw(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.write(data)
+    }
+
+    /// Run `f`, restoring the buffer to what it was on failure.
+    fn transaction(&mut self, f: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        let len = self.buf.len();
+        let res = f(self);
+        if res.is_err() {
+            self.buf.truncate(len);
+        }
+        res
+    }
+
+    fn write_tag(&mut self, tag: Tag) -> Result<(), Error> {
+        let (bytes, n) = tag.to_bytes();
+        if Tag::parse(&bytes[..n]) != Ok((tag, n)) {
+            return Err(Error::InvalidTag);
+        }
+        self.write(&bytes[..n])
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.buf.extend_from_slice(data).map_err(|_| Error::BufferTooSmall)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn finish(self) -> Vec<u8, N> {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_tag() {
+        assert_eq!(Tag::parse(&hex!("6F 1A")), Ok((Tag(0x6F), 1)));
+        assert_eq!(Tag::parse(&hex!("9F38 00")), Ok((Tag(0x9F38), 2)));
+        assert_eq!(Tag::parse(&hex!("BF0C")), Ok((Tag(0xBF0C), 2)));
+        assert_eq!(Tag::parse(&hex!("5F8102")), Ok((Tag(0x5F8102), 3)));
+        assert_eq!(Tag::parse(&hex!("1F818181")), Err(Error::InvalidTag));
+        assert_eq!(Tag::parse(&hex!("9F")), Err(Error::Truncated));
+        assert_eq!(Tag::parse(&hex!("00")), Err(Error::InvalidTag));
+
+        assert_eq!(Tag(0x6F).class(), Class::Application);
+        assert!(Tag(0x6F).is_constructed());
+        assert_eq!(Tag(0x9F38).class(), Class::ContextSpecific);
+        assert!(!Tag(0x9F38).is_constructed());
+        assert!(Tag(0xBF0C).is_constructed());
+        assert_eq!(Tag(0x5F8102).encoded_len(), 3);
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(parse_length(&hex!("7F")), Ok((0x7F, 1)));
+        assert_eq!(parse_length(&hex!("8180")), Ok((0x80, 2)));
+        assert_eq!(parse_length(&hex!("820100")), Ok((0x100, 3)));
+        assert_eq!(parse_length(&hex!("8401000000")), Ok((0x0100_0000, 5)));
+        assert_eq!(parse_length(&hex!("80")), Err(Error::InvalidLength));
+        assert_eq!(parse_length(&hex!("85")), Err(Error::InvalidLength));
+        assert_eq!(parse_length(&hex!("82 01")), Err(Error::Truncated));
+
+        for len in [0, 0x7F, 0x80, 0xFF, 0x100, 0xFFFF, 0x10000] {
+            let (buf, n) = encode_length(len).unwrap();
+            assert_eq!(parse_length(&buf[..n]), Ok((len, n)));
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        // SELECT PPSE response, from EMV Book 1.
+        let data = hex!(
+            "6F 23 840E 325041592E5359532E4444463031"
+            "A5 11 BF0C 0E 61 0C 4F07 A0000000031010 8701 01"
+        );
+        let mut it = iter(&data);
+        let fci = it.next().unwrap().unwrap();
+        assert_eq!(fci.tag, Tag(0x6F));
+        assert_eq!(fci.find(Tag(0x84)).unwrap().value, b"2PAY.SYS.DDF01");
+
+        let app = fci.find_path(&[Tag(0xA5), Tag(0xBF0C), Tag(0x61)]).unwrap();
+        assert_eq!(app.find(Tag(0x4F)).unwrap().value, hex!("A0000000031010"));
+        assert_eq!(
+            find_path(&data, &[Tag(0x6F), Tag(0xA5), Tag(0xBF0C), Tag(0x61), Tag(0x87)])
+                .unwrap()
+                .value,
+            hex!("01")
+        );
+        assert_eq!(find_path(&data, &[Tag(0x6F), Tag(0x61)]), None);
+
+        assert_eq!(it.next(), None);
+
+        let mut it = iter(&data[..data.len() - 1]);
+        assert_eq!(it.next(), Some(Err(Error::Truncated)));
+        assert_eq!(it.next(), None);
+
+        assert_eq!(parse_header(&data[..4]), Ok((Tag(0x6F), 0x23, 2)));
+        assert_eq!(parse_header(&hex!("7F61 82 1234")), Ok((Tag(0x7F61), 0x1234, 5)));
+        assert_eq!(parse_header(&hex!("7F61 82 12")), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn test_padding_and_primitive() {
+        let data = hex!("00 00 57 02 1234 FF FF 5A 01 99 00");
+        let tlvs: Vec<_> = iter(&data).map(Result::unwrap).collect();
+        assert_eq!(tlvs.len(), 2);
+        assert_eq!(tlvs[1].tag, Tag(0x5A));
+        assert_eq!(tlvs[1].value, hex!("99"));
+        // Primitive values aren't parsed as data objects.
+        assert_eq!(tlvs[0].children().count(), 0);
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut e = Encoder::<64>::new();
+        e.push(Tag(0x9F02), &hex!("000000001000")).unwrap();
+        e.push_with(Tag(0x70), |e| {
+            e.push(Tag(0x5A), &hex!("4761739001010010"))?;
+            e.push(Tag(0x5F24), &hex!("301231"))
+        })
+        .unwrap();
+        assert_eq!(
+            e.as_bytes(),
+            hex!("9F02 06 000000001000 70 10 5A08 4761739001010010 5F2403 301231")
+        );
+
+        // Failed pushes leave the buffer untouched.
+        let len = e.as_bytes().len();
+        assert_eq!(e.push(Tag(0x1F8181FF), &[]), Err(Error::InvalidTag));
+        assert_eq!(e.push(Tag(0x00), &[]), Err(Error::InvalidTag));
+        assert_eq!(e.push(Tag(0x53), &[0; 64]), Err(Error::BufferTooSmall));
+        assert_eq!(
+            e.push_with(Tag(0x70), |e| e.push(Tag(0x53), &[0; 34])),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(e.as_bytes().len(), len);
+
+        // Long values get a long form length, moving the value of constructed ones.
+        let mut e = Encoder::<512>::new();
+        e.push_with(Tag(0x7F21), |e| e.push(Tag(0x53), &[0xAA; 0x100])).unwrap();
+        let buf = e.finish();
+        assert_eq!(buf[..10], hex!("7F21 820104 53 820100 AA"));
+        assert_eq!(buf.len(), 2 + 3 + 4 + 0x100);
+    }
+
+    /// xorshift32, so the property tests below are reproducible.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn below(&mut self, n: u32) -> u32 {
+            self.next() % n
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Node {
+        Primitive(Tag, Vec<u8>),
+        Constructed(Tag, Vec<Node>),
+    }
+
+    fn random_tag(rng: &mut Rng, constructed: bool) -> Tag {
+        let first = (rng.below(4) as u8) << 6 | (constructed as u8) << 5;
+        if rng.below(2) == 0 {
+            // Low tag number, not 0 (padding) and not 1F (more bytes).
+            return Tag((first | (1 + rng.below(30) as u8)) as u32);
+        }
+        if first == 0xE0 {
+            // Private constructed high tag numbers start with FF.
+            return Tag(0xE1);
+        }
+        let mut tag = (first | TAG_NUMBER_MASK) as u32;
+        for _ in 0..rng.below(2) {
+            tag = tag << 8 | (TAG_MORE | rng.below(0x80) as u8) as u32;
+        }
+        Tag(tag << 8 | rng.below(0x80))
+    }
+
+    fn random_tree(rng: &mut Rng, depth: u32) -> Vec<Node> {
+        (0..rng.below(4))
+            .map(|_| {
+                if depth > 0 && rng.below(3) == 0 {
+                    Node::Constructed(random_tag(rng, true), random_tree(rng, depth - 1))
+                } else {
+                    let len = match rng.below(4) {
+                        0 => rng.below(0x200),
+                        _ => rng.below(8),
+                    };
+                    Node::Primitive(random_tag(rng, false), (0..len).map(|_| rng.next() as u8).collect())
+                }
+            })
+            .collect()
+    }
+
+    fn encode_tree<const N: usize>(e: &mut Encoder<N>, nodes: &[Node]) -> Result<(), Error> {
+        for node in nodes {
+            match node {
+                Node::Primitive(tag, value) => e.push(*tag, value)?,
+                Node::Constructed(tag, children) => e.push_with(*tag, |e| encode_tree(e, children))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_tree(data: &[u8]) -> Result<Vec<Node>, Error> {
+        iter(data)
+            .map(|tlv| {
+                let tlv = tlv?;
+                Ok(match tlv.is_constructed() {
+                    true => Node::Constructed(tlv.tag, parse_tree(tlv.value)?),
+                    false => Node::Primitive(tlv.tag, tlv.value.to_vec()),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_random() {
+        let mut rng = Rng(0x1234_5678);
+        for _ in 0..500 {
+            let tree = random_tree(&mut rng, 3);
+            let mut e = Encoder::<8192>::new();
+            encode_tree(&mut e, &tree).unwrap();
+            let data = e.finish();
+            assert_eq!(parse_tree(&data).unwrap(), tree);
+
+            // Cutting the data anywhere but between top-level data objects is detected.
+            let mut boundaries = vec![0];
+            let mut it = iter(&data);
+            while it.next().is_some() {
+                boundaries.push(data.len() - it.remaining().len());
+            }
+            for len in 0..data.len() {
+                let cut = iter(&data[..len]).any(|r| r.is_err());
+                assert_eq!(cut, !boundaries.contains(&len), "len {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_random() {
+        // Any input must either fail or yield data objects within the input.
+        let mut rng = Rng(0xDEAD_BEEF);
+        for _ in 0..2000 {
+            let data: Vec<u8> = (0..rng.below(64)).map(|_| rng.next() as u8).collect();
+            let mut it = iter(&data);
+            while let Some(Ok(tlv)) = it.next() {
+                let start = tlv.value.as_ptr() as usize - data.as_ptr() as usize;
+                assert!(start + tlv.value.len() <= data.len());
+                let _ = parse_tree(tlv.value);
+                let _ = tlv.find_path(&[Tag(0x61), Tag(0x4F)]);
+            }
+        }
+    }
+}
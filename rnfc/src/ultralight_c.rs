@@ -0,0 +1,268 @@
+//! MIFARE Ultralight C: 2K3DES mutual authentication and memory protection.
+//!
+//! After AUTHENTICATE, pages from AUTH0 on can be written, and read too unless AUTH1 only
+//! restricts writes. The tag stays authenticated until it's halted or leaves the field.
+//! Memory access is plain [`t2t`](crate::t2t) READ and WRITE.
+
+use rand_core::CryptoRngCore;
+use rnfc_traits::iso14443a::Reader;
+
+use crate::crypto::{Cipher, Key};
+use crate::{ntag, t2t};
+
+const CMD_AUTHENTICATE: u8 = 0x1A;
+const AUTH_CONTINUE: u8 = 0xAF;
+const AUTH_DONE: u8 = 0x00;
+
+const TIMEOUT_1FC: u32 = 65536;
+
+/// Page holding AUTH0, the first protected page.
+pub const AUTH0_PAGE: u8 = 0x2A;
+/// Page holding AUTH1, whether reads are protected too.
+pub const AUTH1_PAGE: u8 = 0x2B;
+/// First of the 4 write-only key pages.
+pub const KEY_PAGE: u8 = 0x2C;
+/// AUTH0 value disabling protection: the page after the last one.
+pub const AUTH0_DISABLED: u8 = 0x30;
+
+/// Default key of new tags, `BREAKMEIFYOUCAN!` with each half reversed.
+pub const DEFAULT_KEY: [u8; 16] = *b"IEMKAERB!NACUOYF";
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Lower(E),
+    Protocol,
+    /// The tag answered with a NAK. The value is the 4-bit NAK code.
+    Nak(u8),
+    /// The tag's authentication response doesn't match, or it refused ours: it has
+    /// another key.
+    AuthFailed,
+}
+
+impl<E> From<t2t::Error<E>> for Error<E> {
+    fn from(e: t2t::Error<E>) -> Self {
+        match e {
+            t2t::Error::Lower(e) => Self::Lower(e),
+            t2t::Error::Nak(n) => Self::Nak(n),
+            _ => Self::Protocol,
+        }
+    }
+}
+
+impl<E> From<ntag::Error<E>> for Error<E> {
+    fn from(e: ntag::Error<E>) -> Self {
+        match e {
+            ntag::Error::Lower(e) => Self::Lower(e),
+            ntag::Error::Nak(n) => Self::Nak(n),
+            _ => Self::Protocol,
+        }
+    }
+}
+
+/// Memory protection settings, from AUTH0 and AUTH1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AuthConfig {
+    /// First page needing authentication, 0x03 to [`AUTH0_DISABLED`].
+    pub auth0: u8,
+    /// Reads need authentication too, not just writes.
+    pub protect_reads: bool,
+}
+
+/// A MIFARE Ultralight C tag.
+pub struct UltralightC<T: Reader> {
+    card: T,
+    authenticated: bool,
+}
+
+impl<T: Reader> UltralightC<T> {
+    pub fn new(card: T) -> Self {
+        Self {
+            card,
+            authenticated: false,
+        }
+    }
+
+    /// Get the underlying card, for sending other commands.
+    pub fn card(&mut self) -> &mut T {
+        &mut self.card
+    }
+
+    pub fn into_inner(self) -> T {
+        self.card
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// AUTHENTICATE with the 2K3DES `key`.
+    ///
+    /// The tag sends ek(RndB), the reader answers ek(RndA || RndB rotated left by one byte),
+    /// and the tag proves it knows the key with ek(RndA rotated). All steps chain the IV in
+    /// CBC mode.
+    pub async fn authenticate(&mut self, rng: &mut impl CryptoRngCore, key: &[u8; 16]) -> Result<(), Error<T::Error>> {
+        self.authenticated = false;
+        let cipher = Cipher::new(&Key::TDes2(*key));
+
+        let mut rx = [0; 9];
+        let n = ntag::transceive(&mut self.card, &[CMD_AUTHENTICATE, 0x00], &mut rx, TIMEOUT_1FC).await?;
+        if n != 9 || rx[0] != AUTH_CONTINUE {
+            warn!("AUTHENTICATE: bad challenge");
+            return Err(Error::Protocol);
+        }
+        let mut iv = [0; 8];
+        let mut rnd_b: [u8; 8] = unwrap!(rx[1..].try_into());
+        cipher.cbc_decrypt(&mut iv, &mut rnd_b);
+
+        let mut rnd_a = [0; 8];
+        rng.fill_bytes(&mut rnd_a);
+        let mut tx = [0; 17];
+        tx[0] = AUTH_CONTINUE;
+        tx[1..9].copy_from_slice(&rnd_a);
+        tx[9..].copy_from_slice(&rnd_b);
+        tx[9..].rotate_left(1);
+        cipher.cbc_encrypt(&mut iv, &mut tx[1..]);
+
+        let n = match ntag::transceive(&mut self.card, &tx, &mut rx, TIMEOUT_1FC).await {
+            Ok(n) => n,
+            Err(ntag::Error::Nak(_)) => return Err(Error::AuthFailed),
+            Err(e) => return Err(e.into()),
+        };
+        if n != 9 || rx[0] != AUTH_DONE {
+            warn!("AUTHENTICATE: bad response");
+            return Err(Error::Protocol);
+        }
+        cipher.cbc_decrypt(&mut iv, &mut rx[1..]);
+        rnd_a.rotate_left(1);
+        if rx[1..] != rnd_a {
+            warn!("AUTHENTICATE: response mismatch");
+            return Err(Error::AuthFailed);
+        }
+
+        self.authenticated = true;
+        Ok(())
+    }
+
+    /// READ: read 4 pages starting at `page`.
+    pub async fn read(&mut self, page: u8) -> Result<[u8; t2t::READ_LEN], Error<T::Error>> {
+        Ok(t2t::read(&mut self.card, page).await?)
+    }
+
+    /// WRITE: write one page.
+    pub async fn write(&mut self, page: u8, data: &[u8; t2t::PAGE_SIZE]) -> Result<(), Error<T::Error>> {
+        Ok(t2t::write(&mut self.card, page, data).await?)
+    }
+
+    /// Write a new key. The key pages can't be read back, so authenticate with the new key
+    /// to check it.
+    ///
+    /// The tag stores each 8-byte half of the key reversed, so [`DEFAULT_KEY`] is written
+    /// as `BREAKMEIFYOUCAN!`.
+    pub async fn write_key(&mut self, key: &[u8; 16]) -> Result<(), Error<T::Error>> {
+        for (i, half) in key.chunks_exact(8).enumerate() {
+            let mut reversed: [u8; 8] = unwrap!(half.try_into());
+            reversed.reverse();
+            let page = KEY_PAGE + i as u8 * 2;
+            self.write(page, unwrap!(reversed[..4].try_into())).await?;
+            self.write(page + 1, unwrap!(reversed[4..].try_into())).await?;
+        }
+        Ok(())
+    }
+
+    /// Read AUTH0 and AUTH1.
+    pub async fn read_auth_config(&mut self) -> Result<AuthConfig, Error<T::Error>> {
+        // Pages 0x28 to 0x2B: lock bytes, counter, AUTH0, AUTH1.
+        let data = self.read(0x28).await?;
+        Ok(AuthConfig {
+            auth0: data[8],
+            protect_reads: data[12] & 0x01 == 0,
+        })
+    }
+
+    /// Write AUTH1, then AUTH0.
+    ///
+    /// Protection starts right away, so with reads protected the tag must already be
+    /// authenticated to read the configuration back.
+    pub async fn write_auth_config(&mut self, config: &AuthConfig) -> Result<(), Error<T::Error>> {
+        let auth1 = match config.protect_reads {
+            true => 0x00,
+            false => 0x01,
+        };
+        self.write(AUTH1_PAGE, &[auth1, 0, 0, 0]).await?;
+        self.write(AUTH0_PAGE, &[config.auth0, 0, 0, 0]).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{FixedRng, mock};
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let mut r = mock!(
+            // RndB = 0102030405060708, RndA = 5555555555555555
+            "1A00" => "AF 14872DC9707C94A5",
+            "AF 019A9FEC618D12D25C9A766838D2F3B0" => "00 018F8FB43F4273D5",
+            // Wrong answer to the same challenge.
+            "1A00" => "AF 14872DC9707C94A5",
+            "AF 019A9FEC618D12D25C9A766838D2F3B0" => "00 018F8FB43F4273D6",
+            // Tag with another key.
+            "1A00" => "AF 14872DC9707C94A5",
+            "AF 019A9FEC618D12D25C9A766838D2F3B0" => "04",
+        );
+        let mut tag = UltralightC::new(&mut r);
+        tag.authenticate(&mut FixedRng(&[0x55]), &DEFAULT_KEY).await.unwrap();
+        assert!(tag.is_authenticated());
+
+        assert!(matches!(
+            tag.authenticate(&mut FixedRng(&[0x55]), &DEFAULT_KEY).await,
+            Err(Error::AuthFailed)
+        ));
+        assert!(!tag.is_authenticated());
+        assert!(matches!(
+            tag.authenticate(&mut FixedRng(&[0x55]), &DEFAULT_KEY).await,
+            Err(Error::AuthFailed)
+        ));
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_write_key() {
+        let mut r = mock!(
+            "A2 2C 42524541" => "0A",
+            "A2 2D 4B4D4549" => "0A",
+            "A2 2E 46594F55" => "0A",
+            "A2 2F 43414E21" => "0A",
+        );
+        let mut tag = UltralightC::new(&mut r);
+        tag.write_key(&DEFAULT_KEY).await.unwrap();
+        r.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_auth_config() {
+        let mut r = mock!(
+            "30 28" => "00000000 00000000 30000000 00000000",
+            "A2 2B 01000000" => "0A",
+            "A2 2A 10000000" => "0A",
+        );
+        let mut tag = UltralightC::new(&mut r);
+        assert_eq!(
+            tag.read_auth_config().await.unwrap(),
+            AuthConfig {
+                auth0: AUTH0_DISABLED,
+                protect_reads: true,
+            }
+        );
+        tag.write_auth_config(&AuthConfig {
+            auth0: 0x10,
+            protect_reads: false,
+        })
+        .await
+        .unwrap();
+        r.assert_done();
+    }
+}